@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // the gRPC service is generated from proto/forkscanner.proto, with the protoc binary
+    // of protoc-bin-vendored so building doesn't need one installed
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("No protoc for this platform");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::configure()
+            .build_client(false)
+            .compile(&["proto/forkscanner.proto"], &["proto"])
+            .expect("Could not compile proto/forkscanner.proto");
+    }
+}
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blocks
+DROP COLUMN body_source,
+DROP COLUMN body_source_node;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE blocks
+ADD COLUMN body_source varchar,
+ADD COLUMN body_source_node bigint;
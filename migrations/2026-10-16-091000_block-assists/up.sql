@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE block_assists (
+	block_hash varchar not null,
+	node_id bigint not null,
+	source_node bigint,
+	attempts integer not null default 0,
+	accepted boolean not null default false,
+	created_at timestamp with time zone not null,
+	updated_at timestamp with time zone not null,
+	PRIMARY KEY (block_hash, node_id),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE,
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE rollback_schedule (
+	block_hash varchar not null,
+	node_id bigint not null,
+	height bigint not null,
+	branch_len bigint not null,
+	priority integer not null,
+	status varchar not null,
+	last_checked_at timestamp with time zone,
+	updated_at timestamp with time zone not null,
+	PRIMARY KEY (block_hash, node_id),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE,
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
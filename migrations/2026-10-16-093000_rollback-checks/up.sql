@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE rollback_checks (
+	id bigserial,
+	block_hash varchar not null,
+	node_id bigint not null,
+	verdict varchar not null,
+	detail varchar,
+	started_at timestamp with time zone not null,
+	finished_at timestamp with time zone not null,
+	PRIMARY KEY (id),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE,
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX rollback_checks_hash_node ON rollback_checks (block_hash, node_id);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE tip_statuses (
+	node_id bigint not null,
+	block_hash varchar not null,
+	height bigint not null,
+	status varchar not null,
+	updated_at timestamp with time zone not null,
+	PRIMARY KEY (node_id, block_hash),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
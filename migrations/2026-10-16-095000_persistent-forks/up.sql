@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE persistent_forks (
+	node_id bigint not null,
+	fork_point varchar not null,
+	tip varchar not null,
+	branch_len bigint not null,
+	alerted_len bigint,
+	first_seen_at timestamp with time zone not null,
+	alerted_at timestamp with time zone,
+	updated_at timestamp with time zone not null,
+	PRIMARY KEY (node_id, fork_point),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
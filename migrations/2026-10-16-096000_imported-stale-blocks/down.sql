@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE imported_stale_blocks;
+
+ALTER TABLE stale_candidate
+DROP COLUMN source;
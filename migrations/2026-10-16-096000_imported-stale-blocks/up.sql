@@ -0,0 +1,16 @@
+-- Your SQL goes here
+ALTER TABLE stale_candidate
+ADD COLUMN source varchar not null default 'local';
+
+CREATE TABLE imported_stale_blocks (
+	hash varchar not null,
+	height bigint not null,
+	parent_hash varchar,
+	source varchar not null,
+	imported_at timestamp with time zone not null,
+	PRIMARY KEY (hash),
+	CONSTRAINT fk_candidate_height
+	  FOREIGN KEY(height)
+	    REFERENCES stale_candidate(height)
+	    ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE nodes
+ADD COLUMN zmq_endpoint varchar;
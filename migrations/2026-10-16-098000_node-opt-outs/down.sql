@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE nodes
+DROP COLUMN fetch_templates,
+DROP COLUMN fetch_peers,
+DROP COLUMN participate_in_consensus,
+DROP COLUMN allow_mirror_ops;
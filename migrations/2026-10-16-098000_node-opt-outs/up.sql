@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE nodes
+ADD COLUMN fetch_templates boolean not null default true,
+ADD COLUMN fetch_peers boolean not null default true,
+ADD COLUMN participate_in_consensus boolean not null default true,
+ADD COLUMN allow_mirror_ops boolean not null default true;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+	id bigserial primary key,
+	name varchar not null unique,
+	key_hash varchar not null unique,
+	permission varchar not null,
+	created_at timestamptz not null default now(),
+	revoked_at timestamptz
+);
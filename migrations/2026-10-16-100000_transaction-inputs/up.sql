@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE transaction_inputs (
+	block_id varchar not null,
+	txid varchar not null,
+	vin integer not null,
+	prev_txid varchar not null,
+	prev_vout bigint not null,
+	PRIMARY KEY(block_id, txid, vin)
+);
+
+CREATE INDEX transaction_inputs_prev_idx ON transaction_inputs (prev_txid, prev_vout);
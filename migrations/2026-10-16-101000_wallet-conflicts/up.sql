@@ -0,0 +1,26 @@
+-- Your SQL goes here
+CREATE TABLE unconfirmed_spends (
+	node_id bigint not null,
+	txid varchar not null,
+	vin integer not null,
+	prev_txid varchar not null,
+	prev_vout bigint not null,
+	seen_at timestamp with time zone not null,
+	PRIMARY KEY (node_id, txid, vin),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX unconfirmed_spends_prev_idx ON unconfirmed_spends (prev_txid, prev_vout);
+
+CREATE TABLE wallet_conflicts (
+	prev_txid varchar not null,
+	prev_vout bigint not null,
+	address varchar not null,
+	spending_txids text[] not null,
+	first_seen_at timestamp with time zone not null,
+	alerted_at timestamp with time zone not null,
+	PRIMARY KEY (prev_txid, prev_vout)
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blocks
+DROP COLUMN template_match_node,
+DROP COLUMN template_similarity,
+DROP COLUMN template_matched_at;
+
+DROP TABLE template_snapshots;
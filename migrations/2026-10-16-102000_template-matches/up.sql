@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE template_snapshots (
+	parent_block_hash varchar not null,
+	node_id bigint not null,
+	height bigint not null,
+	tx_ids bytea not null,
+	created_at timestamp with time zone not null,
+	PRIMARY KEY (parent_block_hash, node_id),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+ALTER TABLE blocks
+ADD COLUMN template_match_node bigint,
+ADD COLUMN template_similarity double precision,
+ADD COLUMN template_matched_at timestamp with time zone;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE lags
+DROP COLUMN height_deficit,
+DROP COLUMN work_deficit_blocks;
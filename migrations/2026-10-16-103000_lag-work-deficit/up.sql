@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE lags
+ADD COLUMN height_deficit bigint not null default 0,
+ADD COLUMN work_deficit_blocks double precision;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE invalid_blocks
+DROP COLUMN updated_at,
+ALTER COLUMN created_at DROP NOT NULL,
+ALTER COLUMN created_at DROP DEFAULT;
+
+ALTER TABLE chaintips
+DROP COLUMN created_at,
+DROP COLUMN updated_at;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE chaintips
+ADD COLUMN created_at timestamp with time zone not null default now(),
+ADD COLUMN updated_at timestamp with time zone not null default now();
+
+UPDATE invalid_blocks SET created_at = now() WHERE created_at IS NULL;
+
+ALTER TABLE invalid_blocks
+ALTER COLUMN created_at SET DEFAULT now(),
+ALTER COLUMN created_at SET NOT NULL,
+ADD COLUMN updated_at timestamp with time zone not null default now();
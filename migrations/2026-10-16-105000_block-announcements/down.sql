@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blocks
+DROP COLUMN first_seen_peer,
+DROP COLUMN first_seen_peer_at;
+
+ALTER TABLE peers
+DROP COLUMN last_block;
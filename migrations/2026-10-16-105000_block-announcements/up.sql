@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE peers
+ADD COLUMN last_block timestamp with time zone;
+
+ALTER TABLE blocks
+ADD COLUMN first_seen_peer varchar,
+ADD COLUMN first_seen_peer_at timestamp with time zone;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE peer_reputation (
+	address varchar not null,
+	failed_requests int not null default 0,
+	ban_score int not null default 0,
+	last_node_id bigint,
+	created_at timestamp with time zone not null default now(),
+	updated_at timestamp with time zone not null default now(),
+	PRIMARY KEY (address),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(last_node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE SET NULL
+);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE node_status (
+	node_id bigint not null,
+	version bigint,
+	subversion varchar,
+	last_error varchar,
+	last_error_at timestamp with time zone,
+	updated_at timestamp with time zone not null default now(),
+	PRIMARY KEY (node_id),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE subscriber_acks;
+DROP TABLE pending_events;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE pending_events (
+	id bigserial,
+	client_id varchar not null,
+	channel varchar not null,
+	payload text not null,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX pending_events_client_idx ON pending_events (client_id, channel);
+
+CREATE TABLE subscriber_acks (
+	client_id varchar not null,
+	channel varchar not null,
+	acked_id bigint not null default 0,
+	updated_at timestamp with time zone not null default now(),
+	PRIMARY KEY (client_id, channel)
+);
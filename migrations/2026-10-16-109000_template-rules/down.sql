@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE template_snapshots
+DROP COLUMN rules;
+
+ALTER TABLE block_templates
+DROP COLUMN rules;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE block_templates
+ADD COLUMN rules text[] not null default '{}';
+
+ALTER TABLE template_snapshots
+ADD COLUMN rules text[] not null default '{}';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE block_payouts;
+
+DROP TABLE pool_addresses;
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TABLE pool_addresses (
+	address varchar not null,
+	name varchar not null,
+	url varchar not null,
+	created_at timestamp with time zone not null default now(),
+	updated_at timestamp with time zone not null default now(),
+	PRIMARY KEY (address)
+);
+
+CREATE TABLE block_payouts (
+	block_hash varchar not null,
+	address varchar not null,
+	amount bigint not null,
+	tagged boolean not null,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (block_hash),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX block_payouts_address ON block_payouts (address);
+CREATE INDEX block_payouts_created_at ON block_payouts (created_at);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE censorship_candidates;
+
+ALTER TABLE block_templates
+DROP COLUMN tx_fee_rates;
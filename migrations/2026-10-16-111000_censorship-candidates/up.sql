@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE block_templates
+ADD COLUMN tx_fee_rates integer[] not null default '{}';
+
+CREATE TABLE censorship_candidates (
+	txid varchar not null,
+	pool_name varchar not null,
+	fee_rate integer not null,
+	omissions bigint not null,
+	first_height bigint not null,
+	last_height bigint not null,
+	last_block varchar not null,
+	first_omitted_at timestamp with time zone not null,
+	last_omitted_at timestamp with time zone not null,
+	alerted_at timestamp with time zone,
+	PRIMARY KEY (txid, pool_name)
+);
+
+CREATE INDEX censorship_candidates_omissions ON censorship_candidates (omissions);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE unknown_coinbase_tags (
+	block_hash varchar not null,
+	height bigint not null,
+	coinbase_message bytea not null,
+	tag varchar not null,
+	payout_address varchar,
+	pool_name varchar,
+	labeled_at timestamp with time zone,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (block_hash),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE
+);
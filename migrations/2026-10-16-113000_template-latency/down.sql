@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE blocks
+DROP COLUMN template_latency,
+DROP COLUMN stale_template;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE blocks
+ADD COLUMN template_latency double precision,
+ADD COLUMN stale_template boolean;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE raw_blocks (
+	block_hash varchar not null,
+	height bigint not null,
+	node_id bigint not null,
+	size integer not null,
+	compressed bytea not null,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (block_hash),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE
+);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE reorgs (
+	id bigserial,
+	node_id bigint not null,
+	old_tip varchar not null,
+	old_height bigint not null,
+	new_tip varchar not null,
+	new_height bigint not null,
+	common_ancestor varchar not null,
+	ancestor_height bigint not null,
+	depth bigint not null,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (id),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX reorgs_node_created ON reorgs (node_id, created_at);
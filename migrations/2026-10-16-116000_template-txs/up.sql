@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE template_txs (
+	txid varchar not null,
+	parent_block_hash varchar not null,
+	node_id bigint not null,
+	height bigint not null,
+	first_seen_at timestamp with time zone not null,
+	PRIMARY KEY (txid, parent_block_hash, node_id),
+	CONSTRAINT fk_template_snapshot
+	  FOREIGN KEY(parent_block_hash, node_id)
+	    REFERENCES template_snapshots(parent_block_hash, node_id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX template_txs_snapshot ON template_txs (parent_block_hash, node_id);
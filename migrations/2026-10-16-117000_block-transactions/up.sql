@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE block_transactions (
+	block_hash varchar not null,
+	txid varchar not null,
+	position integer not null,
+	PRIMARY KEY (block_hash, position),
+	CONSTRAINT fk_block
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX block_transactions_txid ON block_transactions (txid);
+
+-- txids are packed in internal byte order, reverse them to the usual hex
+INSERT INTO block_transactions (block_hash, txid, position)
+SELECT b.hash, (
+		SELECT string_agg(encode(substring(b.txids from p * 32 + k + 1 for 1), 'hex'), '' ORDER BY k DESC)
+		FROM generate_series(0, 31) k
+	), p
+FROM blocks b, generate_series(0, length(b.txids) / 32 - 1) p
+WHERE b.txids IS NOT NULL;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE watched
+DROP COLUMN descriptor,
+DROP COLUMN derivation_index;
+
+DROP TABLE watched_descriptors;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE watched_descriptors (
+	descriptor varchar not null,
+	gap_limit integer not null,
+	derived integer not null,
+	created_at timestamp with time zone not null,
+	watch_until timestamp with time zone not null,
+	PRIMARY KEY (descriptor)
+);
+
+ALTER TABLE watched
+ADD COLUMN descriptor varchar,
+ADD COLUMN derivation_index integer,
+ADD CONSTRAINT fk_watched_descriptor
+	FOREIGN KEY(descriptor)
+	  REFERENCES watched_descriptors(descriptor)
+	  ON DELETE CASCADE;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE watched_outpoints (
+	txid varchar not null,
+	vout bigint not null,
+	created_at timestamp with time zone not null,
+	watch_until timestamp with time zone not null,
+	PRIMARY KEY (txid, vout)
+);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE maintenance_windows (
+	id bigserial,
+	node_id bigint not null,
+	starts_at timestamp with time zone not null,
+	ends_at timestamp with time zone not null,
+	reason varchar,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (id),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX maintenance_windows_node ON maintenance_windows (node_id);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE conflict_addresses (
+	candidate_height bigint not null,
+	kind varchar not null,
+	address varchar not null,
+	amount float(53) not null,
+	txs int not null,
+	PRIMARY KEY(candidate_height, kind, address),
+	CONSTRAINT fk_candidate_height
+		FOREIGN KEY(candidate_height)
+		REFERENCES stale_candidate(height)
+		ON DELETE CASCADE
+);
+
+CREATE INDEX conflict_addresses_address ON conflict_addresses(address);
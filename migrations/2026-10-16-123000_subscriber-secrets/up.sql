@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE subscriber_secrets (
+	client_id varchar not null,
+	secret varchar not null,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (client_id)
+);
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE external_observations (
+	id bigserial,
+	source varchar not null,
+	block_hash varchar(64) not null,
+	height bigint not null,
+	observed_at timestamp with time zone not null default now(),
+	PRIMARY KEY (id)
+);
+
+CREATE INDEX external_observations_source ON external_observations (source, observed_at);
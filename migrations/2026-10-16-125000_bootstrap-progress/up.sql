@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE bootstrap_progress (
+	id bigserial,
+	depth bigint not null,
+	tip_hash varchar(64) not null,
+	tip_height bigint not null,
+	next_hash varchar(64),
+	next_height bigint not null,
+	started_at timestamp with time zone not null default now(),
+	updated_at timestamp with time zone not null default now(),
+	finished_at timestamp with time zone,
+	PRIMARY KEY (id)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+UPDATE api_keys SET role = 'read' WHERE role = 'observer';
+UPDATE api_keys SET role = 'write' WHERE role = 'operator';
+ALTER TABLE api_keys RENAME COLUMN role TO permission;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE api_keys RENAME COLUMN permission TO role;
+UPDATE api_keys SET role = 'observer' WHERE role = 'read';
+UPDATE api_keys SET role = 'operator' WHERE role = 'write';
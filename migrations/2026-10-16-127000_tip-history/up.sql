@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE tip_history (
+	id bigserial,
+	node_id bigint not null,
+	block_hash varchar(64) not null,
+	height bigint not null,
+	status varchar not null,
+	observed_at timestamp with time zone not null default now(),
+	PRIMARY KEY (id),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX tip_history_node ON tip_history (node_id, observed_at);
+CREATE INDEX tip_history_block ON tip_history (block_hash, observed_at);
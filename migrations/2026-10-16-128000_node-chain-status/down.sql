@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE node_status
+DROP COLUMN last_success_at,
+DROP COLUMN best_block_hash,
+DROP COLUMN best_height,
+DROP COLUMN header_height,
+DROP COLUMN initial_block_download;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE node_status
+ADD COLUMN last_success_at timestamp with time zone,
+ADD COLUMN best_block_hash varchar(64),
+ADD COLUMN best_height bigint,
+ADD COLUMN header_height bigint,
+ADD COLUMN initial_block_download boolean;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE nodes
+DROP COLUMN rpc_connect_timeout_secs,
+DROP COLUMN rpc_read_timeout_secs;
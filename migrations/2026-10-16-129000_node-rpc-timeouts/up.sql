@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE nodes
+ADD COLUMN rpc_connect_timeout_secs integer,
+ADD COLUMN rpc_read_timeout_secs integer;
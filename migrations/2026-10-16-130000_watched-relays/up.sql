@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE watched_relays (
+	id bigserial,
+	txid varchar(64) not null,
+	node_id bigint not null,
+	accepted boolean not null,
+	error varchar,
+	relayed_at timestamp with time zone not null default now(),
+	PRIMARY KEY (id),
+	CONSTRAINT fk_node
+	  FOREIGN KEY(node_id)
+	    REFERENCES nodes(id)
+	    ON DELETE CASCADE
+);
+
+CREATE INDEX watched_relays_txid ON watched_relays (txid, relayed_at);
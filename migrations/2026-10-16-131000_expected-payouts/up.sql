@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE expected_payouts (
+	pool_name varchar not null,
+	address varchar not null,
+	created_at timestamp with time zone not null default now(),
+	PRIMARY KEY (pool_name, address)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE node_status
+DROP COLUMN rpc_retries,
+DROP COLUMN rpc_failures;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE node_status
+ADD COLUMN rpc_retries bigint NOT NULL DEFAULT 0,
+ADD COLUMN rpc_failures bigint NOT NULL DEFAULT 0;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE raw_block_attempts (
+	block_hash varchar not null,
+	attempts integer not null default 1,
+	last_attempt_at timestamp with time zone not null default now(),
+	PRIMARY KEY (block_hash),
+	CONSTRAINT fk_hash
+	  FOREIGN KEY(block_hash)
+	    REFERENCES blocks(hash)
+	    ON DELETE CASCADE
+);
@@ -0,0 +1,202 @@
+//! Acknowledged delivery for websocket subscriptions. Subscribers that name themselves
+//! with a `client_id` get an `event_id` in every notification and ack them with
+//! `ack_events`. Events are kept until they are acked, and the ones that weren't are
+//! sent again when the client subscribes again, e.g. after reconnecting, so delivery
+//! is at least once. A connection opened with an api key can only use the key's name as
+//! its `client_id`, so one consumer can't ack or read another's events.
+use crate::credentials::resolve_password;
+use crate::service::WsError;
+use crate::signing::sign_event;
+use crate::{PendingEvent, SubscriberAck, SubscriberSecret};
+use diesel::prelude::PgConnection;
+use jsonrpc_core::{types::error::Error as JsonRpcError, Params, Value};
+use jsonrpc_pubsub::Sink;
+use log::error;
+use r2d2_diesel::ConnectionManager;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::thread;
+
+type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SubscribeArgs {
+    client_id: Option<String>,
+}
+
+/// The `client_id` a subscription was made with, subscriptions without one don't ack.
+/// `key_name` is the key the connection was opened with.
+pub fn client_id(params: Params, key_name: Option<&str>) -> jsonrpc_core::Result<Option<String>> {
+    if params == Params::None {
+        return Ok(None);
+    }
+    match params.parse::<SubscribeArgs>()?.client_id {
+        Some(client_id) => check_client_id(&client_id, key_name).map(|_| Some(client_id)),
+        None => Ok(None),
+    }
+}
+
+// Callers with a key ack as the key, anonymous ones only exist when auth isn't required.
+fn check_client_id(client_id: &str, key_name: Option<&str>) -> jsonrpc_core::Result<()> {
+    match key_name {
+        Some(key_name) if key_name != client_id => Err(JsonRpcError::invalid_params(format!(
+            "client_id must be the api key name {}",
+            key_name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct AckArgs {
+    client_id: String,
+    channel: String,
+    event_id: i64,
+}
+
+/// Ack every event of a channel up to `event_id`.
+pub fn ack_events(
+    conn: &PgConnection,
+    params: Params,
+    key_name: Option<&str>,
+) -> jsonrpc_core::Result<Value> {
+    let args = match params.parse::<AckArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+    check_client_id(&args.client_id, key_name)?;
+
+    match SubscriberAck::ack(conn, &args.client_id, &args.channel, args.event_id) {
+        Ok(0) => Err(JsonRpcError::invalid_params(format!(
+            "No subscriber {} on {}",
+            args.client_id, args.channel
+        ))),
+        Ok(_) => Ok(Value::Bool(true)),
+        Err(e) => {
+            error!("Could not ack events {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+/// The sink of a subscription, keeping the events sent to subscribers that ack.
+#[derive(Clone)]
+pub struct AckedSink {
+    sink: Sink,
+    pool: ManagedPool,
+    channel: &'static str,
+    client_id: Option<String>,
+}
+
+impl AckedSink {
+    pub fn new(
+        sink: Sink,
+        pool: ManagedPool,
+        channel: &'static str,
+        client_id: Option<String>,
+    ) -> AckedSink {
+        AckedSink {
+            sink,
+            pool,
+            channel,
+            client_id,
+        }
+    }
+
+    /// Send a notification, every object in it gets an `event_id` if the subscriber acks,
+    /// and a `signature` if it has a secret.
+    pub fn notify(&self, params: Params) -> Result<(), WsError> {
+        let (client_id, values) = match (&self.client_id, params) {
+            (Some(client_id), Params::Array(values)) => (client_id, values),
+            (_, params) => return Ok(self.sink.notify(params)?),
+        };
+
+        let conn = self.pool.get()?;
+        let secret = secret(&conn, client_id)?;
+        let values = values
+            .into_iter()
+            .map(|mut value| {
+                if let Value::Object(map) = &mut value {
+                    let payload = serde_json::to_string(map)?;
+                    let event_id = PendingEvent::create(&conn, client_id, self.channel, &payload)?;
+                    map.insert("event_id".into(), event_id.into());
+                }
+                if let Some(secret) = &secret {
+                    sign_event(secret, &mut value);
+                }
+                Ok(value)
+            })
+            .collect::<Result<_, WsError>>()?;
+
+        Ok(self.sink.notify(Params::Array(values))?)
+    }
+
+    /// Start tracking the subscriber and send the events it didn't ack before, in the
+    /// background so they follow the subscription id.
+    pub fn redeliver(&self) {
+        let client_id = match &self.client_id {
+            Some(client_id) => client_id.clone(),
+            None => return,
+        };
+        let sink = self.clone();
+
+        thread::spawn(move || {
+            if let Err(e) = sink.send_unacked(&client_id) {
+                error!("Error sending unacked events to {} {:?}", client_id, e);
+            }
+        });
+    }
+
+    fn send_unacked(&self, client_id: &str) -> Result<(), WsError> {
+        let conn = self.pool.get()?;
+        SubscriberAck::register(&conn, client_id, self.channel)?;
+        let secret = secret(&conn, client_id)?;
+
+        for event in PendingEvent::unacked(&conn, client_id, self.channel)? {
+            let mut value: Value = serde_json::from_str(&event.payload)?;
+            value["event_id"] = event.id.into();
+            if let Some(secret) = &secret {
+                sign_event(secret, &mut value);
+            }
+            self.sink.notify(Params::Array(vec![value]))?;
+        }
+        Ok(())
+    }
+}
+
+// The secret to sign a subscriber's notifications with.
+fn secret(conn: &PgConnection, client_id: &str) -> Result<Option<String>, WsError> {
+    match SubscriberSecret::get(conn, client_id)? {
+        Some(stored) => Ok(Some(resolve_password(&stored)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Map};
+
+    #[test]
+    fn test_client_id() {
+        assert_eq!(client_id(Params::None, None).unwrap(), None);
+        assert_eq!(client_id(Params::Map(Map::new()), None).unwrap(), None);
+
+        let params =
+            || -> Params { serde_json::from_value(json!({ "client_id": "pager" })).unwrap() };
+        assert_eq!(client_id(params(), None).unwrap(), Some("pager".into()));
+        assert_eq!(
+            client_id(params(), Some("pager")).unwrap(),
+            Some("pager".into())
+        );
+        assert!(client_id(params(), Some("dashboard")).is_err());
+        assert_eq!(client_id(Params::None, Some("dashboard")).unwrap(), None);
+
+        let params: Params = serde_json::from_value(json!({ "name": "pager" })).unwrap();
+        assert!(client_id(params, None).is_err());
+    }
+}
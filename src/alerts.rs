@@ -0,0 +1,759 @@
+//! Alerts for on-call. Scanner findings at or above a sink's severity are posted to a
+//! Slack or Matrix room or a webhook, or sent by email as they happen. Sinks are
+//! configured with `[[alert_sinks]]` in the config file.
+use crate::signing::{sign_webhook, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::{ExternalDisagreementKind, PayoutAnomalyKind, ScannerMessage};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+// an alert the scanner keeps finding, e.g. conflicting blocks seen every run, is
+// repeated once an hour
+const ALERT_REPEAT_SECS: u64 = 3600;
+
+#[derive(Debug, Error)]
+pub enum AlertError {
+    #[error("HTTP request failed {0}")]
+    Http(String),
+    #[error("Email sinks need the email feature")]
+    EmailDisabled,
+    #[error("Email error {0}")]
+    Email(String),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Lagging nodes and scanner errors.
+    Info,
+    /// Forks, double spends, stalls, peer partitions, split node groups, diverging
+    /// mempools and pools paying to unexpected addresses worth a look.
+    Warning,
+    /// Inflated and conflicting blocks, deep reorgs.
+    #[default]
+    Critical,
+}
+
+/// Where alerts are sent, alerts below `min_severity` (default `critical`) are not.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    /// A Slack incoming webhook.
+    Slack {
+        webhook_url: String,
+        #[serde(default)]
+        min_severity: Severity,
+    },
+    /// A Matrix room, posted to as the user `access_token` belongs to.
+    Matrix {
+        homeserver: String,
+        room_id: String,
+        access_token: String,
+        #[serde(default)]
+        min_severity: Severity,
+    },
+    /// Any HTTP endpoint, alerts are posted as JSON. With a `secret` every body is signed,
+    /// see `signing`.
+    Webhook {
+        url: String,
+        secret: Option<String>,
+        #[serde(default)]
+        min_severity: Severity,
+    },
+    /// Email over SMTP with STARTTLS, needs the `email` feature.
+    Email {
+        smtp_host: String,
+        smtp_port: Option<u16>,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+        #[serde(default)]
+        min_severity: Severity,
+    },
+}
+
+impl SinkConfig {
+    fn min_severity(&self) -> Severity {
+        match self {
+            SinkConfig::Slack { min_severity, .. }
+            | SinkConfig::Matrix { min_severity, .. }
+            | SinkConfig::Webhook { min_severity, .. }
+            | SinkConfig::Email { min_severity, .. } => *min_severity,
+        }
+    }
+}
+
+/// A scanner finding to tell on-call about.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Alert {
+    pub severity: Severity,
+    /// Alerts with the same key are only sent once an hour.
+    pub key: String,
+    pub title: String,
+    pub text: String,
+}
+
+/// The alert for a scanner message, if it is one.
+pub fn alert(message: &ScannerMessage) -> Option<Alert> {
+    let (severity, key, title, lines): (_, _, _, Vec<String>) = match message {
+        ScannerMessage::InflatedBlock(ib) => (
+            Severity::Critical,
+            format!("inflation {}", ib.block_hash),
+            format!("Inflated block {}", ib.block_hash),
+            vec![format!(
+                "Node {} found the block created {} while {} is allowed",
+                ib.node_id, ib.actual_inflation, ib.max_inflation
+            )],
+        ),
+        ScannerMessage::NewBlockConflicts(blocks) if !blocks.is_empty() => (
+            Severity::Critical,
+            format!(
+                "conflicts {}",
+                blocks
+                    .iter()
+                    .map(|b| b.hash.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Conflicting blocks".into(),
+            blocks
+                .iter()
+                .map(|b| {
+                    format!(
+                        "Block {} is valid on nodes {:?} and invalid on nodes {:?}",
+                        b.hash, b.valid_by, b.invalid_by
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::PersistentForks(forks) if !forks.is_empty() => (
+            Severity::Warning,
+            format!(
+                "persistent forks {}",
+                forks
+                    .iter()
+                    .map(|f| f.tip.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Persistent forks".into(),
+            forks
+                .iter()
+                .map(|f| {
+                    format!(
+                        "Node {} has a {} block fork from height {}, tip {}",
+                        f.node_id, f.branch_len, f.fork_height, f.tip
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::DoubleSpends(found) => (
+            Severity::Warning,
+            format!("double spends {}", found.height),
+            format!("Double spends at height {}", found.height),
+            vec![format!(
+                "{} transactions double spent for {} BTC, {} replaced for {} BTC",
+                found.double_spent.len(),
+                found.double_spent_total,
+                found.rbf.len(),
+                found.rbf_total
+            )],
+        ),
+        ScannerMessage::WalletConflicts(conflicts) if !conflicts.is_empty() => (
+            Severity::Warning,
+            format!(
+                "wallet conflicts {}",
+                conflicts
+                    .iter()
+                    .map(|c| format!("{}:{}", c.txid, c.vout))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Watched outputs spent twice".into(),
+            conflicts
+                .iter()
+                .map(|c| {
+                    format!(
+                        "Output {}:{} of {} is spent by {} transactions",
+                        c.txid,
+                        c.vout,
+                        c.address,
+                        c.spends.len()
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::StuckChain(stuck) => (
+            Severity::Warning,
+            format!("stuck chain {} {}", stuck.block_hash, stuck.stalled_minutes),
+            format!("Chain stuck at height {}", stuck.height),
+            vec![format!(
+                "No node's tip moved for {} minutes, since {}. The chain should be at height {}",
+                stuck.stalled_minutes, stuck.last_tip_at, stuck.expected_height
+            )],
+        ),
+        ScannerMessage::PeerPartitions(pairs) if !pairs.is_empty() => (
+            Severity::Warning,
+            format!(
+                "peer partitions {}",
+                pairs
+                    .iter()
+                    .map(|p| format!("{}-{}", p.node_a, p.node_b))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Nodes share no peers".into(),
+            pairs
+                .iter()
+                .map(|p| format!("Nodes {} and {} share no peers", p.node_a, p.node_b))
+                .collect(),
+        ),
+        ScannerMessage::GroupSplits(splits) if !splits.is_empty() => (
+            Severity::Warning,
+            format!(
+                "group splits {}",
+                splits
+                    .iter()
+                    .map(|s| format!("{}:{}", s.group, s.tip))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Node groups on another branch".into(),
+            splits
+                .iter()
+                .map(|s| {
+                    format!(
+                        "Group {} is on {} at height {}, all nodes on {} at height {}{}",
+                        s.group,
+                        s.tip,
+                        s.height,
+                        s.global_tip,
+                        s.global_height,
+                        if s.isolated { ", other groups agree" } else { "" }
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::ExternalDisagreements(disagreements) if !disagreements.is_empty() => (
+            Severity::Warning,
+            format!(
+                "external disagreements {}",
+                disagreements
+                    .iter()
+                    .map(|d| format!("{}:{}", d.source, d.block_hash))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "External watchers disagree with our nodes".into(),
+            disagreements
+                .iter()
+                .map(|d| {
+                    let how = match d.kind {
+                        ExternalDisagreementKind::Ahead => "ahead of",
+                        ExternalDisagreementKind::Fork => "on another branch than",
+                    };
+                    format!(
+                        "{} saw {} at height {}, {} our nodes on {} at height {}",
+                        d.source, d.block_hash, d.height, how, d.consensus_tip, d.consensus_height
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::MempoolDivergence(nodes) if !nodes.is_empty() => (
+            Severity::Warning,
+            format!(
+                "mempool divergence {}",
+                nodes
+                    .iter()
+                    .map(|d| d.node_id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Diverging mempools".into(),
+            nodes
+                .iter()
+                .map(|d| {
+                    format!(
+                        "Node {} is missing {} of the {} transactions in most mempools",
+                        d.node_id, d.missing, d.common
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::Censorship(candidates) if !candidates.is_empty() => (
+            Severity::Warning,
+            format!(
+                "censorship {}",
+                candidates
+                    .iter()
+                    .map(|c| format!("{}:{}", c.pool_name, c.txid))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Transactions left out by pools".into(),
+            candidates
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} left out {} at {} sat/vB in {} blocks, last at height {}",
+                        c.pool_name, c.txid, c.fee_rate, c.omissions, c.last_height
+                    )
+                })
+                .collect(),
+        ),
+        ScannerMessage::Reorg(reorg) => (
+            Severity::Info,
+            format!("reorg {} {}", reorg.node_id, reorg.new_tip),
+            format!("Node {} reorged {} blocks", reorg.node_id, reorg.depth),
+            vec![format!(
+                "Tip moved from {} at height {} to {} at height {}, branching off at {} at height {}",
+                reorg.old_tip,
+                reorg.old_height,
+                reorg.new_tip,
+                reorg.new_height,
+                reorg.common_ancestor,
+                reorg.ancestor_height
+            )],
+        ),
+        ScannerMessage::DeepReorg(deep) => (
+            Severity::Critical,
+            format!("deep reorg {}", deep.reorg.old_tip),
+            format!("Reorg of {} blocks", deep.reorg.depth),
+            vec![
+                format!(
+                    "Nodes {:?} abandoned {} at height {} for {} at height {}",
+                    deep.node_ids,
+                    deep.reorg.old_tip,
+                    deep.reorg.old_height,
+                    deep.reorg.new_tip,
+                    deep.reorg.new_height
+                ),
+                format!(
+                    "{} blocks abandoned since {}, {} transactions double spent",
+                    deep.abandoned.len(),
+                    deep.reorg.common_ancestor,
+                    deep.double_spent_by.len()
+                ),
+            ],
+        ),
+        ScannerMessage::PayoutAnomalies(anomalies) if !anomalies.is_empty() => (
+            // a pool paying outside the addresses it was pinned to may be compromised
+            if anomalies
+                .iter()
+                .any(|a| a.kind == PayoutAnomalyKind::UnexpectedAddress)
+            {
+                Severity::Warning
+            } else {
+                Severity::Info
+            },
+            format!(
+                "payout anomalies {}",
+                anomalies
+                    .iter()
+                    .map(|a| format!("{}:{}", a.pool_name, a.address))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Pool payout anomalies".into(),
+            anomalies
+                .iter()
+                .map(|a| match a.kind {
+                    PayoutAnomalyKind::NewAddress => format!(
+                        "{} paid to new address {} at height {}",
+                        a.pool_name, a.address, a.height
+                    ),
+                    PayoutAnomalyKind::UnexpectedAddress => format!(
+                        "{} paid to {} at height {}, not one of its expected addresses",
+                        a.pool_name, a.address, a.height
+                    ),
+                    PayoutAnomalyKind::SharedAddress => format!(
+                        "{} paid to {} at height {}, an address of {}",
+                        a.pool_name,
+                        a.address,
+                        a.height,
+                        a.other_pools.join(", ")
+                    ),
+                    PayoutAnomalyKind::UnknownTag => format!(
+                        "Block {} at height {} has no known coinbase tag but pays {}'s address {}",
+                        a.block_hash, a.height, a.pool_name, a.address
+                    ),
+                })
+                .collect(),
+        ),
+        ScannerMessage::Anomalies(anomalies) if !anomalies.is_empty() => (
+            anomalies
+                .iter()
+                .map(|a| a.severity)
+                .max()
+                .unwrap_or_default(),
+            format!(
+                "anomalies {}",
+                anomalies
+                    .iter()
+                    .map(|a| format!("{}:{}", a.detector, a.message))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Anomalies found by detectors".into(),
+            anomalies
+                .iter()
+                .map(|a| format!("{}: {}", a.detector, a.message))
+                .collect(),
+        ),
+        ScannerMessage::LaggingNodes(lags) if !lags.is_empty() => (
+            Severity::Info,
+            format!(
+                "lagging {}",
+                lags.iter()
+                    .map(|l| format!("{}-{}", l.node_id, l.height_deficit))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            "Lagging nodes".into(),
+            lags.iter()
+                .map(|l| format!("Node {} is {} blocks behind", l.node_id, l.height_deficit))
+                .collect(),
+        ),
+        ScannerMessage::Error(event) => (
+            Severity::Info,
+            format!("error {}", event.message),
+            "Scanner error".into(),
+            vec![event.message.clone()],
+        ),
+        _ => return None,
+    };
+
+    Some(Alert {
+        severity,
+        key,
+        title,
+        text: lines.join("\n"),
+    })
+}
+
+/// Somewhere alerts can be sent.
+pub trait AlertSink {
+    fn send(&self, alert: &Alert) -> Result<(), AlertError>;
+}
+
+struct SlackSink {
+    webhook_url: String,
+}
+
+impl AlertSink for SlackSink {
+    fn send(&self, alert: &Alert) -> Result<(), AlertError> {
+        let body = json!({ "text": format!("*{}*\n{}", alert.title, alert.text) });
+        ureq::post(&self.webhook_url)
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map_err(|e| AlertError::Http(e.to_string()))?;
+        Ok(())
+    }
+}
+
+struct MatrixSink {
+    homeserver: String,
+    room_id: String,
+    access_token: String,
+}
+
+impl AlertSink for MatrixSink {
+    fn send(&self, alert: &Alert) -> Result<(), AlertError> {
+        // every message needs a transaction id, resending one is a no-op
+        let txn_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/forkscanner-{}",
+            self.homeserver.trim_end_matches('/'),
+            self.room_id,
+            txn_id
+        );
+        let body = json!({
+            "msgtype": "m.text",
+            "body": format!("{}\n{}", alert.title, alert.text),
+        });
+
+        ureq::put(&url)
+            .set("Authorization", &format!("Bearer {}", self.access_token))
+            .set("Content-Type", "application/json")
+            .send_string(&body.to_string())
+            .map_err(|e| AlertError::Http(e.to_string()))?;
+        Ok(())
+    }
+}
+
+struct WebhookSink {
+    url: String,
+    secret: Option<String>,
+}
+
+impl AlertSink for WebhookSink {
+    fn send(&self, alert: &Alert) -> Result<(), AlertError> {
+        let body = serde_json::to_string(alert).expect("JSON serde failed");
+        let mut request = ureq::post(&self.url).set("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            request = request
+                .set(TIMESTAMP_HEADER, &timestamp.to_string())
+                .set(SIGNATURE_HEADER, &sign_webhook(secret, timestamp, &body));
+        }
+
+        request
+            .send_string(&body)
+            .map_err(|e| AlertError::Http(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "email")]
+mod email {
+    use super::{Alert, AlertError, AlertSink};
+    use lettre::{
+        message::Mailbox, transport::smtp::authentication::Credentials, Message, SmtpTransport,
+        Transport,
+    };
+
+    pub struct EmailSink {
+        transport: SmtpTransport,
+        from: Mailbox,
+        to: Vec<Mailbox>,
+    }
+
+    fn mailbox(address: &str) -> Result<Mailbox, AlertError> {
+        address
+            .parse()
+            .map_err(|e| AlertError::Email(format!("{}: {}", address, e)))
+    }
+
+    impl EmailSink {
+        pub fn new(
+            host: &str,
+            port: Option<u16>,
+            credentials: Option<(String, String)>,
+            from: &str,
+            to: &[String],
+        ) -> Result<EmailSink, AlertError> {
+            let mut builder = SmtpTransport::starttls_relay(host)
+                .map_err(|e| AlertError::Email(e.to_string()))?;
+            if let Some(port) = port {
+                builder = builder.port(port);
+            }
+            if let Some((user, pass)) = credentials {
+                builder = builder.credentials(Credentials::new(user, pass));
+            }
+
+            Ok(EmailSink {
+                transport: builder.build(),
+                from: mailbox(from)?,
+                to: to.iter().map(|t| mailbox(t)).collect::<Result<_, _>>()?,
+            })
+        }
+    }
+
+    impl AlertSink for EmailSink {
+        fn send(&self, alert: &Alert) -> Result<(), AlertError> {
+            let mut message = Message::builder()
+                .from(self.from.clone())
+                .subject(format!("[forkscanner] {}", alert.title));
+            for to in &self.to {
+                message = message.to(to.clone());
+            }
+            let message = message
+                .body(alert.text.clone())
+                .map_err(|e| AlertError::Email(e.to_string()))?;
+
+            self.transport
+                .send(&message)
+                .map_err(|e| AlertError::Email(e.to_string()))?;
+            Ok(())
+        }
+    }
+}
+
+fn new_sink(config: &SinkConfig) -> Result<Box<dyn AlertSink + Send>, AlertError> {
+    match config {
+        SinkConfig::Slack { webhook_url, .. } => Ok(Box::new(SlackSink {
+            webhook_url: webhook_url.clone(),
+        })),
+        SinkConfig::Matrix {
+            homeserver,
+            room_id,
+            access_token,
+            ..
+        } => Ok(Box::new(MatrixSink {
+            homeserver: homeserver.clone(),
+            room_id: room_id.clone(),
+            access_token: access_token.clone(),
+        })),
+        SinkConfig::Webhook { url, secret, .. } => Ok(Box::new(WebhookSink {
+            url: url.clone(),
+            secret: secret.clone(),
+        })),
+        #[cfg(feature = "email")]
+        SinkConfig::Email {
+            smtp_host,
+            smtp_port,
+            username,
+            password,
+            from,
+            to,
+            ..
+        } => {
+            let credentials = username.clone().zip(password.clone());
+            let sink = email::EmailSink::new(smtp_host, *smtp_port, credentials, from, to)?;
+            Ok(Box::new(sink))
+        }
+        #[cfg(not(feature = "email"))]
+        SinkConfig::Email { .. } => Err(AlertError::EmailDisabled),
+    }
+}
+
+/// Sends alerts for scanner messages to the configured sinks, on a thread of its own
+/// so a slow sink doesn't hold up notifications.
+#[derive(Clone, Default)]
+pub struct Alerter {
+    alerts: Option<Sender<Alert>>,
+}
+
+impl Alerter {
+    pub fn start(configs: &[SinkConfig]) -> Result<Alerter, AlertError> {
+        if configs.is_empty() {
+            return Ok(Alerter::default());
+        }
+
+        let sinks = configs
+            .iter()
+            .map(|config| Ok((config.min_severity(), new_sink(config)?)))
+            .collect::<Result<Vec<_>, AlertError>>()?;
+        info!("Sending alerts to {} sinks", sinks.len());
+
+        let (alerts, receiver) = unbounded();
+        thread::spawn(move || deliver(receiver, sinks));
+        Ok(Alerter {
+            alerts: Some(alerts),
+        })
+    }
+
+    /// Alert the sinks of `message` if it is worth an alert.
+    pub fn handle(&self, message: &ScannerMessage) {
+        if let (Some(alerts), Some(alert)) = (&self.alerts, alert(message)) {
+            let _ = alerts.send(alert);
+        }
+    }
+}
+
+fn deliver(receiver: Receiver<Alert>, sinks: Vec<(Severity, Box<dyn AlertSink + Send>)>) {
+    let repeat = Duration::from_secs(ALERT_REPEAT_SECS);
+    let mut sent = HashMap::<String, Instant>::new();
+
+    for alert in receiver {
+        let now = Instant::now();
+        sent.retain(|_, at| now.duration_since(*at) < repeat);
+        if sent.insert(alert.key.clone(), now).is_some() {
+            continue;
+        }
+
+        for (min_severity, sink) in &sinks {
+            if alert.severity < *min_severity {
+                continue;
+            }
+            if let Err(e) = sink.send(&alert) {
+                error!("Could not send alert {} {:?}", alert.title, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Anomaly, ErrorCategory, ErrorEvent, StuckChain};
+    use chrono::Utc;
+
+    #[test]
+    fn test_sink_config() {
+        #[derive(Deserialize)]
+        struct Sinks {
+            alert_sinks: Vec<SinkConfig>,
+        }
+
+        let sinks: Sinks = toml::from_str(
+            r#"
+            [[alert_sinks]]
+            type = "slack"
+            webhook_url = "https://hooks.slack.com/services/T0/B0/x"
+
+            [[alert_sinks]]
+            type = "email"
+            smtp_host = "smtp.example.com"
+            from = "forkscanner@example.com"
+            to = ["oncall@example.com"]
+            min_severity = "warning"
+
+            [[alert_sinks]]
+            type = "webhook"
+            url = "https://alerts.example.com/forkscanner"
+            secret = "s3cret"
+            min_severity = "info"
+            "#,
+        )
+        .expect("Parse failed");
+
+        assert_eq!(sinks.alert_sinks[0].min_severity(), Severity::Critical);
+        assert_eq!(sinks.alert_sinks[1].min_severity(), Severity::Warning);
+        assert_eq!(sinks.alert_sinks[2].min_severity(), Severity::Info);
+        assert!(toml::from_str::<Sinks>("[[alert_sinks]]\ntype = \"pager\"").is_err());
+        assert!(Severity::Critical > Severity::Warning);
+    }
+
+    #[test]
+    fn test_alert() {
+        let stuck = ScannerMessage::StuckChain(StuckChain {
+            block_hash: "00aa".into(),
+            height: 100,
+            last_tip_at: Utc::now(),
+            stalled_minutes: 60,
+            expected_height: 106,
+        });
+        let found = alert(&stuck).expect("No alert");
+        assert_eq!(found.severity, Severity::Warning);
+        assert_eq!(found.title, "Chain stuck at height 100");
+
+        let error = ScannerMessage::Error(ErrorEvent {
+            category: ErrorCategory::Rpc,
+            node_id: Some(1),
+            message: "RPC get peers failed!".into(),
+            created_at: Utc::now(),
+        });
+        assert_eq!(alert(&error).map(|a| a.severity), Some(Severity::Info));
+
+        // the most severe anomaly sets the severity
+        let anomalies = ScannerMessage::Anomalies(vec![
+            Anomaly::new("pool_streak", Severity::Warning, "6 in a row".into()),
+            Anomaly::new("research", Severity::Info, "odd timestamp".into()),
+        ]);
+        let found = alert(&anomalies).expect("No alert");
+        assert_eq!(found.severity, Severity::Warning);
+        assert_eq!(
+            found.text,
+            "pool_streak: 6 in a row\nresearch: odd timestamp"
+        );
+        assert_eq!(alert(&ScannerMessage::Anomalies(vec![])), None);
+
+        assert_eq!(alert(&ScannerMessage::NewBlockConflicts(vec![])), None);
+        assert_eq!(alert(&ScannerMessage::NewChaintip), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Tokio based variant of the scanner, built with the `async` feature. The RPC calls
+//! made to every node each cycle run concurrently on tokio's blocking pool, the rest
+//! of the cycle is shared with [`ForkScanner`].
+//!
+//! The timeout only bounds how long a cycle waits on a node. A call that times out
+//! isn't cancelled, it keeps running on the blocking pool until the node answers or
+//! the node's `rpc_read_timeout_secs` fires.
+use crate::credentials::node_auth;
+use crate::rpc_client::RpcTimeouts;
+use crate::scanner::{
+    process_new_tips, record_block_template, record_blockchain_info, record_network_info,
+    record_node_error, record_peers, report_error, BtcClient, DbPool, ForkScannerError,
+    ForkScannerResult, KnownTips, NetworkInfo, PeerInfo,
+};
+use crate::{Feature, ForkScanner, Node, ScannerMessage};
+use async_trait::async_trait;
+use bitcoincore_rpc::bitcoincore_rpc_json::{
+    GetBlockTemplateCapabilities, GetBlockTemplateModes, GetBlockTemplateResult,
+    GetBlockTemplateRules, GetBlockchainInfoResult, GetChainTipsResultTip,
+};
+use bitcoincore_rpc::Auth;
+use crossbeam::channel::Sender;
+use log::info;
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::task::spawn_blocking;
+
+const RPC_TIMEOUT_SECS: u64 = 30;
+
+/// Async interface to the bitcoin RPC calls polled for every node each cycle.
+#[async_trait]
+pub trait AsyncBtcClient: Sized + Send + Sync + 'static {
+    /// Blocking client for the follow up calls made while processing chaintips.
+    type Client: BtcClient;
+
+    fn new(node_id: i64, host: &str, auth: Auth, timeouts: RpcTimeouts) -> ForkScannerResult<Self>;
+    fn client(&self) -> &Self::Client;
+    async fn get_blockchain_info(&self) -> Result<GetBlockchainInfoResult, bitcoincore_rpc::Error>;
+    async fn get_chain_tips(&self) -> Result<Vec<GetChainTipsResultTip>, bitcoincore_rpc::Error>;
+    async fn get_block_template(
+        &self,
+        mode: GetBlockTemplateModes,
+        rules: &[GetBlockTemplateRules],
+        capabilities: &[GetBlockTemplateCapabilities],
+    ) -> Result<GetBlockTemplateResult, bitcoincore_rpc::Error>;
+    async fn get_peer_info(&self) -> Result<Vec<PeerInfo>, bitcoincore_rpc::Error>;
+    async fn get_network_info(&self) -> Result<NetworkInfo, bitcoincore_rpc::Error>;
+}
+
+/// Runs the calls of a blocking [`BtcClient`] on tokio's blocking thread pool.
+pub struct BlockingClient<BC>(Arc<BC>);
+
+#[async_trait]
+impl<BC: BtcClient + Send + Sync + 'static> AsyncBtcClient for BlockingClient<BC> {
+    type Client = BC;
+
+    fn new(node_id: i64, host: &str, auth: Auth, timeouts: RpcTimeouts) -> ForkScannerResult<Self> {
+        Ok(BlockingClient(Arc::new(BC::new(
+            node_id, host, auth, timeouts,
+        )?)))
+    }
+
+    fn client(&self) -> &BC {
+        &self.0
+    }
+
+    async fn get_blockchain_info(&self) -> Result<GetBlockchainInfoResult, bitcoincore_rpc::Error> {
+        let client = self.0.clone();
+        spawn_blocking(move || client.get_blockchain_info())
+            .await
+            .expect("RPC task panicked")
+    }
+
+    async fn get_chain_tips(&self) -> Result<Vec<GetChainTipsResultTip>, bitcoincore_rpc::Error> {
+        let client = self.0.clone();
+        spawn_blocking(move || client.get_chain_tips())
+            .await
+            .expect("RPC task panicked")
+    }
+
+    async fn get_block_template(
+        &self,
+        mode: GetBlockTemplateModes,
+        rules: &[GetBlockTemplateRules],
+        capabilities: &[GetBlockTemplateCapabilities],
+    ) -> Result<GetBlockTemplateResult, bitcoincore_rpc::Error> {
+        let client = self.0.clone();
+        let rules = rules.to_vec();
+        let capabilities = capabilities.to_vec();
+        spawn_blocking(move || client.get_block_template(mode, &rules, &capabilities))
+            .await
+            .expect("RPC task panicked")
+    }
+
+    async fn get_peer_info(&self) -> Result<Vec<PeerInfo>, bitcoincore_rpc::Error> {
+        let client = self.0.clone();
+        spawn_blocking(move || client.get_peer_info())
+            .await
+            .expect("RPC task panicked")
+    }
+
+    async fn get_network_info(&self) -> Result<NetworkInfo, bitcoincore_rpc::Error> {
+        let client = self.0.clone();
+        spawn_blocking(move || client.get_network_info())
+            .await
+            .expect("RPC task panicked")
+    }
+}
+
+// Stop waiting on `call` after `timeout`. The blocking RPC call behind it runs on.
+async fn with_timeout<T>(
+    timeout: Duration,
+    call: impl Future<Output = Result<T, bitcoincore_rpc::Error>>,
+) -> ForkScannerResult<T> {
+    match tokio::time::timeout(timeout, call).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(ForkScannerError::RpcTimeout),
+    }
+}
+
+// Poll a node's peers, blockchain info, block template and chaintips concurrently,
+// then record the results.
+async fn scan_node<AC: AsyncBtcClient>(
+    node: Node,
+    db_pool: DbPool,
+    notify_tx: Sender<ScannerMessage>,
+    timeout: Duration,
+    template_rules: Option<Vec<GetBlockTemplateRules>>,
+    known_tips: Arc<KnownTips>,
+) -> ForkScannerResult<bool> {
+    let host = format!("http://{}:{}", node.rpc_host, node.rpc_port);
+    let auth = node_auth(&node)?;
+    let client = AC::new(node.id, &host, auth, (&node).into())?;
+
+    let (peers, network, info, template, tips) = tokio::join!(
+        async {
+            if !node.fetch_peers {
+                return None;
+            }
+            Some(with_timeout(timeout, client.get_peer_info()).await)
+        },
+        with_timeout(timeout, client.get_network_info()),
+        with_timeout(timeout, client.get_blockchain_info()),
+        async {
+            let rules = template_rules.as_ref()?;
+            let template = client.get_block_template(GetBlockTemplateModes::Template, rules, &[]);
+            Some(with_timeout(timeout, template).await)
+        },
+        with_timeout(timeout, client.get_chain_tips()),
+    );
+
+    // database work and fetching ancestor blocks is blocking.
+    spawn_blocking(move || {
+        let db_conn = db_pool.get()?;
+
+        let relays_since = match peers {
+            Some(Ok(peers)) => record_peers(&db_conn, &notify_tx, &node, peers),
+            Some(Err(e)) => {
+                let message = format!("RPC get peers failed! {:?}", e);
+                report_error(&notify_tx, e.category(), Some(node.id), message);
+                None
+            }
+            None => None,
+        };
+
+        match network {
+            Ok(network) => record_network_info(&db_conn, &node, network),
+            Err(e) => {
+                let message = format!("RPC get network info failed! {:?}", e);
+                report_error(&notify_tx, e.category(), Some(node.id), message);
+            }
+        }
+
+        match info {
+            Ok(info) => record_blockchain_info(&db_conn, &node, info),
+            Err(e) => {
+                let message = format!("Failed to fetch blockchain info from {}! {:?}", host, e);
+                report_error(&notify_tx, e.category(), Some(node.id), message);
+                let error = format!("getblockchaininfo failed: {}", e);
+                record_node_error(&db_conn, node.id, &error);
+                return Ok(false);
+            }
+        }
+
+        match template {
+            Some(Ok(template)) => record_block_template(&db_conn, &node, template),
+            Some(Err(e)) => {
+                let message = format!("Error fetching block templates! {:?}", e);
+                report_error(&notify_tx, e.category(), Some(node.id), message);
+            }
+            None => {}
+        }
+
+        let client = client.client();
+        let tips = tips?;
+        process_new_tips(
+            &db_conn,
+            &notify_tx,
+            client,
+            &node,
+            tips,
+            &known_tips,
+            relays_since,
+        )
+    })
+    .await
+    .expect("Scan task panicked")
+}
+
+/// Runs a [`ForkScanner`], polling all nodes concurrently on a tokio runtime.
+pub struct AsyncForkScanner<BC: BtcClient + std::fmt::Debug, AC = BlockingClient<BC>> {
+    scanner: ForkScanner<BC>,
+    rpc_timeout: Duration,
+    client: std::marker::PhantomData<fn() -> AC>,
+}
+
+impl<BC, AC> AsyncForkScanner<BC, AC>
+where
+    BC: BtcClient + std::fmt::Debug,
+    AC: AsyncBtcClient<Client = BC>,
+{
+    pub fn new(scanner: ForkScanner<BC>) -> AsyncForkScanner<BC, AC> {
+        AsyncForkScanner {
+            scanner,
+            rpc_timeout: Duration::from_secs(RPC_TIMEOUT_SECS),
+            client: std::marker::PhantomData,
+        }
+    }
+
+    /// How many seconds to wait on each RPC call before giving up on the node for
+    /// this cycle. The call itself isn't cancelled.
+    pub fn set_rpc_timeout(&mut self, secs: u64) {
+        self.rpc_timeout = Duration::from_secs(secs);
+    }
+
+    /// Sleep until the next run is due, or a new block is announced.
+    pub fn wait(&self, timeout: Duration) {
+        self.scanner.wait(timeout);
+    }
+
+    /// Fetch the history of a new database, see [`ForkScanner::bootstrap`].
+    pub fn bootstrap(&self) {
+        self.scanner.bootstrap();
+    }
+
+    /// Re-enable p2p on mirrors and undo interrupted rollbacks, see
+    /// [`ForkScanner::restore_mirrors`].
+    pub fn restore_mirrors(&self) {
+        self.scanner.restore_mirrors();
+    }
+
+    /// One scanner cycle. This has to be driven with `Runtime::block_on`, the
+    /// housekeeping and checks around the node scans are still blocking.
+    pub async fn run(&mut self) {
+        if !self.scanner.start_cycle() {
+            return;
+        }
+
+        let nodes = self.scanner.nodes();
+        let tasks: Vec<_> = nodes
+            .iter()
+            .map(|node| {
+                tokio::spawn(scan_node::<AC>(
+                    node.clone(),
+                    self.scanner.db_pool(),
+                    self.scanner.notifier(),
+                    self.rpc_timeout,
+                    self.scanner
+                        .uses(Feature::Templates, node.id)
+                        .then(|| self.scanner.template_rules(node.id)),
+                    self.scanner.known_tips(),
+                ))
+            })
+            .collect();
+
+        info!("Scanning {} nodes", tasks.len());
+        let mut changed = false;
+        for (node, task) in nodes.iter().zip(tasks) {
+            let result = task.await.expect("Scan task panicked");
+            changed |= self.scanner.node_scanned(node.id, result);
+        }
+
+        self.scanner.finish_cycle(changed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let result = with_timeout(Duration::from_millis(10), async { Ok(1) }).await;
+        assert_eq!(result.ok(), Some(1));
+
+        let result = with_timeout(Duration::from_millis(10), async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(1)
+        })
+        .await;
+        assert!(matches!(result, Err(ForkScannerError::RpcTimeout)));
+    }
+}
@@ -0,0 +1,205 @@
+//! Supply attestations, signed statements of the UTXO set totals the mirror nodes found
+//! at a block next to the supply the subsidy schedule allows up to it. Statements are
+//! signed the way `bitcoin-cli signmessage` signs, with the WIF private key in
+//! `FORKSCANNER_ATTESTATION_KEY`, so anyone can check one with
+//! `bitcoin-cli verifymessage <address> <signature> <message>`.
+use crate::network::network;
+use crate::{Block, TxOutset};
+use base64::Engine;
+use bigdecimal::BigDecimal;
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::util::misc::{signed_msg_hash, MessageSignature};
+use bitcoin::{Address, PrivateKey};
+use chrono::{DateTime, Utc};
+use diesel::PgConnection;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::str::FromStr;
+use thiserror::Error;
+
+const KEY_VAR: &str = "FORKSCANNER_ATTESTATION_KEY";
+const SATOSHI_TO_BTC: i64 = 100_000_000;
+
+#[derive(Debug, Error)]
+pub enum AttestationError {
+    #[error("{} is not set", KEY_VAR)]
+    NoKey,
+    #[error("{} is not a WIF private key", KEY_VAR)]
+    BadKey,
+    #[error("Unknown block {0}")]
+    UnknownBlock(String),
+    #[error("No UTXO set totals for block {0}")]
+    NoTotals(String),
+    #[error("Database error {0}")]
+    Database(#[from] diesel::result::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SupplyVerdict {
+    /// Every mirror found the same total, within the expected supply.
+    Ok,
+    /// The mirrors found different totals, none above the expected supply.
+    Mismatch,
+    /// A mirror found more than the expected supply, or inflation at the block.
+    Inflated,
+}
+
+/// The UTXO set totals one mirror found at the block, amounts are BTC strings.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct MirrorTotals {
+    pub node_id: i64,
+    pub txouts: i64,
+    pub total_amount: String,
+    pub inflated: bool,
+    pub observed_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct SupplyStatement {
+    pub block_hash: String,
+    pub height: i64,
+    /// All subsidy up to and including the block. The UTXO set holds less, unspendable
+    /// outputs and unclaimed fees aren't in it.
+    pub expected_supply: String,
+    pub mirrors: Vec<MirrorTotals>,
+    pub verdict: SupplyVerdict,
+    pub issued_at: DateTime<Utc>,
+}
+
+/// A statement with the exact `message` that was signed, the JSON of `statement`.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct SupplyAttestation {
+    pub statement: SupplyStatement,
+    pub message: String,
+    pub address: String,
+    pub signature: String,
+}
+
+/// The subsidy of every block up to and including `height` on the nodes' network, in
+/// satoshis.
+pub fn expected_supply(height: i64) -> i64 {
+    network().expected_supply(height)
+}
+
+impl SupplyStatement {
+    pub fn new(conn: &PgConnection, hash: &str) -> Result<SupplyStatement, AttestationError> {
+        let block = match Block::get(conn, &hash.to_string()) {
+            Ok(block) => block,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(AttestationError::UnknownBlock(hash.into()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let outsets = TxOutset::list(conn, hash, None)?;
+        if outsets.is_empty() {
+            return Err(AttestationError::NoTotals(hash.into()));
+        }
+
+        let supply = expected_supply(block.height);
+        let expected = format!("{}.{:08}", supply / SATOSHI_TO_BTC, supply % SATOSHI_TO_BTC);
+        let allowed = BigDecimal::from_str(&expected).expect("BigDecimal parsing failed");
+        let verdict = if outsets
+            .iter()
+            .any(|o| o.inflated || o.total_amount > allowed)
+        {
+            SupplyVerdict::Inflated
+        } else if outsets
+            .iter()
+            .any(|o| o.total_amount != outsets[0].total_amount)
+        {
+            SupplyVerdict::Mismatch
+        } else {
+            SupplyVerdict::Ok
+        };
+
+        Ok(SupplyStatement {
+            block_hash: block.hash,
+            height: block.height,
+            expected_supply: expected,
+            mirrors: outsets
+                .into_iter()
+                .map(|o| MirrorTotals {
+                    node_id: o.node_id,
+                    txouts: o.txouts,
+                    total_amount: o.total_amount.to_string(),
+                    inflated: o.inflated,
+                    observed_at: o.updated_at,
+                })
+                .collect(),
+            verdict,
+            issued_at: Utc::now(),
+        })
+    }
+
+    /// Sign the statement with the configured key.
+    pub fn sign(self) -> Result<SupplyAttestation, AttestationError> {
+        let wif = std::env::var(KEY_VAR).map_err(|_| AttestationError::NoKey)?;
+        let message = serde_json::to_string(&self).expect("JSON serde failed");
+        let (address, signature) = sign_message(wif.trim(), &message)?;
+
+        Ok(SupplyAttestation {
+            statement: self,
+            message,
+            address,
+            signature,
+        })
+    }
+}
+
+// The P2PKH address of the key and the base64 signature of `message`, the way
+// `signmessage` makes them.
+fn sign_message(wif: &str, message: &str) -> Result<(String, String), AttestationError> {
+    let key = PrivateKey::from_wif(wif).map_err(|_| AttestationError::BadKey)?;
+    let secp = Secp256k1::signing_only();
+    let hash = signed_msg_hash(message);
+    let msg = Message::from_slice(&hash[..]).expect("32 byte hash");
+
+    let signature = MessageSignature::new(secp.sign_recoverable(&msg, &key.key), key.compressed);
+    let address = Address::p2pkh(&key.public_key(&secp), key.network);
+    let encoded = base64::engine::general_purpose::STANDARD.encode(signature.serialize());
+    Ok((address.to_string(), encoded))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::network::INITIAL_SUBSIDY;
+
+    #[test]
+    fn test_expected_supply() {
+        assert_eq!(expected_supply(0), INITIAL_SUBSIDY);
+        assert_eq!(expected_supply(209_999), 210_000 * INITIAL_SUBSIDY);
+        assert_eq!(
+            expected_supply(210_000),
+            210_000 * INITIAL_SUBSIDY + INITIAL_SUBSIDY / 2
+        );
+        // the subsidy runs out a little short of 21 million
+        assert_eq!(expected_supply(10_000_000), 2_099_999_997_690_000);
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let wif = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn";
+        let (address, signature) = sign_message(wif, "supply").expect("Signing failed");
+        assert_eq!(address, "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH");
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(signature)
+            .expect("Bad base64");
+        let signature = MessageSignature::from_slice(&bytes).expect("Bad signature");
+        let secp = Secp256k1::verification_only();
+        let address = Address::from_str(&address).unwrap();
+        assert!(signature
+            .is_signed_by_address(&secp, &address, signed_msg_hash("supply"))
+            .unwrap());
+        assert!(!signature
+            .is_signed_by_address(&secp, &address, signed_msg_hash("supply!"))
+            .unwrap());
+
+        assert!(matches!(
+            sign_message("not a key", "supply"),
+            Err(AttestationError::BadKey)
+        ));
+    }
+}
@@ -0,0 +1,376 @@
+//! API key authentication for the RPC and websocket servers. Keys are sent as
+//! `Authorization: Bearer <key>`, or as an `api_key` query parameter when opening a
+//! websocket, and only their hashes are stored. With `auth_required` set every request
+//! needs a key, and each key's role decides which methods it may call. Without it
+//! requests that don't send a key are anonymous observers.
+use crate::limits::RateLimiter;
+use crate::ApiKey;
+use bitcoin_hashes::{hex::ToHex, sha256, Hash};
+use diesel::prelude::PgConnection;
+use jsonrpc_core::{
+    futures::future::{self, Either},
+    middleware::{Middleware, NoopCallFuture, NoopFuture},
+    Call, Error, ErrorCode, Failure, Metadata, Output,
+};
+use jsonrpc_http_server::hyper::{header::AUTHORIZATION, Body, Request};
+use jsonrpc_ws_server::ws;
+use log::{error, warn};
+use r2d2_diesel::ConnectionManager;
+use rand::RngCore;
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const KEY_BYTES: usize = 32;
+pub(crate) const UNAUTHORIZED: i64 = -32001;
+
+type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+static AUTH_REQUIRED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // The role and key name of the websocket connection being opened on this thread,
+    // the middleware and the session metadata extractor run one after the other on it.
+    static WS_CALLER: RefCell<Option<(Role, Option<String>)>> = const { RefCell::new(None) };
+}
+
+/// What a key may do, each role can do what the ones below it can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Queries and subscriptions.
+    Observer,
+    /// Calls that change chain state on nodes or scanner data, e.g. `set_tip`, and
+    /// managing watches.
+    Operator,
+    /// Managing the monitored nodes and api keys.
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    // `read` and `write` are what the roles were called before
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "observer" | "read" => Ok(Role::Observer),
+            "operator" | "write" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Role::Observer => "observer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The role needed to call an RPC method, methods not listed here need an admin.
+pub fn method_role(method: &str) -> Role {
+    match method {
+        "ping"
+        | "describe"
+        | "get_tips"
+        | "get_tips_as_of"
+        | "get_block"
+        | "get_blocks"
+        | "list_blocks"
+        | "get_block_status_as_of"
+        | "get_raw_block"
+        | "get_block_templates"
+        | "get_template_latency"
+        | "get_template_match_rates"
+        | "get_fee_rates"
+        | "get_stale_candidates"
+        | "get_fork_analysis"
+        | "get_reorgs"
+        | "get_rollback_checks"
+        | "get_rollback_progress"
+        | "get_group_consensus"
+        | "get_peers"
+        | "get_peer_overlap"
+        | "get_peer_reputation"
+        | "get_propagation_stats"
+        | "get_node_stats"
+        | "get_node_status"
+        | "get_error_counts"
+        | "get_maintenance_windows"
+        | "get_network"
+        | "get_bootstrap_progress"
+        | "get_recent_activity"
+        | "get_subscriber_lag"
+        | "get_supply_attestation"
+        | "get_tx_outset"
+        | "get_censorship_report"
+        | "get_conflict_addresses"
+        | "get_wallet_conflicts"
+        | "get_expected_payouts"
+        | "get_payout_clusters"
+        | "get_unknown_coinbase_tags"
+        | "get_external_observations"
+        | "get_transaction"
+        | "get_address_transactions"
+        | "get_tx_status"
+        | "get_tx_statuses"
+        | "get_tx_blocks"
+        | "get_tx_ancestry"
+        | "tx_is_active"
+        | "tx_in_templates"
+        | "get_watched_activity"
+        | "get_watched_relays"
+        | "export_watchlist"
+        | "ack_events"
+        | "validation_checks"
+        | "invalid_block_checks"
+        | "lagging_nodes_checks"
+        | "watch_transaction" => Role::Observer,
+        "add_node"
+        | "remove_node"
+        | "set_node_flags"
+        | "set_node_group"
+        | "add_maintenance_window"
+        | "remove_maintenance_window"
+        | "prune_now"
+        | "set_subscriber_secret"
+        | "remove_subscriber_secret"
+        | "add_api_key"
+        | "revoke_api_key"
+        | "get_api_keys" => Role::Admin,
+        "set_tip"
+        | "submit_block"
+        | "get_block_from_peer"
+        | "update_watched_addresses"
+        | "watch_outpoints"
+        | "watched_address_checks"
+        | "import_watchlist"
+        | "import_stale_blocks"
+        | "submit_external_tip"
+        | "label_coinbase_tag"
+        | "update_expected_payouts"
+        | "check_integrity" => Role::Operator,
+        // the other websocket subscriptions
+        m if m.starts_with("subscribe_") || m.starts_with("unsubscribe_") => Role::Observer,
+        _ => Role::Admin,
+    }
+}
+
+/// Reject requests without a valid api key. Otherwise only requests sending a key are
+/// checked, whether or not any keys exist.
+pub fn set_auth_required(required: bool) {
+    AUTH_REQUIRED.store(required, Ordering::SeqCst);
+}
+
+fn hash_key(key: &str) -> String {
+    sha256::Hash::hash(key.as_bytes()).to_hex()
+}
+
+/// Create a key, returning it. Only its hash is stored so it can't be shown again.
+pub fn create_api_key(conn: &PgConnection, name: &str, role: Role) -> diesel::QueryResult<String> {
+    let mut bytes = [0u8; KEY_BYTES];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    let key = hex::encode(bytes);
+
+    ApiKey::create(conn, name, &hash_key(&key), &role.to_string())?;
+    Ok(key)
+}
+
+// The role a request has and the name of its key, `None` if it has to be rejected.
+// Requests without a key are anonymous observers unless auth is required.
+pub(crate) fn authorize(pool: &ManagedPool, key: Option<&str>) -> Option<(Role, Option<String>)> {
+    let key = match key {
+        Some(key) => key,
+        None if AUTH_REQUIRED.load(Ordering::SeqCst) => return None,
+        None => return Some((Role::Observer, None)),
+    };
+
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Could not get a database connection {:?}", e);
+            return None;
+        }
+    };
+
+    match ApiKey::find_active(&conn, &hash_key(key)) {
+        Ok(Some(api_key)) => Some((api_key.role.parse().ok()?, Some(api_key.name))),
+        Ok(None) => {
+            warn!("Request with an unknown api key");
+            None
+        }
+        Err(e) => {
+            error!("Could not check api key {:?}", e);
+            None
+        }
+    }
+}
+
+pub(crate) fn bearer(value: &[u8]) -> Option<&str> {
+    std::str::from_utf8(value).ok()?.strip_prefix("Bearer ")
+}
+
+/// Metadata that knows who made a call, for the middlewares.
+pub trait Caller: Metadata {
+    /// The role of the caller, `None` if it wasn't authorized.
+    fn role(&self) -> Option<Role>;
+    /// The name of the caller's api key.
+    fn key_name(&self) -> Option<&str>;
+}
+
+/// Metadata of an RPC request, the role and name of its api key.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMeta {
+    role: Option<Role>,
+    key_name: Option<String>,
+}
+
+impl Metadata for RpcMeta {}
+
+impl Caller for RpcMeta {
+    fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    fn key_name(&self) -> Option<&str> {
+        self.key_name.as_deref()
+    }
+}
+
+/// Reads the api key of HTTP requests.
+pub fn http_meta(pool: ManagedPool) -> impl Fn(&Request<Body>) -> RpcMeta + Send + Sync + 'static {
+    move |request: &Request<Body>| {
+        let key = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| bearer(v.as_bytes()));
+
+        match authorize(&pool, key) {
+            Some((role, key_name)) => RpcMeta {
+                role: Some(role),
+                key_name,
+            },
+            None => RpcMeta::default(),
+        }
+    }
+}
+
+/// Rejects calls to methods the request's api key does not allow, over HTTP and on
+/// websocket connections.
+#[derive(Default)]
+pub struct AuthMiddleware;
+
+impl<M: Caller> Middleware<M> for AuthMiddleware {
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: Call, meta: M, next: F) -> Either<NoopCallFuture, X>
+    where
+        F: Fn(Call, M) -> X + Send + Sync,
+        X: Future<Output = Option<Output>> + Send + 'static,
+    {
+        let (method, id, jsonrpc) = match &call {
+            Call::MethodCall(c) => (&c.method, Some(c.id.clone()), c.jsonrpc),
+            Call::Notification(n) => (&n.method, None, n.jsonrpc),
+            Call::Invalid { .. } => return Either::Right(next(call, meta)),
+        };
+
+        let required = method_role(method);
+        if meta.role().map(|r| r >= required).unwrap_or(false) {
+            return Either::Right(next(call, meta));
+        }
+
+        let output = id.map(|id| {
+            Output::Failure(Failure {
+                jsonrpc,
+                error: Error {
+                    code: ErrorCode::ServerError(UNAUTHORIZED),
+                    message: format!("Unauthorized, {} needs the {} role", method, required),
+                    data: None,
+                },
+                id,
+            })
+        });
+        Either::Left(Box::pin(future::ready(output)))
+    }
+}
+
+/// The role and key name the websocket connection being opened authorized with, once.
+pub(crate) fn take_ws_caller() -> Option<(Role, Option<String>)> {
+    WS_CALLER.with(|caller| caller.borrow_mut().take())
+}
+
+/// Rejects websocket connections without a valid api key, or over the key's rate.
+pub fn ws_middleware(
+    pool: ManagedPool,
+    limiter: RateLimiter,
+) -> impl Fn(&ws::Request) -> Option<ws::Response> + Send + Sync + 'static {
+    move |request: &ws::Request| {
+        let query_key = request
+            .resource()
+            .split_once('?')
+            .and_then(|(_, query)| query.split('&').find_map(|p| p.strip_prefix("api_key=")));
+        let key = request
+            .header(AUTHORIZATION.as_str())
+            .and_then(|v| bearer(v))
+            .or(query_key);
+
+        let authorized = authorize(&pool, key);
+        WS_CALLER.with(|caller| *caller.borrow_mut() = authorized.clone());
+
+        match authorized {
+            Some((_, key_name)) if limiter.take(key_name.as_deref()) => None,
+            Some(_) => Some(ws::Response::new(429, "Too Many Requests", vec![])),
+            None => Some(ws::Response::new(401, "Unauthorized", vec![])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_method_role() {
+        assert_eq!(method_role("get_tips"), Role::Observer);
+        assert_eq!(method_role("set_tip"), Role::Operator);
+        assert_eq!(method_role("watch_outpoints"), Role::Operator);
+        assert_eq!(method_role("remove_node"), Role::Admin);
+        assert_eq!(method_role("add_api_key"), Role::Admin);
+        assert_eq!(method_role("check_integrity"), Role::Operator);
+        assert_eq!(method_role("get_tx_outset"), Role::Observer);
+        assert_eq!(method_role("subscribe_reorgs"), Role::Observer);
+        assert_eq!(method_role("unsubscribe_watch_outpoints"), Role::Observer);
+        assert_eq!(method_role("watched_address_checks"), Role::Operator);
+        assert_eq!(method_role("no_such_method"), Role::Admin);
+        assert!(Role::Admin > Role::Operator);
+        assert!(Role::Operator > Role::Observer);
+        assert_eq!("operator".parse(), Ok(Role::Operator));
+        assert_eq!("write".parse(), Ok(Role::Operator));
+        assert_eq!("read".parse(), Ok(Role::Observer));
+        assert_eq!(Role::Admin.to_string(), "admin");
+        assert_eq!(Role::Observer.to_string(), "observer");
+        assert!("root".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_anonymous() {
+        let manager = ConnectionManager::<PgConnection>::new("postgres://localhost/none");
+        let pool = r2d2::Pool::builder().build_unchecked(manager);
+        assert_eq!(authorize(&pool, None), Some((Role::Observer, None)));
+    }
+
+    #[test]
+    fn test_bearer() {
+        assert_eq!(bearer(b"Bearer abcd"), Some("abcd"));
+        assert_eq!(bearer(b"Basic abcd"), None);
+    }
+}
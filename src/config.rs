@@ -0,0 +1,433 @@
+//! Settings read from a TOML file given with `--config`. Environment variables
+//! override the file, and command line flags override both.
+use crate::credentials::{seal_password, CredentialError};
+use crate::{
+    check_node, BusConfig, DataSource, JsonNumbers, Network, NetworkError, NewNode, Node,
+    NodeFlags, NodeTimeouts, RequestLimits, RetentionPolicy, SinkConfig, TlsConfig,
+};
+use diesel::prelude::PgConnection;
+use log::error;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+const DEFAULT_LISTEN: &str = "0.0.0.0";
+const DEFAULT_RPC_PORT: u16 = 8339;
+const DEFAULT_WS_PORT: u16 = 8340;
+const DEFAULT_SCAN_INTERVAL: u64 = 10;
+const DEFAULT_BOOTSTRAP_DEPTH: i64 = 100;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Could not read config file {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse config file {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Bad value for {0}: {1}")]
+    BadVar(&'static str, String),
+    #[error("Node credentials error {0:?}")]
+    Credentials(#[from] CredentialError),
+    #[error("Database query error {0:?}")]
+    Database(#[from] diesel::result::Error),
+    #[error("Node {0} can't be added, {1}")]
+    Network(String, #[source] NetworkError),
+}
+
+/// A node to monitor, added on startup unless a node with the same name exists.
+#[derive(Debug, Deserialize)]
+pub struct NodeConfig {
+    pub name: String,
+    pub rpc_host: String,
+    pub rpc_port: i32,
+    pub mirror_rpc_port: Option<i32>,
+    pub mirror_host: Option<String>,
+    pub user: String,
+    pub pass: String,
+    #[serde(default)]
+    pub archive: bool,
+    pub zmq_endpoint: Option<String>,
+    /// The location or provider group of the node, see `GroupConsensus`.
+    pub group: Option<String>,
+    #[serde(flatten)]
+    pub flags: NodeFlags,
+    #[serde(flatten)]
+    pub timeouts: NodeTimeouts,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Overridden by `DATABASE_URL`.
+    pub database_url: Option<String>,
+    /// Address the RPC and websocket servers listen on, `FORKSCANNER_LISTEN`.
+    pub listen: String,
+    /// `FORKSCANNER_RPC_PORT`.
+    pub rpc_port: u16,
+    /// `FORKSCANNER_WS_PORT`.
+    pub ws_port: u16,
+    /// Port of the read only REST API, off when unset, `FORKSCANNER_REST_PORT`.
+    pub rest_port: Option<u16>,
+    /// Port of the GraphQL endpoint, off when unset, `FORKSCANNER_GRAPHQL_PORT`.
+    pub graphql_port: Option<u16>,
+    /// Port of the gRPC event streams, off when unset, `FORKSCANNER_GRPC_PORT`.
+    pub grpc_port: Option<u16>,
+    /// Seconds between scanner runs, `FORKSCANNER_SCAN_INTERVAL`.
+    pub scan_interval: u64,
+    /// Blocks of history to fetch on the first run, `FORKSCANNER_BOOTSTRAP_DEPTH`.
+    pub bootstrap_depth: i64,
+    /// Enable the address watcher, `FORKSCANNER_WATCH_ADDRESSES`.
+    pub watch_addresses: bool,
+    /// Look for payments to watched addresses in node mempools,
+    /// `FORKSCANNER_WATCH_MEMPOOL`.
+    pub watch_mempool: bool,
+    /// Relay the transactions the mempool watcher found to the nodes missing them,
+    /// `FORKSCANNER_RELAY_WATCHED`.
+    pub relay_watched: bool,
+    /// Log filter in `RUST_LOG` syntax, overridden by `RUST_LOG`.
+    pub log: Option<String>,
+    /// PEM certificate chain to serve TLS with, `FORKSCANNER_TLS_CERT`.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for `tls_cert`, `FORKSCANNER_TLS_KEY`.
+    pub tls_key: Option<PathBuf>,
+    /// Write amounts and decimals as JSON strings or numbers, `FORKSCANNER_JSON_NUMBERS`.
+    pub json_numbers: JsonNumbers,
+    /// The network the nodes are on, `FORKSCANNER_NETWORK`. Taken from the archive node
+    /// when unset.
+    pub network: Option<Network>,
+    pub nodes: Vec<NodeConfig>,
+    /// Where alerts are sent.
+    pub alert_sinks: Vec<SinkConfig>,
+    /// NATS or Kafka to publish every scanner event to.
+    pub event_bus: Option<BusConfig>,
+    /// How long transactions, stale candidates and peer history are kept.
+    pub retention: RetentionPolicy,
+    /// Rate, concurrency and size limits of RPC and websocket requests.
+    pub limits: RequestLimits,
+    /// Reject requests without a valid api key, `FORKSCANNER_AUTH_REQUIRED`. Otherwise
+    /// requests that don't send a key are anonymous observers.
+    pub auth_required: bool,
+    /// Nodes each feature sends RPC calls to, `FORKSCANNER_DATA_SOURCES` separated by
+    /// `;`. `--data-source` flags override them per feature.
+    pub data_sources: Vec<DataSource>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            database_url: None,
+            listen: DEFAULT_LISTEN.into(),
+            rpc_port: DEFAULT_RPC_PORT,
+            ws_port: DEFAULT_WS_PORT,
+            rest_port: None,
+            graphql_port: None,
+            grpc_port: None,
+            scan_interval: DEFAULT_SCAN_INTERVAL,
+            bootstrap_depth: DEFAULT_BOOTSTRAP_DEPTH,
+            watch_addresses: false,
+            watch_mempool: false,
+            relay_watched: false,
+            log: None,
+            tls_cert: None,
+            tls_key: None,
+            json_numbers: JsonNumbers::default(),
+            network: None,
+            nodes: vec![],
+            alert_sinks: vec![],
+            event_bus: None,
+            retention: RetentionPolicy::default(),
+            limits: RequestLimits::default(),
+            auth_required: false,
+            data_sources: vec![],
+        }
+    }
+}
+
+fn parse_var<T: std::str::FromStr>(name: &'static str, value: String) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::BadVar(name, value))
+}
+
+impl Config {
+    /// Read the config file if one is given, then apply environment overrides.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => toml::from_str(&std::fs::read_to_string(path)?)?,
+            None => Config::default(),
+        };
+
+        config.override_with(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    fn override_with(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(url) = var("DATABASE_URL") {
+            self.database_url = Some(url);
+        }
+        if let Some(log) = var("RUST_LOG") {
+            self.log = Some(log);
+        }
+        if let Some(listen) = var("FORKSCANNER_LISTEN") {
+            self.listen = listen;
+        }
+        if let Some(port) = var("FORKSCANNER_RPC_PORT") {
+            self.rpc_port = parse_var("FORKSCANNER_RPC_PORT", port)?;
+        }
+        if let Some(port) = var("FORKSCANNER_WS_PORT") {
+            self.ws_port = parse_var("FORKSCANNER_WS_PORT", port)?;
+        }
+        if let Some(port) = var("FORKSCANNER_REST_PORT") {
+            self.rest_port = Some(parse_var("FORKSCANNER_REST_PORT", port)?);
+        }
+        if let Some(port) = var("FORKSCANNER_GRAPHQL_PORT") {
+            self.graphql_port = Some(parse_var("FORKSCANNER_GRAPHQL_PORT", port)?);
+        }
+        if let Some(port) = var("FORKSCANNER_GRPC_PORT") {
+            self.grpc_port = Some(parse_var("FORKSCANNER_GRPC_PORT", port)?);
+        }
+        if let Some(secs) = var("FORKSCANNER_SCAN_INTERVAL") {
+            self.scan_interval = parse_var("FORKSCANNER_SCAN_INTERVAL", secs)?;
+        }
+        if let Some(depth) = var("FORKSCANNER_BOOTSTRAP_DEPTH") {
+            self.bootstrap_depth = parse_var("FORKSCANNER_BOOTSTRAP_DEPTH", depth)?;
+        }
+        if let Some(watch) = var("FORKSCANNER_WATCH_ADDRESSES") {
+            self.watch_addresses = parse_var("FORKSCANNER_WATCH_ADDRESSES", watch)?;
+        }
+        if let Some(watch) = var("FORKSCANNER_WATCH_MEMPOOL") {
+            self.watch_mempool = parse_var("FORKSCANNER_WATCH_MEMPOOL", watch)?;
+        }
+        if let Some(relay) = var("FORKSCANNER_RELAY_WATCHED") {
+            self.relay_watched = parse_var("FORKSCANNER_RELAY_WATCHED", relay)?;
+        }
+        if let Some(cert) = var("FORKSCANNER_TLS_CERT") {
+            self.tls_cert = Some(cert.into());
+        }
+        if let Some(key) = var("FORKSCANNER_TLS_KEY") {
+            self.tls_key = Some(key.into());
+        }
+        if let Some(mode) = var("FORKSCANNER_JSON_NUMBERS") {
+            self.json_numbers = parse_var("FORKSCANNER_JSON_NUMBERS", mode)?;
+        }
+        if let Some(network) = var("FORKSCANNER_NETWORK") {
+            self.network = Some(parse_var("FORKSCANNER_NETWORK", network)?);
+        }
+        if let Some(days) = var("FORKSCANNER_RETENTION_DAYS") {
+            self.retention.days = Some(parse_var("FORKSCANNER_RETENTION_DAYS", days)?);
+        }
+        if let Some(blocks) = var("FORKSCANNER_RETENTION_BLOCKS") {
+            self.retention.blocks = Some(parse_var("FORKSCANNER_RETENTION_BLOCKS", blocks)?);
+        }
+        if let Some(rate) = var("FORKSCANNER_REQUESTS_PER_SECOND") {
+            self.limits.requests_per_second = parse_var("FORKSCANNER_REQUESTS_PER_SECOND", rate)?;
+        }
+        if let Some(bytes) = var("FORKSCANNER_MAX_BODY_BYTES") {
+            self.limits.max_body_bytes = parse_var("FORKSCANNER_MAX_BODY_BYTES", bytes)?;
+        }
+        if let Some(required) = var("FORKSCANNER_AUTH_REQUIRED") {
+            self.auth_required = parse_var("FORKSCANNER_AUTH_REQUIRED", required)?;
+        }
+        if let Some(sources) = var("FORKSCANNER_DATA_SOURCES") {
+            self.data_sources = sources
+                .split(';')
+                .filter(|s| !s.trim().is_empty())
+                .map(|s| parse_var("FORKSCANNER_DATA_SOURCES", s.into()))
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(())
+    }
+
+    /// TLS settings, if both a certificate and key are configured.
+    pub fn tls(&self) -> Result<Option<TlsConfig>, ConfigError> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some(TlsConfig {
+                cert: cert.clone(),
+                key: key.clone(),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(ConfigError::BadVar(
+                "tls",
+                "both tls_cert and tls_key are needed".into(),
+            )),
+        }
+    }
+
+    /// Insert configured nodes that are not in the database yet, returning how
+    /// many were added.
+    pub fn add_nodes(&mut self, conn: &PgConnection) -> Result<usize, ConfigError> {
+        let existing = Node::list(conn)?;
+
+        let mut added = 0;
+        for node in self.nodes.drain(..) {
+            if existing.iter().any(|n| n.node == node.name) {
+                continue;
+            }
+            if !node.timeouts.is_valid() {
+                let msg = format!("{} has a timeout under a second", node.name);
+                return Err(ConfigError::BadVar("nodes", msg));
+            }
+            // a node on the wrong network is a config mistake, one that is down is
+            // left for the next start
+            let timeouts = (&node.timeouts).into();
+            match check_node(
+                &node.rpc_host,
+                node.rpc_port,
+                &node.user,
+                &node.pass,
+                timeouts,
+            ) {
+                Ok(_) => {}
+                Err(e @ NetworkError::Mismatch { .. }) => {
+                    return Err(ConfigError::Network(node.name, e));
+                }
+                Err(e) => {
+                    error!("Skipping configured node {}, {}", node.name, e);
+                    continue;
+                }
+            }
+
+            let new_node = NewNode {
+                node: node.name,
+                rpc_host: node.rpc_host,
+                rpc_port: node.rpc_port,
+                mirror_rpc_port: node.mirror_rpc_port,
+                rpc_user: node.user,
+                rpc_pass: seal_password(&node.pass)?,
+                mirror_host: node.mirror_host,
+                archive: node.archive,
+                zmq_endpoint: node.zmq_endpoint,
+                flags: node.flags,
+            };
+            let inserted = Node::insert(conn, new_node)?;
+            if node.group.is_some() {
+                Node::set_group(conn, inserted.id, node.group)?;
+            }
+            if !node.timeouts.is_empty() {
+                Node::set_timeouts(conn, inserted.id, &node.timeouts)?;
+            }
+            added += 1;
+        }
+
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+            database_url = "postgres://localhost/forkscanner"
+            rpc_port = 9000
+            watch_addresses = true
+            json_numbers = "number"
+            bootstrap_depth = 52560
+            network = "testnet4"
+            data_sources = ["templates=1,2", "txindex=archive"]
+
+            [retention]
+            days = 30
+
+            [limits]
+            requests_per_second = 5
+            max_concurrent = 2
+
+            [[nodes]]
+            name = "east-us"
+            rpc_host = "10.0.0.1"
+            rpc_port = 8332
+            user = "btc_user"
+            pass = "my-pass"
+            fetch_templates = false
+            "#,
+        )
+        .expect("Parse failed");
+
+        assert_eq!(config.rpc_port, 9000);
+        assert_eq!(config.ws_port, DEFAULT_WS_PORT);
+        assert_eq!(config.rest_port, None);
+        assert_eq!(config.listen, DEFAULT_LISTEN);
+        assert!(config.watch_addresses);
+        assert_eq!(config.json_numbers, JsonNumbers::Number);
+        assert_eq!(config.bootstrap_depth, 52560);
+        assert_eq!(config.network, Some(Network::Testnet4));
+        assert_eq!(config.data_sources.len(), 2);
+        assert_eq!(config.data_sources[1], "txindex=archive".parse().unwrap());
+        assert_eq!(config.retention.days, Some(30));
+        assert_eq!(config.retention.blocks, None);
+        assert_eq!(config.limits.requests_per_second, 5);
+        assert_eq!(config.limits.max_concurrent, 2);
+        assert_eq!(config.limits.burst, RequestLimits::default().burst);
+        assert_eq!(config.nodes.len(), 1);
+        assert!(!config.nodes[0].archive);
+        assert_eq!(config.nodes[0].flags.fetch_templates, Some(false));
+        assert_eq!(config.nodes[0].flags.fetch_peers, None);
+
+        assert!(toml::from_str::<Config>("rpc = 9000").is_err());
+    }
+
+    #[test]
+    fn test_config_env_overrides() {
+        let env: HashMap<_, _> = vec![
+            ("DATABASE_URL", "postgres://db/forkscanner"),
+            ("FORKSCANNER_RPC_PORT", "9001"),
+            ("FORKSCANNER_WATCH_ADDRESSES", "true"),
+            ("FORKSCANNER_WATCH_MEMPOOL", "true"),
+            ("FORKSCANNER_RELAY_WATCHED", "true"),
+            ("FORKSCANNER_RETENTION_BLOCKS", "4320"),
+            ("FORKSCANNER_BOOTSTRAP_DEPTH", "1000"),
+            ("FORKSCANNER_REQUESTS_PER_SECOND", "0"),
+            ("FORKSCANNER_NETWORK", "signet"),
+            ("FORKSCANNER_REST_PORT", "8341"),
+            ("FORKSCANNER_GRAPHQL_PORT", "8342"),
+            ("FORKSCANNER_GRPC_PORT", "8343"),
+            ("FORKSCANNER_AUTH_REQUIRED", "true"),
+            ("FORKSCANNER_DATA_SOURCES", "templates=1,2; mempool=archive"),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut config = Config::default();
+        config
+            .override_with(|name| env.get(name).map(|v| v.to_string()))
+            .expect("Overrides failed");
+
+        assert_eq!(
+            config.database_url.as_deref(),
+            Some("postgres://db/forkscanner")
+        );
+        assert_eq!(config.rpc_port, 9001);
+        assert_eq!(config.ws_port, DEFAULT_WS_PORT);
+        assert!(config.watch_addresses);
+        assert!(config.watch_mempool);
+        assert!(config.relay_watched);
+        assert_eq!(config.retention.blocks, Some(4320));
+        assert!(config.retention.is_enabled());
+        assert_eq!(config.bootstrap_depth, 1000);
+        assert_eq!(config.limits.requests_per_second, 0);
+        assert_eq!(config.network, Some(Network::Signet));
+        assert_eq!(config.rest_port, Some(8341));
+        assert_eq!(config.graphql_port, Some(8342));
+        assert_eq!(config.grpc_port, Some(8343));
+        assert!(config.auth_required);
+        assert_eq!(
+            config.data_sources,
+            vec![
+                "templates=1,2".parse().unwrap(),
+                "mempool=archive".parse().unwrap()
+            ]
+        );
+
+        let result = config.override_with(|name| match name {
+            "FORKSCANNER_SCAN_INTERVAL" => Some("soon".into()),
+            _ => None,
+        });
+        assert!(matches!(
+            result,
+            Err(ConfigError::BadVar("FORKSCANNER_SCAN_INTERVAL", _))
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+//! Encryption of node RPC passwords at rest. With `FORKSCANNER_CREDENTIALS_KEY` set
+//! to a hex encoded 32 byte key, passwords are stored AES-256-GCM encrypted and only
+//! decrypted in memory when an RPC client is created. Passwords stored before the key
+//! was set are still accepted as plain text.
+//!
+//! A password can also be a reference to a secret kept outside the database, either
+//! `env:VAR` or `file:/path`, which is read whenever the node is connected to. References
+//! are only accepted from the config file, never over RPC.
+use crate::Node;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use bitcoincore_rpc::Auth;
+use thiserror::Error;
+
+const KEY_VAR: &str = "FORKSCANNER_CREDENTIALS_KEY";
+const ENCRYPTED_PREFIX: &str = "enc:";
+const ENV_PREFIX: &str = "env:";
+const FILE_PREFIX: &str = "file:";
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CredentialError {
+    #[error("{} must be 32 hex encoded bytes", KEY_VAR)]
+    BadKey,
+    #[error("Password is encrypted but {} is not set", KEY_VAR)]
+    NoKey,
+    #[error("Could not decrypt password")]
+    Decrypt,
+    #[error("Could not read secret {0}")]
+    Secret(String),
+}
+
+/// Key used to encrypt and decrypt stored passwords.
+pub struct CredentialKey(Aes256Gcm);
+
+impl CredentialKey {
+    pub fn new(hex_key: &str) -> Result<CredentialKey, CredentialError> {
+        let bytes = hex::decode(hex_key.trim()).map_err(|_| CredentialError::BadKey)?;
+        if bytes.len() != 32 {
+            return Err(CredentialError::BadKey);
+        }
+
+        let key = Key::<Aes256Gcm>::from_slice(&bytes);
+        Ok(CredentialKey(Aes256Gcm::new(key)))
+    }
+
+    /// The configured key, if any.
+    pub fn from_env() -> Result<Option<CredentialKey>, CredentialError> {
+        match std::env::var(KEY_VAR) {
+            Ok(key) => CredentialKey::new(&key).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn encrypt(&self, password: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, password.as_bytes())
+            .expect("Encrypting password failed");
+
+        let sealed: Vec<_> = nonce.iter().chain(ciphertext.iter()).cloned().collect();
+        format!("{}{}", ENCRYPTED_PREFIX, hex::encode(sealed))
+    }
+
+    pub fn decrypt(&self, stored: &str) -> Result<String, CredentialError> {
+        let sealed = match stored.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(sealed) => hex::decode(sealed).map_err(|_| CredentialError::Decrypt)?,
+            None => return Ok(stored.to_string()),
+        };
+
+        if sealed.len() < NONCE_LEN {
+            return Err(CredentialError::Decrypt);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let password = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| CredentialError::Decrypt)?;
+
+        String::from_utf8(password).map_err(|_| CredentialError::Decrypt)
+    }
+}
+
+/// Whether a stored password is encrypted.
+pub fn is_encrypted(stored: &str) -> bool {
+    stored.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Whether a stored password refers to a secret kept outside the database.
+pub fn is_reference(stored: &str) -> bool {
+    stored.starts_with(ENV_PREFIX) || stored.starts_with(FILE_PREFIX)
+}
+
+/// The password to store for a new node, encrypted if a key is configured.
+/// References are stored as given.
+pub fn seal_password(password: &str) -> Result<String, CredentialError> {
+    match CredentialKey::from_env()? {
+        Some(key) if !is_reference(password) => Ok(key.encrypt(password)),
+        _ => Ok(password.to_string()),
+    }
+}
+
+/// The password a stored password stands for.
+pub(crate) fn resolve_password(stored: &str) -> Result<String, CredentialError> {
+    if let Some(var) = stored.strip_prefix(ENV_PREFIX) {
+        return std::env::var(var).map_err(|_| CredentialError::Secret(stored.into()));
+    }
+
+    if let Some(path) = stored.strip_prefix(FILE_PREFIX) {
+        return std::fs::read_to_string(path)
+            .map(|secret| secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+            .map_err(|_| CredentialError::Secret(stored.into()));
+    }
+
+    if is_encrypted(stored) {
+        return CredentialKey::from_env()?
+            .ok_or(CredentialError::NoKey)?
+            .decrypt(stored);
+    }
+
+    Ok(stored.to_string())
+}
+
+/// RPC credentials of a node, decrypting or looking up the stored password.
+pub fn node_auth(node: &Node) -> Result<Auth, CredentialError> {
+    let password = resolve_password(&node.rpc_pass)?;
+    Ok(Auth::UserPass(node.rpc_user.clone(), password))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn test_encrypt_password() {
+        let key = CredentialKey::new(KEY).unwrap();
+
+        let sealed = key.encrypt("my-pass");
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.contains("my-pass"));
+        assert_ne!(sealed, key.encrypt("my-pass"));
+        assert_eq!(key.decrypt(&sealed), Ok("my-pass".into()));
+
+        // plain text passwords from before encryption was enabled
+        assert_eq!(key.decrypt("my-pass"), Ok("my-pass".into()));
+
+        let other = CredentialKey::new(&KEY.replace("00", "ff")).unwrap();
+        assert_eq!(other.decrypt(&sealed), Err(CredentialError::Decrypt));
+        assert!(CredentialKey::new("abcd").is_err());
+    }
+
+    #[test]
+    fn test_secret_references() {
+        let path = std::env::temp_dir().join("forkscanner-test-secret");
+        std::fs::write(&path, "file-pass\n").unwrap();
+        let file_ref = format!("file:{}", path.display());
+
+        assert!(is_reference(&file_ref));
+        assert_eq!(resolve_password(&file_ref), Ok("file-pass".into()));
+        assert_eq!(
+            resolve_password("env:FORKSCANNER_TEST_NO_SUCH_VAR"),
+            Err(CredentialError::Secret(
+                "env:FORKSCANNER_TEST_NO_SUCH_VAR".into()
+            ))
+        );
+        assert_eq!(resolve_password("plain"), Ok("plain".into()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+//! Which nodes each scanner feature sends its RPC calls to, so operators can decide
+//! which nodes bear which load.
+use crate::Node;
+use serde::{de, Deserialize, Deserializer};
+use std::{collections::HashMap, str::FromStr};
+use thiserror::Error;
+
+/// Scanner features that make RPC calls to a configurable set of nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Block templates, for fee and omitted transaction checks.
+    Templates,
+    /// Full blocks, for the address watcher and stale candidate transactions.
+    Blocks,
+    /// Transaction lookups by txid, these need `-txindex`.
+    TxIndex,
+    /// Inflation checks on mirror nodes.
+    Inflation,
+    /// Rollback checks on mirror nodes.
+    Rollback,
+    /// Fetching bodies of blocks some nodes only have headers for.
+    BlockBodies,
+    /// Mempool txids, to find nodes whose mempool diverges from the others.
+    Mempool,
+}
+
+impl Feature {
+    // What the scanner used before data sources were configurable.
+    fn default_role(&self) -> NodeRole {
+        match self {
+            Feature::TxIndex => NodeRole::Archive,
+            Feature::Inflation | Feature::Rollback => NodeRole::Mirrors,
+            _ => NodeRole::All,
+        }
+    }
+}
+
+/// The nodes a feature may use.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeRole {
+    All,
+    Archive,
+    Mirrors,
+    /// Node ids or names.
+    Nodes(Vec<String>),
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DataSourceError {
+    #[error("Expected FEATURE=NODES, got {0}")]
+    Format(String),
+    #[error("Unknown feature {0}")]
+    UnknownFeature(String),
+    #[error("No nodes given for {0}")]
+    NoNodes(String),
+}
+
+/// One `FEATURE=NODES` mapping, e.g. `templates=1,2`, `txindex=archive` or
+/// `inflation=mirrors`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataSource {
+    pub feature: Feature,
+    pub role: NodeRole,
+}
+
+impl FromStr for DataSource {
+    type Err = DataSourceError;
+
+    fn from_str(s: &str) -> Result<DataSource, DataSourceError> {
+        let (feature, nodes) = s
+            .split_once('=')
+            .ok_or_else(|| DataSourceError::Format(s.into()))?;
+
+        let feature = match feature.trim() {
+            "templates" => Feature::Templates,
+            "blocks" => Feature::Blocks,
+            "txindex" => Feature::TxIndex,
+            "inflation" => Feature::Inflation,
+            "rollback" => Feature::Rollback,
+            "block_bodies" => Feature::BlockBodies,
+            "mempool" => Feature::Mempool,
+            other => return Err(DataSourceError::UnknownFeature(other.into())),
+        };
+
+        let role = match nodes.trim() {
+            "all" => NodeRole::All,
+            "archive" => NodeRole::Archive,
+            "mirrors" => NodeRole::Mirrors,
+            list => {
+                let nodes: Vec<_> = list
+                    .split(',')
+                    .map(|n| n.trim().to_string())
+                    .filter(|n| !n.is_empty())
+                    .collect();
+
+                if nodes.is_empty() {
+                    return Err(DataSourceError::NoNodes(s.into()));
+                }
+                NodeRole::Nodes(nodes)
+            }
+        };
+
+        Ok(DataSource { feature, role })
+    }
+}
+
+// In the config file data sources are written as on the command line.
+impl<'de> Deserialize<'de> for DataSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<DataSource, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Node roles for every feature, features that are not configured keep their default.
+#[derive(Debug, Default)]
+pub struct DataSources(HashMap<Feature, NodeRole>);
+
+impl DataSources {
+    pub fn new(sources: Vec<DataSource>) -> DataSources {
+        DataSources(sources.into_iter().map(|s| (s.feature, s.role)).collect())
+    }
+
+    pub fn role(&self, feature: Feature) -> NodeRole {
+        self.0
+            .get(&feature)
+            .cloned()
+            .unwrap_or_else(|| feature.default_role())
+    }
+
+    /// Whether `feature` may send RPC calls to `node`.
+    pub fn uses(&self, feature: Feature, node: &Node, archive_id: i64) -> bool {
+        // a node's own opt outs win over the configured role
+        let opted_in = match feature {
+            Feature::Templates => node.fetch_templates,
+            Feature::Inflation | Feature::Rollback => node.allow_mirror_ops,
+            _ => true,
+        };
+        if !opted_in {
+            return false;
+        }
+
+        match self.role(feature) {
+            NodeRole::All => true,
+            NodeRole::Archive => node.id == archive_id,
+            NodeRole::Mirrors => node.mirror_rpc_port.is_some(),
+            NodeRole::Nodes(names) => names
+                .iter()
+                .any(|n| n == &node.node || n == &node.id.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(id: i64, name: &str, mirror: bool) -> Node {
+        Node {
+            id,
+            node: name.into(),
+            rpc_host: "127.0.0.1".into(),
+            rpc_port: 8332,
+            mirror_rpc_port: if mirror { Some(8333) } else { None },
+            rpc_user: "user".into(),
+            rpc_pass: "pass".into(),
+            unreachable_since: None,
+            last_polled: None,
+            initial_block_download: false,
+            mirror_host: None,
+            mirror_last_polled: None,
+            mirror_unreachable_since: None,
+            archive: false,
+            zmq_endpoint: None,
+            fetch_templates: true,
+            fetch_peers: true,
+            participate_in_consensus: true,
+            allow_mirror_ops: true,
+            node_group: None,
+            rpc_connect_timeout_secs: None,
+            rpc_read_timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_data_source() {
+        assert_eq!(
+            "templates=1, b".parse(),
+            Ok(DataSource {
+                feature: Feature::Templates,
+                role: NodeRole::Nodes(vec!["1".into(), "b".into()]),
+            })
+        );
+        assert_eq!(
+            "txindex=archive".parse(),
+            Ok(DataSource {
+                feature: Feature::TxIndex,
+                role: NodeRole::Archive,
+            })
+        );
+        assert_eq!(
+            "templates".parse::<DataSource>(),
+            Err(DataSourceError::Format("templates".into()))
+        );
+        assert_eq!(
+            "fees=all".parse::<DataSource>(),
+            Err(DataSourceError::UnknownFeature("fees".into()))
+        );
+        assert_eq!(
+            "blocks=,".parse::<DataSource>(),
+            Err(DataSourceError::NoNodes("blocks=,".into()))
+        );
+    }
+
+    #[test]
+    fn test_data_sources_uses() {
+        let sources = DataSources::new(vec!["templates=1,b".parse().unwrap()]);
+        let (a, b, c) = (node(1, "a", false), node(2, "b", true), node(3, "c", false));
+
+        assert!(sources.uses(Feature::Templates, &a, 3));
+        assert!(sources.uses(Feature::Templates, &b, 3));
+        assert!(!sources.uses(Feature::Templates, &c, 3));
+
+        // defaults
+        assert!(sources.uses(Feature::Blocks, &a, 3));
+        assert!(sources.uses(Feature::TxIndex, &c, 3));
+        assert!(!sources.uses(Feature::TxIndex, &a, 3));
+        assert!(sources.uses(Feature::Inflation, &b, 3));
+        assert!(!sources.uses(Feature::Rollback, &a, 3));
+
+        // per node opt outs
+        let mut b = b;
+        b.fetch_templates = false;
+        b.allow_mirror_ops = false;
+        assert!(!sources.uses(Feature::Templates, &b, 3));
+        assert!(!sources.uses(Feature::Inflation, &b, 3));
+        assert!(sources.uses(Feature::Blocks, &b, 3));
+    }
+}
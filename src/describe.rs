@@ -0,0 +1,300 @@
+//! The OpenRPC document returned by `describe`, listing every RPC method and websocket
+//! subscription with the schemas of their params and results, so clients can check
+//! an integration without reading the source. Schemas are generated from the types
+//! params are parsed into and results are serialized from.
+use crate::auth::method_role;
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{
+        ArrayValidation, InstanceType, ObjectValidation, Schema, SchemaObject, SubschemaValidation,
+    },
+    JsonSchema,
+};
+use serde_json::{json, Value};
+
+const OPENRPC_VERSION: &str = "1.2.6";
+
+/// The document, built up one method at a time.
+pub struct ApiDoc {
+    gen: SchemaGenerator,
+    methods: Vec<Value>,
+}
+
+impl Default for ApiDoc {
+    fn default() -> ApiDoc {
+        let settings = SchemaSettings::draft07().with(|s| {
+            s.definitions_path = "#/components/schemas/".into();
+        });
+        ApiDoc {
+            gen: settings.into_generator(),
+            methods: vec![],
+        }
+    }
+}
+
+/// An object with the given fields, for results built as JSON rather than serialized
+/// from a type.
+pub fn object(fields: &[&str]) -> Schema {
+    let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        object: Some(Box::new(ObjectValidation {
+            properties: fields
+                .iter()
+                .map(|f| (f.clone(), Schema::Bool(true)))
+                .collect(),
+            required: fields.into_iter().collect(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// A result that is one of `schemas`, depending on the params.
+pub fn one_of(schemas: Vec<Schema>) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            one_of: Some(schemas),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// An array of `items`.
+pub fn array(items: Schema) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Array.into()),
+        array: Some(Box::new(ArrayValidation {
+            items: Some(items.into()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl ApiDoc {
+    /// The schema of `T`, with the types it refers to added to the document.
+    pub fn schema<T: JsonSchema>(&mut self) -> Schema {
+        self.gen.root_schema_for::<T>().schema.into()
+    }
+
+    // A content descriptor for each field of `P`. The fields of an enum's variants,
+    // like `{ "height": .. }` or `{ "hash": .. }`, are alternatives, so none of them
+    // is required.
+    fn params<P: JsonSchema>(&mut self) -> Vec<Value> {
+        let mut schema = self.schema::<P>().into_object();
+        let variants = match schema.subschemas.take() {
+            Some(subschemas) => subschemas
+                .one_of
+                .into_iter()
+                .chain(subschemas.any_of)
+                .flatten()
+                .collect(),
+            None => vec![Schema::Object(schema)],
+        };
+        let alternatives = variants.len() > 1;
+
+        let mut params = vec![];
+        for variant in variants {
+            if let Some(object) = variant.into_object().object {
+                for (name, field) in object.properties {
+                    let required = !alternatives && object.required.contains(&name);
+                    params.push(json!({ "name": name, "required": required, "schema": field }));
+                }
+            }
+        }
+        params
+    }
+
+    /// An RPC method whose params are parsed into `P` and whose result is serialized
+    /// from `R`, `()` for a method without params.
+    pub fn method<P: JsonSchema, R: JsonSchema>(&mut self, name: &str) {
+        let result = self.schema::<R>();
+        self.method_returning::<P>(name, result);
+    }
+
+    /// An RPC method whose result is built as JSON, see `object` and `array`.
+    pub fn method_returning<P: JsonSchema>(&mut self, name: &str, result: Schema) {
+        let params = self.params::<P>();
+        self.methods.push(json!({
+            "name": name,
+            "params": params,
+            "paramStructure": "by-name",
+            "result": { "name": "result", "schema": result },
+            "x-role": method_role(name).to_string(),
+        }));
+    }
+
+    /// A method of the websocket server. Websocket calls need any valid api key, there
+    /// are no roles.
+    pub fn websocket_method<P: JsonSchema, R: JsonSchema>(&mut self, name: &str) {
+        let params = self.params::<P>();
+        let result = self.schema::<R>();
+        self.methods.push(json!({
+            "name": name,
+            "params": params,
+            "paramStructure": "by-name",
+            "result": { "name": "result", "schema": result },
+            "x-websocket": true,
+        }));
+    }
+
+    /// A websocket subscription, its events are sent as `notification` until it is
+    /// cancelled with `unsubscribe`.
+    pub fn subscription<P: JsonSchema>(
+        &mut self,
+        subscribe: &str,
+        notification: &str,
+        unsubscribe: &str,
+    ) {
+        let params = self.params::<P>();
+        let id = self.schema::<u64>();
+        self.methods.push(json!({
+            "name": subscribe,
+            "params": params,
+            "paramStructure": "by-name",
+            "result": { "name": "subscription", "schema": id },
+            "x-websocket": true,
+            "x-notification": notification,
+            "x-unsubscribe": unsubscribe,
+        }));
+    }
+
+    pub fn document(self) -> Value {
+        json!({
+            "openrpc": OPENRPC_VERSION,
+            "info": { "title": "forkscanner", "version": env!("CARGO_PKG_VERSION") },
+            "methods": self.methods,
+            "components": { "schemas": self.gen.definitions() },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, JsonSchema)]
+    #[allow(dead_code)]
+    struct Args {
+        hash: String,
+        limit: Option<i64>,
+        #[serde(default)]
+        verbose: bool,
+    }
+
+    #[derive(Deserialize, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    #[allow(dead_code)]
+    enum Lookup {
+        Height(i64),
+        Hash(String),
+    }
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct Row {
+        id: i64,
+    }
+
+    #[test]
+    fn test_api_doc() {
+        let mut doc = ApiDoc::default();
+        doc.method::<Args, Vec<Row>>("get_tips");
+        doc.method::<Lookup, bool>("get_block");
+        doc.method_returning::<()>("add_node", object(&["id", "name"]));
+        doc.subscription::<()>("subscribe_forks", "forks", "unsubscribe_forks");
+        let doc = doc.document();
+        let methods = doc["methods"].as_array().expect("No methods");
+
+        let params = &methods[0]["params"];
+        assert_eq!(params[0]["name"], json!("hash"));
+        assert_eq!(params[0]["required"], json!(true));
+        assert_eq!(params[1]["required"], json!(false));
+        assert_eq!(params[2]["required"], json!(false));
+        assert_eq!(methods[0]["x-role"], json!("observer"));
+        assert_eq!(
+            methods[0]["result"]["schema"]["items"]["$ref"],
+            json!("#/components/schemas/Row")
+        );
+        assert!(doc["components"]["schemas"]["Row"].is_object());
+
+        let names: Vec<_> = methods[1]["params"]
+            .as_array()
+            .expect("No params")
+            .iter()
+            .map(|p| (p["name"].clone(), p["required"].clone()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (json!("height"), json!(false)),
+                (json!("hash"), json!(false))
+            ]
+        );
+
+        assert_eq!(methods[2]["params"], json!([]));
+        assert_eq!(methods[2]["x-role"], json!("admin"));
+        assert_eq!(
+            methods[2]["result"]["schema"]["required"],
+            json!(["id", "name"])
+        );
+        assert_eq!(methods[3]["x-notification"], json!("forks"));
+        assert!(methods[3].get("x-role").is_none());
+    }
+
+    fn refs(value: &Value, found: &mut Vec<String>) {
+        match value {
+            Value::Object(fields) => {
+                for (name, value) in fields {
+                    match value.as_str() {
+                        Some(reference) if name == "$ref" => found.push(reference.into()),
+                        _ => refs(value, found),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_service_doc() {
+        let doc = crate::service::api_doc();
+        let mut found = vec![];
+        refs(&doc, &mut found);
+        assert!(!found.is_empty());
+        for reference in found {
+            let name = reference
+                .strip_prefix("#/components/schemas/")
+                .expect("Reference outside the document");
+            assert!(
+                doc["components"]["schemas"][name].is_object(),
+                "No {}",
+                name
+            );
+        }
+
+        let methods = doc["methods"].as_array().expect("No methods");
+        let method = |name: &str| {
+            methods
+                .iter()
+                .find(|m| m["name"] == json!(name))
+                .unwrap_or_else(|| panic!("No {}", name))
+        };
+        let tips = method("get_tips");
+        assert_eq!(tips["params"][0]["name"], json!("active_only"));
+        assert_eq!(tips["params"][0]["required"], json!(true));
+        assert_eq!(tips["params"][1]["required"], json!(false));
+        assert_eq!(method("add_node")["x-role"], json!("admin"));
+        assert_eq!(
+            method("subscribe_reorgs")["params"][0]["name"],
+            json!("client_id")
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Output descriptors and xpubs for the address watcher. Watched addresses are the
+//! hash160 of an output script, see `output_address`, so a descriptor is watched as the
+//! hashes of the scripts derived from it. Single key descriptors are supported:
+//! `pkh(KEY)`, `wpkh(KEY)` and `sh(wpkh(KEY))`, where KEY is an xpub with an optional
+//! origin and a path of unhardened steps ending in `*`, e.g.
+//! `wpkh([d34db33f/84h/0h/0h]xpub.../0/*)`. A bare xpub is watched as `wpkh(XPUB/0/*)`.
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32::{self, ChildNumber, ExtendedPubKey};
+use bitcoin::Script;
+use std::ops::Range;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DescriptorError {
+    #[error("Unsupported descriptor {0}, expected pkh, wpkh or sh(wpkh) of an xpub")]
+    Unsupported(String),
+    #[error("Invalid derivation path {0}, expected unhardened steps ending in *")]
+    Path(String),
+    #[error("Invalid xpub {0}")]
+    Key(#[from] bip32::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScriptKind {
+    Pkh,
+    Wpkh,
+    ShWpkh,
+}
+
+/// A descriptor deriving one script per index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Descriptor {
+    kind: ScriptKind,
+    xpub: ExtendedPubKey,
+    path: Vec<ChildNumber>,
+}
+
+/// A descriptor without its checksum, the way it's kept in the watchlist. The checksum
+/// isn't verified, a typo in the xpub fails the xpub's own checksum.
+pub fn without_checksum(desc: &str) -> &str {
+    desc.trim().split('#').next().unwrap_or_default().trim()
+}
+
+impl FromStr for Descriptor {
+    type Err = DescriptorError;
+
+    fn from_str(s: &str) -> Result<Descriptor, DescriptorError> {
+        let desc = without_checksum(s);
+        if !desc.contains('(') {
+            return Descriptor::parse_key(ScriptKind::Wpkh, &format!("{}/0/*", desc));
+        }
+
+        let wrapped = |prefix: &str| desc.strip_prefix(prefix)?.strip_suffix(')');
+        if let Some(inner) = wrapped("sh(wpkh(").and_then(|k| k.strip_suffix(')')) {
+            Descriptor::parse_key(ScriptKind::ShWpkh, inner)
+        } else if let Some(inner) = wrapped("wpkh(") {
+            Descriptor::parse_key(ScriptKind::Wpkh, inner)
+        } else if let Some(inner) = wrapped("pkh(") {
+            Descriptor::parse_key(ScriptKind::Pkh, inner)
+        } else {
+            Err(DescriptorError::Unsupported(desc.into()))
+        }
+    }
+}
+
+impl Descriptor {
+    fn parse_key(kind: ScriptKind, key: &str) -> Result<Descriptor, DescriptorError> {
+        // the origin only says where the xpub came from
+        let key = match key.strip_prefix('[') {
+            Some(rest) => rest.split_once(']').map(|(_, key)| key).unwrap_or_default(),
+            None => key,
+        };
+
+        let mut steps = key.split('/');
+        let xpub = ExtendedPubKey::from_str(steps.next().unwrap_or_default())?;
+        let steps: Vec<_> = steps.collect();
+        let path = match steps.split_last() {
+            Some((&"*", path)) => path
+                .iter()
+                .map(|step| match step.parse() {
+                    Ok(index) => ChildNumber::from_normal_idx(index).ok(),
+                    Err(_) => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+
+        match path {
+            Some(path) => Ok(Descriptor { kind, xpub, path }),
+            None => Err(DescriptorError::Path(key.into())),
+        }
+    }
+
+    fn script(&self, key: &ExtendedPubKey) -> Script {
+        let pubkey = key.public_key;
+        let wpkh = || Script::new_v0_wpkh(&pubkey.wpubkey_hash().expect("Compressed key"));
+        match self.kind {
+            ScriptKind::Pkh => Script::new_p2pkh(&pubkey.pubkey_hash()),
+            ScriptKind::Wpkh => wpkh(),
+            ScriptKind::ShWpkh => Script::new_p2sh(&wpkh().script_hash()),
+        }
+    }
+
+    /// The watched address of each index in `indexes`, see `output_address`.
+    pub fn addresses(&self, indexes: Range<u32>) -> Result<Vec<(u32, String)>, DescriptorError> {
+        let secp = Secp256k1::verification_only();
+        let parent = self.xpub.derive_pub(&secp, &self.path)?;
+
+        indexes
+            .map(|index| {
+                let child = ChildNumber::from_normal_idx(index)?;
+                let key = parent.ckd_pub(&secp, child)?;
+                Ok((index, self.script(&key).script_hash().to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::Address;
+
+    // BIP 84 account 0 of the "abandon ... about" mnemonic, as an xpub.
+    const XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+    fn watched(address: &str) -> String {
+        let address = Address::from_str(address).expect("Bad address");
+        address.script_pubkey().script_hash().to_string()
+    }
+
+    #[test]
+    fn test_descriptor_addresses() {
+        let desc: Descriptor = format!("wpkh([73c5da0a/84h/0h/0h]{}/0/*)#checksum", XPUB)
+            .parse()
+            .expect("Parse failed");
+        let addresses = desc.addresses(0..2).expect("Derivation failed");
+
+        assert_eq!(
+            addresses,
+            vec![
+                (0, watched("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu")),
+                (1, watched("bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g")),
+            ]
+        );
+        assert_eq!(XPUB.parse::<Descriptor>().unwrap(), desc);
+    }
+
+    #[test]
+    fn test_descriptor_errors() {
+        let parse = |desc: String| desc.parse::<Descriptor>();
+
+        assert!(matches!(
+            parse(format!("tr({}/0/*)", XPUB)),
+            Err(DescriptorError::Unsupported(_))
+        ));
+        assert!(matches!(
+            parse(format!("pkh({}/0h/*)", XPUB)),
+            Err(DescriptorError::Path(_))
+        ));
+        assert!(matches!(
+            parse(format!("pkh({}/0)", XPUB)),
+            Err(DescriptorError::Path(_))
+        ));
+        assert!(matches!(
+            parse("wpkh(xpub123/0/*)".into()),
+            Err(DescriptorError::Key(_))
+        ));
+        assert!(parse(format!("sh(wpkh({}/1/*))", XPUB)).is_ok());
+    }
+}
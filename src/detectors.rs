@@ -0,0 +1,141 @@
+//! Hooks for anomaly detectors compiled into forkscanner. After each scan the registered
+//! detectors are shown the blocks stored since the last one and the tips nodes moved
+//! to, and what they find goes out like any other scanner event: to `anomalies`
+//! subscribers, alert sinks and the event bus. A research heuristic is an
+//! `AnomalyDetector` registered with `ForkScanner::add_detector`, the scan loop stays
+//! as it is.
+use crate::{Block, Chaintip, Severity};
+use chrono::{DateTime, Utc};
+use diesel::prelude::{PgConnection, QueryResult};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Something a detector found.
+#[derive(Clone, Debug, Serialize)]
+pub struct Anomaly {
+    /// Name of the detector that found it.
+    pub detector: String,
+    pub severity: Severity,
+    pub message: String,
+    pub block_hash: Option<String>,
+    pub node_id: Option<i64>,
+    /// Anything else the detector passes on.
+    pub data: Value,
+    pub detected_at: DateTime<Utc>,
+}
+
+impl Anomaly {
+    pub fn new(detector: &str, severity: Severity, message: String) -> Anomaly {
+        Anomaly {
+            detector: detector.into(),
+            severity,
+            message,
+            block_hash: None,
+            node_id: None,
+            data: Value::Null,
+            detected_at: Utc::now(),
+        }
+    }
+}
+
+/// A detector is called from the scanner thread, with its database connection. Errors
+/// are reported as scanner errors and the detector is called again for the next block.
+pub trait AnomalyDetector: Send {
+    /// Name its anomalies are reported under, e.g. `pool_streak`.
+    fn name(&self) -> &str;
+
+    /// Called once for each new block, parents before children. Headers only blocks
+    /// are included.
+    fn on_block(&mut self, _conn: &PgConnection, _block: &Block) -> QueryResult<Vec<Anomaly>> {
+        Ok(vec![])
+    }
+
+    /// Called when a node's active tip changed.
+    fn on_chaintip(&mut self, _conn: &PgConnection, _tip: &Chaintip) -> QueryResult<Vec<Anomaly>> {
+        Ok(vec![])
+    }
+}
+
+/// Finds one pool mining `length` blocks in a row, the kind of run selfish mining
+/// leaves behind.
+pub struct PoolStreak {
+    length: usize,
+}
+
+impl PoolStreak {
+    pub fn new(length: usize) -> PoolStreak {
+        PoolStreak { length }
+    }
+}
+
+// How many of the last pools are the same one, none if the last pool isn't known.
+fn streak(pools: &[Option<String>]) -> usize {
+    match pools.last() {
+        Some(Some(last)) => pools
+            .iter()
+            .rev()
+            .take_while(|pool| pool.as_ref() == Some(last))
+            .count(),
+        _ => 0,
+    }
+}
+
+impl AnomalyDetector for PoolStreak {
+    fn name(&self) -> &str {
+        "pool_streak"
+    }
+
+    fn on_block(&mut self, conn: &PgConnection, block: &Block) -> QueryResult<Vec<Anomaly>> {
+        let pool = match &block.pool_name {
+            Some(pool) if self.length > 1 => pool,
+            _ => return Ok(vec![]),
+        };
+
+        // one block more than the streak, so a longer run is only reported once
+        let ancestors = block.ancestors(conn, block.height - self.length as i64)?;
+        let pools: Vec<_> = ancestors.into_iter().map(|b| b.pool_name).collect();
+        if streak(&pools) != self.length {
+            return Ok(vec![]);
+        }
+
+        let message = format!(
+            "{} mined {} blocks in a row, up to {} at height {}",
+            pool, self.length, block.hash, block.height
+        );
+        Ok(vec![Anomaly {
+            block_hash: Some(block.hash.clone()),
+            data: json!({ "pool_name": pool, "length": self.length }),
+            ..Anomaly::new(self.name(), Severity::Warning, message)
+        }])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool_streak() {
+        let pools = |names: &[&str]| -> Vec<Option<String>> {
+            names
+                .iter()
+                .map(|n| Some(n.to_string()).filter(|n| !n.is_empty()))
+                .collect()
+        };
+
+        assert_eq!(
+            streak(&pools(&["AntPool", "Foundry", "Foundry", "Foundry"])),
+            3
+        );
+        assert_eq!(streak(&pools(&["Foundry", "Foundry", "AntPool"])), 1);
+        assert_eq!(streak(&pools(&["Foundry", "", "Foundry"])), 1);
+        assert_eq!(streak(&pools(&["Foundry", ""])), 0);
+        assert_eq!(streak(&[]), 0);
+
+        let anomaly = Anomaly::new("pool_streak", Severity::Warning, "run".into());
+        let value = serde_json::to_value(&anomaly).expect("Serialize failed");
+        assert_eq!(value["detector"], json!("pool_streak"));
+        assert_eq!(value["severity"], json!("warning"));
+        assert!(value["block_hash"].is_null());
+    }
+}
@@ -0,0 +1,312 @@
+//! Publishes every scanner message to NATS or Kafka, so large deployments can fan
+//! events out to many consumers without going through the websocket server. The bus
+//! is configured with `[event_bus]` in the config file.
+use crate::json_numbers::decimal_json;
+use crate::{InflatedBlock, ScannerMessage};
+use chrono::Utc;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::thread;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BusError {
+    #[error("The {0} event bus needs the {0} feature")]
+    Disabled(&'static str),
+    #[error("NATS error {0}")]
+    Nats(String),
+    #[error("Kafka error {0}")]
+    Kafka(String),
+    #[error("Could not serialize event {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Where events are published.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BusConfig {
+    /// NATS subjects `<subject_prefix>.<event>`, needs the `nats` feature.
+    Nats {
+        url: String,
+        #[serde(default = "default_prefix")]
+        subject_prefix: String,
+    },
+    /// One Kafka topic with the event name as message key, needs the `kafka` feature.
+    Kafka {
+        brokers: String,
+        #[serde(default = "default_prefix")]
+        topic: String,
+    },
+}
+
+fn default_prefix() -> String {
+    "forkscanner".into()
+}
+
+/// A scanner message as published, `event` is the websocket channel it is sent on
+/// where there is one.
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    pub event: &'static str,
+    pub data: Value,
+    pub published_at: chrono::DateTime<Utc>,
+}
+
+fn inflated_block_json(ib: &InflatedBlock) -> Value {
+    let extra = &ib.actual_inflation - &ib.max_inflation;
+    json!({
+        "block_hash": ib.block_hash,
+        "node_id": ib.node_id,
+        "max_inflation": decimal_json(&ib.max_inflation),
+        "actual_inflation": decimal_json(&ib.actual_inflation),
+        "extra_inflation": decimal_json(&extra),
+        "created_at": ib.created_at,
+    })
+}
+
+/// The event for a scanner message.
+pub fn event(message: &ScannerMessage) -> Event {
+    let (event, data) = match message {
+        ScannerMessage::LaggingNodes(lags) => ("lagging_nodes", json!(lags)),
+        ScannerMessage::NewChaintip => ("new_chaintip", Value::Null),
+        ScannerMessage::NewBlockConflicts(conflicts) => ("validation_checks", json!(conflicts)),
+        ScannerMessage::AllChaintips(tips) => ("chaintips", json!(tips)),
+        ScannerMessage::StaleCandidateUpdate => ("stale_candidates", Value::Null),
+        ScannerMessage::TipUpdateFailed(err) => ("tip_update_failed", json!({ "error": err })),
+        ScannerMessage::TipUpdated(invalidated) => {
+            ("tip_updated", json!({ "invalidated": invalidated }))
+        }
+        ScannerMessage::WatchedAddress(txs) => ("watched_addresses", json!(txs)),
+        ScannerMessage::WatchedAddressMempool(payments) => {
+            ("watched_address_mempool", json!(payments))
+        }
+        ScannerMessage::TipStatusChanged(transitions) => ("tip_transitions", json!(transitions)),
+        ScannerMessage::PersistentForks(forks) => ("persistent_forks", json!(forks)),
+        ScannerMessage::WalletConflicts(conflicts) => ("wallet_conflicts", json!(conflicts)),
+        ScannerMessage::StuckChain(stuck) => ("stuck_chain", json!(stuck)),
+        ScannerMessage::PeerPartitions(pairs) => ("peer_partitions", json!(pairs)),
+        ScannerMessage::GroupSplits(splits) => ("group_splits", json!(splits)),
+        ScannerMessage::ExternalDisagreements(disagreements) => {
+            ("external_disagreements", json!(disagreements))
+        }
+        ScannerMessage::MempoolDivergence(nodes) => ("mempool_divergence", json!(nodes)),
+        ScannerMessage::PayoutAnomalies(anomalies) => ("payout_anomalies", json!(anomalies)),
+        ScannerMessage::Anomalies(anomalies) => ("anomalies", json!(anomalies)),
+        ScannerMessage::Censorship(candidates) => ("censorship", json!(candidates)),
+        ScannerMessage::Reorg(reorg) => ("reorgs", json!(reorg)),
+        ScannerMessage::DeepReorg(deep) => ("deep_reorgs", json!(deep)),
+        ScannerMessage::OutpointSpends(spends) => ("outpoint_spends", json!(spends)),
+        ScannerMessage::InflatedBlock(ib) => ("inflation_checks", inflated_block_json(ib)),
+        ScannerMessage::DoubleSpends(found) => ("double_spends", json!(found)),
+        ScannerMessage::PeerChanges(changes) => ("peer_changes", json!(changes)),
+        ScannerMessage::CycleSummary(summary) => ("cycle_summary", json!(summary)),
+        ScannerMessage::NewHeader(header) => ("headers", json!(header)),
+        ScannerMessage::Error(err) => ("errors", json!(err)),
+    };
+
+    Event {
+        event,
+        data,
+        published_at: Utc::now(),
+    }
+}
+
+trait Publisher {
+    fn publish(&mut self, event: &Event, payload: Vec<u8>) -> Result<(), BusError>;
+}
+
+#[cfg(feature = "nats")]
+mod nats {
+    use super::{BusError, Event, Publisher};
+    use tokio::runtime::Runtime;
+
+    pub struct NatsPublisher {
+        runtime: Runtime,
+        client: async_nats::Client,
+        subject_prefix: String,
+    }
+
+    impl NatsPublisher {
+        /// Connects in the background, events published until then are queued.
+        pub fn new(url: &str, subject_prefix: &str) -> Result<NatsPublisher, BusError> {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(1)
+                .enable_all()
+                .build()
+                .map_err(|e| BusError::Nats(e.to_string()))?;
+            let client = runtime
+                .block_on(
+                    async_nats::ConnectOptions::new()
+                        .name("forkscanner")
+                        .retry_on_initial_connect()
+                        .connect(url),
+                )
+                .map_err(|e| BusError::Nats(e.to_string()))?;
+
+            Ok(NatsPublisher {
+                runtime,
+                client,
+                subject_prefix: subject_prefix.into(),
+            })
+        }
+    }
+
+    impl Publisher for NatsPublisher {
+        fn publish(&mut self, event: &Event, payload: Vec<u8>) -> Result<(), BusError> {
+            let subject = format!("{}.{}", self.subject_prefix, event.event);
+            self.runtime
+                .block_on(self.client.publish(subject, payload.into()))
+                .map_err(|e| BusError::Nats(e.to_string()))
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use super::{BusError, Event, Publisher};
+    use rdkafka::{
+        config::ClientConfig,
+        producer::{BaseRecord, DefaultProducerContext, ThreadedProducer},
+    };
+
+    pub struct KafkaPublisher {
+        producer: ThreadedProducer<DefaultProducerContext>,
+        topic: String,
+    }
+
+    impl KafkaPublisher {
+        pub fn new(brokers: &str, topic: &str) -> Result<KafkaPublisher, BusError> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .set("client.id", "forkscanner")
+                .create()
+                .map_err(|e| BusError::Kafka(e.to_string()))?;
+
+            Ok(KafkaPublisher {
+                producer,
+                topic: topic.into(),
+            })
+        }
+    }
+
+    impl Publisher for KafkaPublisher {
+        fn publish(&mut self, event: &Event, payload: Vec<u8>) -> Result<(), BusError> {
+            let record = BaseRecord::to(&self.topic)
+                .key(event.event)
+                .payload(&payload);
+            self.producer
+                .send(record)
+                .map_err(|(e, _)| BusError::Kafka(e.to_string()))
+        }
+    }
+}
+
+fn new_publisher(config: &BusConfig) -> Result<Box<dyn Publisher + Send>, BusError> {
+    match config {
+        #[cfg(feature = "nats")]
+        BusConfig::Nats {
+            url,
+            subject_prefix,
+        } => Ok(Box::new(nats::NatsPublisher::new(url, subject_prefix)?)),
+        #[cfg(not(feature = "nats"))]
+        BusConfig::Nats { .. } => Err(BusError::Disabled("nats")),
+        #[cfg(feature = "kafka")]
+        BusConfig::Kafka { brokers, topic } => {
+            Ok(Box::new(kafka::KafkaPublisher::new(brokers, topic)?))
+        }
+        #[cfg(not(feature = "kafka"))]
+        BusConfig::Kafka { .. } => Err(BusError::Disabled("kafka")),
+    }
+}
+
+/// Publishes scanner messages to the configured bus, on a thread of its own so a slow
+/// broker doesn't hold up notifications.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    events: Option<Sender<Event>>,
+}
+
+impl EventBus {
+    pub fn start(config: Option<&BusConfig>) -> Result<EventBus, BusError> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(EventBus::default()),
+        };
+
+        let publisher = new_publisher(config)?;
+        info!("Publishing events to {:?}", config);
+
+        let (events, receiver) = unbounded();
+        thread::spawn(move || publish(receiver, publisher));
+        Ok(EventBus {
+            events: Some(events),
+        })
+    }
+
+    pub fn handle(&self, message: &ScannerMessage) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event(message));
+        }
+    }
+}
+
+fn publish(receiver: Receiver<Event>, mut publisher: Box<dyn Publisher + Send>) {
+    for event in receiver {
+        let result = serde_json::to_vec(&event)
+            .map_err(BusError::from)
+            .and_then(|payload| publisher.publish(&event, payload));
+        if let Err(e) = result {
+            error!("Could not publish {} event {:?}", event.event, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ErrorCategory, ErrorEvent};
+
+    #[test]
+    fn test_bus_config() {
+        #[derive(Deserialize)]
+        struct Bus {
+            event_bus: BusConfig,
+        }
+
+        let bus: Bus = toml::from_str(
+            r#"
+            [event_bus]
+            type = "kafka"
+            brokers = "kafka-1:9092,kafka-2:9092"
+            "#,
+        )
+        .expect("Parse failed");
+        assert_eq!(
+            bus.event_bus,
+            BusConfig::Kafka {
+                brokers: "kafka-1:9092,kafka-2:9092".into(),
+                topic: "forkscanner".into(),
+            }
+        );
+
+        assert!(toml::from_str::<Bus>("[event_bus]\ntype = \"nats\"").is_err());
+    }
+
+    #[test]
+    fn test_event() {
+        let message = ScannerMessage::Error(ErrorEvent {
+            category: ErrorCategory::Rpc,
+            node_id: Some(3),
+            message: "Timed out".into(),
+            created_at: Utc::now(),
+        });
+        let event = event(&message);
+
+        assert_eq!(event.event, "errors");
+        assert_eq!(event.data["node_id"], 3);
+        assert_eq!(event.data["category"], "rpc");
+        assert_eq!(self::event(&ScannerMessage::NewChaintip).data, Value::Null);
+    }
+}
@@ -0,0 +1,223 @@
+//! A read only JSON feed in the format of the forkmonitor.info API, so tools
+//! written against forkmonitor can consume a forkscanner instance as well.
+//! It is served by the RPC server for GET requests under `/api/v1/`, with the same api
+//! keys and rate limits as RPC calls.
+use crate::auth::{http_meta, Caller};
+use crate::json_numbers::{btc_json, decimal_json};
+use crate::limits::RateLimiter;
+use crate::{Block, InflatedBlock, InvalidBlock, Node, StaleCandidate};
+use diesel::prelude::PgConnection;
+use jsonrpc_http_server::{
+    hyper::{header::HeaderValue, Body, Method, Request, StatusCode},
+    RequestMiddlewareAction, Response,
+};
+use log::error;
+use r2d2_diesel::ConnectionManager;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const FEED_PREFIX: &str = "/api/v1/";
+const FEED_COIN: &str = "btc";
+const FEED_LIMIT: i64 = 100;
+
+type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+fn node_json(nodes: &HashMap<i64, Node>, node_id: i64) -> Value {
+    match nodes.get(&node_id) {
+        Some(node) => json!({
+            "id": node.id,
+            "name": node.node,
+            "coin": FEED_COIN,
+        }),
+        None => Value::Null,
+    }
+}
+
+fn block_json(conn: &PgConnection, nodes: &HashMap<i64, Node>, hash: &String) -> Value {
+    match Block::get(conn, hash) {
+        Ok(block) => json!({
+            "hash": block.hash,
+            "height": block.height,
+            "work": block.work,
+            "pool": block.pool_name,
+            "headers_only": block.headers_only,
+            "first_seen_by": node_json(nodes, block.first_seen_by),
+        }),
+        Err(_) => json!({ "hash": hash }),
+    }
+}
+
+fn inflated_blocks(conn: &PgConnection, nodes: &HashMap<i64, Node>) -> Option<Value> {
+    let blocks = InflatedBlock::list(conn, FEED_LIMIT).ok()?;
+
+    let feed: Vec<_> = blocks
+        .into_iter()
+        .map(|ib| {
+            let extra = &ib.actual_inflation - &ib.max_inflation;
+            json!({
+                "block": block_json(conn, nodes, &ib.block_hash),
+                "node": node_json(nodes, ib.node_id),
+                "max_inflation": decimal_json(&ib.max_inflation),
+                "actual_inflation": decimal_json(&ib.actual_inflation),
+                "extra_inflation": decimal_json(&extra),
+                "created_at": ib.created_at,
+                "dismissed_at": ib.dismissed_at,
+            })
+        })
+        .collect();
+
+    Some(feed.into())
+}
+
+fn invalid_blocks(conn: &PgConnection, nodes: &HashMap<i64, Node>) -> Option<Value> {
+    let blocks = InvalidBlock::list(conn, FEED_LIMIT).ok()?;
+
+    let feed: Vec<_> = blocks
+        .into_iter()
+        .map(|ib| {
+            json!({
+                "block": block_json(conn, nodes, &ib.hash),
+                "node": node_json(nodes, ib.node),
+                "created_at": ib.created_at,
+                "updated_at": ib.updated_at,
+                "dismissed_at": Value::Null,
+            })
+        })
+        .collect();
+
+    Some(feed.into())
+}
+
+fn stale_candidate_json(
+    conn: &PgConnection,
+    nodes: &HashMap<i64, Node>,
+    candidate: StaleCandidate,
+) -> Value {
+    let children: Vec<_> = candidate
+        .children(conn)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|child| {
+            json!({
+                "root": block_json(conn, nodes, &child.root_id),
+                "tip": block_json(conn, nodes, &child.tip_id),
+                "length": child.len,
+            })
+        })
+        .chain(
+            candidate
+                .imported(conn)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|block| {
+                    let block = json!({ "hash": block.hash, "height": block.height });
+                    json!({ "root": block, "tip": block, "length": 1 })
+                }),
+        )
+        .collect();
+
+    json!({
+        "coin": FEED_COIN,
+        "height": candidate.height,
+        "n_children": candidate.n_children,
+        "children": children,
+        "confirmed_in_one_branch_total": btc_json(candidate.confirmed_in_one_branch_total),
+        "double_spent_in_one_branch_total": btc_json(candidate.double_spent_in_one_branch_total),
+        "rbf_total": btc_json(candidate.rbf_total),
+        "created_at": candidate.created_at,
+        "source": candidate.source,
+    })
+}
+
+fn stale_candidates(conn: &PgConnection, nodes: &HashMap<i64, Node>) -> Option<Value> {
+    let candidates = StaleCandidate::top_n(conn, FEED_LIMIT).ok()?;
+
+    let feed: Vec<_> = candidates
+        .into_iter()
+        .map(|c| stale_candidate_json(conn, nodes, c))
+        .collect();
+
+    Some(feed.into())
+}
+
+fn nodes_feed(nodes: &HashMap<i64, Node>) -> Option<Value> {
+    let mut ids: Vec<_> = nodes.keys().cloned().collect();
+    ids.sort();
+
+    let feed: Vec<_> = ids.into_iter().map(|id| node_json(nodes, id)).collect();
+    Some(feed.into())
+}
+
+fn error_response(code: StatusCode, message: &str) -> Response {
+    Response {
+        code,
+        content_type: HeaderValue::from_static("application/json; charset=utf-8"),
+        content: json!({ "error": message }).to_string(),
+    }
+}
+
+// Route a feed path, e.g. `stale_candidates/btc/700000`, to its JSON.
+fn route(conn: &PgConnection, path: &str) -> Option<Value> {
+    let nodes: HashMap<_, _> = match Node::list(conn) {
+        Ok(nodes) => nodes.into_iter().map(|n| (n.id, n)).collect(),
+        Err(e) => {
+            error!("Could not list nodes {:?}", e);
+            return None;
+        }
+    };
+
+    let parts: Vec<_> = path.trim_end_matches(".json").split('/').collect();
+    match parts.as_slice() {
+        ["inflated_blocks"] => inflated_blocks(conn, &nodes),
+        ["invalid_blocks"] => invalid_blocks(conn, &nodes),
+        ["nodes", FEED_COIN] => nodes_feed(&nodes),
+        ["stale_candidates", FEED_COIN] => stale_candidates(conn, &nodes),
+        ["stale_candidates", FEED_COIN, height] => {
+            let height = height.parse().ok()?;
+            let candidate = StaleCandidate::get(conn, height).ok()?;
+            Some(stale_candidate_json(conn, &nodes, candidate))
+        }
+        _ => None,
+    }
+}
+
+/// HTTP middleware answering feed requests, anything else is passed on to the
+/// RPC handler.
+pub fn feed_middleware(
+    pool: ManagedPool,
+    limiter: RateLimiter,
+) -> impl Fn(Request<Body>) -> RequestMiddlewareAction + Send + Sync + 'static {
+    let meta = http_meta(pool.clone());
+
+    move |request: Request<Body>| {
+        let path = request.uri().path().to_string();
+
+        if request.method() != Method::GET || !path.starts_with(FEED_PREFIX) {
+            return RequestMiddlewareAction::Proceed {
+                should_continue_on_invalid_cors: false,
+                request,
+            };
+        }
+
+        let meta = meta(&request);
+        if meta.role().is_none() {
+            return error_response(StatusCode::UNAUTHORIZED, "Unauthorized").into();
+        }
+        if !limiter.take(meta.key_name()) {
+            return error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests").into();
+        }
+
+        let conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Could not get a database connection {:?}", e);
+                return Response::internal_error("Database unavailable").into();
+            }
+        };
+
+        match route(&conn, &path[FEED_PREFIX.len()..]) {
+            Some(value) => Response::ok(value.to_string()).into(),
+            None => error_response(StatusCode::NOT_FOUND, "Not found").into(),
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! A GraphQL endpoint over the fork database, so frontends can fetch blocks with
+//! their transactions, stale candidates with their branches, or nodes with their tips
+//! and templates in one request. It is served at `/graphql` on its own port when
+//! `graphql_port` is set, with the same api keys and rate limits as RPC.
+use crate::auth::{http_meta, Caller};
+use crate::limits::RateLimiter;
+use crate::{
+    Block, BlockFilter, BlockTemplate, Chaintip, Node, StaleCandidate, StaleCandidateChildren,
+    Transaction,
+};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema};
+use chrono::{DateTime, Utc};
+use diesel::prelude::{OptionalExtension, PgConnection};
+use jsonrpc_http_server::{
+    hyper::{self, header::HeaderValue, Body, Method, Request, StatusCode},
+    RequestMiddlewareAction, Response,
+};
+use log::error;
+use r2d2::PooledConnection;
+use r2d2_diesel::ConnectionManager;
+use serde_json::json;
+
+const GRAPHQL_PATH: &str = "/graphql";
+const LIST_LIMIT: i64 = 100;
+const MAX_DEPTH: usize = 10;
+const MAX_COMPLEXITY: usize = 5000;
+
+type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+type Conn = PooledConnection<ConnectionManager<PgConnection>>;
+pub type ForkSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+fn conn(ctx: &Context<'_>) -> Result<Conn> {
+    Ok(ctx.data::<ManagedPool>()?.get()?)
+}
+
+// A list argument's limit, at most `LIST_LIMIT`.
+fn limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(LIST_LIMIT).clamp(1, LIST_LIMIT)
+}
+
+pub struct GqlBlock(Block);
+
+#[Object(name = "Block")]
+impl GqlBlock {
+    async fn hash(&self) -> &str {
+        &self.0.hash
+    }
+
+    async fn height(&self) -> i64 {
+        self.0.height
+    }
+
+    async fn parent_hash(&self) -> Option<&str> {
+        self.0.parent_hash.as_deref()
+    }
+
+    async fn connected(&self) -> bool {
+        self.0.connected
+    }
+
+    async fn headers_only(&self) -> bool {
+        self.0.headers_only
+    }
+
+    async fn work(&self) -> &str {
+        &self.0.work
+    }
+
+    async fn pool_name(&self) -> Option<&str> {
+        self.0.pool_name.as_deref()
+    }
+
+    /// Total fees in BTC, as a decimal string.
+    async fn total_fee(&self) -> Option<String> {
+        self.0.total_fee.as_ref().map(|fee| fee.to_string())
+    }
+
+    async fn first_seen_peer(&self) -> Option<&str> {
+        self.0.first_seen_peer.as_deref()
+    }
+
+    async fn template_similarity(&self) -> Option<f64> {
+        self.0.template_similarity
+    }
+
+    /// The node that saw the block first.
+    async fn first_seen_by(&self, ctx: &Context<'_>) -> Result<Option<GqlNode>> {
+        let conn = conn(ctx)?;
+        let node = Node::get(&conn, self.0.first_seen_by).optional()?;
+        Ok(node.map(GqlNode))
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<GqlBlock>> {
+        let hash = match &self.0.parent_hash {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        let conn = conn(ctx)?;
+        let parent = Block::get(&conn, hash).optional()?;
+        Ok(parent.map(GqlBlock))
+    }
+
+    /// Blocks built on this one, more than one is a fork.
+    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let children = Block::children(&conn, &self.0.hash)?;
+        Ok(children.into_iter().map(GqlBlock).collect())
+    }
+
+    /// The transactions stored of the block, only blocks of stale candidate branches
+    /// have them.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Vec<GqlTransaction>> {
+        let conn = conn(ctx)?;
+        let txs = Transaction::of_block(&conn, &self.0.hash, self::limit(limit))?;
+        Ok(txs.into_iter().map(GqlTransaction).collect())
+    }
+
+    /// The stale candidate at the block's height, if there is more than one block.
+    async fn stale_candidate(&self, ctx: &Context<'_>) -> Result<Option<GqlStaleCandidate>> {
+        let conn = conn(ctx)?;
+        let candidate = StaleCandidate::get(&conn, self.0.height).optional()?;
+        Ok(candidate.map(GqlStaleCandidate))
+    }
+}
+
+pub struct GqlTransaction(Transaction);
+
+#[Object(name = "Transaction")]
+impl GqlTransaction {
+    async fn txid(&self) -> &str {
+        &self.0.txid
+    }
+
+    async fn is_coinbase(&self) -> bool {
+        self.0.is_coinbase
+    }
+
+    /// The amount of its outputs in BTC.
+    async fn amount(&self) -> f64 {
+        self.0.amount
+    }
+
+    async fn swept(&self) -> Option<bool> {
+        self.0.swept
+    }
+
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let block = Block::get(&conn, &self.0.block_id).optional()?;
+        Ok(block.map(GqlBlock))
+    }
+}
+
+pub struct GqlChaintip(Chaintip);
+
+#[Object(name = "Chaintip")]
+impl GqlChaintip {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    /// `active`, `valid-fork`, `valid-headers`, `headers-only` or `invalid`.
+    async fn status(&self) -> &str {
+        &self.0.status
+    }
+
+    async fn height(&self) -> i64 {
+        self.0.height
+    }
+
+    async fn block_hash(&self) -> &str {
+        &self.0.block
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    async fn block(&self, ctx: &Context<'_>) -> Result<Option<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let block = Block::get(&conn, &self.0.block).optional()?;
+        Ok(block.map(GqlBlock))
+    }
+
+    async fn node(&self, ctx: &Context<'_>) -> Result<Option<GqlNode>> {
+        let conn = conn(ctx)?;
+        let node = Node::get(&conn, self.0.node).optional()?;
+        Ok(node.map(GqlNode))
+    }
+}
+
+/// A monitored node, without its RPC credentials.
+pub struct GqlNode(Node);
+
+#[Object(name = "Node")]
+impl GqlNode {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.node
+    }
+
+    async fn archive(&self) -> bool {
+        self.0.archive
+    }
+
+    async fn group(&self) -> Option<&str> {
+        self.0.node_group.as_deref()
+    }
+
+    async fn reachable(&self) -> bool {
+        self.0.unreachable_since.is_none()
+    }
+
+    async fn unreachable_since(&self) -> Option<DateTime<Utc>> {
+        self.0.unreachable_since
+    }
+
+    async fn last_polled(&self) -> Option<DateTime<Utc>> {
+        self.0.last_polled
+    }
+
+    async fn initial_block_download(&self) -> bool {
+        self.0.initial_block_download
+    }
+
+    async fn chaintips(&self, ctx: &Context<'_>) -> Result<Vec<GqlChaintip>> {
+        let conn = conn(ctx)?;
+        let tips = Chaintip::list(&conn)?;
+        let tips = tips.into_iter().filter(|tip| tip.node == self.0.id);
+        Ok(tips.map(GqlChaintip).collect())
+    }
+
+    async fn active_tip(&self, ctx: &Context<'_>) -> Result<Option<GqlChaintip>> {
+        let conn = conn(ctx)?;
+        let tip = Chaintip::get_active(&conn, self.0.id).optional()?;
+        Ok(tip.map(GqlChaintip))
+    }
+
+    /// The node's latest block templates, newest first.
+    async fn templates(&self, ctx: &Context<'_>, limit: Option<i64>) -> Result<Vec<GqlTemplate>> {
+        let conn = conn(ctx)?;
+        let templates = BlockTemplate::list(&conn, Some(self.0.id), self::limit(limit))?;
+        Ok(templates.into_iter().map(GqlTemplate).collect())
+    }
+}
+
+pub struct GqlTemplate(BlockTemplate);
+
+#[Object(name = "Template")]
+impl GqlTemplate {
+    async fn parent_block_hash(&self) -> &str {
+        &self.0.parent_block_hash
+    }
+
+    async fn height(&self) -> i64 {
+        self.0.height
+    }
+
+    /// Total fees in BTC, as a decimal string.
+    async fn fee_total(&self) -> String {
+        self.0.fee_total.to_string()
+    }
+
+    async fn ts(&self) -> DateTime<Utc> {
+        self.0.ts
+    }
+
+    async fn n_transactions(&self) -> i32 {
+        self.0.n_transactions
+    }
+
+    /// In sat/vB.
+    async fn lowest_fee_rate(&self) -> i32 {
+        self.0.lowest_fee_rate
+    }
+
+    async fn rules(&self) -> &[String] {
+        &self.0.rules
+    }
+
+    async fn parent_block(&self, ctx: &Context<'_>) -> Result<Option<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let block = Block::get(&conn, &self.0.parent_block_hash).optional()?;
+        Ok(block.map(GqlBlock))
+    }
+
+    async fn node(&self, ctx: &Context<'_>) -> Result<Option<GqlNode>> {
+        let conn = conn(ctx)?;
+        let node = Node::get(&conn, self.0.node_id).optional()?;
+        Ok(node.map(GqlNode))
+    }
+}
+
+pub struct GqlStaleCandidate(StaleCandidate);
+
+#[Object(name = "StaleCandidate")]
+impl GqlStaleCandidate {
+    async fn height(&self) -> i64 {
+        self.0.height
+    }
+
+    async fn n_children(&self) -> i32 {
+        self.0.n_children
+    }
+
+    /// BTC confirmed in one branch only.
+    async fn confirmed_in_one_branch_total(&self) -> f64 {
+        self.0.confirmed_in_one_branch_total
+    }
+
+    /// BTC double spent between the branches.
+    async fn double_spent_in_one_branch_total(&self) -> f64 {
+        self.0.double_spent_in_one_branch_total
+    }
+
+    async fn rbf_total(&self) -> f64 {
+        self.0.rbf_total
+    }
+
+    async fn source(&self) -> &str {
+        &self.0.source
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// The competing branches.
+    async fn children(&self, ctx: &Context<'_>) -> Result<Vec<GqlStaleChild>> {
+        let conn = conn(ctx)?;
+        let children = self.0.children(&conn)?;
+        Ok(children.into_iter().map(GqlStaleChild).collect())
+    }
+
+    /// The blocks at the candidate's height.
+    async fn blocks(&self, ctx: &Context<'_>) -> Result<Vec<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let blocks = Block::get_at_height(&conn, self.0.height)?;
+        Ok(blocks.into_iter().map(GqlBlock).collect())
+    }
+}
+
+/// A branch of a stale candidate.
+pub struct GqlStaleChild(StaleCandidateChildren);
+
+#[Object(name = "StaleCandidateBranch")]
+impl GqlStaleChild {
+    async fn length(&self) -> i32 {
+        self.0.len
+    }
+
+    async fn root(&self, ctx: &Context<'_>) -> Result<Option<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let block = Block::get(&conn, &self.0.root_id).optional()?;
+        Ok(block.map(GqlBlock))
+    }
+
+    async fn tip(&self, ctx: &Context<'_>) -> Result<Option<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let block = Block::get(&conn, &self.0.tip_id).optional()?;
+        Ok(block.map(GqlBlock))
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn block(&self, ctx: &Context<'_>, hash: String) -> Result<Option<GqlBlock>> {
+        let conn = conn(ctx)?;
+        let block = Block::get(&conn, &hash).optional()?;
+        Ok(block.map(GqlBlock))
+    }
+
+    /// Blocks highest first, like `list_blocks`.
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        min_height: Option<i64>,
+        max_height: Option<i64>,
+        pool_name: Option<String>,
+        headers_only: Option<bool>,
+        limit: Option<i64>,
+    ) -> Result<Vec<GqlBlock>> {
+        let filter = BlockFilter {
+            min_height,
+            max_height,
+            pool_name,
+            headers_only,
+        };
+        let conn = conn(ctx)?;
+        let blocks = Block::list(&conn, &filter, None, self::limit(limit))?;
+        Ok(blocks.into_iter().map(GqlBlock).collect())
+    }
+
+    async fn chaintips(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] active_only: bool,
+    ) -> Result<Vec<GqlChaintip>> {
+        let conn = conn(ctx)?;
+        let tips = if active_only {
+            Chaintip::list_active(&conn)?
+        } else {
+            Chaintip::list(&conn)?
+        };
+        Ok(tips.into_iter().map(GqlChaintip).collect())
+    }
+
+    async fn nodes(&self, ctx: &Context<'_>) -> Result<Vec<GqlNode>> {
+        let conn = conn(ctx)?;
+        let nodes = Node::list(&conn)?;
+        Ok(nodes.into_iter().map(GqlNode).collect())
+    }
+
+    async fn node(&self, ctx: &Context<'_>, id: i64) -> Result<Option<GqlNode>> {
+        let conn = conn(ctx)?;
+        let node = Node::get(&conn, id).optional()?;
+        Ok(node.map(GqlNode))
+    }
+
+    /// Block templates of all nodes, newest first.
+    async fn templates(
+        &self,
+        ctx: &Context<'_>,
+        node_id: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<GqlTemplate>> {
+        let conn = conn(ctx)?;
+        let templates = BlockTemplate::list(&conn, node_id, self::limit(limit))?;
+        Ok(templates.into_iter().map(GqlTemplate).collect())
+    }
+
+    /// Stale candidates highest first.
+    async fn stale_candidates(
+        &self,
+        ctx: &Context<'_>,
+        source: Option<String>,
+        limit: Option<i64>,
+    ) -> Result<Vec<GqlStaleCandidate>> {
+        let conn = conn(ctx)?;
+        let candidates = StaleCandidate::list(&conn, source, self::limit(limit))?;
+        Ok(candidates.into_iter().map(GqlStaleCandidate).collect())
+    }
+
+    async fn stale_candidate(
+        &self,
+        ctx: &Context<'_>,
+        height: i64,
+    ) -> Result<Option<GqlStaleCandidate>> {
+        let conn = conn(ctx)?;
+        let candidate = StaleCandidate::get(&conn, height).optional()?;
+        Ok(candidate.map(GqlStaleCandidate))
+    }
+}
+
+pub fn schema(pool: ManagedPool) -> ForkSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(pool)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+fn error_response(code: StatusCode, message: &str) -> Response {
+    Response {
+        code,
+        content_type: HeaderValue::from_static("application/json; charset=utf-8"),
+        content: json!({ "error": message }).to_string(),
+    }
+}
+
+/// HTTP middleware running the GraphQL queries posted to `/graphql`.
+pub fn graphql_middleware(
+    pool: ManagedPool,
+    limiter: RateLimiter,
+    max_body_bytes: usize,
+) -> impl Fn(Request<Body>) -> RequestMiddlewareAction + Send + Sync + 'static {
+    let meta = http_meta(pool.clone());
+    let schema = schema(pool);
+
+    move |request: Request<Body>| {
+        if request.uri().path() != GRAPHQL_PATH {
+            return error_response(StatusCode::NOT_FOUND, "Not found").into();
+        }
+        if request.method() != Method::POST {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "Only POST is supported").into();
+        }
+
+        let meta = meta(&request);
+        if meta.role().is_none() {
+            return error_response(StatusCode::UNAUTHORIZED, "Unauthorized").into();
+        }
+        if !limiter.take(meta.key_name()) {
+            return error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests").into();
+        }
+
+        let schema = schema.clone();
+        let response = async move {
+            let body = hyper::body::to_bytes(request.into_body()).await?;
+            if body.len() > max_body_bytes {
+                return Ok(error_response(StatusCode::PAYLOAD_TOO_LARGE, "Query too large").into());
+            }
+
+            let query = match serde_json::from_slice::<async_graphql::Request>(&body) {
+                Ok(query) => query,
+                Err(e) => {
+                    let message = format!("Invalid GraphQL request, {}", e);
+                    return Ok(error_response(StatusCode::BAD_REQUEST, &message).into());
+                }
+            };
+
+            let response = schema.execute(query).await;
+            for e in &response.errors {
+                error!("GraphQL query failed {}", e.message);
+            }
+            let content = serde_json::to_string(&response).expect("JSON serde failed");
+            Ok(Response::ok(content).into())
+        };
+
+        RequestMiddlewareAction::Respond {
+            should_validate_hosts: true,
+            response: Box::pin(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_schema() {
+        let sdl = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .finish()
+            .sdl();
+
+        // credentials are never exposed
+        assert!(sdl.contains("type Node {"));
+        assert!(!sdl.contains("rpcPass"));
+        assert!(!sdl.contains("rpcUser"));
+        assert!(sdl.contains("staleCandidates(source: String, limit: Int): [StaleCandidate!]!"));
+        assert!(sdl.contains("chaintips(activeOnly: Boolean! = false): [Chaintip!]!"));
+
+        assert_eq!(limit(None), LIST_LIMIT);
+        assert_eq!(limit(Some(1000)), LIST_LIMIT);
+        assert_eq!(limit(Some(0)), 1);
+    }
+}
@@ -0,0 +1,353 @@
+//! gRPC streams of scanner events, for services that would rather use a generated
+//! client than the websocket subscriptions, e.g. from Go. The service is generated from
+//! `proto/forkscanner.proto` and served on its own port when `grpc_port` is set, with
+//! the same api keys and rate limits as RPC.
+use crate::auth::{authorize, bearer};
+use crate::limits::RateLimiter;
+use crate::{Chaintip, ConflictingBlock, MempoolPayment, ScannerMessage, Transaction};
+use chrono::{DateTime, Utc};
+use diesel::prelude::PgConnection;
+use log::{error, info, warn};
+use proto::fork_events_server::{ForkEvents, ForkEventsServer};
+use r2d2_diesel::ConnectionManager;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::thread;
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, TcpListenerStream},
+    Stream, StreamExt,
+};
+use tonic::{transport::Server, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("forkscanner.v1");
+}
+
+const SATOSHI_TO_BTC: f64 = 100_000_000.0;
+// events a client can fall behind by before it misses some
+const CHANNEL_CAPACITY: usize = 1024;
+
+type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+type EventStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+fn timestamp(time: DateTime<Utc>) -> Option<prost_types::Timestamp> {
+    Some(prost_types::Timestamp {
+        seconds: time.timestamp(),
+        nanos: time.timestamp_subsec_nanos() as i32,
+    })
+}
+
+fn sats(btc: f64) -> i64 {
+    (btc * SATOSHI_TO_BTC).round() as i64
+}
+
+impl From<&Chaintip> for proto::Chaintip {
+    fn from(tip: &Chaintip) -> proto::Chaintip {
+        proto::Chaintip {
+            id: tip.id,
+            node_id: tip.node,
+            status: tip.status.clone(),
+            block_hash: tip.block.clone(),
+            height: tip.height,
+            updated_at: timestamp(tip.updated_at),
+        }
+    }
+}
+
+impl From<&ConflictingBlock> for proto::InvalidBlock {
+    fn from(block: &ConflictingBlock) -> proto::InvalidBlock {
+        proto::InvalidBlock {
+            hash: block.hash.clone(),
+            valid_by: block.valid_by.clone(),
+            invalid_by: block.invalid_by.clone(),
+            created_at: timestamp(block.created_at),
+            updated_at: timestamp(block.updated_at),
+            changed: block.changed,
+        }
+    }
+}
+
+impl From<&Transaction> for proto::WatchedActivity {
+    fn from(tx: &Transaction) -> proto::WatchedActivity {
+        let transaction = proto::WatchedTransaction {
+            txid: tx.txid.clone(),
+            block_hash: tx.block_id.clone(),
+            is_coinbase: tx.is_coinbase,
+            amount_sats: sats(tx.amount),
+            hex: tx.hex.clone(),
+        };
+        proto::WatchedActivity {
+            activity: Some(proto::watched_activity::Activity::Transaction(transaction)),
+        }
+    }
+}
+
+impl From<&MempoolPayment> for proto::WatchedActivity {
+    fn from(payment: &MempoolPayment) -> proto::WatchedActivity {
+        let payment = proto::MempoolPayment {
+            txid: payment.txid.clone(),
+            vout: payment.vout,
+            address: payment.address.clone(),
+            amount_sats: sats(payment.amount),
+            nodes: payment.nodes.clone(),
+            seen_at: timestamp(payment.seen_at),
+        };
+        proto::WatchedActivity {
+            activity: Some(proto::watched_activity::Activity::MempoolPayment(payment)),
+        }
+    }
+}
+
+/// The channels the streams are fed from, the notification thread hands every
+/// scanner message to `handle`.
+#[derive(Clone)]
+pub struct GrpcEvents {
+    chaintips: broadcast::Sender<Vec<proto::Chaintip>>,
+    invalid_blocks: broadcast::Sender<proto::InvalidBlock>,
+    watched: broadcast::Sender<proto::WatchedActivity>,
+}
+
+impl GrpcEvents {
+    fn new() -> GrpcEvents {
+        GrpcEvents {
+            chaintips: broadcast::channel(CHANNEL_CAPACITY).0,
+            invalid_blocks: broadcast::channel(CHANNEL_CAPACITY).0,
+            watched: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Pass a scanner message on to the open streams. Sending only fails when there
+    /// are none.
+    pub fn handle(&self, message: &ScannerMessage) {
+        match message {
+            ScannerMessage::AllChaintips(tips) => {
+                let _ = self.chaintips.send(tips.iter().map(Into::into).collect());
+            }
+            ScannerMessage::NewBlockConflicts(conflicts) => {
+                for conflict in conflicts {
+                    let _ = self.invalid_blocks.send(conflict.into());
+                }
+            }
+            ScannerMessage::WatchedAddress(txs) => {
+                for tx in txs {
+                    let _ = self.watched.send(tx.into());
+                }
+            }
+            ScannerMessage::WatchedAddressMempool(payments) => {
+                for payment in payments {
+                    let _ = self.watched.send(payment.into());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The events of a channel, without the ones the client fell behind on.
+fn forward<T: Clone + Send + 'static>(receiver: broadcast::Receiver<T>) -> impl Stream<Item = T> {
+    BroadcastStream::new(receiver).filter_map(|event| match event {
+        Ok(event) => Some(event),
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            warn!("gRPC client fell behind by {} events", missed);
+            None
+        }
+    })
+}
+
+struct EventService {
+    pool: ManagedPool,
+    limiter: RateLimiter,
+    events: GrpcEvents,
+}
+
+impl EventService {
+    // Why a call is rejected, none if it has a valid api key and is within the key's
+    // rate.
+    fn rejection<T>(&self, request: &Request<T>) -> Option<Status> {
+        let key = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| bearer(v.as_bytes()));
+
+        match tokio::task::block_in_place(|| authorize(&self.pool, key)) {
+            None => Some(Status::unauthenticated("Unauthorized")),
+            Some((_, key_name)) if !self.limiter.take(key_name.as_deref()) => {
+                Some(Status::resource_exhausted("Too many requests"))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ForkEvents for EventService {
+    type StreamChaintipsStream = EventStream<proto::ChaintipUpdate>;
+    type StreamInvalidBlocksStream = EventStream<proto::InvalidBlock>;
+    type StreamWatchedActivityStream = EventStream<proto::WatchedActivity>;
+
+    async fn stream_chaintips(
+        &self,
+        request: Request<proto::StreamChaintipsRequest>,
+    ) -> Result<Response<Self::StreamChaintipsStream>, Status> {
+        if let Some(status) = self.rejection(&request) {
+            return Err(status);
+        }
+        let filter = request.into_inner();
+
+        let stream = forward(self.events.chaintips.subscribe()).filter_map(move |tips| {
+            let tips: Vec<_> = tips
+                .into_iter()
+                .filter(|tip| !filter.active_only || tip.status == "active")
+                .filter(|tip| filter.node_id == 0 || tip.node_id == filter.node_id)
+                .collect();
+            if tips.is_empty() {
+                return None;
+            }
+            Some(Ok(proto::ChaintipUpdate { tips }))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn stream_invalid_blocks(
+        &self,
+        request: Request<proto::StreamInvalidBlocksRequest>,
+    ) -> Result<Response<Self::StreamInvalidBlocksStream>, Status> {
+        if let Some(status) = self.rejection(&request) {
+            return Err(status);
+        }
+        let stream = forward(self.events.invalid_blocks.subscribe()).map(Ok);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn stream_watched_activity(
+        &self,
+        request: Request<proto::StreamWatchedActivityRequest>,
+    ) -> Result<Response<Self::StreamWatchedActivityStream>, Status> {
+        if let Some(status) = self.rejection(&request) {
+            return Err(status);
+        }
+        let stream = forward(self.events.watched.subscribe()).map(Ok);
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// The gRPC server, running on its own thread until it is closed.
+pub struct GrpcServer {
+    events: GrpcEvents,
+    address: SocketAddr,
+    close: oneshot::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl GrpcServer {
+    pub fn start(pool: ManagedPool, limiter: RateLimiter, addr: SocketAddr) -> GrpcServer {
+        let listener = TcpListener::bind(addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .unwrap_or_else(|e| panic!("Failed to start gRPC server {:?}", e));
+        let address = listener.local_addr().expect("Listener has no address");
+
+        let events = GrpcEvents::new();
+        let service = EventService {
+            pool,
+            limiter,
+            events: events.clone(),
+        };
+        let (close, closed) = oneshot::channel();
+
+        let thread = thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().expect("Starting tokio runtime failed");
+
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener)
+                    .expect("Failed to start gRPC listener");
+                let server = Server::builder()
+                    .add_service(ForkEventsServer::new(service))
+                    .serve_with_incoming(TcpListenerStream::new(listener));
+
+                // open streams never finish, so the server is dropped rather than
+                // waiting for them
+                tokio::select! {
+                    result = server => {
+                        if let Err(e) = result {
+                            error!("gRPC server failed {:?}", e);
+                        }
+                    }
+                    _ = closed => {}
+                }
+            });
+            info!("gRPC service is exiting");
+        });
+
+        GrpcServer {
+            events,
+            address,
+            close,
+            thread,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn events(&self) -> GrpcEvents {
+        self.events.clone()
+    }
+
+    /// Stop the server, ending the open streams.
+    pub fn close(self) {
+        let _ = self.close.send(());
+        self.thread.join().expect("Thread join");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let events = GrpcEvents::new();
+        let mut tips = events.chaintips.subscribe();
+        let mut watched = events.watched.subscribe();
+        let now = Utc::now();
+
+        let tip = Chaintip {
+            id: 1,
+            node: 2,
+            status: "active".into(),
+            block: "00aa".into(),
+            height: 800_000,
+            parent_chaintip: None,
+            created_at: now,
+            updated_at: now,
+        };
+        events.handle(&ScannerMessage::AllChaintips(vec![tip]));
+        let payment = MempoolPayment {
+            txid: "11bb".into(),
+            vout: 1,
+            address: "bc1qexample".into(),
+            amount: 0.1234_5678,
+            nodes: vec![2],
+            seen_at: now,
+        };
+        events.handle(&ScannerMessage::WatchedAddressMempool(vec![payment]));
+        events.handle(&ScannerMessage::NewChaintip);
+
+        let update = tips.try_recv().expect("No chaintips");
+        assert_eq!(update.len(), 1);
+        assert_eq!(update[0].node_id, 2);
+        assert_eq!(update[0].block_hash, "00aa");
+        assert_eq!(update[0].updated_at, timestamp(now));
+        assert!(tips.try_recv().is_err());
+
+        match watched.try_recv().expect("No watched activity").activity {
+            Some(proto::watched_activity::Activity::MempoolPayment(payment)) => {
+                assert_eq!(payment.amount_sats, 12_345_678);
+                assert_eq!(payment.nodes, vec![2]);
+            }
+            other => panic!("Unexpected activity {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! How exact values are written to JSON. Decimals, e.g. satoshi amounts stored as
+//! numeric, and BTC amounts are strings by default, so clients that parse JSON
+//! numbers as doubles, like JavaScript, can't round them. `json_numbers = "number"`
+//! writes them as numbers instead.
+use bigdecimal::{BigDecimal, ToPrimitive};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject},
+};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Number, Value};
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static AS_NUMBERS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JsonNumbers {
+    #[default]
+    String,
+    Number,
+}
+
+impl FromStr for JsonNumbers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<JsonNumbers, String> {
+        match s {
+            "string" => Ok(JsonNumbers::String),
+            "number" => Ok(JsonNumbers::Number),
+            other => Err(format!("Expected string or number, got {}", other)),
+        }
+    }
+}
+
+/// Set how every response and notification writes decimals and amounts.
+pub fn set_json_numbers(mode: JsonNumbers) {
+    AS_NUMBERS.store(mode == JsonNumbers::Number, Ordering::SeqCst);
+}
+
+fn as_numbers() -> bool {
+    AS_NUMBERS.load(Ordering::SeqCst)
+}
+
+fn number(value: f64, fallback: String) -> Value {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .unwrap_or(Value::String(fallback))
+}
+
+/// A decimal value, e.g. an amount in satoshis.
+pub fn decimal_json(decimal: &BigDecimal) -> Value {
+    match decimal.to_f64() {
+        Some(value) if as_numbers() => number(value, decimal.to_string()),
+        _ => Value::String(decimal.to_string()),
+    }
+}
+
+/// An amount in BTC, rounded to satoshis.
+pub fn btc_json(amount: f64) -> Value {
+    let text = format!("{:.8}", amount);
+    if as_numbers() {
+        let rounded = text.parse().unwrap_or(amount);
+        number(rounded, text)
+    } else {
+        Value::String(text)
+    }
+}
+
+pub fn serde_bigdecimal<S>(decimal: &Option<BigDecimal>, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    decimal.as_ref().map(decimal_json).serialize(s)
+}
+
+pub fn serde_btc<S>(amount: &f64, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    btc_json(*amount).serialize(s)
+}
+
+fn number_schema(types: Vec<InstanceType>) -> Schema {
+    SchemaObject {
+        instance_type: Some(types.into()),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Schema of a field written with `serde_bigdecimal`, a string or a number depending
+/// on `json_numbers`.
+pub fn bigdecimal_schema(_: &mut SchemaGenerator) -> Schema {
+    number_schema(vec![
+        InstanceType::String,
+        InstanceType::Number,
+        InstanceType::Null,
+    ])
+}
+
+/// Schema of a field written with `serde_btc`.
+pub fn btc_schema(_: &mut SchemaGenerator) -> Schema {
+    number_schema(vec![InstanceType::String, InstanceType::Number])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_numbers() {
+        let sats = BigDecimal::from_str("2100000000000000").unwrap();
+
+        assert_eq!(btc_json(0.1 + 0.2), Value::String("0.30000000".into()));
+        assert_eq!(
+            decimal_json(&sats),
+            Value::String("2100000000000000".into())
+        );
+
+        set_json_numbers(JsonNumbers::Number);
+        assert_eq!(btc_json(0.1 + 0.2), serde_json::json!(0.3));
+        assert_eq!(decimal_json(&sats), serde_json::json!(2100000000000000.0));
+        set_json_numbers(JsonNumbers::String);
+
+        assert_eq!("number".parse(), Ok(JsonNumbers::Number));
+        assert!("float".parse::<JsonNumbers>().is_err());
+    }
+}
@@ -1,12 +1,71 @@
+// diesel 1.4's derives put their impls inside a const block
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate diesel;
 
+mod acks;
+mod alerts;
+#[cfg(feature = "async")]
+mod async_scanner;
+mod attestation;
+mod auth;
+mod config;
+mod credentials;
+mod data_sources;
+mod describe;
+mod descriptors;
+mod detectors;
+mod event_bus;
+mod feed;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod json_numbers;
+mod limits;
 mod models;
+mod network;
+mod peer_rank;
+mod rest;
+mod retention;
+mod rpc_client;
 mod scanner;
 mod schema;
 mod service;
+mod shutdown;
+mod signing;
+mod simulate;
+mod snapshot;
+mod stale_import;
+mod tls;
+#[cfg(feature = "zmq")]
+mod zmq;
 
+pub use alerts::{AlertError, Alerter, Severity, SinkConfig};
+#[cfg(feature = "async")]
+pub use async_scanner::{AsyncBtcClient, AsyncForkScanner, BlockingClient};
+pub use auth::{create_api_key, set_auth_required, Role};
+pub use config::{Config, ConfigError, NodeConfig};
+pub use credentials::{is_encrypted, is_reference, CredentialError, CredentialKey};
+pub use data_sources::{DataSource, DataSourceError, Feature, NodeRole};
+pub use detectors::{Anomaly, AnomalyDetector, PoolStreak};
+pub use event_bus::{BusConfig, BusError, Event, EventBus};
+pub use json_numbers::{set_json_numbers, JsonNumbers};
+pub use limits::RequestLimits;
 pub use models::*;
-pub(crate) use scanner::MinerPoolInfo;
+pub use network::{
+    check_node, known_network, network, set_network, Network, NetworkError, NetworkParams,
+};
+pub use retention::{PruneReport, RetentionPolicy};
+pub use rpc_client::{node_rpc_client, rpc_client, RpcTimeouts};
 pub use scanner::{ForkScanner, ScannerCommand, ScannerMessage};
-pub use service::run_server;
+pub(crate) use scanner::{MinerPool, MinerPoolInfo};
+pub use service::{run_server, Listeners};
+pub use shutdown::{handle_signals, Shutdown};
+pub use simulate::{node_client, simulate_fork, DoubleSpend, SimulateError, SimulatedFork};
+pub use snapshot::{restore_file, snapshot_file, SnapshotError};
+pub use stale_import::{parse_stale_blocks, ImportError};
+pub use tls::{TlsConfig, TlsError};
+#[cfg(feature = "zmq")]
+pub use zmq::{listen_zmq, ZmqListeners};
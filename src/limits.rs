@@ -0,0 +1,238 @@
+//! Request limits of the RPC and websocket servers. Calls are counted per api key. Calls
+//! without a key all share one budget, the servers can't tell their clients apart: the
+//! jsonrpc servers don't see client addresses, and behind the TLS forwarder every client
+//! is the loopback address anyway. Set `auth_required` to give every client its own. Each
+//! call of a batch counts, every websocket call counts, and so does opening a websocket.
+use crate::auth::Caller;
+use jsonrpc_core::{
+    futures::future::Either,
+    middleware::{Middleware, NoopFuture},
+    Call, Error, ErrorCode, Failure, Output,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const RATE_LIMITED: i64 = -32005;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestLimits {
+    /// Calls per second of each api key, 0 for no limit, `FORKSCANNER_REQUESTS_PER_SECOND`.
+    pub requests_per_second: u32,
+    /// Calls a client can make at once on top of the rate after being idle.
+    pub burst: u32,
+    /// Calls of each api key running at the same time, 0 for no limit.
+    pub max_concurrent: usize,
+    /// Largest HTTP request body and websocket message, `FORKSCANNER_MAX_BODY_BYTES`.
+    pub max_body_bytes: usize,
+    /// Open websocket connections, of all clients.
+    pub max_ws_connections: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> RequestLimits {
+        RequestLimits {
+            requests_per_second: 20,
+            burst: 40,
+            max_concurrent: 8,
+            // room for a hex encoded 4MB block in submit_block
+            max_body_bytes: 10 * 1024 * 1024,
+            max_ws_connections: 100,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    running: usize,
+}
+
+impl Bucket {
+    // a full bucket
+    fn new(limits: &RequestLimits, now: Instant) -> Bucket {
+        Bucket {
+            tokens: (limits.requests_per_second + limits.burst) as f64,
+            updated: now,
+            running: 0,
+        }
+    }
+}
+
+/// Token buckets and running calls of each client.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    limits: RequestLimits,
+    // `None` is every caller without a key
+    buckets: Arc<Mutex<HashMap<Option<String>, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RequestLimits) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn take_at(&self, client: Option<&str>, now: Instant) -> bool {
+        let rate = self.limits.requests_per_second as f64;
+        if rate == 0.0 {
+            return true;
+        }
+
+        let capacity = rate + self.limits.burst as f64;
+        let mut buckets = self.buckets.lock().expect("Lock poisoned");
+        let bucket = buckets
+            .entry(client.map(str::to_string))
+            .or_insert_with(|| Bucket::new(&self.limits, now));
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Take one call from the budget of `client`, the key name, false if it has none
+    /// left. Calls without a key share one budget.
+    pub fn take(&self, client: Option<&str>) -> bool {
+        self.take_at(client, Instant::now())
+    }
+
+    // Count a call of `client` as running until the guard is dropped, none if it
+    // already has as many running as it may.
+    fn start(&self, client: Option<&str>) -> Option<Running> {
+        let client = client.map(str::to_string);
+        let mut buckets = self.buckets.lock().expect("Lock poisoned");
+        let bucket = buckets
+            .entry(client.clone())
+            .or_insert_with(|| Bucket::new(&self.limits, Instant::now()));
+
+        let max = self.limits.max_concurrent;
+        if max > 0 && bucket.running >= max {
+            return None;
+        }
+        bucket.running += 1;
+
+        Some(Running {
+            limiter: self.clone(),
+            client,
+        })
+    }
+}
+
+struct Running {
+    limiter: RateLimiter,
+    client: Option<String>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut buckets = self.limiter.buckets.lock().expect("Lock poisoned");
+        if let Some(bucket) = buckets.get_mut(&self.client) {
+            bucket.running = bucket.running.saturating_sub(1);
+        }
+    }
+}
+
+/// Rejects calls of clients over their rate or running too many calls already.
+impl<M: Caller> Middleware<M> for RateLimiter {
+    type Future = NoopFuture;
+    type CallFuture = Pin<Box<dyn Future<Output = Option<Output>> + Send>>;
+
+    fn on_call<F, X>(&self, call: Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, M) -> X + Send + Sync,
+        X: Future<Output = Option<Output>> + Send + 'static,
+    {
+        let (id, jsonrpc) = match &call {
+            Call::MethodCall(c) => (Some(c.id.clone()), c.jsonrpc),
+            Call::Notification(n) => (None, n.jsonrpc),
+            Call::Invalid { .. } => return Either::Right(next(call, meta)),
+        };
+
+        let client = meta.key_name().map(str::to_string);
+        let message = if !self.take(client.as_deref()) {
+            format!(
+                "Too many requests, the limit is {} per second",
+                self.limits.requests_per_second
+            )
+        } else if let Some(running) = self.start(client.as_deref()) {
+            let result = next(call, meta);
+            return Either::Left(Box::pin(async move {
+                let output = result.await;
+                drop(running);
+                output
+            }));
+        } else {
+            format!(
+                "Too many requests, the limit is {} at once",
+                self.limits.max_concurrent
+            )
+        };
+
+        let output = id.map(|id| {
+            Output::Failure(Failure {
+                jsonrpc,
+                error: Error {
+                    code: ErrorCode::ServerError(RATE_LIMITED),
+                    message,
+                    data: None,
+                },
+                id,
+            })
+        });
+        Either::Left(Box::pin(async move { output }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(RequestLimits {
+            requests_per_second: 2,
+            burst: 1,
+            max_concurrent: 1,
+            ..RequestLimits::default()
+        });
+        let now = Instant::now();
+
+        // the rate and the burst, then nothing until tokens come back
+        assert!((0..3).all(|_| limiter.take_at(Some("ops"), now)));
+        assert!(!limiter.take_at(Some("ops"), now));
+        // callers without a key share a budget of their own
+        assert!((0..3).all(|_| limiter.take_at(None, now)));
+        assert!(!limiter.take_at(None, now));
+        assert!(limiter.take_at(Some("ops"), now + Duration::from_millis(500)));
+        assert!(!limiter.take_at(Some("ops"), now + Duration::from_millis(500)));
+
+        let running = limiter.start(Some("ops"));
+        assert!(running.is_some());
+        assert!(limiter.start(Some("ops")).is_none());
+        assert!(limiter.start(Some("dashboard")).is_some());
+        let anonymous = limiter.start(None);
+        assert!(anonymous.is_some());
+        assert!(limiter.start(None).is_none());
+        drop(running);
+        assert!(limiter.start(Some("ops")).is_some());
+
+        let unlimited = RateLimiter::new(RequestLimits {
+            requests_per_second: 0,
+            ..RequestLimits::default()
+        });
+        assert!((0..1000).all(|_| unlimited.take_at(Some("ops"), now)));
+    }
+}
@@ -1,55 +1,490 @@
 use bitcoincore_rpc::Client;
 use diesel::prelude::PgConnection;
 use diesel::Connection;
-use forkscanner::run_server;
+#[cfg(feature = "zmq")]
+use forkscanner::listen_zmq;
+#[cfg(feature = "async")]
+use forkscanner::AsyncForkScanner;
 use forkscanner::ForkScanner;
-use log::info;
+use forkscanner::{
+    create_api_key, handle_signals, is_encrypted, is_reference, node_client, parse_stale_blocks,
+    restore_file, set_auth_required, set_json_numbers, set_network, simulate_fork, snapshot_file,
+    Alerter, ApiKey, Config, CredentialKey, DataSource, EventBus, ImportedStaleBlock, JsonNumbers,
+    Network, Node, PoolStreak, Role, ScannerCommand, Shutdown,
+};
+use forkscanner::{run_server, Listeners};
+use log::{info, warn};
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "forkscanner", about = "A Bitcoin fork monitor.")]
 struct Opt {
-    /// Set rpc port
-    #[structopt(short = "r", long = "rpc", default_value = "8339")]
-    rpc: u16,
+    /// TOML config file, environment variables and flags override its settings
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
 
-    /// Set ws port
-    #[structopt(short = "w", long = "ws", default_value = "8340")]
-    ws: u16,
+    /// Address the rpc and ws servers listen on [default: 0.0.0.0]
+    #[structopt(long = "listen")]
+    listen: Option<String>,
+
+    /// Set rpc port [default: 8339]
+    #[structopt(short = "r", long = "rpc")]
+    rpc: Option<u16>,
+
+    /// Set ws port [default: 8340]
+    #[structopt(short = "w", long = "ws")]
+    ws: Option<u16>,
+
+    /// Serve the read only REST API on this port, off by default
+    #[structopt(long = "rest")]
+    rest: Option<u16>,
+
+    /// Serve the GraphQL endpoint on this port, needs the graphql feature
+    #[structopt(long = "graphql")]
+    graphql: Option<u16>,
+
+    /// Serve the gRPC event streams on this port, needs the grpc feature
+    #[structopt(long = "grpc")]
+    grpc: Option<u16>,
+
+    /// Seconds between scanner runs [default: 10]
+    #[structopt(long = "scan-interval")]
+    scan_interval: Option<u64>,
+
+    /// Blocks of history to fetch from the archive node on the first run [default: 100]
+    #[structopt(long = "bootstrap-depth")]
+    bootstrap_depth: Option<i64>,
+
+    /// PEM certificate chain, serves the rpc and ws ports over TLS together with --tls-key
+    #[structopt(long = "tls-cert", parse(from_os_str))]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate
+    #[structopt(long = "tls-key", parse(from_os_str))]
+    tls_key: Option<PathBuf>,
+
+    /// Write amounts and decimals as JSON `string`s or `number`s [default: string]
+    #[structopt(long = "json-numbers")]
+    json_numbers: Option<JsonNumbers>,
+
+    /// Chain the nodes are on, `main`, `test`, `testnet4`, `signet` or `regtest`, nodes of
+    /// other chains can't be added [default: the archive node's]
+    #[structopt(long = "network")]
+    network: Option<Network>,
 
     /// Enable address watcher
     #[structopt(short = "a", long = "watch-addresses")]
     watch_addresses: bool,
+
+    /// Look for payments to watched addresses in node mempools, before they are mined
+    #[structopt(long = "watch-mempool")]
+    watch_mempool: bool,
+
+    /// Send mempool transactions paying watched addresses to the nodes that don't have
+    /// them, needs --watch-mempool
+    #[structopt(long = "relay-watched")]
+    relay_watched: bool,
+
+    /// Submit block bodies to monitored nodes that only have the header
+    #[structopt(short = "s", long = "share-blocks")]
+    share_blocks: bool,
+
+    /// Keep the compressed bodies of competing blocks in the stale window, for
+    /// get_raw_block once nodes no longer have them
+    #[structopt(long = "archive-raw-blocks")]
+    archive_raw_blocks: bool,
+
+    /// Max number of tips to validate on mirror nodes per cycle
+    #[structopt(long = "rollback-budget", default_value = "3")]
+    rollback_budget: usize,
+
+    /// Seconds to wait before validating the same tip again
+    #[structopt(long = "rollback-cooldown", default_value = "600")]
+    rollback_cooldown: i64,
+
+    /// How many blocks below the tip to run rollback checks
+    #[structopt(long = "rollback-depth", default_value = "10")]
+    rollback_depth: i64,
+
+    /// How many blocks below the tip to run inflation checks
+    #[structopt(long = "inflation-depth", default_value = "10")]
+    inflation_depth: i64,
+
+    /// How many blocks below the tip to fetch headers-only blocks
+    #[structopt(long = "missing-block-depth", default_value = "40000")]
+    missing_block_depth: i64,
+
+    /// How many blocks below the tip to ask nodes other than the archive node for
+    /// headers-only blocks
+    #[structopt(long = "missing-block-node-depth", default_value = "10")]
+    missing_block_node_depth: i64,
+
+    /// Valid-fork branch length at which a fork is reported as persistent
+    #[structopt(long = "persistent-fork-length", default_value = "3")]
+    persistent_fork_length: i64,
+
+    /// Only report nodes as lagging once their chainwork is this many blocks behind,
+    /// instead of two blocks or any work behind
+    #[structopt(long = "lag-work-threshold")]
+    lag_work_threshold: Option<f64>,
+
+    /// Block templates to keep per node for get_block_templates, by default only the
+    /// ones from the current run are kept
+    #[structopt(long = "template-history", default_value = "0")]
+    template_history: i64,
+
+    /// Alert when no node's tip moved for this many minutes, 0 disables the alert
+    #[structopt(long = "stuck-chain-minutes", default_value = "60")]
+    stuck_chain_minutes: i64,
+
+    /// Blocks within which a pool's blocks leaving out the same high fee transaction
+    /// count towards a censorship alert, 0 disables the check
+    #[structopt(long = "censorship-window", default_value = "144")]
+    censorship_window: i64,
+
+    /// Times a pool's blocks must leave out a transaction within the window to alert
+    #[structopt(long = "censorship-omissions", default_value = "3")]
+    censorship_omissions: i64,
+
+    /// Alert when a reorg disconnects at least this many blocks, 0 disables the alert
+    #[structopt(long = "deep-reorg-depth", default_value = "3")]
+    deep_reorg_depth: i64,
+
+    /// Minutes a block conflict is notified for after a node found the block valid or
+    /// invalid, 0 disables the notifications
+    #[structopt(long = "conflict-window-minutes", default_value = "15")]
+    conflict_window_minutes: i64,
+
+    /// Report a pool mining this many blocks in a row as an anomaly, 0 disables the
+    /// detector
+    #[structopt(long = "pool-streak", default_value = "0")]
+    pool_streak: usize,
+
+    /// Nodes a feature sends RPC calls to, as FEATURE=NODES, e.g. templates=1,2 or
+    /// txindex=archive. Can be given more than once
+    #[structopt(long = "data-source", number_of_values = 1)]
+    data_sources: Vec<DataSource>,
+
+    /// Seconds to wait on a node's RPC calls before skipping it for a cycle
+    #[cfg(feature = "async")]
+    #[structopt(long = "rpc-timeout", default_value = "30")]
+    rpc_timeout: u64,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Import a CSV dataset of stale blocks, e.g. ones that predate this deployment
+    ImportStaleBlocks {
+        /// Name recorded as the source of the imported blocks
+        #[structopt(long = "source", default_value = "import")]
+        source: String,
+
+        /// CSV file with height and hash columns, and optionally parent_hash or header
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Encrypt node passwords still stored as plain text with FORKSCANNER_CREDENTIALS_KEY
+    EncryptCredentials,
+    /// Create an api key for the RPC and ws servers, the key is only shown once
+    CreateApiKey {
+        /// Role of the key: observer, operator or admin
+        #[structopt(long = "role", alias = "permission", default_value = "observer")]
+        role: Role,
+
+        /// Name to refer to the key by
+        name: String,
+    },
+    /// Revoke an api key by name
+    RevokeApiKey { name: String },
+    /// Write the nodes, blocks, chaintips, stale candidates and watchlists to a file,
+    /// gzipped if it ends in .gz
+    Snapshot {
+        /// Leave the nodes' RPC user and password out of the snapshot
+        #[structopt(long = "without-credentials")]
+        without_credentials: bool,
+
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Load a snapshot into an empty database
+    Restore {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Make two connected regtest nodes fork, with double spends, for rehearsing reorgs
+    SimulateFork {
+        /// Blocks on the stale branch, the winning branch is one longer
+        #[structopt(long = "depth", default_value = "2")]
+        depth: usize,
+
+        /// Outputs spent differently on each branch
+        #[structopt(long = "double-spends", default_value = "1")]
+        double_spends: usize,
+
+        /// Id of the node that mines the stale branch and then reorgs
+        stale_node: i64,
+
+        /// Id of the node that mines the winning branch
+        winning_node: i64,
+    },
+}
+
+fn print_rows(action: &str, rows: &[(&str, usize)]) {
+    for (table, count) in rows {
+        println!("{:>26} {}", table, count);
+    }
+    let total: usize = rows.iter().map(|(_, count)| count).sum();
+    println!("{} {} rows", action, total);
 }
 
 fn main() {
-    env_logger::init();
     dotenv::dotenv().expect("Failed loading dotenv");
-    let db_url = std::env::var("DATABASE_URL").expect("No DB url");
-    let db_conn = PgConnection::establish(&db_url).expect("Connection failed");
     let opt = Opt::from_args();
+    let mut config = Config::load(opt.config.as_deref()).expect("Loading config failed");
+    if let Some(listen) = opt.listen {
+        config.listen = listen;
+    }
+    if let Some(rpc) = opt.rpc {
+        config.rpc_port = rpc;
+    }
+    if let Some(ws) = opt.ws {
+        config.ws_port = ws;
+    }
+    if opt.rest.is_some() {
+        config.rest_port = opt.rest;
+    }
+    if opt.graphql.is_some() {
+        config.graphql_port = opt.graphql;
+    }
+    if opt.grpc.is_some() {
+        config.grpc_port = opt.grpc;
+    }
+    if let Some(secs) = opt.scan_interval {
+        config.scan_interval = secs;
+    }
+    if let Some(depth) = opt.bootstrap_depth {
+        config.bootstrap_depth = depth;
+    }
+    if opt.tls_cert.is_some() {
+        config.tls_cert = opt.tls_cert;
+    }
+    if opt.tls_key.is_some() {
+        config.tls_key = opt.tls_key;
+    }
+    if let Some(mode) = opt.json_numbers {
+        config.json_numbers = mode;
+    }
+    if opt.network.is_some() {
+        config.network = opt.network;
+    }
+    // the last source given for a feature wins, so flags override the file per feature
+    config.data_sources.extend(opt.data_sources);
+    set_json_numbers(config.json_numbers);
+    if let Some(network) = config.network {
+        set_network(network);
+    }
+    let tls = config.tls().expect("Bad TLS settings");
+    let alerter = Alerter::start(&config.alert_sinks).expect("Bad alert sinks");
+    let event_bus = EventBus::start(config.event_bus.as_ref()).expect("Bad event bus");
+
+    env_logger::Builder::new()
+        .parse_filters(config.log.as_deref().unwrap_or_default())
+        .init();
+
+    let db_url = config.database_url.clone().expect("No DB url");
+    let db_conn = PgConnection::establish(&db_url).expect("Connection failed");
+
+    match opt.command {
+        Some(Command::ImportStaleBlocks { source, file }) => {
+            let data = std::fs::read_to_string(&file).expect("Could not read dataset");
+            let blocks = parse_stale_blocks(&source, &data).expect("Could not parse dataset");
+            let total = blocks.len();
+            let imported = ImportedStaleBlock::import(&db_conn, blocks).expect("Import failed");
+            println!(
+                "Imported {} of {} stale blocks from {:?}",
+                imported, total, file
+            );
+            return;
+        }
+        Some(Command::EncryptCredentials) => {
+            let key = CredentialKey::from_env()
+                .expect("Bad credentials key")
+                .expect("FORKSCANNER_CREDENTIALS_KEY is not set");
+            let nodes = Node::list(&db_conn).expect("Could not list nodes");
+
+            let mut encrypted = 0;
+            for node in nodes
+                .iter()
+                .filter(|n| !is_encrypted(&n.rpc_pass) && !is_reference(&n.rpc_pass))
+            {
+                Node::set_password(&db_conn, node.id, key.encrypt(&node.rpc_pass))
+                    .expect("Storing password failed");
+                encrypted += 1;
+            }
+            println!("Encrypted passwords of {} nodes", encrypted);
+            return;
+        }
+        Some(Command::CreateApiKey { role, name }) => {
+            let key = create_api_key(&db_conn, &name, role).expect("Creating key failed");
+            println!("Created {} key {}: {}", role, name, key);
+            return;
+        }
+        Some(Command::RevokeApiKey { name }) => {
+            match ApiKey::revoke(&db_conn, &name).expect("Revoking key failed") {
+                0 => println!("No active key {}", name),
+                _ => println!("Revoked key {}", name),
+            }
+            return;
+        }
+        Some(Command::Snapshot {
+            without_credentials,
+            file,
+        }) => {
+            let rows =
+                snapshot_file(&db_conn, &file, !without_credentials).expect("Snapshot failed");
+            print_rows(&format!("Wrote {:?} with", file), &rows);
+            return;
+        }
+        Some(Command::Restore { file }) => {
+            let rows = restore_file(&db_conn, &file).expect("Restore failed");
+            print_rows(&format!("Restored {:?} with", file), &rows);
+            return;
+        }
+        Some(Command::SimulateFork {
+            depth,
+            double_spends,
+            stale_node,
+            winning_node,
+        }) => {
+            let client = |id| {
+                let node = Node::get(&db_conn, id).expect("Could not find node");
+                node_client(&node).expect("Could not connect to node")
+            };
+            let (stale, winner) = (client(stale_node), client(winning_node));
+            let fork = simulate_fork(&stale, &winner, depth, double_spends)
+                .expect("Simulating fork failed");
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&fork).expect("Serialize failed")
+            );
+            return;
+        }
+        None => {}
+    }
+
+    set_auth_required(config.auth_required);
+    if !config.auth_required && ApiKey::any_active(&db_conn).unwrap_or(false) {
+        warn!("Api keys exist but auth_required is off, requests without a key are observers");
+    }
+
+    let added = config
+        .add_nodes(&db_conn)
+        .expect("Adding configured nodes failed");
+    if added > 0 {
+        info!("Added {} nodes from the config file", added);
+    }
+
+    #[cfg(feature = "zmq")]
+    let nodes = Node::list(&db_conn).expect("Could not list nodes");
 
     let (mut scanner, receiver, command) =
-        ForkScanner::<Client>::new(db_conn).expect("Launching forkscanner failed");
-	scanner.enable_address_watcher(opt.watch_addresses);
-    let duration = std::time::Duration::from_millis(10_000);
-
-    let _handle = std::thread::spawn(move || loop {
-        scanner.run();
-        info!("Run finished, sleeping");
-        std::thread::sleep(duration);
+        ForkScanner::<Client>::new(&db_url).expect("Launching forkscanner failed");
+
+    #[cfg(feature = "zmq")]
+    {
+        let listeners = listen_zmq(nodes, command.clone());
+        scanner.on_reload(Box::new(move |nodes| listeners.reload(nodes.to_vec())));
+    }
+    scanner.enable_address_watcher(opt.watch_addresses || config.watch_addresses);
+    scanner.enable_mempool_watcher(opt.watch_mempool || config.watch_mempool);
+    scanner.enable_watched_relay(opt.relay_watched || config.relay_watched);
+    scanner.enable_block_sharing(opt.share_blocks);
+    scanner.enable_raw_block_archive(opt.archive_raw_blocks);
+    scanner.set_rollback_budget(opt.rollback_budget, opt.rollback_cooldown);
+    scanner.set_check_depths(
+        opt.rollback_depth,
+        opt.inflation_depth,
+        opt.missing_block_depth,
+        opt.missing_block_node_depth,
+    );
+    scanner.set_bootstrap_depth(config.bootstrap_depth);
+    scanner.set_persistent_fork_length(opt.persistent_fork_length);
+    scanner.set_lag_work_threshold(opt.lag_work_threshold);
+    scanner.set_template_history(opt.template_history);
+    scanner.set_retention(config.retention);
+    scanner.set_stuck_chain_minutes(opt.stuck_chain_minutes);
+    scanner.set_censorship_window(opt.censorship_window, opt.censorship_omissions);
+    scanner.set_deep_reorg_depth(opt.deep_reorg_depth);
+    scanner.set_conflict_window(opt.conflict_window_minutes);
+    scanner.set_data_sources(config.data_sources);
+    if opt.pool_streak > 0 {
+        scanner.add_detector(Box::new(PoolStreak::new(opt.pool_streak)));
+    }
+    let duration = std::time::Duration::from_secs(config.scan_interval);
+
+    let shutdown = Shutdown::new();
+    scanner.set_shutdown(shutdown.clone());
+    let wake = command.clone();
+    let _signals = handle_signals(shutdown.clone(), move || {
+        let _ = wake.send(ScannerCommand::Shutdown);
     });
 
+    #[cfg(not(feature = "async"))]
+    let handle = std::thread::spawn(move || {
+        scanner.bootstrap();
+        while !shutdown.is_triggered() {
+            scanner.run();
+            info!("Run finished, sleeping");
+            scanner.wait(duration);
+        }
+        info!("Scanner stopped, restoring mirrors");
+        scanner.restore_mirrors();
+    });
+
+    #[cfg(feature = "async")]
+    let handle = {
+        let mut scanner = AsyncForkScanner::<Client>::new(scanner);
+        scanner.set_rpc_timeout(opt.rpc_timeout);
+
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().expect("Starting tokio runtime failed");
+            scanner.bootstrap();
+            while !shutdown.is_triggered() {
+                runtime.block_on(scanner.run());
+                info!("Run finished, sleeping");
+                scanner.wait(duration);
+            }
+            info!("Scanner stopped, restoring mirrors");
+            scanner.restore_mirrors();
+        })
+    };
+
     info!(
-        "Starting RPC server on 127.0.0.1 rpc-port {} subscribe-port {}",
-        opt.rpc, opt.ws
+        "Starting RPC server on {} rpc-port {} subscribe-port {}",
+        config.listen, config.rpc_port, config.ws_port
     );
+    let listeners = Listeners {
+        listen: config.listen,
+        rpc_port: config.rpc_port,
+        ws_port: config.ws_port,
+        rest_port: config.rest_port,
+        graphql_port: config.graphql_port,
+        grpc_port: config.grpc_port,
+        tls,
+    };
     run_server(
-        "0.0.0.0".into(),
-        opt.rpc,
-        opt.ws,
-        db_url.into(),
+        listeners,
+        db_url,
         receiver,
         command,
+        config.limits,
+        alerter,
+        event_bus,
     );
+    handle.join().expect("Scanner thread panicked");
+    info!("Shut down");
 }
@@ -4,29 +4,39 @@ use chrono::prelude::*;
 use diesel::prelude::*;
 use diesel::result::QueryResult;
 use diesel::sql_types;
-use serde::{Deserialize, Serialize, Serializer};
-use std::collections::HashMap;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Write};
 
+use crate::json_numbers::{btc_schema, serde_bigdecimal, serde_btc};
 use crate::schema::{
-    block_templates, blocks, chaintips, double_spent_by, fee_rates, inflated_blocks,
-    invalid_blocks, lags, nodes, peers, pool, rbf_by, softforks, stale_candidate,
-    stale_candidate_children, transaction, transaction_addresses, tx_outsets, valid_blocks,
-    watched,
+    api_keys, block_assists, block_payouts, block_templates, block_transactions, blocks,
+    censorship_candidates, chaintips, conflict_addresses, double_spent_by, expected_payouts,
+    fee_rates, imported_stale_blocks, inflated_blocks, invalid_blocks, lags, node_status, nodes,
+    peer_reputation, peers, persistent_forks, pool, pool_addresses, raw_blocks, rbf_by, reorgs,
+    rollback_checks, rollback_schedule, softforks, stale_candidate, stale_candidate_children,
+    template_snapshots, template_txs, tip_history, tip_statuses, transaction,
+    transaction_addresses, transaction_inputs, tx_outsets, unconfirmed_spends,
+    unknown_coinbase_tags, valid_blocks, wallet_conflicts, watched, watched_descriptors,
+    watched_outpoints,
 };
-use crate::MinerPoolInfo;
+use crate::{MinerPool, MinerPoolInfo};
 
-pub fn serde_bigdecimal<S>(decimal: &Option<BigDecimal>, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    match *decimal {
-        Some(ref d) => s.serialize_some(&d.to_string()),
-        None => s.serialize_none(),
-    }
-}
+const MAX_FORK_ANALYSIS_DEPTH: usize = 1000;
+const STALE_SOURCE_LOCAL: &str = "local";
 
 #[derive(
-    Clone, Deserialize, Serialize, Debug, AsChangeset, QueryableByName, Queryable, Insertable,
+    Clone,
+    Deserialize,
+    Serialize,
+    JsonSchema,
+    Debug,
+    AsChangeset,
+    QueryableByName,
+    Queryable,
+    Insertable,
 )]
 #[table_name = "chaintips"]
 pub struct Chaintip {
@@ -36,6 +46,9 @@ pub struct Chaintip {
     pub block: String,
     pub height: i64,
     pub parent_chaintip: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    /// When the tip last moved to another block.
+    pub updated_at: DateTime<Utc>,
 }
 
 impl Chaintip {
@@ -108,12 +121,21 @@ impl Chaintip {
             .load(conn)
     }
 
-    /// Delete all chaintip entries that are not active.
-    pub fn purge(conn: &PgConnection) -> QueryResult<usize> {
+    /// Delete all chaintip entries that are not active, except the ones of `keep_nodes`.
+    pub fn purge(conn: &PgConnection, keep_nodes: &[i64]) -> QueryResult<usize> {
+        use crate::schema::chaintips::dsl::*;
+        use diesel::dsl::not;
+        diesel::delete(chaintips)
+            .filter(not(status.eq("active")).and(not(node.eq_any(keep_nodes))))
+            .execute(conn)
+    }
+
+    /// Delete the chaintip entries of a node that are not active.
+    pub fn purge_node(conn: &PgConnection, node_id: i64) -> QueryResult<usize> {
         use crate::schema::chaintips::dsl::*;
         use diesel::dsl::not;
         diesel::delete(chaintips)
-            .filter(not(status.eq("active")))
+            .filter(not(status.eq("active")).and(node.eq(node_id)))
             .execute(conn)
     }
 
@@ -178,6 +200,7 @@ impl Chaintip {
                             block.eq(hash),
                             height.eq(block_height),
                             parent_chaintip.eq::<Option<i64>>(None),
+                            updated_at.eq(Utc::now()),
                         ))
                         .execute(conn)
                 } else {
@@ -228,6 +251,305 @@ impl TransactionAddress {
             .on_conflict_do_nothing()
             .execute(conn)
     }
+
+    /// Transactions involving `addr` with their blocks, optionally between two
+    /// heights, ordered by height.
+    pub fn activity(
+        conn: &PgConnection,
+        addr: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> QueryResult<Vec<(TransactionAddress, Block)>> {
+        use crate::schema::blocks::dsl as bdsl;
+        use crate::schema::transaction_addresses::dsl::*;
+
+        let mut query = transaction_addresses
+            .inner_join(bdsl::blocks)
+            .filter(address.eq(addr))
+            .into_boxed();
+
+        if let Some(from) = from {
+            query = query.filter(bdsl::height.ge(from));
+        }
+        if let Some(to) = to {
+            query = query.filter(bdsl::height.le(to));
+        }
+
+        query.order((bdsl::height.asc(), txid.asc())).load(conn)
+    }
+
+    /// A page of the transactions involving `addr`, highest first, after the block
+    /// height, block hash and txid of the last one of the previous page.
+    pub fn history(
+        conn: &PgConnection,
+        addr: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        after: Option<(i64, String, String)>,
+        limit: i64,
+    ) -> QueryResult<Vec<(TransactionAddress, Block)>> {
+        use crate::schema::blocks::dsl as bdsl;
+        use crate::schema::transaction_addresses::dsl::*;
+
+        let mut query = transaction_addresses
+            .inner_join(bdsl::blocks)
+            .filter(address.eq(addr))
+            .into_boxed();
+
+        if let Some(from) = from {
+            query = query.filter(bdsl::height.ge(from));
+        }
+        if let Some(to) = to {
+            query = query.filter(bdsl::height.le(to));
+        }
+        if let Some((last_height, last_hash, last_txid)) = after {
+            query = query.filter(
+                bdsl::height
+                    .lt(last_height)
+                    .or(bdsl::height.eq(last_height).and(
+                        hash.lt(last_hash.clone())
+                            .or(hash.eq(last_hash).and(txid.lt(last_txid))),
+                    )),
+            );
+        }
+
+        query
+            .order((bdsl::height.desc(), hash.desc(), txid.desc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    /// The addresses a transaction involves.
+    pub fn of_tx(conn: &PgConnection, id: &str) -> QueryResult<Vec<String>> {
+        use crate::schema::transaction_addresses::dsl::*;
+
+        transaction_addresses
+            .filter(txid.eq(id))
+            .select(address)
+            .distinct()
+            .order_by(address)
+            .load(conn)
+    }
+}
+
+/// A transaction of a block at its position, coinbase first. Blocks keep their txids
+/// packed as well, these look blocks up by txid.
+#[derive(Clone, Debug, Serialize, Queryable, Insertable)]
+#[table_name = "block_transactions"]
+pub struct BlockTransaction {
+    pub block_hash: String,
+    pub txid: String,
+    pub position: i32,
+}
+
+impl BlockTransaction {
+    // Rows per insert, postgres allows 65535 bind parameters.
+    const INSERT_CHUNK: usize = 10_000;
+
+    pub fn insert(conn: &PgConnection, hash: &str, txids: &[String]) -> QueryResult<usize> {
+        use crate::schema::block_transactions::dsl::*;
+
+        let rows: Vec<_> = txids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| BlockTransaction {
+                block_hash: hash.to_string(),
+                txid: id.clone(),
+                position: i as i32,
+            })
+            .collect();
+
+        let mut inserted = 0;
+        for chunk in rows.chunks(Self::INSERT_CHUNK) {
+            inserted += diesel::insert_into(block_transactions)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+        Ok(inserted)
+    }
+
+    /// The blocks a transaction is in, on any branch, lowest first.
+    pub fn blocks_with(
+        conn: &PgConnection,
+        id: &str,
+    ) -> QueryResult<Vec<(BlockTransaction, Block)>> {
+        use crate::schema::block_transactions::dsl::*;
+        use crate::schema::blocks::dsl as bdsl;
+
+        block_transactions
+            .inner_join(bdsl::blocks)
+            .filter(txid.eq(id))
+            .order((bdsl::height.asc(), bdsl::hash.asc()))
+            .load(conn)
+    }
+}
+
+/// An input of a transaction in a block, the outpoint it spends.
+#[derive(Clone, Debug, Serialize, Queryable, Insertable)]
+#[table_name = "transaction_inputs"]
+pub struct TransactionInput {
+    pub block_id: String,
+    pub txid: String,
+    pub vin: i32,
+    pub prev_txid: String,
+    pub prev_vout: i64,
+}
+
+impl TransactionInput {
+    // Rows per insert, postgres allows 65535 bind parameters.
+    const INSERT_CHUNK: usize = 10_000;
+
+    pub fn insert(conn: &PgConnection, inputs: &[TransactionInput]) -> QueryResult<usize> {
+        use crate::schema::transaction_inputs::dsl::*;
+
+        let mut inserted = 0;
+        for chunk in inputs.chunks(Self::INSERT_CHUNK) {
+            inserted += diesel::insert_into(transaction_inputs)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+        Ok(inserted)
+    }
+
+    /// Inputs spending outputs of `id`, in any block, with the block they are in.
+    pub fn spends_of(conn: &PgConnection, id: &str) -> QueryResult<Vec<(TransactionInput, Block)>> {
+        Self::spends_of_any(conn, &[id.to_string()])
+    }
+
+    /// Inputs of other transactions spending an outpoint `id` spends, with the block they
+    /// are in.
+    pub fn conflicts_of(
+        conn: &PgConnection,
+        id: &str,
+    ) -> QueryResult<Vec<(TransactionInput, Block)>> {
+        use crate::schema::blocks::dsl as bdsl;
+        use crate::schema::transaction_inputs::dsl::*;
+
+        let spent: Vec<(String, i64)> = transaction_inputs
+            .filter(txid.eq(id))
+            .select((prev_txid, prev_vout))
+            .distinct()
+            .load(conn)?;
+
+        let mut conflicts = vec![];
+        for (prev, vout) in spent {
+            let spends: Vec<(TransactionInput, Block)> = transaction_inputs
+                .inner_join(bdsl::blocks)
+                .filter(prev_txid.eq(prev))
+                .filter(prev_vout.eq(vout))
+                .filter(txid.ne(id))
+                .order(bdsl::height.asc())
+                .load(conn)?;
+            conflicts.extend(spends);
+        }
+        Ok(conflicts)
+    }
+
+    /// Inputs spending outputs of any of `txids`, with the block they are in.
+    pub fn spends_of_any(
+        conn: &PgConnection,
+        txids: &[String],
+    ) -> QueryResult<Vec<(TransactionInput, Block)>> {
+        use crate::schema::blocks::dsl as bdsl;
+        use crate::schema::transaction_inputs::dsl::*;
+
+        transaction_inputs
+            .inner_join(bdsl::blocks)
+            .filter(prev_txid.eq_any(txids))
+            .order((prev_vout.asc(), bdsl::height.asc()))
+            .load(conn)
+    }
+}
+
+/// An input of a transaction in a node's latest block template, only kept for
+/// inputs spending from watched addresses.
+#[derive(Clone, Debug, Serialize, Queryable, Insertable)]
+#[table_name = "unconfirmed_spends"]
+pub struct UnconfirmedSpend {
+    pub node_id: i64,
+    pub txid: String,
+    pub vin: i32,
+    pub prev_txid: String,
+    pub prev_vout: i64,
+    pub seen_at: DateTime<Utc>,
+}
+
+impl UnconfirmedSpend {
+    /// Replace the spends seen in a node's previous template.
+    pub fn replace(
+        conn: &PgConnection,
+        node: i64,
+        spends: &[UnconfirmedSpend],
+    ) -> QueryResult<usize> {
+        use crate::schema::unconfirmed_spends::dsl::*;
+
+        conn.transaction(|| {
+            diesel::delete(unconfirmed_spends.filter(node_id.eq(node))).execute(conn)?;
+            diesel::insert_into(unconfirmed_spends)
+                .values(spends)
+                .on_conflict_do_nothing()
+                .execute(conn)
+        })
+    }
+
+    pub fn spends_of_any(
+        conn: &PgConnection,
+        txids: &[String],
+    ) -> QueryResult<Vec<UnconfirmedSpend>> {
+        use crate::schema::unconfirmed_spends::dsl::*;
+
+        unconfirmed_spends
+            .filter(prev_txid.eq_any(txids))
+            .order((prev_vout.asc(), node_id.asc()))
+            .load(conn)
+    }
+}
+
+/// An output of a watched address spent by more than one transaction.
+#[derive(Clone, Debug, Serialize, JsonSchema, AsChangeset, Queryable, Insertable)]
+#[table_name = "wallet_conflicts"]
+pub struct WalletConflict {
+    pub prev_txid: String,
+    pub prev_vout: i64,
+    pub address: String,
+    /// Every transaction seen spending the output.
+    pub spending_txids: Vec<String>,
+    pub first_seen_at: DateTime<Utc>,
+    pub alerted_at: DateTime<Utc>,
+}
+
+impl WalletConflict {
+    pub fn get(conn: &PgConnection, id: &str, vout: i64) -> QueryResult<Option<WalletConflict>> {
+        use crate::schema::wallet_conflicts::dsl::*;
+
+        wallet_conflicts
+            .filter(prev_txid.eq(id).and(prev_vout.eq(vout)))
+            .first(conn)
+            .optional()
+    }
+
+    pub fn upsert(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::wallet_conflicts::dsl::*;
+
+        diesel::insert_into(wallet_conflicts)
+            .values(self)
+            .on_conflict((prev_txid, prev_vout))
+            .do_update()
+            .set(self)
+            .execute(conn)
+    }
+
+    /// Most recently alerted first.
+    pub fn list(conn: &PgConnection, limit: i64) -> QueryResult<Vec<WalletConflict>> {
+        use crate::schema::wallet_conflicts::dsl::*;
+
+        wallet_conflicts
+            .order(alerted_at.desc())
+            .limit(limit)
+            .load(conn)
+    }
 }
 
 #[derive(Debug, AsChangeset, QueryableByName, Queryable, Insertable)]
@@ -247,7 +569,12 @@ impl Pool {
     ) -> QueryResult<usize> {
         use crate::schema::pool::dsl::*;
 
-        let MinerPoolInfo { coinbase_tags, .. } = pool_info;
+        let MinerPoolInfo {
+            coinbase_tags,
+            payout_addresses,
+        } = pool_info;
+        PoolAddress::create_or_update_batch(conn, payout_addresses)?;
+
         let mut pools = Vec::new();
 
         for (key, value) in coinbase_tags.into_iter() {
@@ -273,1128 +600,4956 @@ impl Pool {
     }
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "inflated_blocks"]
-pub struct InflatedBlock {
-    block_hash: String,
-    max_inflation: BigDecimal,
-    actual_inflation: BigDecimal,
-    notified_at: DateTime<Utc>,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
-    node_id: i64,
-    dismissed_at: Option<DateTime<Utc>>,
+/// A payout address the mining pool info lists for a pool.
+#[derive(Debug, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "pool_addresses"]
+pub struct PoolAddress {
+    pub address: String,
+    pub name: String,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
-impl InflatedBlock {
+impl PoolAddress {
+    fn create_or_update_batch(
+        conn: &PgConnection,
+        payout_addresses: HashMap<String, MinerPool>,
+    ) -> QueryResult<usize> {
+        use crate::schema::pool_addresses::dsl::*;
+        use diesel::pg::upsert::excluded;
+
+        if payout_addresses.is_empty() {
+            return Ok(0);
+        }
+
+        let addresses: Vec<_> = payout_addresses
+            .into_iter()
+            .map(|(key, value)| PoolAddress {
+                address: key,
+                name: value.name,
+                url: value.link,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            })
+            .collect();
+        diesel::insert_into(pool_addresses)
+            .values(addresses)
+            .on_conflict(address)
+            .do_update()
+            .set((
+                name.eq(excluded(name)),
+                url.eq(excluded(url)),
+                updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+    }
+}
+
+/// A block whose coinbase matched no pool tag or payout address, kept until the
+/// coinbase is labeled as a pool's.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable, Insertable)]
+#[table_name = "unknown_coinbase_tags"]
+pub struct UnknownCoinbaseTag {
+    pub block_hash: String,
+    pub height: i64,
+    #[serde(serialize_with = "serde_hex")]
+    pub coinbase_message: Vec<u8>,
+    /// The printable text of the coinbase.
+    pub tag: String,
+    pub payout_address: Option<String>,
+    /// The pool it was labeled as.
+    pub pool_name: Option<String>,
+    pub labeled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn serde_hex<S>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    hex::encode(bytes).serialize(s)
+}
+
+impl UnknownCoinbaseTag {
     pub fn create(
         conn: &PgConnection,
-        id: i64,
         block: &Block,
-        max: BigDecimal,
-        actual: BigDecimal,
+        message: Vec<u8>,
+        text: String,
+        address: Option<String>,
     ) -> QueryResult<usize> {
-        use crate::schema::inflated_blocks::dsl::*;
-        let ib = InflatedBlock {
+        use crate::schema::unknown_coinbase_tags::dsl::*;
+
+        let unknown = UnknownCoinbaseTag {
             block_hash: block.hash.clone(),
-            max_inflation: max,
-            actual_inflation: actual,
-            notified_at: Utc::now(),
+            height: block.height,
+            coinbase_message: message,
+            tag: text,
+            payout_address: address,
+            pool_name: None,
+            labeled_at: None,
             created_at: Utc::now(),
-            updated_at: Utc::now(),
-            node_id: id,
-            dismissed_at: None,
         };
-        diesel::insert_into(inflated_blocks)
-            .values(ib)
+        diesel::insert_into(unknown_coinbase_tags)
+            .values(unknown)
+            .on_conflict_do_nothing()
             .execute(conn)
     }
-}
 
-#[derive(Clone, AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "fee_rates"]
-pub struct FeeRate {
-    pub parent_block_hash: String,
-    pub node_id: i64,
-    pub fee_rate: i32,
-    pub omitted: bool,
-}
+    /// The blocks still to label, or the labeled ones, newest first.
+    pub fn list(
+        conn: &PgConnection,
+        labeled: bool,
+        limit: i64,
+    ) -> QueryResult<Vec<UnknownCoinbaseTag>> {
+        use crate::schema::unknown_coinbase_tags::dsl::*;
 
-impl FeeRate {
-    pub fn list_by(conn: &PgConnection, parent: String, node: i64) -> QueryResult<Vec<FeeRate>> {
-        use crate::schema::fee_rates::dsl::*;
+        let mut query = unknown_coinbase_tags.into_boxed();
+        query = if labeled {
+            query.filter(labeled_at.is_not_null())
+        } else {
+            query.filter(labeled_at.is_null())
+        };
 
-        fee_rates
-            .filter(parent_block_hash.eq(parent).and(node_id.eq(node)))
-            .load(conn)
+        query.order(height.desc()).limit(limit).load(conn)
     }
 
-    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::fee_rates::dsl::*;
-        diesel::update(
-            fee_rates.filter(
-                parent_block_hash
-                    .eq(&self.parent_block_hash)
-                    .and(node_id.eq(self.node_id))
-                    .and(fee_rate.eq(self.fee_rate)),
-            ),
-        )
-        .set(self)
-        .execute(conn)
+    /// Add the coinbase tag to the pools as `name`'s, and attribute the unlabeled
+    /// blocks whose coinbase contains it to the pool. Returns the blocks labeled.
+    pub fn label(
+        conn: &PgConnection,
+        pool_tag: &str,
+        name: &str,
+        url: &str,
+    ) -> QueryResult<Vec<String>> {
+        conn.transaction(|| {
+            let entry = Pool {
+                tag: pool_tag.into(),
+                name: name.into(),
+                url: url.into(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            };
+            diesel::insert_into(pool::table)
+                .values(entry)
+                .on_conflict((pool::tag, pool::name, pool::url))
+                .do_update()
+                .set(pool::updated_at.eq(Utc::now()))
+                .execute(conn)?;
+
+            // the scanner matches tags against the coinbase the same way
+            let unlabeled: Vec<UnknownCoinbaseTag> = unknown_coinbase_tags::table
+                .filter(unknown_coinbase_tags::labeled_at.is_null())
+                .load(conn)?;
+            let hashes: Vec<String> = unlabeled
+                .into_iter()
+                .filter(|u| String::from_utf8_lossy(&u.coinbase_message).contains(pool_tag))
+                .map(|u| u.block_hash)
+                .collect();
+
+            diesel::update(
+                unknown_coinbase_tags::table
+                    .filter(unknown_coinbase_tags::block_hash.eq_any(&hashes)),
+            )
+            .set((
+                unknown_coinbase_tags::pool_name.eq(name),
+                unknown_coinbase_tags::labeled_at.eq(Utc::now()),
+            ))
+            .execute(conn)?;
+            diesel::update(blocks::table.filter(blocks::hash.eq_any(&hashes)))
+                .set(blocks::pool_name.eq(name))
+                .execute(conn)?;
+            diesel::update(block_payouts::table.filter(block_payouts::block_hash.eq_any(&hashes)))
+                .set(block_payouts::tagged.eq(true))
+                .execute(conn)?;
+
+            Ok(hashes)
+        })
     }
 }
 
-#[derive(Debug, AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "block_templates"]
-pub struct BlockTemplate {
-    pub parent_block_hash: String,
-    pub node_id: i64,
-    pub fee_total: BigDecimal,
-    pub ts: DateTime<Utc>,
-    pub height: i64,
+/// The largest output of a block's coinbase, where the pool that mined it is paid.
+#[derive(Clone, Debug, Serialize, Queryable, Insertable)]
+#[table_name = "block_payouts"]
+pub struct BlockPayout {
+    pub block_hash: String,
+    /// The output address, or the script hex if it has none.
+    pub address: String,
+    /// In satoshis.
+    pub amount: i64,
+    /// Whether the block's pool was found by its coinbase tag.
+    pub tagged: bool,
     pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    pub n_transactions: i32,
-    pub tx_ids: Vec<u8>,
-    pub lowest_fee_rate: i32,
 }
 
-impl BlockTemplate {
+impl BlockPayout {
     pub fn create(
         conn: &PgConnection,
-        parent: String,
-        node: i64,
-        total: BigDecimal,
-        block_height: i64,
-        n_txs: i32,
-        txids: Vec<u8>,
-        rates: Vec<i32>,
+        hash: &str,
+        payout_address: &str,
+        payout_amount: i64,
+        by_tag: bool,
     ) -> QueryResult<usize> {
-        use crate::schema::block_templates::dsl as btd;
-        use crate::schema::fee_rates::dsl as frd;
+        use crate::schema::block_payouts::dsl::*;
 
-        let lowest = *rates.iter().min().expect("No transaction fees!");
-        let fee_rates: Vec<_> = rates
-            .into_iter()
-            .map(|rate| FeeRate {
-                parent_block_hash: parent.clone(),
-                node_id: node,
-                fee_rate: rate,
-                omitted: false,
-            })
-            .collect();
-
-        let tpl = BlockTemplate {
-            parent_block_hash: parent,
-            node_id: node,
-            fee_total: total,
-            ts: Utc::now(),
-            height: block_height,
+        let payout = BlockPayout {
+            block_hash: hash.into(),
+            address: payout_address.into(),
+            amount: payout_amount,
+            tagged: by_tag,
             created_at: Utc::now(),
-            updated_at: Utc::now(),
-            n_transactions: n_txs,
-            tx_ids: txids,
-            lowest_fee_rate: lowest,
         };
-
-        diesel::insert_into(btd::block_templates)
-            .values(tpl)
-            .on_conflict_do_nothing()
-            .execute(conn)?;
-
-        diesel::insert_into(frd::fee_rates)
-            .values(fee_rates)
+        diesel::insert_into(block_payouts)
+            .values(payout)
             .on_conflict_do_nothing()
             .execute(conn)
     }
 
-    pub fn purge(conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::block_templates::dsl::*;
+    /// The pool a payout address belongs to, the one it is listed for, or else the
+    /// pool whose tagged blocks paid to it most.
+    pub fn owner(conn: &PgConnection, payout_address: &str) -> QueryResult<Option<String>> {
+        let listed = pool_addresses::table
+            .find(payout_address)
+            .select(pool_addresses::name)
+            .first(conn)
+            .optional()?;
+        if listed.is_some() {
+            return Ok(listed);
+        }
+
+        let names: Vec<Option<String>> = block_payouts::table
+            .inner_join(blocks::table)
+            .filter(block_payouts::address.eq(payout_address))
+            .filter(block_payouts::tagged)
+            .select(blocks::pool_name)
+            .load(conn)?;
+        let mut counts = BTreeMap::<String, usize>::new();
+        for name in names.into_iter().flatten() {
+            *counts.entry(name).or_default() += 1;
+        }
 
-        diesel::delete(block_templates).execute(conn)
+        Ok(counts
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(name, _)| name))
     }
+}
 
-    pub fn get_min(conn: &PgConnection) -> QueryResult<Option<i64>> {
-        use crate::schema::block_templates::dsl::*;
-        use diesel::dsl::min;
+/// A block payout and what earlier blocks paid to the same pool and address.
+#[derive(Clone, Debug, QueryableByName)]
+pub struct PayoutObservation {
+    #[sql_type = "sql_types::Text"]
+    pub block_hash: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub height: i64,
+    #[sql_type = "sql_types::Text"]
+    pub pool_name: String,
+    #[sql_type = "sql_types::Text"]
+    pub address: String,
+    #[sql_type = "sql_types::Bool"]
+    pub tagged: bool,
+    /// Whether the pool is in the mining pool info, untagged blocks are when their
+    /// payout address is a pool's.
+    #[sql_type = "sql_types::Bool"]
+    pub known: bool,
+    /// Addresses the pool's tagged blocks paid to below this height.
+    #[sql_type = "sql_types::BigInt"]
+    pub previous_addresses: i64,
+    /// Whether the pool paid to this address below this height.
+    #[sql_type = "sql_types::Bool"]
+    pub address_used: bool,
+    /// Other pools that paid to the address or are listed for it.
+    #[sql_type = "sql_types::Array<sql_types::Text>"]
+    pub other_pools: Vec<String>,
+    /// Addresses the pool is expected to pay to, see `ExpectedPayout`.
+    #[sql_type = "sql_types::BigInt"]
+    pub expected_addresses: i64,
+    /// Whether this is one of them.
+    #[sql_type = "sql_types::Bool"]
+    pub address_expected: bool,
+    #[sql_type = "sql_types::Timestamptz"]
+    pub created_at: DateTime<Utc>,
+}
 
-        block_templates.select(min(height)).first(conn)
+impl PayoutObservation {
+    /// The payouts recorded after `since`, in the order they were.
+    pub fn since(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<Vec<PayoutObservation>> {
+        let raw_query = "
+            WITH known AS (
+                SELECT name FROM pool UNION SELECT name FROM pool_addresses
+            ), history AS (
+                SELECT p.address, p.tagged, b.pool_name, b.height
+                FROM block_payouts p
+                JOIN blocks b ON b.hash = p.block_hash
+            )
+            SELECT p.block_hash, b.height, coalesce(b.pool_name, '') as pool_name, p.address,
+                p.tagged, coalesce(b.pool_name IN (SELECT name FROM known), false) as known,
+                (
+                    SELECT count(DISTINCT h.address) FROM history h
+                    WHERE h.tagged AND h.pool_name = b.pool_name AND h.height < b.height
+                ) as previous_addresses,
+                EXISTS (
+                    SELECT 1 FROM history h
+                    WHERE h.pool_name = b.pool_name AND h.address = p.address
+                        AND h.height < b.height
+                ) as address_used,
+                ARRAY(
+                    SELECT h.pool_name FROM history h
+                    WHERE h.tagged AND h.address = p.address AND h.pool_name <> b.pool_name
+                    UNION
+                    SELECT k.name FROM pool_addresses k
+                    WHERE k.address = p.address AND k.name <> b.pool_name
+                    ORDER BY 1
+                ) as other_pools,
+                (
+                    SELECT count(*) FROM expected_payouts e WHERE e.pool_name = b.pool_name
+                ) as expected_addresses,
+                EXISTS (
+                    SELECT 1 FROM expected_payouts e
+                    WHERE e.pool_name = b.pool_name AND e.address = p.address
+                ) as address_expected,
+                p.created_at
+            FROM block_payouts p
+            JOIN blocks b ON b.hash = p.block_hash
+            WHERE p.created_at > $1
+            ORDER BY p.created_at, b.height
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Timestamptz, _>(since)
+            .load(conn)
     }
+}
 
-    pub fn get_with_txs(conn: &PgConnection, block_height: i64) -> QueryResult<BlockTemplate> {
-        use crate::schema::block_templates::dsl::*;
+/// How a pool's blocks paid to an address.
+#[derive(Clone, Debug, Serialize, JsonSchema, QueryableByName)]
+pub struct PayoutAddress {
+    #[serde(skip)]
+    #[sql_type = "sql_types::Text"]
+    pub pool_name: String,
+    #[sql_type = "sql_types::Text"]
+    pub address: String,
+    /// Blocks with the pool's coinbase tag.
+    #[sql_type = "sql_types::BigInt"]
+    pub tagged_blocks: i64,
+    /// Blocks without a known tag, attributed to the pool by their payout address.
+    #[sql_type = "sql_types::BigInt"]
+    pub untagged_blocks: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub first_height: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub last_height: i64,
+    #[sql_type = "sql_types::Timestamptz"]
+    pub last_seen: DateTime<Utc>,
+    /// Whether the mining pool info lists the address for the pool.
+    #[sql_type = "sql_types::Bool"]
+    pub listed: bool,
+    /// Other pools that paid to the address or are listed for it.
+    #[sql_type = "sql_types::Array<sql_types::Text>"]
+    pub shared_with: Vec<String>,
+}
 
-        block_templates.filter(height.eq(block_height)).first(conn)
+/// The addresses a pool was paid at, the most recently used first.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct PayoutCluster {
+    pub pool_name: String,
+    pub blocks: i64,
+    pub addresses: Vec<PayoutAddress>,
+}
+
+impl PayoutCluster {
+    /// The payout addresses of every known pool, or of one.
+    pub fn list(conn: &PgConnection, pool: Option<&str>) -> QueryResult<Vec<PayoutCluster>> {
+        let raw_query = "
+            WITH known AS (
+                SELECT name FROM pool UNION SELECT name FROM pool_addresses
+            )
+            SELECT b.pool_name, p.address,
+                count(*) FILTER (WHERE p.tagged) as tagged_blocks,
+                count(*) FILTER (WHERE NOT p.tagged) as untagged_blocks,
+                min(b.height) as first_height, max(b.height) as last_height,
+                max(p.created_at) as last_seen,
+                EXISTS (
+                    SELECT 1 FROM pool_addresses k
+                    WHERE k.address = p.address AND k.name = b.pool_name
+                ) as listed,
+                ARRAY(
+                    SELECT c.pool_name FROM block_payouts q
+                    JOIN blocks c ON c.hash = q.block_hash
+                    WHERE q.address = p.address AND c.pool_name <> b.pool_name
+                        AND c.pool_name IN (SELECT name FROM known)
+                    UNION
+                    SELECT k.name FROM pool_addresses k
+                    WHERE k.address = p.address AND k.name <> b.pool_name
+                    ORDER BY 1
+                ) as shared_with
+            FROM block_payouts p
+            JOIN blocks b ON b.hash = p.block_hash
+            WHERE b.pool_name IN (SELECT name FROM known)
+                AND ($1 IS NULL OR b.pool_name = $1)
+            GROUP BY b.pool_name, p.address
+            ORDER BY b.pool_name, last_height DESC
+        ";
+
+        let addresses: Vec<PayoutAddress> = diesel::sql_query(raw_query)
+            .bind::<sql_types::Nullable<sql_types::Text>, _>(pool)
+            .load(conn)?;
+
+        let mut clusters: Vec<PayoutCluster> = Vec::new();
+        for address in addresses {
+            let blocks = address.tagged_blocks + address.untagged_blocks;
+            match clusters.last_mut() {
+                Some(cluster) if cluster.pool_name == address.pool_name => {
+                    cluster.blocks += blocks;
+                    cluster.addresses.push(address);
+                }
+                _ => clusters.push(PayoutCluster {
+                    pool_name: address.pool_name.clone(),
+                    blocks,
+                    addresses: vec![address],
+                }),
+            }
+        }
+
+        Ok(clusters)
     }
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "tx_outsets"]
-pub struct TxOutset {
-    pub block_hash: String,
-    pub node_id: i64,
-    pub txouts: i64,
-    pub total_amount: BigDecimal,
+/// An address a pool's blocks are expected to pay to. Once a pool has any, its tagged
+/// blocks paying to another address are payout anomalies, whatever it paid to before.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable, Insertable)]
+#[table_name = "expected_payouts"]
+pub struct ExpectedPayout {
+    pub pool_name: String,
+    pub address: String,
     pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
-    pub inflated: bool,
 }
 
-impl TxOutset {
-    pub fn get(conn: &PgConnection, block: &String, node: i64) -> QueryResult<Option<TxOutset>> {
-        use crate::schema::tx_outsets::dsl::*;
-        let result = tx_outsets
-            .filter(block_hash.eq(block).and(node_id.eq(node)))
-            .get_result(conn);
+impl ExpectedPayout {
+    /// Add and remove expected addresses of `pool`. With `from_history` the addresses
+    /// its tagged blocks paid to and the ones the mining pool info lists for it are
+    /// added too. Returns the pool's expected addresses.
+    pub fn update(
+        conn: &PgConnection,
+        pool: &str,
+        mut add: Vec<String>,
+        remove: Vec<String>,
+        from_history: bool,
+    ) -> QueryResult<Vec<ExpectedPayout>> {
+        conn.transaction(|| {
+            if from_history {
+                let paid: Vec<String> = block_payouts::table
+                    .inner_join(blocks::table)
+                    .filter(blocks::pool_name.eq(pool))
+                    .filter(block_payouts::tagged)
+                    .select(block_payouts::address)
+                    .distinct()
+                    .load(conn)?;
+                let listed: Vec<String> = pool_addresses::table
+                    .filter(pool_addresses::name.eq(pool))
+                    .select(pool_addresses::address)
+                    .load(conn)?;
+                add.extend(paid.into_iter().chain(listed));
+            }
 
-        match result {
-            Err(diesel::result::Error::NotFound) => Ok(None),
-            Ok(ans) => Ok(Some(ans)),
-            Err(e) => Err(e),
+            let expected: Vec<_> = add
+                .into_iter()
+                .map(|address| ExpectedPayout {
+                    pool_name: pool.into(),
+                    address,
+                    created_at: Utc::now(),
+                })
+                .collect();
+            diesel::insert_into(expected_payouts::table)
+                .values(&expected)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            diesel::delete(
+                expected_payouts::table
+                    .filter(expected_payouts::pool_name.eq(pool))
+                    .filter(expected_payouts::address.eq_any(&remove)),
+            )
+            .execute(conn)?;
+
+            ExpectedPayout::list(conn, Some(pool))
+        })
+    }
+
+    /// The expected addresses of every pool, or of one.
+    pub fn list(conn: &PgConnection, pool: Option<&str>) -> QueryResult<Vec<ExpectedPayout>> {
+        use crate::schema::expected_payouts::dsl::*;
+
+        let mut query = expected_payouts.into_boxed();
+
+        if let Some(name) = pool {
+            query = query.filter(pool_name.eq(name));
         }
+
+        query.order_by((pool_name, address)).load(conn)
     }
+}
+
+/// How a payout doesn't fit a pool's payout history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutAnomalyKind {
+    /// A pool paid to an address it never paid to before.
+    NewAddress,
+    /// A pool with expected payout addresses paid to another address.
+    UnexpectedAddress,
+    /// A pool paid to an address another pool paid to or is listed for.
+    SharedAddress,
+    /// A block without a known coinbase tag paid to a pool's address.
+    UnknownTag,
+}
+
+/// A coinbase payout that doesn't fit the payout history of its pool.
+#[derive(Clone, Debug, Serialize)]
+pub struct PayoutAnomaly {
+    pub kind: PayoutAnomalyKind,
+    pub block_hash: String,
+    pub height: i64,
+    pub pool_name: String,
+    pub address: String,
+    pub other_pools: Vec<String>,
+    /// How many addresses the pool paid to before.
+    pub previous_addresses: i64,
+    pub detected_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "inflated_blocks"]
+pub struct InflatedBlock {
+    pub block_hash: String,
+    pub max_inflation: BigDecimal,
+    pub actual_inflation: BigDecimal,
+    pub notified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub node_id: i64,
+    pub dismissed_at: Option<DateTime<Utc>>,
+}
 
+impl InflatedBlock {
     pub fn create(
         conn: &PgConnection,
-        tx_outs: u64,
-        amount: BigDecimal,
-        block: &String,
-        node: i64,
-    ) -> QueryResult<TxOutset> {
-        use crate::schema::tx_outsets::dsl::*;
-        let outset = TxOutset {
-            block_hash: block.clone(),
-            node_id: node,
-            txouts: tx_outs as i64,
-            total_amount: amount,
+        id: i64,
+        block: &Block,
+        max: BigDecimal,
+        actual: BigDecimal,
+    ) -> QueryResult<InflatedBlock> {
+        use crate::schema::inflated_blocks::dsl::*;
+        let ib = InflatedBlock {
+            block_hash: block.hash.clone(),
+            max_inflation: max,
+            actual_inflation: actual,
+            notified_at: Utc::now(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
-            // TODO: this
-            inflated: false,
+            node_id: id,
+            dismissed_at: None,
         };
-        diesel::insert_into(tx_outsets)
-            .values(outset)
+        diesel::insert_into(inflated_blocks)
+            .values(ib)
             .get_result(conn)
     }
 
+    /// Most recent inflated blocks first.
+    pub fn list(conn: &PgConnection, limit: i64) -> QueryResult<Vec<InflatedBlock>> {
+        use crate::schema::inflated_blocks::dsl::*;
+
+        inflated_blocks
+            .order(created_at.desc())
+            .limit(limit)
+            .load(conn)
+    }
+}
+
+#[derive(Clone, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "fee_rates"]
+pub struct FeeRate {
+    pub parent_block_hash: String,
+    pub node_id: i64,
+    pub fee_rate: i32,
+    pub omitted: bool,
+}
+
+impl FeeRate {
+    pub fn list_by(conn: &PgConnection, parent: String, node: i64) -> QueryResult<Vec<FeeRate>> {
+        use crate::schema::fee_rates::dsl::*;
+
+        fee_rates
+            .filter(parent_block_hash.eq(parent).and(node_id.eq(node)))
+            .load(conn)
+    }
+
     pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::tx_outsets::dsl::*;
+        use crate::schema::fee_rates::dsl::*;
         diesel::update(
-            tx_outsets.filter(
-                block_hash
-                    .eq(&self.block_hash)
-                    .and(node_id.eq(self.node_id)),
+            fee_rates.filter(
+                parent_block_hash
+                    .eq(&self.parent_block_hash)
+                    .and(node_id.eq(self.node_id))
+                    .and(fee_rate.eq(self.fee_rate)),
             ),
         )
         .set(self)
         .execute(conn)
     }
+
+    /// Delete the fee rates that aren't in their template, e.g. rates of a newer template
+    /// on the same parent, or of a template that's gone.
+    pub fn purge_orphaned(conn: &PgConnection) -> QueryResult<usize> {
+        let raw_query = "
+            DELETE FROM fee_rates fr
+            WHERE NOT EXISTS (
+                SELECT 1 FROM block_templates bt
+                WHERE bt.parent_block_hash = fr.parent_block_hash AND bt.node_id = fr.node_id
+                AND (cardinality(bt.tx_fee_rates) = 0 OR fr.fee_rate = ANY(bt.tx_fee_rates))
+            )
+        ";
+
+        diesel::sql_query(raw_query).execute(conn)
+    }
 }
 
-#[derive(Clone, AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "softforks"]
-pub struct SoftForks {
+#[derive(Debug, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "block_templates"]
+pub struct BlockTemplate {
+    pub parent_block_hash: String,
     pub node_id: i64,
-    pub fork_type: i32,
-    pub name: String,
-    pub bit: Option<i32>,
-    pub status: i32,
-    pub since: Option<i64>,
-    pub notified_at: DateTime<Utc>,
+    pub fee_total: BigDecimal,
+    pub ts: DateTime<Utc>,
+    pub height: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub n_transactions: i32,
+    pub tx_ids: Vec<u8>,
+    pub lowest_fee_rate: i32,
+    /// The rules the node built the template with, e.g. `segwit` and `taproot`.
+    pub rules: Vec<String>,
+    /// The fee rate of each transaction in sat/vB, in template order.
+    pub tx_fee_rates: Vec<i32>,
 }
 
-impl SoftForks {
-    pub fn update_or_insert(
+impl BlockTemplate {
+    /// Keep the template of `snapshot`, with its fee total and the fee rate of each
+    /// transaction.
+    pub fn create(
         conn: &PgConnection,
-        node: i64,
-        forks: HashMap<String, Softfork>,
-    ) -> QueryResult<()> {
-        use crate::schema::softforks::dsl::*;
+        snapshot: TemplateSnapshot,
+        total: BigDecimal,
+        rates: Vec<i32>,
+    ) -> QueryResult<usize> {
+        use crate::schema::block_templates::dsl as btd;
+        use crate::schema::fee_rates::dsl as frd;
 
-        for (key, info) in forks.into_iter() {
-            let b = if let Some(b9) = info.bip9 {
-                b9.bit.map(|b| b as i32)
-            } else {
-                None
-            };
+        let lowest = *rates.iter().min().expect("No transaction fees!");
+        // one fee rate per transaction
+        let n_txs = rates.len() as i32;
+        let tx_fee_rates = rates.clone();
+        let fee_rates: Vec<_> = rates
+            .into_iter()
+            .map(|rate| FeeRate {
+                parent_block_hash: snapshot.parent_block_hash.clone(),
+                node_id: snapshot.node_id,
+                fee_rate: rate,
+                omitted: false,
+            })
+            .collect();
 
-            let sf = SoftForks {
-                node_id: node,
-                fork_type: info.type_ as i32,
-                name: key,
-                bit: b,
-                status: info.active as i32,
-                since: info.height.map(|k| k as i64),
-                notified_at: Utc::now(),
-                updated_at: Utc::now(),
-                created_at: Utc::now(),
-            };
-            diesel::insert_into(softforks)
-                .values(&sf)
-                .on_conflict((node_id, fork_type, name))
-                .do_update()
-                .set(&sf)
-                .execute(conn)?;
+        let tpl = BlockTemplate {
+            parent_block_hash: snapshot.parent_block_hash,
+            node_id: snapshot.node_id,
+            fee_total: total,
+            ts: Utc::now(),
+            height: snapshot.height,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            n_transactions: n_txs,
+            tx_ids: snapshot.tx_ids,
+            lowest_fee_rate: lowest,
+            rules: snapshot.rules,
+            tx_fee_rates,
+        };
+
+        let inserted = diesel::insert_into(btd::block_templates)
+            .values(tpl)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        // the first template on a parent is kept, so are its fee rates
+        if inserted == 0 {
+            return Ok(0);
         }
-        Ok(())
+
+        diesel::insert_into(frd::fee_rates)
+            .values(fee_rates)
+            .on_conflict_do_nothing()
+            .execute(conn)
     }
-}
 
-#[derive(Debug, Clone, Serialize, AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "blocks"]
-pub struct Block {
-    pub hash: String,
-    pub height: i64,
-    pub parent_hash: Option<String>,
-    pub connected: bool,
-    pub first_seen_by: i64,
-    pub headers_only: bool,
-    pub work: String,
-    pub txids: Option<Vec<u8>>,
-    pub txids_added: Option<Vec<u8>>,
-    pub txids_omitted: Option<Vec<u8>>,
-    pub pool_name: Option<String>,
-    #[serde(serialize_with = "serde_bigdecimal")]
-    pub template_txs_fee_diff: Option<BigDecimal>,
-    #[serde(serialize_with = "serde_bigdecimal")]
-    pub tx_omitted_fee_rates: Option<BigDecimal>,
-    #[serde(serialize_with = "serde_bigdecimal")]
-    pub lowest_template_fee_rate: Option<BigDecimal>,
-    #[serde(serialize_with = "serde_bigdecimal")]
-    pub total_fee: Option<BigDecimal>,
-    pub coinbase_message: Option<Vec<u8>>,
-}
+    /// Delete all but the `keep` most recent templates of each node.
+    pub fn purge(conn: &PgConnection, keep: i64) -> QueryResult<usize> {
+        use crate::schema::block_templates::dsl::*;
 
-impl Block {
-    pub fn get_latest(conn: &PgConnection) -> QueryResult<Block> {
-        use crate::schema::blocks::dsl::*;
-        blocks.order_by(height.desc()).first(conn)
-    }
+        if keep == 0 {
+            return diesel::delete(block_templates).execute(conn);
+        }
 
-    pub fn get_with_fee_no_diffs(conn: &PgConnection, min_height: i64) -> QueryResult<Vec<Block>> {
-        use crate::schema::blocks::dsl::*;
+        let raw_query = "
+            DELETE FROM block_templates bt
+            WHERE (
+                SELECT count(*) FROM block_templates newer
+                WHERE newer.node_id = bt.node_id AND newer.ts > bt.ts
+            ) >= $1
+        ";
 
-        blocks
-            .filter(
-                height
-                    .gt(min_height)
-                    .and(template_txs_fee_diff.is_null())
-                    .and(total_fee.is_not_null()),
-            )
-            .load(conn)
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::BigInt, _>(keep)
+            .execute(conn)
     }
 
-    pub fn get(conn: &PgConnection, block_hash: &String) -> QueryResult<Block> {
-        use crate::schema::blocks::dsl::*;
-        blocks.find(block_hash).first(conn)
+    /// Delete the templates of nodes that were removed.
+    pub fn purge_orphaned(conn: &PgConnection) -> QueryResult<usize> {
+        let raw_query = "
+            DELETE FROM block_templates bt
+            WHERE NOT EXISTS (SELECT 1 FROM nodes n WHERE n.id = bt.node_id)
+        ";
+
+        diesel::sql_query(raw_query).execute(conn)
     }
 
-    /// Look up a parent block.
-    pub fn parent(&self, conn: &PgConnection) -> QueryResult<Block> {
-        use crate::schema::blocks::dsl::*;
+    /// Most recent templates first.
+    pub fn list(
+        conn: &PgConnection,
+        node: Option<i64>,
+        limit: i64,
+    ) -> QueryResult<Vec<BlockTemplate>> {
+        use crate::schema::block_templates::dsl::*;
 
-        if let Some(parent) = &self.parent_hash {
-            blocks.find(parent).first(conn)
-        } else {
-            Err(diesel::result::Error::NotFound)
+        let mut query = block_templates.into_boxed();
+        if let Some(node) = node {
+            query = query.filter(node_id.eq(node));
         }
+
+        query.order(ts.desc()).limit(limit).load(conn)
     }
 
-    pub fn count_at_height(conn: &PgConnection, block_height: i64) -> QueryResult<usize> {
-        use crate::schema::blocks::dsl::*;
+    pub fn get_min(conn: &PgConnection) -> QueryResult<Option<i64>> {
+        use crate::schema::block_templates::dsl::*;
 
-        blocks.filter(height.eq(block_height)).execute(conn)
+        block_templates.select(diesel::dsl::min(height)).first(conn)
     }
 
-    pub fn get_at_height(conn: &PgConnection, block_height: i64) -> QueryResult<Vec<Block>> {
-        use crate::schema::blocks::dsl::*;
+    pub fn get_with_txs(conn: &PgConnection, block_height: i64) -> QueryResult<BlockTemplate> {
+        use crate::schema::block_templates::dsl::*;
 
-        blocks.filter(height.eq(block_height)).load(conn)
+        block_templates.filter(height.eq(block_height)).first(conn)
     }
 
-    pub fn find_stale_candidates(conn: &PgConnection, height: i64) -> QueryResult<Vec<Height>> {
-        let raw_query = format!(
-            "
-            SELECT height FROM blocks as b
-			LEFT JOIN invalid_blocks as ib
-			ON b.hash = ib.hash
-            WHERE height > {}
-			AND ib.node IS NULL
-            GROUP BY height
-            HAVING count(height) > 1
-            ORDER BY height ASC
-        ",
-            height
-        );
+    /// The first template `node` built on `parent`, later ones aren't kept.
+    pub fn get(conn: &PgConnection, parent: &str, node: i64) -> QueryResult<Option<BlockTemplate>> {
+        use crate::schema::block_templates::dsl::*;
 
-        diesel::sql_query(raw_query).load(conn)
+        block_templates.find((parent, node)).first(conn).optional()
     }
+}
 
-    pub fn num_transactions(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::transaction::dsl::*;
+/// A transaction with a high fee rate in the templates that blocks of a pool left out.
+#[derive(Clone, Debug, Serialize, QueryableByName, Queryable)]
+#[table_name = "censorship_candidates"]
+pub struct CensorshipCandidate {
+    pub txid: String,
+    pub pool_name: String,
+    /// The highest fee rate it had in the templates, in sat/vB.
+    pub fee_rate: i32,
+    /// How many of the pool's blocks left it out within the window.
+    pub omissions: i64,
+    pub first_height: i64,
+    pub last_height: i64,
+    pub last_block: String,
+    pub first_omitted_at: DateTime<Utc>,
+    pub last_omitted_at: DateTime<Utc>,
+    pub alerted_at: Option<DateTime<Utc>>,
+}
 
-        transaction.filter(block_id.eq(&self.hash)).execute(conn)
+impl CensorshipCandidate {
+    /// Count a block of the pool leaving out the transaction. Omissions start over when
+    /// the previous one is more than `window` blocks back. Nothing is counted if the
+    /// block was already.
+    pub fn record(
+        conn: &PgConnection,
+        tx: &str,
+        block: &Block,
+        pool: &str,
+        rate: i32,
+        window: i64,
+    ) -> QueryResult<Option<CensorshipCandidate>> {
+        let raw_query = "
+            INSERT INTO censorship_candidates AS c (txid, pool_name, fee_rate, omissions,
+                first_height, last_height, last_block, first_omitted_at, last_omitted_at)
+            VALUES ($1, $2, $3, 1, $4, $4, $5, now(), now())
+            ON CONFLICT (txid, pool_name) DO UPDATE SET
+                omissions = CASE WHEN c.last_height < excluded.last_height - $6 THEN 1
+                    ELSE c.omissions + 1 END,
+                first_height = CASE WHEN c.last_height < excluded.last_height - $6
+                    THEN excluded.first_height ELSE c.first_height END,
+                first_omitted_at = CASE WHEN c.last_height < excluded.last_height - $6
+                    THEN excluded.first_omitted_at ELSE c.first_omitted_at END,
+                alerted_at = CASE WHEN c.last_height < excluded.last_height - $6
+                    THEN NULL ELSE c.alerted_at END,
+                fee_rate = greatest(c.fee_rate, excluded.fee_rate),
+                last_height = greatest(c.last_height, excluded.last_height),
+                last_block = excluded.last_block,
+                last_omitted_at = excluded.last_omitted_at
+            WHERE c.last_block <> excluded.last_block
+            RETURNING *
+        ";
+
+        let recorded: Vec<CensorshipCandidate> = diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(tx)
+            .bind::<sql_types::Text, _>(pool)
+            .bind::<sql_types::Integer, _>(rate)
+            .bind::<sql_types::BigInt, _>(block.height)
+            .bind::<sql_types::Text, _>(&block.hash)
+            .bind::<sql_types::BigInt, _>(window)
+            .load(conn)?;
+
+        Ok(recorded.into_iter().next())
     }
 
-    pub fn block_and_descendant_transactions(
-        &self,
+    pub fn set_alerted(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::censorship_candidates::dsl::*;
+
+        diesel::update(censorship_candidates.find((&self.txid, &self.pool_name)))
+            .set(alerted_at.eq(Utc::now()))
+            .execute(conn)
+    }
+
+    /// Transactions left out at least `min_omissions` times, the most often first.
+    pub fn list(
         conn: &PgConnection,
+        pool: Option<String>,
+        min_omissions: i64,
         limit: i64,
-    ) -> QueryResult<Vec<Transaction>> {
-        use crate::schema::transaction::dsl::*;
-
-        let block_ids: Vec<_> = self
-            .descendants(conn, Some(limit))?
-            .iter()
-            .map(|b| b.hash.clone())
-            .collect();
+    ) -> QueryResult<Vec<CensorshipCandidate>> {
+        use crate::schema::censorship_candidates::dsl::*;
+
+        let mut query = censorship_candidates
+            .filter(omissions.ge(min_omissions))
+            .into_boxed();
+        if let Some(pool) = pool {
+            query = query.filter(pool_name.eq(pool));
+        }
 
-        transaction
-            .filter(is_coinbase.eq(false).and(block_id.eq_any(block_ids)))
+        query
+            .order((omissions.desc(), last_height.desc()))
+            .limit(limit)
             .load(conn)
     }
+}
 
-    /// Fetch the entire list of descendants for the current block.
-    pub fn descendants(&self, conn: &PgConnection, limit: Option<i64>) -> QueryResult<Vec<Block>> {
-        let limit = if limit.is_some() {
-            format!("WHERE height <= {}", self.height + limit.unwrap())
-        } else {
-            "".into()
-        };
+/// The transactions of the latest template a node built on a block, kept for a few
+/// blocks to compare the blocks mined on that parent with.
+#[derive(Debug, AsChangeset, Queryable, Insertable)]
+#[table_name = "template_snapshots"]
+pub struct TemplateSnapshot {
+    pub parent_block_hash: String,
+    pub node_id: i64,
+    pub height: i64,
+    pub tx_ids: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    pub rules: Vec<String>,
+}
 
-        let raw_query = format!(
-            "
-            WITH RECURSIVE rec_query AS (
-                SELECT * FROM blocks WHERE hash = '{}'
-                UNION ALL
-                SELECT b.* FROM blocks b INNER JOIN rec_query r ON r.hash = b.parent_hash
-            ) SELECT * FROM rec_query
-			{}
-			ORDER BY height ASC;
-        ",
-            self.hash, limit
-        );
+impl TemplateSnapshot {
+    pub fn upsert(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::template_snapshots::dsl::*;
 
-        diesel::sql_query(raw_query).load(conn)
+        diesel::insert_into(template_snapshots)
+            .values(self)
+            .on_conflict((parent_block_hash, node_id))
+            .do_update()
+            .set(self)
+            .execute(conn)
     }
 
-    /// Fetch the list of descendants for the current block ordered by work.
-    pub fn descendants_by_work(&self, conn: &PgConnection, limit: i64) -> QueryResult<Vec<Block>> {
-        let raw_query = format!(
-            "
-            WITH RECURSIVE rec_query AS (
-                SELECT * FROM blocks WHERE hash = '{}'
-                UNION ALL
-                SELECT b.* FROM blocks b INNER JOIN rec_query r ON r.hash = b.parent_hash
-            ) SELECT * FROM rec_query
-			WHERE height < {}
-			ORDER BY height,work ASC;
-        ",
-            self.hash, limit
-        );
+    pub fn for_parent(conn: &PgConnection, parent: &str) -> QueryResult<Vec<TemplateSnapshot>> {
+        use crate::schema::template_snapshots::dsl::*;
 
-        diesel::sql_query(raw_query).load(conn)
+        template_snapshots
+            .filter(parent_block_hash.eq(parent))
+            .order(node_id.asc())
+            .load(conn)
     }
-    /// Fetch all blocks that point to the block with a given hash.
-    pub fn children(conn: &PgConnection, block_hash: &String) -> QueryResult<Vec<Block>> {
-        use crate::schema::blocks::dsl::*;
 
-        blocks.filter(parent_hash.eq(block_hash)).load(conn)
+    /// Drop snapshots more than `keep` blocks below the newest one.
+    pub fn purge(conn: &PgConnection, keep: i64) -> QueryResult<usize> {
+        use crate::schema::template_snapshots::dsl::*;
+
+        let newest: Option<i64> = template_snapshots
+            .select(diesel::dsl::max(height))
+            .first(conn)?;
+        match newest {
+            Some(newest) => {
+                diesel::delete(template_snapshots.filter(height.lt(newest - keep))).execute(conn)
+            }
+            None => Ok(0),
+        }
     }
+}
 
-    /// Highest block height.
-    pub fn max_height(conn: &PgConnection) -> QueryResult<Option<i64>> {
-        use crate::schema::blocks::dsl::*;
-        use diesel::dsl::max;
+/// A transaction in one of a node's templates on a parent, the templates are looked up
+/// by txid with these.
+#[derive(Debug, Queryable, Insertable)]
+#[table_name = "template_txs"]
+pub struct TemplateTx {
+    pub txid: String,
+    pub parent_block_hash: String,
+    pub node_id: i64,
+    pub height: i64,
+    /// When the transaction was first in a template of the node on the parent.
+    pub first_seen_at: DateTime<Utc>,
+}
+
+impl TemplateTx {
+    // Rows per insert, postgres allows 65535 bind parameters.
+    const INSERT_CHUNK: usize = 10_000;
 
-        blocks.select(max(height)).first(conn)
+    pub fn insert(conn: &PgConnection, txs: &[TemplateTx]) -> QueryResult<usize> {
+        use crate::schema::template_txs::dsl::*;
+
+        let mut inserted = 0;
+        for chunk in txs.chunks(Self::INSERT_CHUNK) {
+            inserted += diesel::insert_into(template_txs)
+                .values(chunk)
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+        Ok(inserted)
     }
+}
 
-    /// Which blocks do we only have headers for?
-    pub fn headers_only(conn: &PgConnection, max_depth: i64) -> QueryResult<Vec<Block>> {
-        use crate::schema::blocks::dsl::*;
+/// A node's template snapshot and whether a transaction was in any of its templates
+/// on the same parent.
+#[derive(Debug, QueryableByName)]
+pub struct TemplateMembership {
+    #[sql_type = "sql_types::BigInt"]
+    pub node_id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub height: i64,
+    #[sql_type = "sql_types::Text"]
+    pub parent_block_hash: String,
+    /// When the node built its latest template on the parent.
+    #[sql_type = "sql_types::Timestamptz"]
+    pub template_at: DateTime<Utc>,
+    #[sql_type = "sql_types::Nullable<sql_types::Timestamptz>"]
+    pub first_seen_at: Option<DateTime<Utc>>,
+    /// The transactions of the latest template.
+    #[sql_type = "sql_types::Binary"]
+    pub tx_ids: Vec<u8>,
+}
 
-        blocks
-            .filter(headers_only.eq(true).and(height.gt(max_depth)))
-            .order(height.asc())
+impl TemplateMembership {
+    /// The kept templates, of `node` and at `at_height` if given, highest first.
+    pub fn list(
+        conn: &PgConnection,
+        id: &str,
+        node: Option<i64>,
+        at_height: Option<i64>,
+    ) -> QueryResult<Vec<TemplateMembership>> {
+        let raw_query = "
+            SELECT s.node_id, s.height, s.parent_block_hash, s.created_at as template_at,
+                t.first_seen_at, s.tx_ids
+            FROM template_snapshots s
+            LEFT JOIN template_txs t
+            ON t.txid = $1 AND t.parent_block_hash = s.parent_block_hash AND t.node_id = s.node_id
+            WHERE ($2 IS NULL OR s.node_id = $2) AND ($3 IS NULL OR s.height = $3)
+            ORDER BY s.height DESC, s.node_id
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(id)
+            .bind::<sql_types::Nullable<sql_types::BigInt>, _>(node)
+            .bind::<sql_types::Nullable<sql_types::BigInt>, _>(at_height)
             .load(conn)
     }
+}
 
-    /// Fetch block if we have it, or create.
-    pub fn get_or_create(
+/// How closely one pool's blocks matched the nodes' templates during a period.
+#[derive(Clone, Debug, Serialize, QueryableByName)]
+pub struct TemplateMatchRate {
+    #[sql_type = "sql_types::Nullable<sql_types::Text>"]
+    pub pool_name: Option<String>,
+    #[sql_type = "sql_types::Timestamptz"]
+    pub period: DateTime<Utc>,
+    #[sql_type = "sql_types::BigInt"]
+    pub blocks: i64,
+    /// Blocks with a similarity of at least the requested threshold.
+    #[sql_type = "sql_types::BigInt"]
+    pub matched: i64,
+    #[sql_type = "sql_types::Double"]
+    pub mean_similarity: f64,
+}
+
+impl TemplateMatchRate {
+    /// Per pool and `interval` ("hour", "day", "week"), oldest period first.
+    pub fn list(
         conn: &PgConnection,
-        headers_only: bool,
-        first_seen_by: i64,
-        header: &GetBlockHeaderResult,
-    ) -> QueryResult<Block> {
-        use crate::schema::blocks::dsl as bs;
-        let block = bs::blocks
-            .find(header.hash.to_string())
-            .first::<Block>(conn);
+        interval: &str,
+        threshold: f64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> QueryResult<Vec<TemplateMatchRate>> {
+        let raw_query = "
+            SELECT
+                pool_name,
+                date_trunc($1, template_matched_at) as period,
+                count(*) as blocks,
+                count(*) FILTER (WHERE template_similarity >= $2) as matched,
+                avg(template_similarity) as mean_similarity
+            FROM blocks
+            WHERE template_matched_at >= $3 AND template_matched_at < $4
+            GROUP BY pool_name, period
+            ORDER BY period ASC, pool_name ASC
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(interval)
+            .bind::<sql_types::Double, _>(threshold)
+            .bind::<sql_types::Timestamptz, _>(from)
+            .bind::<sql_types::Timestamptz, _>(to)
+            .load(conn)
+    }
+}
 
-        match block {
-            Err(diesel::result::Error::NotFound) => {
-                let prev_hash = header.previous_block_hash.map(|h| h.to_string());
+/// How long one pool's blocks took to arrive after the templates they were built on.
+#[derive(Clone, Debug, Serialize, QueryableByName)]
+pub struct TemplateLatency {
+    #[sql_type = "sql_types::Nullable<sql_types::Text>"]
+    pub pool_name: Option<String>,
+    #[sql_type = "sql_types::BigInt"]
+    pub blocks: i64,
+    /// In seconds.
+    #[sql_type = "sql_types::Double"]
+    pub mean_latency: f64,
+    #[sql_type = "sql_types::Double"]
+    pub max_latency: f64,
+    #[sql_type = "sql_types::BigInt"]
+    pub stale_blocks: i64,
+    #[sql_type = "sql_types::Double"]
+    pub mean_similarity: f64,
+}
 
-                let block = Block {
-                    hash: header.hash.to_string(),
-                    height: header.height as i64,
-                    parent_hash: prev_hash,
-                    connected: false,
-                    headers_only,
-                    first_seen_by,
-                    work: hex::encode(&header.chainwork),
-                    template_txs_fee_diff: None,
-                    txids: None,
-                    txids_added: None,
-                    txids_omitted: None,
-                    pool_name: None,
-                    total_fee: None,
-                    coinbase_message: None,
-                    tx_omitted_fee_rates: None,
-                    lowest_template_fee_rate: None,
-                };
+impl TemplateLatency {
+    /// Per pool, over the blocks matched with a template in the period.
+    pub fn list(
+        conn: &PgConnection,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> QueryResult<Vec<TemplateLatency>> {
+        let raw_query = "
+            SELECT
+                pool_name,
+                count(*) as blocks,
+                avg(template_latency) as mean_latency,
+                max(template_latency) as max_latency,
+                count(*) FILTER (WHERE stale_template) as stale_blocks,
+                avg(template_similarity) as mean_similarity
+            FROM blocks
+            WHERE template_matched_at >= $1 AND template_matched_at < $2
+                AND template_latency IS NOT NULL
+            GROUP BY pool_name
+            ORDER BY pool_name ASC
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Timestamptz, _>(from)
+            .bind::<sql_types::Timestamptz, _>(to)
+            .load(conn)
+    }
+}
 
-                conn.transaction::<usize, diesel::result::Error, _>(|| {
-                    let _ = block.insert(conn)?;
-                    diesel::update(bs::blocks.filter(bs::parent_hash.eq(header.hash.to_string())))
-                        .set(bs::connected.eq(true))
-                        .execute(conn)
-                })?;
+/// The serialized body of a block in the stale window, gzip compressed, so stale
+/// branches can be looked at after the nodes dropped them.
+#[derive(Debug, Queryable, Insertable)]
+#[table_name = "raw_blocks"]
+pub struct RawBlock {
+    pub block_hash: String,
+    pub height: i64,
+    /// The node the body was fetched from.
+    pub node_id: i64,
+    /// Bytes before compression.
+    pub size: i32,
+    pub compressed: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
 
-                Ok(block)
-            }
-            Ok(mut block) => {
-                block.headers_only &= headers_only;
-                block.update(&conn)?;
+impl RawBlock {
+    /// Compress a block as `getblock` returns it with verbosity 0.
+    pub fn new(block: &Block, node: i64, block_hex: &str) -> io::Result<RawBlock> {
+        let bytes =
+            hex::decode(block_hex).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&bytes)?;
 
-                Ok(block)
-            }
-            e => e,
-        }
+        Ok(RawBlock {
+            block_hash: block.hash.clone(),
+            height: block.height,
+            node_id: node,
+            size: bytes.len() as i32,
+            compressed: encoder.finish()?,
+            created_at: Utc::now(),
+        })
     }
 
-    /// Update the database with current block info.
-    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::blocks::dsl::*;
-        diesel::update(blocks.filter(hash.eq(&self.hash)))
-            .set(self)
-            .execute(conn)
+    pub fn block_hex(&self) -> io::Result<String> {
+        let mut bytes = Vec::with_capacity(self.size as usize);
+        GzDecoder::new(self.compressed.as_slice()).read_to_end(&mut bytes)?;
+        Ok(hex::encode(bytes))
     }
 
-    /// Node has marked block valid.
-    pub fn set_valid(conn: &PgConnection, block_hash: &String, node_id: i64) -> QueryResult<usize> {
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(raw_blocks::table)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+
+    pub fn get(conn: &PgConnection, hash: &str) -> QueryResult<Option<RawBlock>> {
+        raw_blocks::table.find(hash).first(conn).optional()
+    }
+
+    /// Note that no node had the body of a block, it is tried again later and later.
+    pub fn record_failed_fetch(conn: &PgConnection, hash: &str) -> QueryResult<usize> {
+        let raw_query = "
+            INSERT INTO raw_block_attempts (block_hash) VALUES ($1)
+            ON CONFLICT (block_hash) DO UPDATE
+            SET attempts = raw_block_attempts.attempts + 1, last_attempt_at = now()
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(hash)
+            .execute(conn)
+    }
+}
+
+#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "tx_outsets"]
+pub struct TxOutset {
+    pub block_hash: String,
+    pub node_id: i64,
+    pub txouts: i64,
+    pub total_amount: BigDecimal,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub inflated: bool,
+}
+
+impl TxOutset {
+    /// The UTXO set snapshots taken at a block, optionally only on one node.
+    pub fn list(conn: &PgConnection, block: &str, node: Option<i64>) -> QueryResult<Vec<TxOutset>> {
+        use crate::schema::tx_outsets::dsl::*;
+
+        let mut query = tx_outsets.filter(block_hash.eq(block)).into_boxed();
+        if let Some(node) = node {
+            query = query.filter(node_id.eq(node));
+        }
+
+        query.order(node_id.asc()).load(conn)
+    }
+
+    pub fn get(conn: &PgConnection, block: &String, node: i64) -> QueryResult<Option<TxOutset>> {
+        use crate::schema::tx_outsets::dsl::*;
+        let result = tx_outsets
+            .filter(block_hash.eq(block).and(node_id.eq(node)))
+            .get_result(conn);
+
+        match result {
+            Err(diesel::result::Error::NotFound) => Ok(None),
+            Ok(ans) => Ok(Some(ans)),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn create(
+        conn: &PgConnection,
+        tx_outs: u64,
+        amount: BigDecimal,
+        block: &str,
+        node: i64,
+    ) -> QueryResult<TxOutset> {
+        use crate::schema::tx_outsets::dsl::*;
+        let outset = TxOutset {
+            block_hash: block.to_string(),
+            node_id: node,
+            txouts: tx_outs as i64,
+            total_amount: amount,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            // TODO: this
+            inflated: false,
+        };
+        diesel::insert_into(tx_outsets)
+            .values(outset)
+            .get_result(conn)
+    }
+
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::tx_outsets::dsl::*;
+        diesel::update(
+            tx_outsets.filter(
+                block_hash
+                    .eq(&self.block_hash)
+                    .and(node_id.eq(self.node_id)),
+            ),
+        )
+        .set(self)
+        .execute(conn)
+    }
+}
+
+#[derive(Clone, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "softforks"]
+pub struct SoftForks {
+    pub node_id: i64,
+    pub fork_type: i32,
+    pub name: String,
+    pub bit: Option<i32>,
+    pub status: i32,
+    pub since: Option<i64>,
+    pub notified_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SoftForks {
+    pub fn update_or_insert(
+        conn: &PgConnection,
+        node: i64,
+        forks: HashMap<String, Softfork>,
+    ) -> QueryResult<()> {
+        use crate::schema::softforks::dsl::*;
+
+        for (key, info) in forks.into_iter() {
+            let b = if let Some(b9) = info.bip9 {
+                b9.bit.map(|b| b as i32)
+            } else {
+                None
+            };
+
+            let sf = SoftForks {
+                node_id: node,
+                fork_type: info.type_ as i32,
+                name: key,
+                bit: b,
+                status: info.active as i32,
+                since: info.height.map(|k| k as i64),
+                notified_at: Utc::now(),
+                updated_at: Utc::now(),
+                created_at: Utc::now(),
+            };
+            diesel::insert_into(softforks)
+                .values(&sf)
+                .on_conflict((node_id, fork_type, name))
+                .do_update()
+                .set(&sf)
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "blocks"]
+pub struct Block {
+    pub hash: String,
+    pub height: i64,
+    pub parent_hash: Option<String>,
+    pub connected: bool,
+    pub first_seen_by: i64,
+    pub headers_only: bool,
+    pub work: String,
+    pub txids: Option<Vec<u8>>,
+    pub txids_added: Option<Vec<u8>>,
+    pub txids_omitted: Option<Vec<u8>>,
+    pub pool_name: Option<String>,
+    #[serde(serialize_with = "serde_bigdecimal")]
+    pub template_txs_fee_diff: Option<BigDecimal>,
+    #[serde(serialize_with = "serde_bigdecimal")]
+    pub tx_omitted_fee_rates: Option<BigDecimal>,
+    #[serde(serialize_with = "serde_bigdecimal")]
+    pub lowest_template_fee_rate: Option<BigDecimal>,
+    #[serde(serialize_with = "serde_bigdecimal")]
+    pub total_fee: Option<BigDecimal>,
+    pub coinbase_message: Option<Vec<u8>>,
+    pub body_source: Option<String>,
+    pub body_source_node: Option<i64>,
+    /// The node whose template the block's transactions matched best.
+    pub template_match_node: Option<i64>,
+    /// Jaccard similarity of the block's and that template's transactions.
+    pub template_similarity: Option<f64>,
+    pub template_matched_at: Option<DateTime<Utc>>,
+    /// The peer the first node to see the block most likely got it from.
+    pub first_seen_peer: Option<String>,
+    /// When that peer last relayed a block to the node.
+    pub first_seen_peer_at: Option<DateTime<Utc>>,
+    /// Seconds from the template the block matched best being built to its arrival.
+    pub template_latency: Option<f64>,
+    /// Whether it was built on an outdated template, or on its parent's header alone.
+    pub stale_template: Option<bool>,
+}
+
+/// A block header as it was first stored, pushed to `subscribe_headers`.
+#[derive(Clone, Debug, Serialize)]
+pub struct NewHeader {
+    pub hash: String,
+    pub height: i64,
+    pub parent_hash: Option<String>,
+    pub work: String,
+    pub first_seen_by: i64,
+    pub headers_only: bool,
+    pub stored_at: DateTime<Utc>,
+}
+
+impl NewHeader {
+    pub fn new(block: &Block) -> NewHeader {
+        NewHeader {
+            hash: block.hash.clone(),
+            height: block.height,
+            parent_hash: block.parent_hash.clone(),
+            work: block.work.clone(),
+            first_seen_by: block.first_seen_by,
+            headers_only: block.headers_only,
+            stored_at: Utc::now(),
+        }
+    }
+}
+
+/// Which blocks `Block::list` returns, fields that are `None` don't filter.
+#[derive(Debug, Default)]
+pub struct BlockFilter {
+    pub min_height: Option<i64>,
+    pub max_height: Option<i64>,
+    pub pool_name: Option<String>,
+    pub headers_only: Option<bool>,
+}
+
+impl Block {
+    pub fn get_latest(conn: &PgConnection) -> QueryResult<Block> {
+        use crate::schema::blocks::dsl::*;
+        blocks.order_by(height.desc()).first(conn)
+    }
+
+    pub fn get_with_fee_no_diffs(conn: &PgConnection, min_height: i64) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks
+            .filter(
+                height
+                    .gt(min_height)
+                    .and(template_txs_fee_diff.is_null())
+                    .and(total_fee.is_not_null()),
+            )
+            .load(conn)
+    }
+
+    pub fn get(conn: &PgConnection, block_hash: &String) -> QueryResult<Block> {
+        use crate::schema::blocks::dsl::*;
+        blocks.find(block_hash).first(conn)
+    }
+
+    /// The blocks of `hashes` that are stored, in no particular order.
+    pub fn get_many(conn: &PgConnection, hashes: &[String]) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+        blocks.filter(hash.eq_any(hashes)).load(conn)
+    }
+
+    /// Blocks with transactions that are not compared to templates yet, but have
+    /// template snapshots for their parent.
+    pub fn without_template_match(conn: &PgConnection) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+        use crate::schema::template_snapshots::dsl as tsd;
+
+        let parents = tsd::template_snapshots
+            .select(tsd::parent_block_hash)
+            .nullable();
+        blocks
+            .filter(
+                txids
+                    .is_not_null()
+                    .and(template_similarity.is_null())
+                    .and(parent_hash.eq_any(parents)),
+            )
+            .order(height.asc())
+            .load(conn)
+    }
+
+    pub fn set_template_match(
+        &self,
+        conn: &PgConnection,
+        node: i64,
+        similarity: f64,
+        latency: Option<f64>,
+        stale: Option<bool>,
+    ) -> QueryResult<usize> {
+        use crate::schema::blocks::dsl::*;
+
+        diesel::update(blocks.find(&self.hash))
+            .set((
+                template_match_node.eq(node),
+                template_similarity.eq(similarity),
+                template_matched_at.eq(Utc::now()),
+                template_latency.eq(latency),
+                stale_template.eq(stale),
+            ))
+            .execute(conn)
+    }
+
+    /// Attribute the block to the peer that relayed it to `node`, unless another node
+    /// saw it first or it was already attributed.
+    pub fn set_first_seen_peer(
+        conn: &PgConnection,
+        block_hash: &str,
+        node: i64,
+        peer: &Peer,
+    ) -> QueryResult<usize> {
+        use crate::schema::blocks::dsl::*;
+
+        diesel::update(
+            blocks
+                .find(block_hash)
+                .filter(first_seen_by.eq(node))
+                .filter(first_seen_peer.is_null()),
+        )
+        .set((
+            first_seen_peer.eq(&peer.address),
+            first_seen_peer_at.eq(peer.last_block),
+        ))
+        .execute(conn)
+    }
+
+    /// How much more chainwork this block has than `behind`, and that deficit in
+    /// blocks at this block's difficulty.
+    pub fn work_deficit(&self, conn: &PgConnection, behind: &Block) -> (Option<i128>, Option<f64>) {
+        let work = parse_work(&self.work);
+        let deficit = match (work, parse_work(&behind.work)) {
+            (Some(a), Some(b)) => Some(a as i128 - b as i128),
+            _ => None,
+        };
+
+        let block_work = self
+            .parent(conn)
+            .ok()
+            .and_then(|p| parse_work(&p.work))
+            .and_then(|p| work.map(|w| w.saturating_sub(p)))
+            .filter(|w| *w > 0);
+        let deficit_blocks = match (deficit, block_work) {
+            (Some(d), Some(w)) => Some(d as f64 / w as f64),
+            _ => None,
+        };
+
+        (deficit, deficit_blocks)
+    }
+
+    /// Look up a parent block.
+    pub fn parent(&self, conn: &PgConnection) -> QueryResult<Block> {
+        use crate::schema::blocks::dsl::*;
+
+        if let Some(parent) = &self.parent_hash {
+            blocks.find(parent).first(conn)
+        } else {
+            Err(diesel::result::Error::NotFound)
+        }
+    }
+
+    pub fn count_at_height(conn: &PgConnection, block_height: i64) -> QueryResult<usize> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks.filter(height.eq(block_height)).execute(conn)
+    }
+
+    pub fn get_at_height(conn: &PgConnection, block_height: i64) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks.filter(height.eq(block_height)).load(conn)
+    }
+
+    /// Blocks matching `filter`, highest first, continuing below the `after` height
+    /// and hash of the last block of the previous page.
+    pub fn list(
+        conn: &PgConnection,
+        filter: &BlockFilter,
+        after: Option<(i64, String)>,
+        limit: i64,
+    ) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+
+        let mut query = blocks.into_boxed();
+        if let Some(min) = filter.min_height {
+            query = query.filter(height.ge(min));
+        }
+        if let Some(max) = filter.max_height {
+            query = query.filter(height.le(max));
+        }
+        if let Some(pool) = &filter.pool_name {
+            query = query.filter(pool_name.eq(pool));
+        }
+        if let Some(headers) = filter.headers_only {
+            query = query.filter(headers_only.eq(headers));
+        }
+        if let Some((last_height, last_hash)) = after {
+            query = query.filter(
+                height
+                    .lt(last_height)
+                    .or(height.eq(last_height).and(hash.lt(last_hash))),
+            );
+        }
+
+        query
+            .order((height.desc(), hash.desc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    pub fn find_stale_candidates(conn: &PgConnection, height: i64) -> QueryResult<Vec<Height>> {
+        let raw_query = format!(
+            "
+            SELECT height FROM blocks as b
+			LEFT JOIN invalid_blocks as ib
+			ON b.hash = ib.hash
+            WHERE height > {}
+			AND ib.node IS NULL
+            GROUP BY height
+            HAVING count(height) > 1
+            ORDER BY height ASC
+        ",
+            height
+        );
+
+        diesel::sql_query(raw_query).load(conn)
+    }
+
+    /// Blocks above `height` that have a competitor at their height and whose body
+    /// isn't archived yet, lowest first. After a failed fetch a block waits 2^attempts
+    /// minutes, and is given up on after `max_attempts`.
+    pub fn without_raw_block(
+        conn: &PgConnection,
+        height: i64,
+        max_attempts: i32,
+    ) -> QueryResult<Vec<Block>> {
+        let raw_query = "
+            SELECT b.* FROM blocks as b
+            LEFT JOIN raw_blocks as rb
+            ON b.hash = rb.block_hash
+            LEFT JOIN raw_block_attempts as ra
+            ON b.hash = ra.block_hash
+            WHERE b.height > $1
+            AND b.headers_only = false
+            AND rb.block_hash IS NULL
+            AND (
+                ra.block_hash IS NULL
+                OR (
+                    ra.attempts < $2
+                    AND ra.last_attempt_at < now() - interval '1 minute' * power(2, ra.attempts)
+                )
+            )
+            AND b.height IN (
+                SELECT height FROM blocks
+                WHERE height > $1
+                GROUP BY height
+                HAVING count(height) > 1
+            )
+            ORDER BY b.height ASC
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::BigInt, _>(height)
+            .bind::<sql_types::Integer, _>(max_attempts)
+            .load(conn)
+    }
+
+    pub fn num_transactions(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::transaction::dsl::*;
+
+        transaction.filter(block_id.eq(&self.hash)).execute(conn)
+    }
+
+    pub fn block_and_descendant_transactions(
+        &self,
+        conn: &PgConnection,
+        limit: i64,
+    ) -> QueryResult<Vec<Transaction>> {
+        use crate::schema::transaction::dsl::*;
+
+        let block_ids: Vec<_> = self
+            .descendants(conn, Some(limit))?
+            .iter()
+            .map(|b| b.hash.clone())
+            .collect();
+
+        transaction
+            .filter(is_coinbase.eq(false).and(block_id.eq_any(block_ids)))
+            .load(conn)
+    }
+
+    /// Fetch the entire list of descendants for the current block.
+    pub fn descendants(&self, conn: &PgConnection, limit: Option<i64>) -> QueryResult<Vec<Block>> {
+        let limit = match limit {
+            Some(limit) => format!("WHERE height <= {}", self.height + limit),
+            None => "".into(),
+        };
+
+        let raw_query = format!(
+            "
+            WITH RECURSIVE rec_query AS (
+                SELECT * FROM blocks WHERE hash = '{}'
+                UNION ALL
+                SELECT b.* FROM blocks b INNER JOIN rec_query r ON r.hash = b.parent_hash
+            ) SELECT * FROM rec_query
+			{}
+			ORDER BY height ASC;
+        ",
+            self.hash, limit
+        );
+
+        diesel::sql_query(raw_query).load(conn)
+    }
+
+    /// This block and its ancestors down to `min_height`.
+    pub fn ancestors(&self, conn: &PgConnection, min_height: i64) -> QueryResult<Vec<Block>> {
+        let raw_query = format!(
+            "
+            WITH RECURSIVE rec_query AS (
+                SELECT * FROM blocks WHERE hash = '{}'
+                UNION ALL
+                SELECT b.* FROM blocks b INNER JOIN rec_query r ON b.hash = r.parent_hash
+                WHERE r.height > {}
+            ) SELECT * FROM rec_query
+            ORDER BY height ASC;
+        ",
+            self.hash, min_height
+        );
+
+        diesel::sql_query(raw_query).load(conn)
+    }
+
+    /// The highest block both `a` and `b` descend from, not looking below `min_height`.
+    pub fn common_ancestor(
+        conn: &PgConnection,
+        a: &str,
+        b: &str,
+        min_height: i64,
+    ) -> QueryResult<Option<Block>> {
+        let raw_query = "
+            WITH RECURSIVE branch_a AS (
+                SELECT * FROM blocks WHERE hash = $1
+                UNION ALL
+                SELECT b.* FROM blocks b INNER JOIN branch_a r ON b.hash = r.parent_hash
+                WHERE r.height > $3
+            ), branch_b AS (
+                SELECT * FROM blocks WHERE hash = $2
+                UNION ALL
+                SELECT b.* FROM blocks b INNER JOIN branch_b r ON b.hash = r.parent_hash
+                WHERE r.height > $3
+            ) SELECT a.* FROM branch_a a
+            INNER JOIN branch_b b ON a.hash = b.hash
+            ORDER BY a.height DESC
+            LIMIT 1;
+        ";
+
+        let ancestors: Vec<Block> = diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(a)
+            .bind::<sql_types::Text, _>(b)
+            .bind::<sql_types::BigInt, _>(min_height)
+            .load(conn)?;
+        Ok(ancestors.into_iter().next())
+    }
+
+    /// Whether this block is `tip` or one of its ancestors.
+    pub fn is_ancestor_of(&self, conn: &PgConnection, tip: &Block) -> QueryResult<bool> {
+        if self.height > tip.height {
+            return Ok(false);
+        }
+        let ancestor = Block::common_ancestor(conn, &self.hash, &tip.hash, self.height)?;
+        Ok(ancestor.map(|a| a.hash).as_ref() == Some(&self.hash))
+    }
+
+    /// The nodes that had found the block valid by `at`, and the ones that had found it
+    /// invalid, as far as the validity tables still tell.
+    pub fn validity_as_of(
+        conn: &PgConnection,
+        block_hash: &str,
+        at: DateTime<Utc>,
+    ) -> QueryResult<(Vec<i64>, Vec<i64>)> {
+        let valid = valid_blocks::table
+            .filter(valid_blocks::hash.eq(block_hash))
+            .filter(valid_blocks::created_at.le(at))
+            .select(valid_blocks::node)
+            .order(valid_blocks::node)
+            .load(conn)?;
+        let invalid = invalid_blocks::table
+            .filter(invalid_blocks::hash.eq(block_hash))
+            .filter(invalid_blocks::created_at.le(at))
+            .select(invalid_blocks::node)
+            .order(invalid_blocks::node)
+            .load(conn)?;
+        Ok((valid, invalid))
+    }
+
+    /// The nodes whose active chain had this block at `at`, going by the tip history.
+    pub fn active_nodes_as_of(
+        &self,
+        conn: &PgConnection,
+        at: DateTime<Utc>,
+    ) -> QueryResult<Vec<i64>> {
+        let mut nodes = vec![];
+        for tip in TipHistory::as_of(conn, at, None)? {
+            if tip.status != "active" || tip.height < self.height {
+                continue;
+            }
+            let ancestor = Block::common_ancestor(conn, &self.hash, &tip.block_hash, self.height)?;
+            if ancestor.map(|a| a.hash).as_ref() == Some(&self.hash) {
+                nodes.push(tip.node_id);
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// The nodes whose active chain has this block.
+    pub fn active_nodes(&self, conn: &PgConnection) -> QueryResult<Vec<i64>> {
+        let mut nodes = vec![];
+        for tip in Chaintip::list_active(conn)? {
+            if tip.height < self.height {
+                continue;
+            }
+            let ancestor = Block::common_ancestor(conn, &self.hash, &tip.block, self.height)?;
+            if ancestor.map(|a| a.hash).as_ref() == Some(&self.hash) {
+                nodes.push(tip.node);
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Fetch the list of descendants for the current block ordered by work.
+    pub fn descendants_by_work(&self, conn: &PgConnection, limit: i64) -> QueryResult<Vec<Block>> {
+        let raw_query = format!(
+            "
+            WITH RECURSIVE rec_query AS (
+                SELECT * FROM blocks WHERE hash = '{}'
+                UNION ALL
+                SELECT b.* FROM blocks b INNER JOIN rec_query r ON r.hash = b.parent_hash
+            ) SELECT * FROM rec_query
+			WHERE height < {}
+			ORDER BY height,work ASC;
+        ",
+            self.hash, limit
+        );
+
+        diesel::sql_query(raw_query).load(conn)
+    }
+    /// Fetch all blocks that point to the block with a given hash.
+    pub fn children(conn: &PgConnection, block_hash: &String) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks.filter(parent_hash.eq(block_hash)).load(conn)
+    }
+
+    /// Highest block height.
+    pub fn max_height(conn: &PgConnection) -> QueryResult<Option<i64>> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks.select(diesel::dsl::max(height)).first(conn)
+    }
+
+    pub fn count(conn: &PgConnection) -> QueryResult<i64> {
+        use crate::schema::blocks::dsl::*;
+        blocks.count().get_result(conn)
+    }
+
+    /// The most recent blocks a node was the first to see.
+    pub fn first_seen(conn: &PgConnection, node: i64, limit: i64) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks
+            .filter(first_seen_by.eq(node))
+            .order(height.desc())
+            .limit(limit)
+            .load(conn)
+    }
+
+    /// Which blocks do we only have headers for?
+    pub fn headers_only(conn: &PgConnection, max_depth: i64) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl::*;
+
+        blocks
+            .filter(headers_only.eq(true).and(height.gt(max_depth)))
+            .order(height.asc())
+            .load(conn)
+    }
+
+    /// Fetch block if we have it, or create, and whether it was created.
+    pub fn get_or_create(
+        conn: &PgConnection,
+        headers_only: bool,
+        first_seen_by: i64,
+        header: &GetBlockHeaderResult,
+    ) -> QueryResult<(Block, bool)> {
+        use crate::schema::blocks::dsl as bs;
+        let block = bs::blocks
+            .find(header.hash.to_string())
+            .first::<Block>(conn);
+
+        match block {
+            Err(diesel::result::Error::NotFound) => {
+                let prev_hash = header.previous_block_hash.map(|h| h.to_string());
+
+                let block = Block {
+                    hash: header.hash.to_string(),
+                    height: header.height as i64,
+                    parent_hash: prev_hash,
+                    connected: false,
+                    headers_only,
+                    first_seen_by,
+                    work: hex::encode(&header.chainwork),
+                    template_txs_fee_diff: None,
+                    txids: None,
+                    txids_added: None,
+                    txids_omitted: None,
+                    pool_name: None,
+                    total_fee: None,
+                    coinbase_message: None,
+                    tx_omitted_fee_rates: None,
+                    lowest_template_fee_rate: None,
+                    body_source: None,
+                    body_source_node: None,
+                    template_match_node: None,
+                    template_similarity: None,
+                    template_matched_at: None,
+                    first_seen_peer: None,
+                    first_seen_peer_at: None,
+                    template_latency: None,
+                    stale_template: None,
+                };
+
+                conn.transaction::<usize, diesel::result::Error, _>(|| {
+                    let _ = block.insert(conn)?;
+                    diesel::update(bs::blocks.filter(bs::parent_hash.eq(header.hash.to_string())))
+                        .set(bs::connected.eq(true))
+                        .execute(conn)
+                })?;
+
+                Ok((block, true))
+            }
+            Ok(mut block) => {
+                block.headers_only &= headers_only;
+                block.update(conn)?;
+
+                Ok((block, false))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Update the database with current block info.
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::blocks::dsl::*;
+        diesel::update(blocks.filter(hash.eq(&self.hash)))
+            .set(self)
+            .execute(conn)
+    }
+
+    /// Node has marked block valid.
+    pub fn set_valid(conn: &PgConnection, block_hash: &String, node_id: i64) -> QueryResult<usize> {
+        use crate::schema::valid_blocks::dsl::*;
+
+        let block = ValidBlock {
+            hash: block_hash.to_string(),
+            node: node_id,
+            created_at: Some(Utc::now()),
+        };
+
+        diesel::insert_into(valid_blocks)
+            .values(block)
+            .on_conflict((hash, node))
+            .do_nothing()
+            .execute(conn)
+    }
+
+    /// Has block_hash been marked invalid by this node?
+    pub fn marked_invalid_by(
+        conn: &PgConnection,
+        block_hash: &String,
+        node_id: i64,
+    ) -> QueryResult<bool> {
+        use crate::schema::invalid_blocks::dsl::*;
+
+        let rows = invalid_blocks
+            .filter(hash.eq(block_hash).and(node.eq(node_id)))
+            .execute(conn)?;
+        Ok(rows > 0)
+    }
+
+    /// Has block_hash been marked valid by this node?
+    pub fn marked_valid_by(
+        conn: &PgConnection,
+        block_hash: &String,
+        node_id: i64,
+    ) -> QueryResult<bool> {
         use crate::schema::valid_blocks::dsl::*;
 
-        let block = ValidBlock {
-            hash: block_hash.to_string(),
-            node: node_id,
-            created_at: Some(Utc::now()),
-        };
+        let rows = valid_blocks
+            .filter(hash.eq(block_hash).and(node.eq(node_id)))
+            .execute(conn)?;
+        Ok(rows > 0)
+    }
+
+    /// Fetch number of nodes that marked invalid.
+    pub fn marked_invalid(conn: &PgConnection, block_hash: &String) -> QueryResult<usize> {
+        use crate::schema::invalid_blocks::dsl::*;
+
+        invalid_blocks.filter(hash.eq(block_hash)).execute(conn)
+    }
+
+    /// Node has marked block invalid.
+    pub fn set_invalid(
+        conn: &PgConnection,
+        block_hash: &String,
+        node_id: i64,
+    ) -> QueryResult<usize> {
+        use crate::schema::invalid_blocks::dsl::*;
+
+        let block = InvalidBlock {
+            hash: block_hash.to_string(),
+            node: node_id,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        diesel::insert_into(invalid_blocks)
+            .values(&block)
+            .on_conflict((hash, node))
+            .do_update()
+            .set(updated_at.eq(block.updated_at))
+            .execute(conn)
+    }
+
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(blocks::dsl::blocks)
+            .values(self)
+            .execute(conn)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, QueryableByName, Queryable, Insertable)]
+#[table_name = "transaction"]
+pub struct Transaction {
+    pub block_id: String,
+    pub txid: String,
+    pub is_coinbase: bool,
+    pub hex: String,
+    #[serde(serialize_with = "serde_btc")]
+    pub amount: f64,
+    pub swept: Option<bool>,
+}
+
+impl Transaction {
+    pub fn create(
+        conn: &PgConnection,
+        sweep: bool,
+        block: String,
+        idx: usize,
+        tx_id: &str,
+        tx_hex: &str,
+        tx_amount: f64,
+    ) -> QueryResult<usize> {
+        use crate::schema::transaction::dsl::*;
+
+        let tx = Transaction {
+            block_id: block,
+            is_coinbase: idx == 0,
+            txid: tx_id.to_string(),
+            hex: tx_hex.to_string(),
+            amount: tx_amount,
+            swept: Some(sweep),
+        };
+
+        diesel::insert_into(transaction)
+            .values(tx)
+            .on_conflict((block_id, txid))
+            .do_nothing()
+            .execute(conn)
+    }
+
+    pub fn amount_for_txs(conn: &PgConnection, txids: &Vec<String>) -> QueryResult<f64> {
+        use crate::schema::transaction::dsl::*;
+
+        let results: Vec<Option<f64>> = transaction
+            .filter(txid.eq_any(txids))
+            .group_by(txid)
+            .select(diesel::dsl::max(amount))
+            .load(conn)?;
+        Ok(results
+            .into_iter()
+            .fold(0.0, |a, b| a + b.unwrap_or_default()))
+    }
+
+    pub fn tx_block_and_descendants(conn: &PgConnection, id: String) -> QueryResult<Vec<Block>> {
+        use crate::schema::blocks::dsl as bdsl;
+        use crate::schema::transaction::dsl::*;
+
+        let mut blocks: Vec<Block> = transaction
+            .inner_join(bdsl::blocks)
+            .filter(txid.eq(&id))
+            .load::<(Transaction, Block)>(conn)?
+            .into_iter()
+            .map(|(_, block)| block)
+            .collect();
+        // blocks whose transactions weren't fetched are indexed by txid too
+        blocks.extend(
+            BlockTransaction::blocks_with(conn, &id)?
+                .into_iter()
+                .map(|(_, block)| block),
+        );
+        blocks.sort_by(|a, b| a.hash.cmp(&b.hash));
+        blocks.dedup_by(|a, b| a.hash == b.hash);
+
+        let descendants: Vec<Block> = blocks
+            .into_iter()
+            .filter_map(|b| b.descendants(conn, None).ok())
+            .flatten()
+            .collect();
+        Ok(descendants)
+    }
+
+    /// Every copy of the transactions, one per block they are in, with the block.
+    pub fn with_blocks(
+        conn: &PgConnection,
+        txids: &[String],
+    ) -> QueryResult<Vec<(Transaction, Block)>> {
+        use crate::schema::blocks::dsl as bdsl;
+        use crate::schema::transaction::dsl::*;
+
+        transaction
+            .inner_join(bdsl::blocks)
+            .filter(txid.eq_any(txids))
+            .order(bdsl::height.asc())
+            .load(conn)
+    }
+
+    /// The stored transactions of a block, the coinbase first.
+    pub fn of_block(conn: &PgConnection, hash: &str, limit: i64) -> QueryResult<Vec<Transaction>> {
+        use crate::schema::transaction::dsl::*;
+
+        transaction
+            .filter(block_id.eq(hash))
+            .order((is_coinbase.desc(), txid.asc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    pub fn block_processed(conn: &PgConnection, hash: &String) -> QueryResult<bool> {
+        use crate::schema::transaction::dsl::*;
+        let result: Vec<Transaction> = transaction.filter(block_id.eq(hash)).load(conn)?;
+        Ok(!result.is_empty())
+    }
+}
+
+#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "stale_candidate_children"]
+pub struct StaleCandidateChildren {
+    pub candidate_height: i64,
+    pub root_id: String,
+    pub tip_id: String,
+    pub len: i32,
+}
+
+impl StaleCandidateChildren {
+    pub fn create(
+        conn: &PgConnection,
+        root: &Block,
+        tip: &Block,
+        branch_len: i32,
+    ) -> QueryResult<usize> {
+        use crate::schema::stale_candidate_children::dsl::*;
+
+        let c = StaleCandidateChildren {
+            candidate_height: root.height,
+            root_id: root.hash.clone(),
+            tip_id: tip.hash.clone(),
+            len: branch_len,
+        };
+
+        diesel::insert_into(stale_candidate_children)
+            .values(c)
+            .execute(conn)
+    }
+
+    pub fn purge(conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::stale_candidate_children::dsl::*;
+
+        diesel::delete(stale_candidate_children).execute(conn)
+    }
+}
+
+#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "rbf_by"]
+pub struct RbfBy {
+    pub candidate_height: i64,
+    pub txid: String,
+}
+
+#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "double_spent_by"]
+pub struct DoubleSpentBy {
+    pub candidate_height: i64,
+    pub txid: String,
+}
+
+/// What the double spends or replacements of a stale candidate took from one
+/// destination address, `kind` is `double_spent` or `rbf`. `amount` is the BTC paid to
+/// the address by the `txs` conflicting transactions of the shorter branches.
+#[derive(
+    Clone, Debug, PartialEq, QueryableByName, Queryable, Insertable, Serialize, JsonSchema,
+)]
+#[table_name = "conflict_addresses"]
+pub struct ConflictAddress {
+    pub candidate_height: i64,
+    pub kind: String,
+    pub address: String,
+    #[serde(serialize_with = "serde_btc")]
+    #[schemars(schema_with = "btc_schema")]
+    pub amount: f64,
+    pub txs: i32,
+}
+
+impl ConflictAddress {
+    /// Conflicts that took from `of`, or from any address, the latest candidates and
+    /// largest amounts first.
+    pub fn list(
+        conn: &PgConnection,
+        of: Option<String>,
+        limit: i64,
+    ) -> QueryResult<Vec<ConflictAddress>> {
+        use crate::schema::conflict_addresses::dsl::*;
+
+        let mut query = conflict_addresses.into_boxed();
+
+        if let Some(a) = of {
+            query = query.filter(address.eq(a));
+        }
+
+        query
+            .order_by((candidate_height.desc(), amount.desc(), address))
+            .limit(limit)
+            .load(conn)
+    }
+}
+
+#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "stale_candidate"]
+pub struct StaleCandidate {
+    pub height: i64,
+    pub n_children: i32,
+    pub confirmed_in_one_branch_total: f64,
+    pub double_spent_in_one_branch_total: f64,
+    pub rbf_total: f64,
+    pub height_processed: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub source: String,
+}
+
+impl StaleCandidate {
+    pub fn list_ge(conn: &PgConnection, block_height: i64) -> QueryResult<Vec<StaleCandidate>> {
+        use crate::schema::stale_candidate::dsl::*;
+        stale_candidate.filter(height.ge(block_height)).load(conn)
+    }
+
+    pub fn get(conn: &PgConnection, candidate: i64) -> QueryResult<StaleCandidate> {
+        use crate::schema::stale_candidate::dsl::*;
+        stale_candidate.find(candidate).first(conn)
+    }
+
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::stale_candidate::dsl::*;
+        diesel::update(stale_candidate.filter(height.eq(self.height)))
+            .set(self)
+            .execute(conn)
+    }
+
+    pub fn update_rbf_by(&self, conn: &PgConnection, txids: &[String]) -> QueryResult<usize> {
+        use crate::schema::rbf_by::dsl::*;
+
+        let rbfs: Vec<RbfBy> = txids
+            .iter()
+            .map(|tx| RbfBy {
+                candidate_height: self.height,
+                txid: tx.clone(),
+            })
+            .collect();
+
+        diesel::insert_into(rbf_by).values(rbfs).execute(conn)
+    }
+
+    pub fn update_double_spent_by(
+        &self,
+        conn: &PgConnection,
+        txids: &[String],
+    ) -> QueryResult<usize> {
+        use crate::schema::double_spent_by::dsl::*;
+        let double_spends: Vec<DoubleSpentBy> = txids
+            .iter()
+            .map(|tx| DoubleSpentBy {
+                candidate_height: self.height,
+                txid: tx.clone(),
+            })
+            .collect();
+
+        diesel::insert_into(double_spent_by)
+            .values(double_spends)
+            .execute(conn)
+    }
+
+    /// Replace the per address breakdown of the candidate's conflicts, they are
+    /// recomputed with the totals on every pass.
+    pub fn update_conflict_addresses(
+        &self,
+        conn: &PgConnection,
+        addresses: &[ConflictAddress],
+    ) -> QueryResult<usize> {
+        use crate::schema::conflict_addresses::dsl::*;
+
+        conn.transaction(|| {
+            diesel::delete(conflict_addresses)
+                .filter(candidate_height.eq(self.height))
+                .execute(conn)?;
+
+            diesel::insert_into(conflict_addresses)
+                .values(addresses)
+                .execute(conn)
+        })
+    }
+
+    pub fn conflict_addresses(&self, conn: &PgConnection) -> QueryResult<Vec<ConflictAddress>> {
+        use crate::schema::conflict_addresses::dsl::*;
+
+        conflict_addresses
+            .filter(candidate_height.eq(self.height))
+            .order_by((kind, amount.desc(), address))
+            .load(conn)
+    }
+
+    pub fn double_spent_txids(&self, conn: &PgConnection) -> QueryResult<Vec<String>> {
+        use crate::schema::double_spent_by::dsl::*;
+
+        double_spent_by
+            .filter(candidate_height.eq(self.height))
+            .select(txid)
+            .order_by(txid)
+            .load(conn)
+    }
+
+    pub fn children(&self, conn: &PgConnection) -> QueryResult<Vec<StaleCandidateChildren>> {
+        use crate::schema::stale_candidate_children::dsl::*;
+
+        stale_candidate_children
+            .filter(candidate_height.eq(self.height))
+            .order_by(len)
+            .load(conn)
+    }
+
+    pub fn purge_children(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::stale_candidate_children::dsl::*;
+
+        diesel::delete(stale_candidate_children)
+            .filter(candidate_height.eq(self.height))
+            .execute(conn)
+    }
+
+    pub fn create(conn: &PgConnection, candidate_height: i64, children: i32) -> QueryResult<usize> {
+        use crate::schema::stale_candidate::dsl::*;
+
+        let candidate = StaleCandidate {
+            n_children: children,
+            height: candidate_height,
+            confirmed_in_one_branch_total: 0.,
+            double_spent_in_one_branch_total: 0.,
+            rbf_total: 0.,
+            height_processed: None,
+            created_at: Utc::now(),
+            source: STALE_SOURCE_LOCAL.into(),
+        };
+
+        diesel::insert_into(stale_candidate)
+            .values(candidate)
+            .on_conflict(height)
+            .do_nothing()
+            .execute(conn)
+    }
+
+    pub fn top_n(conn: &PgConnection, n: i64) -> QueryResult<Vec<StaleCandidate>> {
+        use crate::schema::stale_candidate::dsl::*;
+
+        stale_candidate.order_by(height.desc()).limit(n).load(conn)
+    }
+
+    pub fn list(
+        conn: &PgConnection,
+        from_source: Option<String>,
+        n: i64,
+    ) -> QueryResult<Vec<StaleCandidate>> {
+        use crate::schema::stale_candidate::dsl::*;
+
+        let mut query = stale_candidate.into_boxed();
+
+        if let Some(s) = from_source {
+            query = query.filter(source.eq(s));
+        }
+
+        query.order_by(height.desc()).limit(n).load(conn)
+    }
+
+    /// Delete the candidates the scanner found below `below` with their branches, see
+    /// `RetentionPolicy`.
+    pub fn prune_below(conn: &PgConnection, below: i64) -> QueryResult<usize> {
+        use crate::schema::stale_candidate::dsl::*;
+
+        conn.transaction(|| {
+            let pruned = stale_candidate
+                .filter(height.lt(below))
+                .filter(source.eq(STALE_SOURCE_LOCAL))
+                .select(height);
+
+            diesel::delete(stale_candidate_children::table)
+                .filter(stale_candidate_children::candidate_height.eq_any(pruned))
+                .execute(conn)?;
+            diesel::delete(stale_candidate)
+                .filter(height.lt(below))
+                .filter(source.eq(STALE_SOURCE_LOCAL))
+                .execute(conn)
+        })
+    }
+
+    pub fn imported(&self, conn: &PgConnection) -> QueryResult<Vec<ImportedStaleBlock>> {
+        use crate::schema::imported_stale_blocks::dsl::*;
+
+        imported_stale_blocks
+            .filter(height.eq(self.height))
+            .order_by(hash)
+            .load(conn)
+    }
+}
+
+/// A stale block from an external dataset, for forks that happened before this
+/// deployment was watching.
+#[derive(Clone, Debug, PartialEq, QueryableByName, Queryable, Insertable, Serialize)]
+#[table_name = "imported_stale_blocks"]
+pub struct ImportedStaleBlock {
+    pub hash: String,
+    pub height: i64,
+    pub parent_hash: Option<String>,
+    pub source: String,
+    pub imported_at: DateTime<Utc>,
+}
+
+impl ImportedStaleBlock {
+    /// Store the blocks, adding a stale candidate for every height that doesn't have one
+    /// yet. Returns how many blocks were new.
+    pub fn import(conn: &PgConnection, blocks: Vec<ImportedStaleBlock>) -> QueryResult<usize> {
+        let mut by_height: BTreeMap<i64, Vec<ImportedStaleBlock>> = BTreeMap::new();
+        for block in blocks {
+            by_height.entry(block.height).or_default().push(block);
+        }
+
+        conn.transaction(|| {
+            let mut imported = 0;
+
+            for (block_height, blocks) in by_height {
+                use crate::schema::stale_candidate::dsl::*;
+
+                // the stale blocks and the one that made it into the main chain.
+                let candidate = StaleCandidate {
+                    height: block_height,
+                    n_children: blocks.len() as i32 + 1,
+                    confirmed_in_one_branch_total: 0.,
+                    double_spent_in_one_branch_total: 0.,
+                    rbf_total: 0.,
+                    height_processed: None,
+                    created_at: blocks[0].imported_at,
+                    source: blocks[0].source.clone(),
+                };
+
+                diesel::insert_into(stale_candidate)
+                    .values(candidate)
+                    .on_conflict(height)
+                    .do_nothing()
+                    .execute(conn)?;
+
+                imported += diesel::insert_into(imported_stale_blocks::table)
+                    .values(&blocks)
+                    .on_conflict(imported_stale_blocks::hash)
+                    .do_nothing()
+                    .execute(conn)?;
+            }
+
+            Ok(imported)
+        })
+    }
+}
+
+#[derive(AsChangeset, Clone, QueryableByName, Queryable, Insertable)]
+#[table_name = "nodes"]
+pub struct Node {
+    pub id: i64,
+    pub node: String,
+    pub rpc_host: String,
+    pub rpc_port: i32,
+    pub mirror_rpc_port: Option<i32>,
+    pub rpc_user: String,
+    pub rpc_pass: String,
+    pub unreachable_since: Option<DateTime<Utc>>,
+    pub last_polled: Option<DateTime<Utc>>,
+    pub initial_block_download: bool,
+    pub mirror_host: Option<String>,
+    pub mirror_last_polled: Option<DateTime<Utc>>,
+    pub mirror_unreachable_since: Option<i64>,
+    pub archive: bool,
+    pub zmq_endpoint: Option<String>,
+    pub fetch_templates: bool,
+    pub fetch_peers: bool,
+    pub participate_in_consensus: bool,
+    pub allow_mirror_ops: bool,
+    /// The location or provider group the node is in, e.g. a cloud region.
+    pub node_group: Option<String>,
+    pub rpc_connect_timeout_secs: Option<i32>,
+    pub rpc_read_timeout_secs: Option<i32>,
+}
+
+/// A node to add, `rpc_pass` is sealed already.
+#[derive(Insertable)]
+#[table_name = "nodes"]
+pub struct NewNode {
+    pub node: String,
+    pub rpc_host: String,
+    pub rpc_port: i32,
+    pub mirror_rpc_port: Option<i32>,
+    pub rpc_user: String,
+    pub rpc_pass: String,
+    pub mirror_host: Option<String>,
+    pub archive: bool,
+    pub zmq_endpoint: Option<String>,
+    #[diesel(embed)]
+    pub flags: NodeFlags,
+}
+
+/// Per node opt outs of scanner work, flags that are not given keep their current
+/// value, or default to enabled for new nodes.
+#[derive(Debug, Default, Deserialize, JsonSchema, AsChangeset, Insertable)]
+#[table_name = "nodes"]
+pub struct NodeFlags {
+    /// Poll block templates from this node.
+    pub fetch_templates: Option<bool>,
+    /// Record this node's peers.
+    pub fetch_peers: Option<bool>,
+    /// Count this node's tips when deciding which nodes lag and which tip most
+    /// nodes agree on.
+    pub participate_in_consensus: Option<bool>,
+    /// Run rollback, inflation and block fetching work on this node's mirror.
+    pub allow_mirror_ops: Option<bool>,
+}
+
+/// RPC timeouts of a node, 15 seconds each when not set.
+#[derive(Debug, Default, Deserialize, JsonSchema, AsChangeset)]
+#[table_name = "nodes"]
+pub struct NodeTimeouts {
+    /// Seconds to wait for the node to accept a connection.
+    pub rpc_connect_timeout_secs: Option<i32>,
+    /// Seconds to wait for each read of an answer, a call answered slowly but
+    /// steadily can take longer.
+    pub rpc_read_timeout_secs: Option<i32>,
+}
+
+impl NodeTimeouts {
+    pub fn is_empty(&self) -> bool {
+        self.rpc_connect_timeout_secs.is_none() && self.rpc_read_timeout_secs.is_none()
+    }
+
+    /// Timeouts given are at least a second.
+    pub fn is_valid(&self) -> bool {
+        let valid = |secs: Option<i32>| !matches!(secs, Some(s) if s <= 0);
+        valid(self.rpc_connect_timeout_secs) && valid(self.rpc_read_timeout_secs)
+    }
+}
+
+impl Node {
+    pub fn get(conn: &PgConnection, node_id: i64) -> QueryResult<Node> {
+        use crate::schema::nodes::dsl::*;
+        nodes.filter(id.eq(node_id)).get_result(conn)
+    }
+
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<Node>> {
+        nodes::dsl::nodes.load(conn)
+    }
+
+    /// Fetch nodes that also have a mirror.
+    pub fn get_mirrors(conn: &PgConnection) -> QueryResult<Vec<Node>> {
+        use crate::schema::nodes::dsl::*;
+        nodes.filter(mirror_rpc_port.is_not_null()).load(conn)
+    }
+
+    pub fn remove(conn: &PgConnection, node_id: i64) -> QueryResult<usize> {
+        use crate::schema::nodes::dsl::*;
+        diesel::delete(nodes).filter(id.eq(node_id)).execute(conn)
+    }
+
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::nodes::dsl::*;
+        diesel::update(nodes.filter(id.eq(self.id)))
+            .set(self)
+            .execute(conn)
+    }
+
+    pub fn get_active_reachable(conn: &PgConnection) -> QueryResult<Vec<Node>> {
+        use crate::schema::nodes::dsl::*;
+        nodes
+            .filter(
+                mirror_rpc_port.is_not_null().and(
+                    initial_block_download
+                        .eq(false)
+                        .and(mirror_unreachable_since.is_null()),
+                ),
+            )
+            .load(conn)
+    }
+
+    pub fn insert(conn: &PgConnection, new_node: NewNode) -> QueryResult<Node> {
+        use crate::schema::nodes::dsl::*;
+        diesel::insert_into(nodes).values(new_node).get_result(conn)
+    }
+
+    pub fn set_password(conn: &PgConnection, node_id: i64, pass: String) -> QueryResult<usize> {
+        use crate::schema::nodes::dsl::*;
+        diesel::update(nodes.filter(id.eq(node_id)))
+            .set(rpc_pass.eq(pass))
+            .execute(conn)
+    }
+
+    pub fn set_flags(conn: &PgConnection, node_id: i64, flags: &NodeFlags) -> QueryResult<Node> {
+        use crate::schema::nodes::dsl::*;
+        diesel::update(nodes.filter(id.eq(node_id)))
+            .set(flags)
+            .get_result(conn)
+    }
+
+    pub fn set_group(
+        conn: &PgConnection,
+        node_id: i64,
+        group: Option<String>,
+    ) -> QueryResult<Node> {
+        use crate::schema::nodes::dsl::*;
+        diesel::update(nodes.filter(id.eq(node_id)))
+            .set(node_group.eq(group))
+            .get_result(conn)
+    }
+
+    pub fn set_timeouts(
+        conn: &PgConnection,
+        node_id: i64,
+        timeouts: &NodeTimeouts,
+    ) -> QueryResult<Node> {
+        use crate::schema::nodes::dsl::*;
+        diesel::update(nodes.filter(id.eq(node_id)))
+            .set(timeouts)
+            .get_result(conn)
+    }
+}
+
+/// A time a node is down for maintenance, its lag and scan errors aren't alerted and
+/// its tips don't count towards consensus. Windows are dropped once they end.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable)]
+pub struct MaintenanceWindow {
+    pub id: i64,
+    pub node_id: i64,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    pub fn insert(
+        conn: &PgConnection,
+        node: i64,
+        starts: DateTime<Utc>,
+        ends: DateTime<Utc>,
+        why: Option<String>,
+    ) -> QueryResult<MaintenanceWindow> {
+        use crate::schema::maintenance_windows::dsl::*;
+
+        diesel::insert_into(maintenance_windows)
+            .values((
+                node_id.eq(node),
+                starts_at.eq(starts),
+                ends_at.eq(ends),
+                reason.eq(why),
+            ))
+            .get_result(conn)
+    }
+
+    pub fn remove(conn: &PgConnection, window: i64) -> QueryResult<usize> {
+        use crate::schema::maintenance_windows::dsl::*;
+        diesel::delete(maintenance_windows.filter(id.eq(window))).execute(conn)
+    }
+
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<MaintenanceWindow>> {
+        use crate::schema::maintenance_windows::dsl::*;
+
+        maintenance_windows
+            .order((starts_at.asc(), id.asc()))
+            .load(conn)
+    }
+
+    /// Nodes in a window that has started and not ended yet.
+    pub fn active_nodes(conn: &PgConnection) -> QueryResult<HashSet<i64>> {
+        use crate::schema::maintenance_windows::dsl::*;
+
+        let now = Utc::now();
+        let nodes: Vec<i64> = maintenance_windows
+            .select(node_id)
+            .filter(starts_at.le(now).and(ends_at.gt(now)))
+            .load(conn)?;
+        Ok(nodes.into_iter().collect())
+    }
+
+    /// Drop windows that have ended.
+    pub fn clear(conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::maintenance_windows::dsl::*;
+        diesel::delete(maintenance_windows.filter(ends_at.le(Utc::now()))).execute(conn)
+    }
+}
+
+/// The active tip most consensus nodes of a group agree on. The `group` of the
+/// consensus over all of them is `None`.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct GroupConsensus {
+    pub group: Option<String>,
+    pub nodes: Vec<i64>,
+    pub tip: String,
+    pub height: i64,
+    /// The nodes whose active tip is `tip`.
+    pub agreeing: Vec<i64>,
+    /// The nodes two or more blocks behind the highest tip of the group.
+    pub lagging: Vec<i64>,
+}
+
+impl GroupConsensus {
+    /// The consensus of every node group, then over all nodes.
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<GroupConsensus>> {
+        let maintenance = MaintenanceWindow::active_nodes(conn)?;
+        let groups: HashMap<_, _> = Node::list(conn)?
+            .into_iter()
+            .filter(|n| n.participate_in_consensus && !maintenance.contains(&n.id))
+            .map(|n| (n.id, n.node_group))
+            .collect();
+        let tips: Vec<_> = Chaintip::list_active(conn)?
+            .into_iter()
+            .filter(|t| groups.contains_key(&t.node))
+            .collect();
+
+        Ok(group_consensus(&tips, &groups))
+    }
+
+    fn new(group: Option<String>, tips: &[&Chaintip]) -> Option<GroupConsensus> {
+        let counts = tips
+            .iter()
+            .map(|t| t.block.clone())
+            .collect::<counter::Counter<_>>();
+        let best = tips.iter().max_by_key(|t| (counts[&t.block], t.height))?;
+        let max_height = tips.iter().map(|t| t.height).max()?;
+
+        Some(GroupConsensus {
+            group,
+            nodes: tips.iter().map(|t| t.node).collect(),
+            tip: best.block.clone(),
+            height: best.height,
+            agreeing: tips
+                .iter()
+                .filter(|t| t.block == best.block)
+                .map(|t| t.node)
+                .collect(),
+            lagging: tips
+                .iter()
+                .filter(|t| t.height < max_height - 1)
+                .map(|t| t.node)
+                .collect(),
+        })
+    }
+}
+
+/// The consensus of the active tips of every node group, by group name, and over all
+/// of them last. Nodes without a group only count in the last.
+pub fn group_consensus(
+    tips: &[Chaintip],
+    groups: &HashMap<i64, Option<String>>,
+) -> Vec<GroupConsensus> {
+    let mut by_group: BTreeMap<&String, Vec<&Chaintip>> = BTreeMap::new();
+    for tip in tips {
+        if let Some(Some(group)) = groups.get(&tip.node) {
+            by_group.entry(group).or_default().push(tip);
+        }
+    }
+
+    let all: Vec<_> = tips.iter().collect();
+    by_group
+        .into_iter()
+        .filter_map(|(group, tips)| GroupConsensus::new(Some(group.clone()), &tips))
+        .chain(GroupConsensus::new(None, &all))
+        .collect()
+}
+
+/// A node group whose consensus tip is on another branch than the consensus over all
+/// nodes, neither one descends from the other.
+#[derive(Clone, Debug, Serialize)]
+pub struct GroupSplit {
+    pub group: String,
+    pub tip: String,
+    pub height: i64,
+    pub nodes: Vec<i64>,
+    pub global_tip: String,
+    pub global_height: i64,
+    /// Whether every other group agrees with the global tip, i.e. a partition of this
+    /// group rather than a split of the whole network.
+    pub isolated: bool,
+}
+
+/// A tip an external watcher, e.g. a remote light client, saw, submitted with
+/// `submit_external_tip`.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable)]
+pub struct ExternalObservation {
+    pub id: i64,
+    pub source: String,
+    pub block_hash: String,
+    pub height: i64,
+    pub observed_at: DateTime<Utc>,
+}
+
+impl ExternalObservation {
+    pub fn insert(
+        conn: &PgConnection,
+        from: &str,
+        hash: &str,
+        block_height: i64,
+    ) -> QueryResult<ExternalObservation> {
+        use crate::schema::external_observations::dsl::*;
+
+        diesel::insert_into(external_observations)
+            .values((
+                source.eq(from),
+                block_hash.eq(hash),
+                height.eq(block_height),
+            ))
+            .get_result(conn)
+    }
+
+    /// Observations of `from`, or of any source, newest first.
+    pub fn list(
+        conn: &PgConnection,
+        from: Option<String>,
+        limit: i64,
+    ) -> QueryResult<Vec<ExternalObservation>> {
+        use crate::schema::external_observations::dsl::*;
+
+        let mut query = external_observations.into_boxed();
+
+        if let Some(s) = from {
+            query = query.filter(source.eq(s));
+        }
+
+        query
+            .order_by((observed_at.desc(), id.desc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    /// The latest observation of each source, of the ones made since `since`.
+    pub fn latest(
+        conn: &PgConnection,
+        since: DateTime<Utc>,
+    ) -> QueryResult<Vec<ExternalObservation>> {
+        use crate::schema::external_observations::dsl::*;
+
+        let observations: Vec<ExternalObservation> = external_observations
+            .filter(observed_at.ge(since))
+            .order_by((observed_at.desc(), id.desc()))
+            .load(conn)?;
+
+        let mut seen = HashSet::new();
+        Ok(observations
+            .into_iter()
+            .filter(|o| seen.insert(o.source.clone()))
+            .collect())
+    }
+
+    /// Delete the observations made before `since`.
+    pub fn prune_before(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<usize> {
+        use crate::schema::external_observations::dsl::*;
+
+        diesel::delete(external_observations)
+            .filter(observed_at.lt(since))
+            .execute(conn)
+    }
+}
+
+/// How far the history fetched on the first run got, walking down from the tip the
+/// archive node had then. `next_hash` is the next block to fetch, none once done.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable)]
+pub struct BootstrapProgress {
+    pub id: i64,
+    pub depth: i64,
+    pub tip_hash: String,
+    pub tip_height: i64,
+    pub next_hash: Option<String>,
+    pub next_height: i64,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl BootstrapProgress {
+    /// The bootstrap of this database, if one was started.
+    pub fn get(conn: &PgConnection) -> QueryResult<Option<BootstrapProgress>> {
+        use crate::schema::bootstrap_progress::dsl::*;
+
+        bootstrap_progress
+            .order_by(id.desc())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn start(
+        conn: &PgConnection,
+        blocks: i64,
+        hash: &str,
+        height: i64,
+    ) -> QueryResult<BootstrapProgress> {
+        use crate::schema::bootstrap_progress::dsl::*;
+
+        diesel::insert_into(bootstrap_progress)
+            .values((
+                depth.eq(blocks),
+                tip_hash.eq(hash),
+                tip_height.eq(height),
+                next_hash.eq(hash),
+                next_height.eq(height),
+            ))
+            .get_result(conn)
+    }
+
+    /// Record that the blocks above `height` are stored, and `hash` is the next one
+    /// to fetch. Without one the bootstrap is finished.
+    pub fn advance(
+        &mut self,
+        conn: &PgConnection,
+        hash: Option<String>,
+        height: i64,
+    ) -> QueryResult<()> {
+        use crate::schema::bootstrap_progress::dsl::*;
+
+        let now = Utc::now();
+        let finished = if hash.is_none() { Some(now) } else { None };
+        diesel::update(bootstrap_progress.filter(id.eq(self.id)))
+            .set((
+                next_hash.eq(&hash),
+                next_height.eq(height),
+                updated_at.eq(now),
+                finished_at.eq(finished),
+            ))
+            .execute(conn)?;
+
+        self.next_hash = hash;
+        self.next_height = height;
+        self.updated_at = now;
+        self.finished_at = finished;
+        Ok(())
+    }
+
+    /// Blocks fetched so far.
+    pub fn fetched(&self) -> i64 {
+        self.tip_height - self.next_height
+    }
+}
+
+/// How an external tip disagrees with the consensus of our nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExternalDisagreementKind {
+    /// The tip is two or more blocks ahead of the consensus tip.
+    Ahead,
+    /// The tip is on another branch, at or above the height of the consensus tip.
+    Fork,
+}
+
+/// An external observation our nodes disagree with, a sign they may be eclipsed.
+#[derive(Clone, Debug, Serialize)]
+pub struct ExternalDisagreement {
+    pub kind: ExternalDisagreementKind,
+    pub source: String,
+    pub block_hash: String,
+    pub height: i64,
+    pub observed_at: DateTime<Utc>,
+    pub consensus_tip: String,
+    pub consensus_height: i64,
+}
+
+/// What a node last reported about itself, and the last error scanning it.
+#[derive(Clone, Debug, Serialize, Queryable, Insertable)]
+#[table_name = "node_status"]
+pub struct NodeStatus {
+    pub node_id: i64,
+    pub version: Option<i64>,
+    pub subversion: Option<String>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+    /// When `getblockchaininfo` last succeeded, the chain fields are from then.
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub best_block_hash: Option<String>,
+    pub best_height: Option<i64>,
+    pub header_height: Option<i64>,
+    pub initial_block_download: Option<bool>,
+    /// RPC calls to the node that were retried, and that failed after their retries.
+    pub rpc_retries: i64,
+    pub rpc_failures: i64,
+}
+
+impl NodeStatus {
+    pub fn get(conn: &PgConnection, node: i64) -> QueryResult<Option<NodeStatus>> {
+        use crate::schema::node_status::dsl::*;
+        node_status.filter(node_id.eq(node)).first(conn).optional()
+    }
+
+    pub fn set_version(
+        conn: &PgConnection,
+        node: i64,
+        node_version: i64,
+        node_subversion: &str,
+    ) -> QueryResult<usize> {
+        use crate::schema::node_status::dsl::*;
+
+        diesel::insert_into(node_status)
+            .values((
+                node_id.eq(node),
+                version.eq(node_version),
+                subversion.eq(node_subversion),
+            ))
+            .on_conflict(node_id)
+            .do_update()
+            .set((
+                version.eq(node_version),
+                subversion.eq(node_subversion),
+                updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+    }
+
+    /// Keep what a successful `getblockchaininfo` reported.
+    pub fn set_chain(
+        conn: &PgConnection,
+        node: i64,
+        hash: &str,
+        blocks: i64,
+        headers: i64,
+        ibd: bool,
+    ) -> QueryResult<usize> {
+        use crate::schema::node_status::dsl::*;
+
+        let now = Utc::now();
+        let chain = (
+            last_success_at.eq(now),
+            best_block_hash.eq(hash),
+            best_height.eq(blocks),
+            header_height.eq(headers),
+            initial_block_download.eq(ibd),
+        );
+        diesel::insert_into(node_status)
+            .values((node_id.eq(node), chain))
+            .on_conflict(node_id)
+            .do_update()
+            .set((chain, updated_at.eq(now)))
+            .execute(conn)
+    }
+
+    /// Add to the node's counts of retried and failed RPC calls.
+    pub fn add_rpc_errors(
+        conn: &PgConnection,
+        node: i64,
+        retries: i64,
+        failures: i64,
+    ) -> QueryResult<usize> {
+        use crate::schema::node_status::dsl::*;
+        use diesel::pg::upsert::excluded;
+
+        diesel::insert_into(node_status)
+            .values((
+                node_id.eq(node),
+                rpc_retries.eq(retries),
+                rpc_failures.eq(failures),
+            ))
+            .on_conflict(node_id)
+            .do_update()
+            .set((
+                rpc_retries.eq(rpc_retries + excluded(rpc_retries)),
+                rpc_failures.eq(rpc_failures + excluded(rpc_failures)),
+                updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+    }
+
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<NodeStatus>> {
+        use crate::schema::node_status::dsl::*;
+        node_status.order(node_id.asc()).load(conn)
+    }
+
+    /// Nodes that failed to scan since `since`.
+    pub fn errors_since(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<Vec<NodeStatus>> {
+        use crate::schema::node_status::dsl::*;
+
+        node_status
+            .filter(last_error_at.ge(since))
+            .order(node_id.asc())
+            .load(conn)
+    }
+
+    pub fn set_error(conn: &PgConnection, node: i64, error: &str) -> QueryResult<usize> {
+        use crate::schema::node_status::dsl::*;
+
+        let now = Utc::now();
+        diesel::insert_into(node_status)
+            .values((
+                node_id.eq(node),
+                last_error.eq(error),
+                last_error_at.eq(now),
+            ))
+            .on_conflict(node_id)
+            .do_update()
+            .set((
+                last_error.eq(error),
+                last_error_at.eq(now),
+                updated_at.eq(now),
+            ))
+            .execute(conn)
+    }
+}
+
+#[derive(Serialize, QueryableByName, Queryable, Insertable)]
+#[table_name = "peers"]
+pub struct NewPeer {
+    pub node_id: i64,
+    pub peer_id: i64,
+    pub address: String,
+    pub version: i64,
+    pub last_block: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema, QueryableByName, Queryable, Insertable)]
+#[table_name = "peers"]
+pub struct Peer {
+    pub id: i64,
+    pub node_id: i64,
+    pub peer_id: i64,
+    pub address: String,
+    pub version: i64,
+    /// When the peer last relayed a new block to the node.
+    pub last_block: Option<DateTime<Utc>>,
+}
+
+impl Peer {
+    pub fn update_peers(
+        conn: &PgConnection,
+        n_id: i64,
+        peer_list: Vec<NewPeer>,
+    ) -> QueryResult<usize> {
+        use crate::schema::peers::dsl::*;
+
+        diesel::delete(peers)
+            .filter(node_id.eq(n_id))
+            .execute(conn)?;
+
+        diesel::insert_into(peers)
+            .values(peer_list)
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+
+    pub fn list(conn: &PgConnection, n_id: i64) -> QueryResult<Vec<Peer>> {
+        use crate::schema::peers::dsl::*;
+        peers.filter(node_id.eq(n_id)).load(conn)
+    }
+
+    /// The peer that most recently relayed a block to the node, after `since`.
+    pub fn last_block_relay(
+        conn: &PgConnection,
+        n_id: i64,
+        since: DateTime<Utc>,
+    ) -> QueryResult<Option<Peer>> {
+        use crate::schema::peers::dsl::*;
+        peers
+            .filter(node_id.eq(n_id).and(last_block.gt(since)))
+            .order(last_block.desc())
+            .first(conn)
+            .optional()
+    }
+}
+
+/// A peer address and the monitored nodes connected to it.
+#[derive(Clone, Debug, Serialize, QueryableByName)]
+pub struct SharedPeer {
+    #[sql_type = "sql_types::Text"]
+    pub address: String,
+    #[sql_type = "sql_types::Array<sql_types::BigInt>"]
+    pub nodes: Vec<i64>,
+}
+
+impl SharedPeer {
+    /// Every peer address of all nodes, the most shared first.
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<SharedPeer>> {
+        let raw_query = "
+            SELECT address, array_agg(DISTINCT node_id ORDER BY node_id) as nodes
+            FROM peers
+            GROUP BY address
+            ORDER BY count(DISTINCT node_id) DESC, address ASC
+        ";
+
+        diesel::sql_query(raw_query).load(conn)
+    }
+}
+
+/// A peer of a node and its protocol version.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PeerVersion {
+    pub address: String,
+    pub version: i64,
+}
+
+/// A peer that is still connected, but with another protocol version.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PeerVersionChange {
+    pub address: String,
+    pub old_version: i64,
+    pub new_version: i64,
+}
+
+/// How a node's peers changed since the previous scan, by address.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PeerChanges {
+    pub node_id: i64,
+    pub added: Vec<PeerVersion>,
+    pub removed: Vec<PeerVersion>,
+    pub version_changes: Vec<PeerVersionChange>,
+}
+
+impl PeerChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.version_changes.is_empty()
+    }
+}
+
+/// How many peers two nodes have in common.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct PeerOverlap {
+    pub node_a: i64,
+    pub node_b: i64,
+    pub peers_a: usize,
+    pub peers_b: usize,
+    pub shared: usize,
+}
+
+impl PeerOverlap {
+    /// Every pair of nodes that have peers, the least overlapping first.
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<PeerOverlap>> {
+        let mut counts = BTreeMap::<i64, usize>::new();
+        let mut shared = HashMap::<(i64, i64), usize>::new();
+
+        // node ids of a shared peer are sorted, so pairs are too
+        for peer in SharedPeer::list(conn)? {
+            for (i, a) in peer.nodes.iter().enumerate() {
+                *counts.entry(*a).or_default() += 1;
+                for b in &peer.nodes[i + 1..] {
+                    *shared.entry((*a, *b)).or_default() += 1;
+                }
+            }
+        }
+
+        let nodes: Vec<_> = counts.into_iter().collect();
+        let mut overlaps = Vec::new();
+        for (i, (a, peers_a)) in nodes.iter().enumerate() {
+            for (b, peers_b) in &nodes[i + 1..] {
+                overlaps.push(PeerOverlap {
+                    node_a: *a,
+                    node_b: *b,
+                    peers_a: *peers_a,
+                    peers_b: *peers_b,
+                    shared: shared.get(&(*a, *b)).copied().unwrap_or(0),
+                });
+            }
+        }
+        overlaps.sort_by_key(|o| o.shared);
+
+        Ok(overlaps)
+    }
+}
+
+/// A node missing transactions that are in the mempools of most other nodes.
+#[derive(Clone, Debug, Serialize)]
+pub struct MempoolDivergence {
+    pub node_id: i64,
+    pub mempool_size: usize,
+    /// Transactions in most mempools since the previous scan.
+    pub common: usize,
+    /// Of those, the ones the node doesn't have.
+    pub missing: usize,
+    /// A few of the missing txids.
+    pub missing_txids: Vec<String>,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// A payment to a watched address in node mempools, before it is mined.
+#[derive(Clone, Debug, Serialize)]
+pub struct MempoolPayment {
+    pub txid: String,
+    pub vout: u32,
+    pub address: String,
+    #[serde(serialize_with = "serde_btc")]
+    pub amount: f64,
+    /// The nodes with the transaction in their mempool when it was first seen.
+    pub nodes: Vec<i64>,
+    pub seen_at: DateTime<Utc>,
+}
+
+/// What the monitored nodes learned about a peer, kept by host so it outlives the
+/// connection and is shared by all nodes.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable, Insertable)]
+#[table_name = "peer_reputation"]
+pub struct PeerReputation {
+    pub address: String,
+    /// `getblockfrompeer` requests the peer refused.
+    pub failed_requests: i32,
+    /// The highest misbehavior score a node gave the peer for invalid data.
+    pub ban_score: i32,
+    /// The node that last reported the peer.
+    pub last_node_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PeerReputation {
+    /// Delete the records of peers not heard of since `since`.
+    pub fn prune_before(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<usize> {
+        use crate::schema::peer_reputation::dsl::*;
+
+        diesel::delete(peer_reputation)
+            .filter(updated_at.lt(since))
+            .execute(conn)
+    }
+
+    pub fn record_failed_request(conn: &PgConnection, host: &str, node: i64) -> QueryResult<usize> {
+        use crate::schema::peer_reputation::dsl::*;
+
+        diesel::insert_into(peer_reputation)
+            .values((
+                address.eq(host),
+                failed_requests.eq(1),
+                last_node_id.eq(node),
+            ))
+            .on_conflict(address)
+            .do_update()
+            .set((
+                failed_requests.eq(failed_requests + 1),
+                last_node_id.eq(node),
+                updated_at.eq(Utc::now()),
+            ))
+            .execute(conn)
+    }
+
+    /// Keep the highest ban score reported for the host.
+    pub fn record_ban_score(
+        conn: &PgConnection,
+        host: &str,
+        node: i64,
+        score: i32,
+    ) -> QueryResult<usize> {
+        let raw_query = "
+            INSERT INTO peer_reputation (address, ban_score, last_node_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (address) DO UPDATE SET
+                ban_score = GREATEST(peer_reputation.ban_score, EXCLUDED.ban_score),
+                last_node_id = EXCLUDED.last_node_id,
+                updated_at = now()
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(host)
+            .bind::<sql_types::Integer, _>(score)
+            .bind::<sql_types::BigInt, _>(node)
+            .execute(conn)
+    }
+
+    /// Peers with the worst reputation first, optionally only one host.
+    pub fn list(
+        conn: &PgConnection,
+        host: Option<String>,
+        limit: i64,
+    ) -> QueryResult<Vec<PeerReputation>> {
+        use crate::schema::peer_reputation::dsl::*;
+
+        let mut query = peer_reputation.into_boxed();
+        if let Some(host) = host {
+            query = query.filter(address.eq(host));
+        }
+
+        query
+            .order((ban_score.desc(), failed_requests.desc(), address.asc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    /// Hosts that were given a ban score or refused at least `failures` requests.
+    pub fn flagged(conn: &PgConnection, failures: i32) -> QueryResult<Vec<String>> {
+        use crate::schema::peer_reputation::dsl::*;
+
+        peer_reputation
+            .select(address)
+            .filter(ban_score.gt(0).or(failed_requests.ge(failures)))
+            .load(conn)
+    }
+}
+
+#[derive(QueryableByName, Queryable, Insertable)]
+#[table_name = "invalid_blocks"]
+pub struct InvalidBlock {
+    pub hash: String,
+    pub node: i64,
+    pub created_at: DateTime<Utc>,
+    /// When the node last reported the block invalid.
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, QueryableByName)]
+pub struct ConflictingBlock {
+    #[sql_type = "sql_types::Text"]
+    pub hash: String,
+    #[sql_type = "sql_types::Array<sql_types::BigInt>"]
+    pub valid_by: Vec<i64>,
+    #[sql_type = "sql_types::Array<sql_types::BigInt>"]
+    pub invalid_by: Vec<i64>,
+    /// When the first node marked the block invalid.
+    #[sql_type = "sql_types::Timestamptz"]
+    pub created_at: DateTime<Utc>,
+    #[sql_type = "sql_types::Timestamptz"]
+    pub updated_at: DateTime<Utc>,
+    /// Whether the block was notified before and the nodes that found it valid or
+    /// invalid changed since, only set in scanner notifications.
+    #[sql_type = "sql_types::Bool"]
+    pub changed: bool,
+}
+
+impl InvalidBlock {
+    /// The nodes that consider a block invalid.
+    pub fn nodes_of(conn: &PgConnection, block: &str) -> QueryResult<Vec<i64>> {
+        use crate::schema::invalid_blocks::dsl::*;
+
+        invalid_blocks
+            .filter(hash.eq(block))
+            .select(node)
+            .order(node.asc())
+            .load(conn)
+    }
+
+    /// Most recent invalid blocks first.
+    pub fn list(conn: &PgConnection, limit: i64) -> QueryResult<Vec<InvalidBlock>> {
+        use crate::schema::invalid_blocks::dsl::*;
+
+        invalid_blocks
+            .order(created_at.desc())
+            .limit(limit)
+            .load(conn)
+    }
+
+    /// Blocks valid on some nodes and invalid on others, where a node found the block
+    /// valid or invalid after `since`.
+    pub fn get_recent_conflicts(
+        conn: &PgConnection,
+        since: DateTime<Utc>,
+    ) -> QueryResult<Vec<ConflictingBlock>> {
+        let raw_query = "
+			SELECT hash, array_agg(distinct valid_by) as valid_by, array_agg(distinct invalid_by) as invalid_by,
+				min(created_at) as created_at, max(updated_at) as updated_at, false as changed
+			FROM (
+				SELECT
+					ivb.hash as hash,
+					vb.node as valid_by,
+					ivb.node as invalid_by,
+					ivb.created_at as created_at,
+					ivb.updated_at as updated_at
+				FROM valid_blocks as vb
+				INNER JOIN invalid_blocks as ivb
+				ON vb.hash = ivb.hash
+				WHERE ivb.hash IN (
+					SELECT i.hash FROM invalid_blocks i
+					INNER JOIN valid_blocks v ON v.hash = i.hash
+					WHERE i.created_at > $1 OR v.created_at > $1
+				)
+			) q
+			GROUP BY hash
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Timestamptz, _>(since)
+            .load(conn)
+    }
+
+    /// The `limit` blocks most recently found valid on some nodes and invalid on
+    /// others, newest first.
+    pub fn conflicts(conn: &PgConnection, limit: i64) -> QueryResult<Vec<ConflictingBlock>> {
+        let raw_query = "
+			SELECT hash, array_agg(distinct valid_by) as valid_by, array_agg(distinct invalid_by) as invalid_by,
+				min(created_at) as created_at, max(updated_at) as updated_at, false as changed
+			FROM (
+				SELECT
+					ivb.hash as hash,
+					vb.node as valid_by,
+					ivb.node as invalid_by,
+					ivb.created_at as created_at,
+					ivb.updated_at as updated_at
+				FROM valid_blocks as vb
+				INNER JOIN invalid_blocks as ivb
+				ON vb.hash = ivb.hash
+			) q
+			GROUP BY hash
+			ORDER BY created_at DESC
+			LIMIT $1
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::BigInt, _>(limit)
+            .load(conn)
+    }
+}
+
+/// An output descriptor or xpub on the watchlist, its addresses are watched from index 0
+/// up to `derived`, `gap_limit` past the last one used.
+#[derive(Clone, Debug, Serialize, AsChangeset, Queryable, Insertable)]
+#[table_name = "watched_descriptors"]
+pub struct WatchedDescriptor {
+    pub descriptor: String,
+    pub gap_limit: i32,
+    pub derived: i32,
+    pub created_at: DateTime<Utc>,
+    pub watch_until: DateTime<Utc>,
+}
+
+impl WatchedDescriptor {
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<WatchedDescriptor>> {
+        use crate::schema::watched_descriptors::dsl::*;
+        watched_descriptors.order(descriptor.asc()).load(conn)
+    }
+
+    /// Save the descriptor, keeping the gap limit and expiry of the latest registration,
+    /// and watch `addresses` derived from it.
+    pub fn watch(&self, conn: &PgConnection, addresses: &[(u32, String)]) -> QueryResult<usize> {
+        use crate::schema::watched::dsl as wdsl;
+        use crate::schema::watched_descriptors::dsl::*;
+        use diesel::pg::upsert::excluded;
+
+        let derived_addresses: Vec<_> = addresses
+            .iter()
+            .map(|(index, addr)| Watched {
+                address: addr.clone(),
+                created_at: Utc::now(),
+                watch_until: self.watch_until,
+                descriptor: Some(self.descriptor.clone()),
+                derivation_index: Some(*index as i32),
+            })
+            .collect();
+
+        conn.transaction(|| {
+            diesel::insert_into(watched_descriptors)
+                .values(self)
+                .on_conflict(descriptor)
+                .do_update()
+                .set((
+                    gap_limit.eq(excluded(gap_limit)),
+                    derived.eq(excluded(derived)),
+                    watch_until.eq(excluded(watch_until)),
+                ))
+                .execute(conn)?;
+
+            diesel::insert_into(wdsl::watched)
+                .values(&derived_addresses)
+                .on_conflict_do_nothing()
+                .execute(conn)
+        })
+    }
+
+    /// The index of the last derived address seen in a transaction.
+    pub fn highest_used(&self, conn: &PgConnection) -> QueryResult<Option<i32>> {
+        use crate::schema::transaction_addresses::dsl as tadsl;
+        use crate::schema::watched::dsl::*;
+
+        watched
+            .filter(descriptor.eq(&self.descriptor))
+            .filter(address.eq_any(tadsl::transaction_addresses.select(tadsl::address)))
+            .select(diesel::dsl::max(derivation_index))
+            .first(conn)
+    }
+
+    /// Stop watching descriptors and the addresses derived from them.
+    pub fn remove(conn: &PgConnection, descriptors: Vec<String>) -> QueryResult<usize> {
+        use crate::schema::watched_descriptors::dsl::*;
+
+        diesel::delete(watched_descriptors)
+            .filter(descriptor.eq_any(descriptors))
+            .execute(conn)
+    }
+}
+
+/// An output watched for spends on any branch.
+#[derive(Clone, Debug, Serialize, Queryable, Insertable)]
+#[table_name = "watched_outpoints"]
+pub struct WatchedOutpoint {
+    pub txid: String,
+    pub vout: i64,
+    pub created_at: DateTime<Utc>,
+    pub watch_until: DateTime<Utc>,
+}
+
+impl WatchedOutpoint {
+    /// Watch outpoints, updating the expiry of the ones already watched.
+    pub fn insert(
+        conn: &PgConnection,
+        watches: Vec<(String, i64, DateTime<Utc>)>,
+    ) -> QueryResult<usize> {
+        use crate::schema::watched_outpoints::dsl::*;
+        use diesel::pg::upsert::excluded;
+
+        let outpoints: Vec<_> = watches
+            .into_iter()
+            .map(|(id, index, until)| WatchedOutpoint {
+                txid: id,
+                vout: index,
+                created_at: Utc::now(),
+                watch_until: until,
+            })
+            .collect();
+
+        diesel::insert_into(watched_outpoints)
+            .values(&outpoints)
+            .on_conflict((txid, vout))
+            .do_update()
+            .set(watch_until.eq(excluded(watch_until)))
+            .execute(conn)
+    }
+
+    pub fn remove(conn: &PgConnection, outpoints: Vec<(String, i64)>) -> QueryResult<usize> {
+        use crate::schema::watched_outpoints::dsl::*;
+
+        conn.transaction(|| {
+            let mut removed = 0;
+            for (id, index) in outpoints {
+                removed += diesel::delete(watched_outpoints.find((id, index))).execute(conn)?;
+            }
+            Ok(removed)
+        })
+    }
+
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<WatchedOutpoint>> {
+        use crate::schema::watched_outpoints::dsl::*;
+        watched_outpoints.order((txid.asc(), vout.asc())).load(conn)
+    }
+
+    /// Stop watching expired outpoints.
+    pub fn clear(conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::watched_outpoints::dsl::*;
+
+        diesel::delete(watched_outpoints)
+            .filter(watch_until.lt(Utc::now()))
+            .execute(conn)
+    }
+}
+
+/// A watched transaction sent to a node whose mempool didn't have it, and whether the
+/// node accepted it.
+#[derive(Clone, Debug, Serialize, JsonSchema, Queryable)]
+pub struct WatchedRelay {
+    pub id: i64,
+    pub txid: String,
+    pub node_id: i64,
+    pub accepted: bool,
+    /// Why the node rejected the transaction.
+    pub error: Option<String>,
+    pub relayed_at: DateTime<Utc>,
+}
+
+impl WatchedRelay {
+    pub fn insert(
+        conn: &PgConnection,
+        tx: &str,
+        node: i64,
+        rejected: Option<String>,
+    ) -> QueryResult<WatchedRelay> {
+        use crate::schema::watched_relays::dsl::*;
+
+        diesel::insert_into(watched_relays)
+            .values((
+                txid.eq(tx),
+                node_id.eq(node),
+                accepted.eq(rejected.is_none()),
+                error.eq(rejected),
+            ))
+            .get_result(conn)
+    }
+
+    /// Relays of `tx`, or of any transaction, newest first.
+    pub fn list(
+        conn: &PgConnection,
+        tx: Option<String>,
+        limit: i64,
+    ) -> QueryResult<Vec<WatchedRelay>> {
+        use crate::schema::watched_relays::dsl::*;
+
+        let mut query = watched_relays.into_boxed();
+
+        if let Some(t) = tx {
+            query = query.filter(txid.eq(t));
+        }
+
+        query
+            .order_by((relayed_at.desc(), id.desc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    pub fn prune_before(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<usize> {
+        use crate::schema::watched_relays::dsl::*;
+
+        diesel::delete(watched_relays)
+            .filter(relayed_at.lt(since))
+            .execute(conn)
+    }
+}
+
+/// A spend of a watched outpoint, in a block on any branch.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutpointSpend {
+    pub txid: String,
+    pub vout: i64,
+    pub spending_txid: String,
+    pub vin: i32,
+    pub block_hash: String,
+    pub height: i64,
+    pub pool_name: Option<String>,
+    /// The nodes whose active chain has the block, none if it's only on a fork.
+    pub active_nodes: Vec<i64>,
+}
+
+#[derive(QueryableByName, Queryable, Insertable)]
+#[table_name = "valid_blocks"]
+pub struct ValidBlock {
+    pub hash: String,
+    pub node: i64,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Serialize, QueryableByName, Queryable, Insertable)]
+#[table_name = "watched"]
+pub struct Watched {
+    pub address: String,
+    pub created_at: DateTime<Utc>,
+    pub watch_until: DateTime<Utc>,
+    /// The descriptor the address was derived from, and at which index.
+    pub descriptor: Option<String>,
+    pub derivation_index: Option<i32>,
+}
+
+impl Watched {
+    // Rows per insert, postgres allows 65535 bind parameters.
+    const INSERT_CHUNK: usize = 10_000;
+
+    /// Transactions paying to a watched address.
+    pub fn funding_txids(conn: &PgConnection) -> QueryResult<Vec<String>> {
+        use crate::schema::transaction_addresses::dsl as tadsl;
+        use crate::schema::watched::dsl as wdsl;
+
+        tadsl::transaction_addresses
+            .filter(tadsl::address.eq_any(wdsl::watched.select(wdsl::address)))
+            .select(tadsl::txid)
+            .distinct()
+            .load(conn)
+    }
 
-        diesel::insert_into(valid_blocks)
-            .values(block)
-            .on_conflict((hash, node))
-            .do_nothing()
+    pub fn insert(
+        conn: &PgConnection,
+        watches: Vec<(String, DateTime<Utc>)>,
+    ) -> QueryResult<usize> {
+        use crate::schema::watched::dsl::*;
+
+        let watch_list: Vec<_> = watches
+            .into_iter()
+            .map(|(addr, exp)| Watched {
+                address: addr,
+                created_at: Utc::now(),
+                watch_until: exp,
+                descriptor: None,
+                derivation_index: None,
+            })
+            .collect();
+
+        diesel::insert_into(watched)
+            .values(watch_list)
+            .on_conflict_do_nothing()
             .execute(conn)
     }
 
-    /// Has block_hash been marked invalid by this node?
-    pub fn marked_invalid_by(
+    pub fn remove(conn: &PgConnection, addresses: Vec<String>) -> QueryResult<usize> {
+        use crate::schema::watched::dsl::*;
+
+        diesel::delete(watched)
+            .filter(address.eq_any(addresses))
+            .execute(conn)
+    }
+
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<Watched>> {
+        use crate::schema::watched::dsl::*;
+        watched.order(address.asc()).load(conn)
+    }
+
+    /// Add a watchlist in one transaction, updating the expiry of addresses that are
+    /// already watched. With `replace` the current watchlist is dropped first.
+    /// Descriptors come with the addresses derived from them. An address listed twice
+    /// is watched until the later of its expiries.
+    pub fn import(
         conn: &PgConnection,
-        block_hash: &String,
-        node_id: i64,
-    ) -> QueryResult<bool> {
-        use crate::schema::invalid_blocks::dsl::*;
+        watches: Vec<(String, DateTime<Utc>)>,
+        descriptors: Vec<(WatchedDescriptor, Vec<(u32, String)>)>,
+        replace: bool,
+    ) -> QueryResult<usize> {
+        use crate::schema::watched::dsl::*;
+        use crate::schema::watched_descriptors::dsl as wddsl;
+        use diesel::pg::upsert::excluded;
+
+        // postgres refuses to update the same row twice in one upsert
+        let mut expiries = BTreeMap::new();
+        for (addr, exp) in watches {
+            let until = expiries.entry(addr).or_insert(exp);
+            *until = exp.max(*until);
+        }
 
-        let rows = invalid_blocks
-            .filter(hash.eq(block_hash).and(node.eq(node_id)))
+        let watch_list: Vec<_> = expiries
+            .into_iter()
+            .map(|(addr, exp)| Watched {
+                address: addr,
+                created_at: Utc::now(),
+                watch_until: exp,
+                descriptor: None,
+                derivation_index: None,
+            })
+            .collect();
+
+        conn.transaction(|| {
+            if replace {
+                diesel::delete(watched).execute(conn)?;
+                diesel::delete(wddsl::watched_descriptors).execute(conn)?;
+            }
+
+            let mut imported = 0;
+            for chunk in watch_list.chunks(Self::INSERT_CHUNK) {
+                imported += diesel::insert_into(watched)
+                    .values(chunk)
+                    .on_conflict(address)
+                    .do_update()
+                    .set(watch_until.eq(excluded(watch_until)))
+                    .execute(conn)?;
+            }
+            for (desc, addresses) in &descriptors {
+                desc.watch(conn, addresses)?;
+                imported += 1;
+            }
+            Ok(imported)
+        })
+    }
+
+    pub fn clear(conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::watched::dsl::*;
+        use crate::schema::watched_descriptors::dsl as wddsl;
+        let utc_now = Utc::now();
+
+        diesel::delete(wddsl::watched_descriptors)
+            .filter(wddsl::watch_until.lt(utc_now))
             .execute(conn)?;
-        Ok(rows > 0)
+        diesel::delete(watched)
+            .filter(watch_until.lt(utc_now))
+            .execute(conn)
     }
 
-    /// Has block_hash been marked valid by this node?
-    pub fn marked_valid_by(
+    pub fn fetch(conn: &PgConnection) -> QueryResult<Vec<Transaction>> {
+        use crate::schema::transaction::dsl as tdsl;
+        use crate::schema::transaction_addresses::dsl as tadsl;
+        use crate::schema::watched::dsl as wdsl;
+        use diesel::dsl::any;
+
+        let watched: Vec<_> = wdsl::watched.load(conn)?;
+        let watched: Vec<_> = watched.into_iter().map(|w: Watched| w.address).collect();
+
+        let transactions: Vec<(String, String, String)> = tadsl::transaction_addresses
+            .filter(tadsl::address.eq(any(watched)))
+            .load(conn)?;
+
+        let mut block_ids = Vec::new();
+        let mut tx_ids = Vec::new();
+
+        for (h, id, _) in transactions.into_iter() {
+            block_ids.push(h);
+            tx_ids.push(id);
+        }
+
+        let transactions: Vec<_> = tdsl::transaction
+            .filter(
+                tdsl::block_id
+                    .eq_any(block_ids)
+                    .and(tdsl::txid.eq_any(tx_ids)),
+            )
+            .load(conn)?;
+
+        Ok(transactions)
+    }
+}
+
+#[derive(Clone, Serialize, QueryableByName, Queryable, Insertable)]
+#[table_name = "lags"]
+pub struct Lags {
+    pub node_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+    /// Blocks behind the highest tip.
+    pub height_deficit: i64,
+    /// Chainwork behind the most worked tip, in blocks at the current difficulty.
+    pub work_deficit_blocks: Option<f64>,
+}
+
+impl Lags {
+    pub fn purge(conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::lags::dsl::*;
+        diesel::delete(lags).execute(conn)
+    }
+
+    pub fn insert(
         conn: &PgConnection,
-        block_hash: &String,
-        node_id: i64,
-    ) -> QueryResult<bool> {
-        use crate::schema::valid_blocks::dsl::*;
+        id: i64,
+        height_behind: i64,
+        work_behind: Option<f64>,
+    ) -> QueryResult<usize> {
+        use crate::schema::lags::dsl::*;
 
-        let rows = valid_blocks
-            .filter(hash.eq(block_hash).and(node.eq(node_id)))
-            .execute(conn)?;
-        Ok(rows > 0)
+        let lag = Lags {
+            node_id: id,
+            created_at: Utc::now(),
+            deleted_at: None,
+            updated_at: Utc::now(),
+            height_deficit: height_behind,
+            work_deficit_blocks: work_behind,
+        };
+
+        diesel::insert_into(lags).values(lag).execute(conn)
     }
 
-    /// Fetch number of nodes that marked invalid.
-    pub fn marked_invalid(conn: &PgConnection, block_hash: &String) -> QueryResult<usize> {
-        use crate::schema::invalid_blocks::dsl::*;
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<Lags>> {
+        use crate::schema::lags::dsl::*;
+        lags.load(conn)
+    }
+}
 
-        invalid_blocks.filter(hash.eq(block_hash)).execute(conn)
+#[derive(Clone, Serialize, QueryableByName, Queryable, Insertable)]
+#[table_name = "block_assists"]
+pub struct BlockAssist {
+    pub block_hash: String,
+    pub node_id: i64,
+    pub source_node: Option<i64>,
+    pub attempts: i32,
+    pub accepted: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Serialize, JsonSchema, QueryableByName)]
+pub struct AssistStats {
+    #[sql_type = "sql_types::BigInt"]
+    pub node_id: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub missing: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub received: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub pending: i64,
+    #[sql_type = "sql_types::BigInt"]
+    pub provided: i64,
+}
+
+impl BlockAssist {
+    /// Note that a node only has the header for a block.
+    pub fn request(conn: &PgConnection, hash: &str, node: i64) -> QueryResult<usize> {
+        use crate::schema::block_assists::dsl::*;
+
+        let assist = BlockAssist {
+            block_hash: hash.to_string(),
+            node_id: node,
+            source_node: None,
+            attempts: 0,
+            accepted: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        diesel::insert_into(block_assists)
+            .values(assist)
+            .on_conflict_do_nothing()
+            .execute(conn)
     }
 
-    /// Node has marked block invalid.
-    pub fn set_invalid(
+    /// Record an attempt at getting a block body to a node.
+    pub fn record(
         conn: &PgConnection,
-        block_hash: &String,
-        node_id: i64,
+        hash: &str,
+        node: i64,
+        source: Option<i64>,
+        was_accepted: bool,
     ) -> QueryResult<usize> {
-        use crate::schema::invalid_blocks::dsl::*;
+        use crate::schema::block_assists::dsl::*;
 
-        let block = InvalidBlock {
-            hash: block_hash.to_string(),
-            node: node_id,
-            created_at: Some(Utc::now()),
+        let assist = BlockAssist {
+            block_hash: hash.to_string(),
+            node_id: node,
+            source_node: source,
+            attempts: 1,
+            accepted: was_accepted,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
         };
 
-        diesel::insert_into(invalid_blocks)
-            .values(block)
+        diesel::insert_into(block_assists)
+            .values(assist)
+            .on_conflict((block_hash, node_id))
+            .do_update()
+            .set((
+                attempts.eq(attempts + 1),
+                accepted.eq(was_accepted),
+                source_node.eq(source),
+                updated_at.eq(Utc::now()),
+            ))
             .execute(conn)
     }
 
-    pub fn insert(&self, conn: &PgConnection) -> QueryResult<usize> {
-        diesel::insert_into(blocks::dsl::blocks)
+    pub fn list_pending(conn: &PgConnection, max_attempts: i32) -> QueryResult<Vec<BlockAssist>> {
+        use crate::schema::block_assists::dsl::*;
+
+        block_assists
+            .filter(accepted.eq(false).and(attempts.lt(max_attempts)))
+            .order(created_at.asc())
+            .load(conn)
+    }
+
+    pub fn pending_for(conn: &PgConnection, hash: &String) -> QueryResult<Vec<BlockAssist>> {
+        use crate::schema::block_assists::dsl::*;
+
+        block_assists
+            .filter(block_hash.eq(hash).and(accepted.eq(false)))
+            .load(conn)
+    }
+
+    pub fn stats(conn: &PgConnection) -> QueryResult<Vec<AssistStats>> {
+        let raw_query = "
+            SELECT
+                n.id as node_id,
+                (SELECT count(*) FROM block_assists WHERE node_id = n.id) as missing,
+                (SELECT count(*) FROM block_assists WHERE node_id = n.id AND accepted) as received,
+                (SELECT count(*) FROM block_assists WHERE node_id = n.id AND NOT accepted) as pending,
+                (SELECT count(*) FROM block_assists WHERE source_node = n.id AND accepted) as provided
+            FROM nodes as n
+            ORDER BY n.id
+        ";
+
+        diesel::sql_query(raw_query).load(conn)
+    }
+}
+
+#[derive(Clone, Serialize, JsonSchema, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "rollback_schedule"]
+pub struct RollbackSchedule {
+    pub block_hash: String,
+    pub node_id: i64,
+    pub height: i64,
+    pub branch_len: i64,
+    pub priority: i32,
+    pub status: String,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl RollbackSchedule {
+    pub fn get(
+        conn: &PgConnection,
+        hash: &String,
+        node: i64,
+    ) -> QueryResult<Option<RollbackSchedule>> {
+        use crate::schema::rollback_schedule::dsl::*;
+
+        rollback_schedule
+            .filter(block_hash.eq(hash).and(node_id.eq(node)))
+            .first(conn)
+            .optional()
+    }
+
+    // A missing last_checked_at leaves the previous check time in place.
+    pub fn upsert(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::rollback_schedule::dsl::*;
+
+        diesel::insert_into(rollback_schedule)
             .values(self)
+            .on_conflict((block_hash, node_id))
+            .do_update()
+            .set(self)
+            .execute(conn)
+    }
+
+    /// Drop entries that were not scheduled in the latest cycle.
+    pub fn purge_before(conn: &PgConnection, before: DateTime<Utc>) -> QueryResult<usize> {
+        use crate::schema::rollback_schedule::dsl::*;
+
+        diesel::delete(rollback_schedule)
+            .filter(updated_at.lt(before))
             .execute(conn)
     }
+
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<RollbackSchedule>> {
+        use crate::schema::rollback_schedule::dsl::*;
+
+        rollback_schedule.order(priority.asc()).load(conn)
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize, QueryableByName, Queryable, Insertable)]
-#[table_name = "transaction"]
-pub struct Transaction {
-    pub block_id: String,
-    pub txid: String,
-    pub is_coinbase: bool,
-    pub hex: String,
-    pub amount: f64,
-    pub swept: Option<bool>,
+#[derive(Insertable)]
+#[table_name = "rollback_checks"]
+pub struct NewRollbackCheck {
+    pub block_hash: String,
+    pub node_id: i64,
+    pub verdict: String,
+    pub detail: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
 }
 
-impl Transaction {
-    pub fn create(
-        conn: &PgConnection,
-        sweep: bool,
-        block: String,
-        idx: usize,
-        tx_id: &String,
-        tx_hex: &String,
-        tx_amount: f64,
-    ) -> QueryResult<usize> {
-        use crate::schema::transaction::dsl::*;
+#[derive(Clone, Serialize, JsonSchema, QueryableByName, Queryable)]
+#[table_name = "rollback_checks"]
+pub struct RollbackCheck {
+    pub id: i64,
+    pub block_hash: String,
+    pub node_id: i64,
+    pub verdict: String,
+    pub detail: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
 
-        let tx = Transaction {
-            block_id: block,
-            is_coinbase: idx == 0,
-            txid: tx_id.clone(),
-            hex: tx_hex.clone(),
-            amount: tx_amount,
-            swept: Some(sweep),
-        };
+impl RollbackCheck {
+    pub fn insert(conn: &PgConnection, check: NewRollbackCheck) -> QueryResult<usize> {
+        use crate::schema::rollback_checks::dsl::*;
 
-        diesel::insert_into(transaction)
-            .values(tx)
-            .on_conflict((block_id, txid))
-            .do_nothing()
+        diesel::insert_into(rollback_checks)
+            .values(check)
             .execute(conn)
     }
 
-    pub fn amount_for_txs(conn: &PgConnection, txids: &Vec<String>) -> QueryResult<f64> {
-        use crate::schema::transaction::dsl::*;
-        use diesel::dsl::max;
+    /// Most recent verdicts first, optionally for one node or block.
+    pub fn list(
+        conn: &PgConnection,
+        node: Option<i64>,
+        hash: Option<String>,
+        limit: i64,
+    ) -> QueryResult<Vec<RollbackCheck>> {
+        use crate::schema::rollback_checks::dsl::*;
 
-        let results: Vec<Option<f64>> = transaction
-            .filter(txid.eq_any(txids))
-            .group_by(txid)
-            .select(max(amount))
-            .load(conn)?;
-        Ok(results
-            .into_iter()
-            .fold(0.0, |a, b| a + b.unwrap_or_default()))
-    }
+        let mut query = rollback_checks.into_boxed();
 
-    pub fn tx_block_and_descendants(conn: &PgConnection, id: String) -> QueryResult<Vec<Block>> {
-        use crate::schema::blocks::dsl as bdsl;
-        use crate::schema::transaction::dsl::*;
+        if let Some(node) = node {
+            query = query.filter(node_id.eq(node));
+        }
 
-        let blocks: Vec<(Transaction, Block)> = transaction
-            .inner_join(bdsl::blocks)
-            .filter(txid.eq(id))
-            .load(conn)?;
+        if let Some(hash) = hash {
+            query = query.filter(block_hash.eq(hash));
+        }
 
-        let descendants: Vec<Block> = blocks
-            .into_iter()
-            .filter_map(|b| {
-                if let Ok(desc) = b.1.descendants(conn, None) {
-                    Some(desc)
-                } else {
-                    None
-                }
-            })
-            .flatten()
-            .collect();
-        Ok(descendants)
+        query.order(finished_at.desc()).limit(limit).load(conn)
     }
+}
 
-    pub fn block_processed(conn: &PgConnection, hash: &String) -> QueryResult<bool> {
-        use crate::schema::transaction::dsl::*;
-        let result: Vec<Transaction> = transaction.filter(block_id.eq(hash)).load(conn)?;
-        Ok(result.len() > 0)
-    }
+#[derive(Clone, Serialize, QueryableByName, Queryable, Insertable)]
+#[table_name = "tip_statuses"]
+pub struct TipStatus {
+    pub node_id: i64,
+    pub block_hash: String,
+    pub height: i64,
+    pub status: String,
+    pub updated_at: DateTime<Utc>,
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "stale_candidate_children"]
-pub struct StaleCandidateChildren {
-    pub candidate_height: i64,
-    pub root_id: String,
-    pub tip_id: String,
-    pub len: i32,
+/// Status of a tip that left a node's chaintips, e.g. an active tip the node moved on
+/// from.
+pub const TIP_GONE: &str = "gone";
+
+/// A chaintip status a node reported, recorded when the tip first showed up, when its
+/// status changed and when it left the node's chaintips, so `get_tips_as_of` can tell
+/// what the nodes reported at any moment.
+#[derive(Clone, Debug, Serialize, JsonSchema, QueryableByName, Queryable)]
+#[table_name = "tip_history"]
+pub struct TipHistory {
+    pub id: i64,
+    pub node_id: i64,
+    pub block_hash: String,
+    pub height: i64,
+    /// A `getchaintips` status, or `gone`.
+    pub status: String,
+    pub observed_at: DateTime<Utc>,
 }
 
-impl StaleCandidateChildren {
-    pub fn create(
-        conn: &PgConnection,
-        root: &Block,
-        tip: &Block,
-        branch_len: i32,
-    ) -> QueryResult<usize> {
-        use crate::schema::stale_candidate_children::dsl::*;
+#[derive(Insertable)]
+#[table_name = "tip_history"]
+pub struct NewTipHistory {
+    pub node_id: i64,
+    pub block_hash: String,
+    pub height: i64,
+    pub status: String,
+}
 
-        let c = StaleCandidateChildren {
-            candidate_height: root.height,
-            root_id: root.hash.clone(),
-            tip_id: tip.hash.clone(),
-            len: branch_len,
-        };
+impl TipHistory {
+    pub fn insert(conn: &PgConnection, entries: &[NewTipHistory]) -> QueryResult<usize> {
+        use crate::schema::tip_history::dsl::*;
 
-        diesel::insert_into(stale_candidate_children)
-            .values(c)
+        diesel::insert_into(tip_history)
+            .values(entries)
             .execute(conn)
     }
 
-    pub fn purge(conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::stale_candidate_children::dsl::*;
+    /// The tips of `node`, or of every node, as they were at `at`, each with its last
+    /// status then. Tips that were gone by then are left out.
+    pub fn as_of(
+        conn: &PgConnection,
+        at: DateTime<Utc>,
+        node: Option<i64>,
+    ) -> QueryResult<Vec<TipHistory>> {
+        let raw_query = "
+            SELECT * FROM (
+                SELECT DISTINCT ON (node_id, block_hash) *
+                FROM tip_history
+                WHERE observed_at <= $1 AND ($2::bigint IS NULL OR node_id = $2)
+                ORDER BY node_id, block_hash, observed_at DESC, id DESC
+            ) h
+            WHERE status <> $3
+            ORDER BY node_id, height DESC
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Timestamptz, _>(at)
+            .bind::<sql_types::Nullable<sql_types::BigInt>, _>(node)
+            .bind::<sql_types::Text, _>(TIP_GONE)
+            .load(conn)
+    }
 
-        diesel::delete(stale_candidate_children).execute(conn)
+    /// The last status each node had reported for `hash` as a tip at `at`, `gone` ones
+    /// included.
+    pub fn of_block_as_of(
+        conn: &PgConnection,
+        hash: &str,
+        at: DateTime<Utc>,
+    ) -> QueryResult<Vec<TipHistory>> {
+        let raw_query = "
+            SELECT DISTINCT ON (node_id) *
+            FROM tip_history
+            WHERE block_hash = $1 AND observed_at <= $2
+            ORDER BY node_id, observed_at DESC, id DESC
+        ";
+
+        diesel::sql_query(raw_query)
+            .bind::<sql_types::Text, _>(hash)
+            .bind::<sql_types::Timestamptz, _>(at)
+            .load(conn)
+    }
+
+    /// Delete the entries that tell nothing about the tips at or after `since`: the ones
+    /// a newer entry of the same tip before `since` replaced, and tips gone before it.
+    pub fn prune_before(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<usize> {
+        let superseded = "
+            DELETE FROM tip_history t
+            WHERE t.observed_at < $1 AND EXISTS (
+                SELECT 1 FROM tip_history n
+                WHERE n.node_id = t.node_id AND n.block_hash = t.block_hash
+                    AND n.observed_at < $1 AND (n.observed_at, n.id) > (t.observed_at, t.id)
+            )
+        ";
+        let gone = "DELETE FROM tip_history WHERE observed_at < $1 AND status = $2";
+
+        let rows = diesel::sql_query(superseded)
+            .bind::<sql_types::Timestamptz, _>(since)
+            .execute(conn)?;
+        let gone = diesel::sql_query(gone)
+            .bind::<sql_types::Timestamptz, _>(since)
+            .bind::<sql_types::Text, _>(TIP_GONE)
+            .execute(conn)?;
+        Ok(rows + gone)
     }
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "rbf_by"]
-pub struct RbfBy {
-    pub candidate_height: i64,
-    pub txid: String,
+/// A chaintip that changed status on one node between two scans.
+#[derive(Clone, Debug, Serialize)]
+pub struct TipTransition {
+    pub node_id: i64,
+    pub block_hash: String,
+    pub height: i64,
+    pub from: String,
+    pub to: String,
+    pub changed_at: DateTime<Utc>,
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "double_spent_by"]
-pub struct DoubleSpentBy {
-    pub candidate_height: i64,
-    pub txid: String,
+#[derive(Insertable)]
+#[table_name = "reorgs"]
+pub struct NewReorg {
+    pub node_id: i64,
+    pub old_tip: String,
+    pub old_height: i64,
+    pub new_tip: String,
+    pub new_height: i64,
+    pub common_ancestor: String,
+    pub ancestor_height: i64,
+    pub depth: i64,
+    pub created_at: DateTime<Utc>,
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "stale_candidate"]
-pub struct StaleCandidate {
-    pub height: i64,
-    pub n_children: i32,
-    pub confirmed_in_one_branch_total: f64,
-    pub double_spent_in_one_branch_total: f64,
-    pub rbf_total: f64,
-    pub height_processed: Option<i64>,
+/// A node's active tip moved to a block that doesn't descend from the previous one.
+#[derive(Clone, Debug, Serialize, JsonSchema, QueryableByName, Queryable)]
+#[table_name = "reorgs"]
+pub struct Reorg {
+    pub id: i64,
+    pub node_id: i64,
+    pub old_tip: String,
+    pub old_height: i64,
+    pub new_tip: String,
+    pub new_height: i64,
+    pub common_ancestor: String,
+    pub ancestor_height: i64,
+    /// Blocks of the old tip's branch that were disconnected.
+    pub depth: i64,
     pub created_at: DateTime<Utc>,
 }
 
-impl StaleCandidate {
-    pub fn list_ge(conn: &PgConnection, block_height: i64) -> QueryResult<Vec<StaleCandidate>> {
-        use crate::schema::stale_candidate::dsl::*;
-        stale_candidate.filter(height.ge(block_height)).load(conn)
+impl Reorg {
+    pub fn create(conn: &PgConnection, reorg: NewReorg) -> QueryResult<Reorg> {
+        use crate::schema::reorgs::dsl::*;
+
+        diesel::insert_into(reorgs).values(reorg).get_result(conn)
     }
 
-    pub fn get(conn: &PgConnection, candidate: i64) -> QueryResult<StaleCandidate> {
-        use crate::schema::stale_candidate::dsl::*;
-        stale_candidate.find(candidate).first(conn)
+    /// Most recent first, optionally of one node or at least `min_depth` deep.
+    pub fn list(
+        conn: &PgConnection,
+        node: Option<i64>,
+        min_depth: Option<i64>,
+        limit: i64,
+    ) -> QueryResult<Vec<Reorg>> {
+        use crate::schema::reorgs::dsl::*;
+
+        let mut query = reorgs.into_boxed();
+        if let Some(node) = node {
+            query = query.filter(node_id.eq(node));
+        }
+        if let Some(min_depth) = min_depth {
+            query = query.filter(depth.ge(min_depth));
+        }
+
+        query.order(id.desc()).limit(limit).load(conn)
     }
 
-    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::stale_candidate::dsl::*;
-        diesel::update(stale_candidate.filter(height.eq(self.height)))
-            .set(self)
-            .execute(conn)
+    /// Reorgs at least `min_depth` deep recorded since `since`, oldest first.
+    pub fn since(
+        conn: &PgConnection,
+        since: DateTime<Utc>,
+        min_depth: i64,
+    ) -> QueryResult<Vec<Reorg>> {
+        use crate::schema::reorgs::dsl::*;
+
+        reorgs
+            .filter(created_at.ge(since).and(depth.ge(min_depth)))
+            .order(id.asc())
+            .load(conn)
     }
+}
 
-    pub fn update_rbf_by(&self, conn: &PgConnection, txids: &Vec<String>) -> QueryResult<usize> {
-        use crate::schema::rbf_by::dsl::*;
+/// A block of the branch a reorg abandoned.
+#[derive(Clone, Debug, Serialize)]
+pub struct AbandonedBlock {
+    pub hash: String,
+    pub height: i64,
+    pub pool_name: Option<String>,
+}
 
-        let rbfs: Vec<RbfBy> = txids
-            .iter()
-            .map(|tx| RbfBy {
-                candidate_height: self.height,
-                txid: tx.clone(),
-            })
-            .collect();
+/// A reorg at least as deep as the deep reorg threshold, with the branch it abandoned.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeepReorg {
+    #[serde(flatten)]
+    pub reorg: Reorg,
+    /// Every node that reorged away from the same tip.
+    pub node_ids: Vec<i64>,
+    /// Lowest first.
+    pub abandoned: Vec<AbandonedBlock>,
+    /// Chainwork of the abandoned blocks.
+    pub abandoned_work: Option<String>,
+    /// Transactions of the new branch seen double spending ones of the abandoned branch.
+    pub double_spent_by: Vec<String>,
+}
 
-        diesel::insert_into(rbf_by).values(rbfs).execute(conn)
-    }
+impl DeepReorg {
+    pub fn new(conn: &PgConnection, reorg: Reorg, node_ids: Vec<i64>) -> QueryResult<DeepReorg> {
+        let old_tip = Block::get(conn, &reorg.old_tip)?;
+        let branch = old_tip.ancestors(conn, reorg.ancestor_height)?;
 
-    pub fn update_double_spent_by(
-        &self,
-        conn: &PgConnection,
-        txids: &Vec<String>,
-    ) -> QueryResult<usize> {
-        use crate::schema::double_spent_by::dsl::*;
-        let double_spends: Vec<DoubleSpentBy> = txids
+        let ancestor_work = branch
             .iter()
-            .map(|tx| DoubleSpentBy {
-                candidate_height: self.height,
-                txid: tx.clone(),
+            .find(|b| b.hash == reorg.common_ancestor)
+            .and_then(|b| parse_work(&b.work));
+        let abandoned_work = match (parse_work(&old_tip.work), ancestor_work) {
+            (Some(tip), Some(ancestor)) => Some(tip.saturating_sub(ancestor).to_string()),
+            _ => None,
+        };
+
+        let abandoned = branch
+            .into_iter()
+            .filter(|b| b.height > reorg.ancestor_height)
+            .map(|b| AbandonedBlock {
+                hash: b.hash,
+                height: b.height,
+                pool_name: b.pool_name,
             })
             .collect();
 
-        diesel::insert_into(double_spent_by)
-            .values(double_spends)
-            .execute(conn)
+        let double_spent_by = match StaleCandidate::get(conn, reorg.ancestor_height + 1) {
+            Ok(candidate) => candidate.double_spent_txids(conn)?,
+            Err(diesel::result::Error::NotFound) => vec![],
+            Err(e) => return Err(e),
+        };
+
+        Ok(DeepReorg {
+            reorg,
+            node_ids,
+            abandoned,
+            abandoned_work,
+            double_spent_by,
+        })
     }
+}
 
-    pub fn children(&self, conn: &PgConnection) -> QueryResult<Vec<StaleCandidateChildren>> {
-        use crate::schema::stale_candidate_children::dsl::*;
+/// No node's active tip moved for longer than the stuck chain threshold.
+#[derive(Clone, Debug, Serialize)]
+pub struct StuckChain {
+    pub block_hash: String,
+    pub height: i64,
+    /// When a node's active tip last moved.
+    pub last_tip_at: DateTime<Utc>,
+    pub stalled_minutes: i64,
+    /// The height the chain should be at by now, at one block every ten minutes.
+    pub expected_height: i64,
+}
 
-        stale_candidate_children
-            .filter(candidate_height.eq(self.height))
-            .order_by(len)
-            .load(conn)
-    }
+/// The last error scanning a node.
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeError {
+    pub node_id: i64,
+    pub error: String,
+}
 
-    pub fn purge_children(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::stale_candidate_children::dsl::*;
+/// What one scan cycle did.
+#[derive(Clone, Debug, Serialize)]
+pub struct CycleSummary {
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: i64,
+    /// How many nodes were scanned.
+    pub nodes: usize,
+    pub blocks_added: i64,
+    /// Nodes whose active tip changed.
+    pub tips_changed: Vec<i64>,
+    /// Nodes lagging behind the others.
+    pub lagging: Vec<i64>,
+    /// Nodes that failed to scan.
+    pub errors: Vec<NodeError>,
+}
 
-        diesel::delete(stale_candidate_children)
-            .filter(candidate_height.eq(self.height))
-            .execute(conn)
-    }
+/// What failed, for error events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// An RPC call to a node failed or timed out.
+    Rpc,
+    /// A database query or connection failed.
+    Database,
+    /// A channel between the scanner and the servers broke.
+    Channel,
+    /// Anything else, e.g. unusable node credentials.
+    Other,
+}
 
-    pub fn create(conn: &PgConnection, candidate_height: i64, children: i32) -> QueryResult<usize> {
-        use crate::schema::stale_candidate::dsl::*;
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 4] = [
+        ErrorCategory::Rpc,
+        ErrorCategory::Database,
+        ErrorCategory::Channel,
+        ErrorCategory::Other,
+    ];
+}
 
-        let candidate = StaleCandidate {
-            n_children: children,
-            height: candidate_height,
-            confirmed_in_one_branch_total: 0.,
-            double_spent_in_one_branch_total: 0.,
-            rbf_total: 0.,
-            height_processed: None,
-            created_at: Utc::now(),
-        };
+/// A failure the scanner logged and carried on from.
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorEvent {
+    pub category: ErrorCategory,
+    /// The node being scanned, if the failure is about one.
+    pub node_id: Option<i64>,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
 
-        diesel::insert_into(stale_candidate)
-            .values(candidate)
-            .on_conflict(height)
-            .do_nothing()
-            .execute(conn)
+/// A transaction in the shorter branch of a stale candidate, and the one in the
+/// longer branch spending the same output.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConflictingTx {
+    pub txid: String,
+    pub by: String,
+    /// Total output of `txid`, in BTC.
+    #[serde(serialize_with = "serde_btc")]
+    pub amount: f64,
+    /// The outputs of `txid` as watched address and BTC, see `output_address`.
+    #[serde(skip)]
+    pub outputs: Vec<(String, f64)>,
+}
+
+/// Double spends and replacements just found in a stale candidate's branches.
+#[derive(Clone, Debug, Serialize)]
+pub struct DoubleSpends {
+    pub height: i64,
+    pub double_spent: Vec<ConflictingTx>,
+    #[serde(serialize_with = "serde_btc")]
+    pub double_spent_total: f64,
+    pub rbf: Vec<ConflictingTx>,
+    #[serde(serialize_with = "serde_btc")]
+    pub rbf_total: f64,
+}
+
+impl TipStatus {
+    pub fn list(conn: &PgConnection, node: i64) -> QueryResult<Vec<TipStatus>> {
+        use crate::schema::tip_statuses::dsl::*;
+        tip_statuses.filter(node_id.eq(node)).load(conn)
     }
 
-    pub fn top_n(conn: &PgConnection, n: i64) -> QueryResult<Vec<StaleCandidate>> {
-        use crate::schema::stale_candidate::dsl::*;
+    /// Replace the last seen chaintip statuses of a node.
+    pub fn update_tips(conn: &PgConnection, node: i64, tips: Vec<TipStatus>) -> QueryResult<usize> {
+        use crate::schema::tip_statuses::dsl::*;
 
-        stale_candidate.order_by(height.desc()).limit(n).load(conn)
+        conn.transaction(|| {
+            diesel::delete(tip_statuses)
+                .filter(node_id.eq(node))
+                .execute(conn)?;
+
+            diesel::insert_into(tip_statuses)
+                .values(tips)
+                .on_conflict_do_nothing()
+                .execute(conn)
+        })
     }
 }
 
-#[derive(AsChangeset, QueryableByName, Queryable, Insertable)]
-#[table_name = "nodes"]
-pub struct Node {
-    pub id: i64,
-    pub node: String,
-    pub rpc_host: String,
-    pub rpc_port: i32,
-    pub mirror_rpc_port: Option<i32>,
-    pub rpc_user: String,
-    pub rpc_pass: String,
-    pub unreachable_since: Option<DateTime<Utc>>,
-    pub last_polled: Option<DateTime<Utc>>,
-    pub initial_block_download: bool,
-    pub mirror_host: Option<String>,
-    pub mirror_last_polled: Option<DateTime<Utc>>,
-    pub mirror_unreachable_since: Option<i64>,
-    pub archive: bool,
+/// A transaction spending a watched output, in a block or in a node's template.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConflictingSpend {
+    pub txid: String,
+    pub block_hash: Option<String>,
+    pub height: Option<i64>,
+    /// The node whose block template has the transaction, when it is unconfirmed.
+    pub node_id: Option<i64>,
+}
+
+/// Competing spends of an output of a watched address.
+#[derive(Clone, Debug, Serialize)]
+pub struct WalletConflictAlert {
+    pub address: String,
+    pub txid: String,
+    pub vout: i64,
+    pub spends: Vec<ConflictingSpend>,
+}
+
+/// Summary of a valid-fork branch against the active chain of the same node.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct ForkAnalysis {
+    pub node_id: i64,
+    pub tip: String,
+    pub tip_height: i64,
+    pub active_tip: String,
+    pub active_height: i64,
+    pub fork_point: String,
+    pub fork_height: i64,
+    pub branch_len: i64,
+    pub active_branch_len: i64,
+    pub work_deficit: Option<String>,
+    pub work_deficit_blocks: Option<f64>,
+    pub pools: BTreeMap<String, usize>,
+    pub active_pools: BTreeMap<String, usize>,
+}
+
+/// Where a chaintip's branch leaves the chain of the consensus tip, the active tip most
+/// nodes agree on. A tip on that chain is its own fork point with a branch of 0.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct TipBranch {
+    pub consensus_tip: String,
+    pub fork_point: String,
+    pub fork_height: i64,
+    pub branch_len: i64,
+    /// The tip's chainwork minus the consensus tip's, as a decimal string.
+    pub chainwork_delta: Option<String>,
+}
+
+impl TipBranch {
+    /// `None` if the tip's block isn't stored or the branches don't meet within
+    /// `MAX_FORK_ANALYSIS_DEPTH` blocks.
+    pub fn new(
+        conn: &PgConnection,
+        tip: &Chaintip,
+        consensus: &Block,
+    ) -> QueryResult<Option<TipBranch>> {
+        let block = match Block::get(conn, &tip.block).optional()? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+
+        let min_height = block.height.min(consensus.height) - MAX_FORK_ANALYSIS_DEPTH as i64;
+        let fork = match Block::common_ancestor(conn, &block.hash, &consensus.hash, min_height)? {
+            Some(fork) => fork,
+            None => return Ok(None),
+        };
+
+        let chainwork_delta = match (parse_work(&block.work), parse_work(&consensus.work)) {
+            (Some(a), Some(b)) => Some((a as i128 - b as i128).to_string()),
+            _ => None,
+        };
+
+        Ok(Some(TipBranch {
+            consensus_tip: consensus.hash.clone(),
+            fork_point: fork.hash,
+            fork_height: fork.height,
+            branch_len: block.height - fork.height,
+            chainwork_delta,
+        }))
+    }
 }
 
-impl Node {
-    pub fn get(conn: &PgConnection, node_id: i64) -> QueryResult<Node> {
-        use crate::schema::nodes::dsl::*;
-        nodes.filter(id.eq(node_id)).get_result(conn)
+// chainwork is stored as a hex string, it fits in 128 bits for now.
+fn parse_work(work: &str) -> Option<u128> {
+    let trimmed = work.trim_start_matches('0');
+    if trimmed.is_empty() {
+        return Some(0);
     }
+    u128::from_str_radix(trimmed, 16).ok()
+}
 
-    pub fn list(conn: &PgConnection) -> QueryResult<Vec<Node>> {
-        nodes::dsl::nodes.load(conn)
+impl ForkAnalysis {
+    pub fn new(conn: &PgConnection, fork: &Chaintip, active: &Chaintip) -> Option<ForkAnalysis> {
+        let fork_tip = Block::get(conn, &fork.block).ok()?;
+        let active_tip = Block::get(conn, &active.block).ok()?;
+
+        let mut pools = BTreeMap::new();
+        let mut active_pools = BTreeMap::new();
+        let mut block1 = fork_tip.clone();
+        let mut block2 = active_tip.clone();
+
+        // Walk both branches back until they meet.
+        for _ in 0..MAX_FORK_ANALYSIS_DEPTH {
+            if block1.hash == block2.hash {
+                break;
+            }
+
+            if block1.height >= block2.height {
+                let pool = block1.pool_name.clone().unwrap_or("unknown".into());
+                *pools.entry(pool).or_insert(0) += 1;
+                block1 = block1.parent(conn).ok()?;
+            } else {
+                let pool = block2.pool_name.clone().unwrap_or("unknown".into());
+                *active_pools.entry(pool).or_insert(0) += 1;
+                block2 = block2.parent(conn).ok()?;
+            }
+        }
+
+        if block1.hash != block2.hash {
+            return None;
+        }
+
+        let (work_deficit, work_deficit_blocks) = active_tip.work_deficit(conn, &fork_tip);
+
+        Some(ForkAnalysis {
+            node_id: fork.node,
+            tip: fork_tip.hash,
+            tip_height: fork_tip.height,
+            active_tip: active_tip.hash,
+            active_height: active_tip.height,
+            fork_point: block1.hash,
+            fork_height: block1.height,
+            branch_len: fork_tip.height - block1.height,
+            active_branch_len: active_tip.height - block1.height,
+            work_deficit: work_deficit.map(|d| d.to_string()),
+            work_deficit_blocks,
+            pools,
+            active_pools,
+        })
     }
 
-    /// Fetch nodes that also have a mirror.
-    pub fn get_mirrors(conn: &PgConnection) -> QueryResult<Vec<Node>> {
-        use crate::schema::nodes::dsl::*;
-        nodes.filter(mirror_rpc_port.is_not_null()).load(conn)
+    /// Analyse every valid-fork chaintip against its node's active tip.
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<ForkAnalysis>> {
+        let tips = Chaintip::list(conn)?;
+
+        let active: HashMap<_, _> = tips
+            .iter()
+            .filter(|t| t.status == "active")
+            .map(|t| (t.node, t))
+            .collect();
+
+        Ok(tips
+            .iter()
+            .filter(|t| t.status == "valid-fork")
+            .filter_map(|t| {
+                active
+                    .get(&t.node)
+                    .and_then(|a| ForkAnalysis::new(conn, t, a))
+            })
+            .collect())
     }
+}
 
-    pub fn remove(conn: &PgConnection, node_id: i64) -> QueryResult<usize> {
-        use crate::schema::nodes::dsl::*;
-        diesel::delete(nodes).filter(id.eq(node_id)).execute(conn)
+#[derive(Clone, Serialize, AsChangeset, QueryableByName, Queryable, Insertable)]
+#[table_name = "persistent_forks"]
+pub struct PersistentFork {
+    pub node_id: i64,
+    pub fork_point: String,
+    pub tip: String,
+    pub branch_len: i64,
+    pub alerted_len: Option<i64>,
+    pub first_seen_at: DateTime<Utc>,
+    pub alerted_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PersistentFork {
+    pub fn get(
+        conn: &PgConnection,
+        node: i64,
+        point: &String,
+    ) -> QueryResult<Option<PersistentFork>> {
+        use crate::schema::persistent_forks::dsl::*;
+
+        persistent_forks
+            .filter(node_id.eq(node).and(fork_point.eq(point)))
+            .first(conn)
+            .optional()
     }
 
-    pub fn update(&self, conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::nodes::dsl::*;
-        diesel::update(nodes.filter(id.eq(self.id)))
+    // Missing alert fields keep their previous values.
+    pub fn upsert(&self, conn: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::persistent_forks::dsl::*;
+
+        diesel::insert_into(persistent_forks)
+            .values(self)
+            .on_conflict((node_id, fork_point))
+            .do_update()
             .set(self)
             .execute(conn)
     }
 
-    pub fn get_active_reachable(conn: &PgConnection) -> QueryResult<Vec<Node>> {
-        use crate::schema::nodes::dsl::*;
-        nodes
-            .filter(
-                mirror_rpc_port.is_not_null().and(
-                    initial_block_download
-                        .eq(false)
-                        .and(mirror_unreachable_since.is_null()),
-                ),
-            )
-            .load(conn)
-    }
+    /// Forget forks that were not seen since `before`, they have been resolved.
+    pub fn purge_before(conn: &PgConnection, before: DateTime<Utc>) -> QueryResult<usize> {
+        use crate::schema::persistent_forks::dsl::*;
 
-    pub fn insert(
-        conn: &PgConnection,
-        name: String,
-        host: String,
-        port: i32,
-        mirror: Option<i32>,
-        user: String,
-        pass: String,
-        mirror_hostname: Option<String>,
-        archiver: bool,
-    ) -> QueryResult<Node> {
-        use crate::schema::nodes::dsl::*;
-        diesel::insert_into(nodes)
-            .values((
-                node.eq(name),
-                rpc_host.eq(host),
-                rpc_port.eq(port),
-                mirror_rpc_port.eq(mirror),
-                rpc_user.eq(user),
-                rpc_pass.eq(pass),
-                mirror_host.eq(mirror_hostname),
-                archive.eq(archiver),
-            ))
-            .get_result(conn)
+        diesel::delete(persistent_forks)
+            .filter(updated_at.lt(before))
+            .execute(conn)
     }
-}
 
-#[derive(Serialize, QueryableByName, Queryable, Insertable)]
-#[table_name = "peers"]
-pub struct NewPeer {
-    pub node_id: i64,
-    pub peer_id: i64,
-    pub address: String,
-    pub version: i64,
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<PersistentFork>> {
+        use crate::schema::persistent_forks::dsl::*;
+        persistent_forks.order(branch_len.desc()).load(conn)
+    }
 }
 
-#[derive(Serialize, QueryableByName, Queryable, Insertable)]
-#[table_name = "peers"]
-pub struct Peer {
+#[derive(Debug, Serialize, JsonSchema, QueryableByName, Queryable)]
+#[table_name = "api_keys"]
+pub struct ApiKey {
     pub id: i64,
-    pub node_id: i64,
-    pub peer_id: i64,
-    pub address: String,
-    pub version: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
 }
 
-impl Peer {
-    pub fn update_peers(
+impl ApiKey {
+    pub fn create(
         conn: &PgConnection,
-        n_id: i64,
-        peer_list: Vec<NewPeer>,
-    ) -> QueryResult<usize> {
-        use crate::schema::peers::dsl::*;
+        key_name: &str,
+        hash: &str,
+        key_role: &str,
+    ) -> QueryResult<ApiKey> {
+        use crate::schema::api_keys::dsl::*;
+        diesel::insert_into(api_keys)
+            .values((name.eq(key_name), key_hash.eq(hash), role.eq(key_role)))
+            .get_result(conn)
+    }
 
-        diesel::delete(peers)
-            .filter(node_id.eq(n_id))
-            .execute(conn)?;
+    /// The unrevoked key with the given hash.
+    pub fn find_active(conn: &PgConnection, hash: &str) -> QueryResult<Option<ApiKey>> {
+        use crate::schema::api_keys::dsl::*;
+        api_keys
+            .filter(key_hash.eq(hash).and(revoked_at.is_null()))
+            .first(conn)
+            .optional()
+    }
 
-        diesel::insert_into(peers)
-            .values(peer_list)
-            .on_conflict_do_nothing()
+    /// Whether any unrevoked keys exist, authentication is only enforced if so.
+    pub fn any_active(conn: &PgConnection) -> QueryResult<bool> {
+        use crate::schema::api_keys::dsl::*;
+        use diesel::dsl::exists;
+        diesel::select(exists(api_keys.filter(revoked_at.is_null()))).get_result(conn)
+    }
+
+    pub fn revoke(conn: &PgConnection, key_name: &str) -> QueryResult<usize> {
+        use crate::schema::api_keys::dsl::*;
+        diesel::update(api_keys.filter(name.eq(key_name).and(revoked_at.is_null())))
+            .set(revoked_at.eq(Utc::now()))
             .execute(conn)
     }
 
-    pub fn list(conn: &PgConnection, n_id: i64) -> QueryResult<Vec<Peer>> {
-        use crate::schema::peers::dsl::*;
-        peers.filter(node_id.eq(n_id)).load(conn)
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<ApiKey>> {
+        use crate::schema::api_keys::dsl::*;
+        api_keys.order(id.asc()).load(conn)
     }
 }
 
-#[derive(QueryableByName, Queryable, Insertable)]
-#[table_name = "invalid_blocks"]
-pub struct InvalidBlock {
-    pub hash: String,
-    pub node: i64,
-    pub created_at: Option<DateTime<Utc>>,
+/// A notification sent to a subscriber that acks, kept until it is acked.
+#[derive(Clone, Debug, Queryable)]
+pub struct PendingEvent {
+    pub id: i64,
+    pub client_id: String,
+    pub channel: String,
+    /// The notification as JSON, without its event id.
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
 }
 
-#[derive(Clone, Serialize, QueryableByName)]
-pub struct ConflictingBlock {
-    #[sql_type = "sql_types::Text"]
-    pub hash: String,
-    #[sql_type = "sql_types::Array<sql_types::BigInt>"]
-    pub valid_by: Vec<i64>,
-    #[sql_type = "sql_types::Array<sql_types::BigInt>"]
-    pub invalid_by: Vec<i64>,
-}
+impl PendingEvent {
+    /// Keep a notification for `client`, returning its event id.
+    pub fn create(conn: &PgConnection, client: &str, chan: &str, value: &str) -> QueryResult<i64> {
+        use crate::schema::pending_events::dsl::*;
 
-impl InvalidBlock {
-    pub fn get_recent_conflicts(conn: &PgConnection) -> QueryResult<Vec<ConflictingBlock>> {
-        let raw_query = format!(
-            "
-			SELECT hash, array_agg(distinct valid_by) as valid_by, array_agg(distinct invalid_by) as invalid_by
-			FROM (
-				SELECT
-					ivb.hash as hash,
-					vb.node as valid_by,
-					ivb.node as invalid_by
-				FROM valid_blocks as vb
-				INNER JOIN invalid_blocks as ivb
-				ON vb.hash = ivb.hash
-				WHERE ivb.created_at > now() - interval '15 minutes'
-			) q
-			GROUP BY hash
-        ",
-        );
+        diesel::insert_into(pending_events)
+            .values((client_id.eq(client), channel.eq(chan), payload.eq(value)))
+            .returning(id)
+            .get_result(conn)
+    }
 
-        diesel::sql_query(raw_query).load(conn)
+    /// The events `client` didn't ack yet, oldest first.
+    pub fn unacked(
+        conn: &PgConnection,
+        client: &str,
+        chan: &str,
+    ) -> QueryResult<Vec<PendingEvent>> {
+        use crate::schema::pending_events::dsl::*;
+
+        pending_events
+            .filter(client_id.eq(client).and(channel.eq(chan)))
+            .order(id.asc())
+            .load(conn)
     }
-}
 
-#[derive(QueryableByName, Queryable, Insertable)]
-#[table_name = "valid_blocks"]
-pub struct ValidBlock {
-    pub hash: String,
-    pub node: i64,
-    pub created_at: Option<DateTime<Utc>>,
-}
+    /// Delete the events kept since before `since`, of subscribers that never came back.
+    pub fn prune_before(conn: &PgConnection, since: DateTime<Utc>) -> QueryResult<usize> {
+        use crate::schema::pending_events::dsl::*;
 
-#[derive(Clone, Serialize, QueryableByName, Queryable, Insertable)]
-#[table_name = "watched"]
-pub struct Watched {
-    pub address: String,
-    pub created_at: DateTime<Utc>,
-    pub watch_until: DateTime<Utc>,
+        diesel::delete(pending_events)
+            .filter(created_at.lt(since))
+            .execute(conn)
+    }
 }
 
-impl Watched {
-    pub fn insert(
-        conn: &PgConnection,
-        watches: Vec<(String, DateTime<Utc>)>,
-    ) -> QueryResult<usize> {
-        use crate::schema::watched::dsl::*;
+/// The highest event id a subscriber acked on a channel.
+#[derive(Clone, Debug, Serialize, Queryable)]
+pub struct SubscriberAck {
+    pub client_id: String,
+    pub channel: String,
+    pub acked_id: i64,
+    pub updated_at: DateTime<Utc>,
+}
 
-        let watch_list: Vec<_> = watches
-            .into_iter()
-            .map(|(addr, exp)| Watched {
-                address: addr,
-                created_at: Utc::now(),
-                watch_until: exp,
-            })
-            .collect();
+impl SubscriberAck {
+    /// Start tracking a subscriber, keeping what it acked before.
+    pub fn register(conn: &PgConnection, client: &str, chan: &str) -> QueryResult<usize> {
+        use crate::schema::subscriber_acks::dsl::*;
 
-        diesel::insert_into(watched)
-            .values(watch_list)
+        diesel::insert_into(subscriber_acks)
+            .values((client_id.eq(client), channel.eq(chan)))
             .on_conflict_do_nothing()
             .execute(conn)
     }
 
-    pub fn remove(
-        conn: &PgConnection,
-        addresses: Vec<String>,
-    ) -> QueryResult<usize> {
-        use crate::schema::watched::dsl::*;
+    /// Ack every event up to `event_id`, acked events are not kept. Returns how many
+    /// subscribers were updated, 0 if `client` never subscribed to `chan`.
+    pub fn ack(conn: &PgConnection, client: &str, chan: &str, event_id: i64) -> QueryResult<usize> {
+        conn.transaction(|| {
+            let raw_query = "
+                UPDATE subscriber_acks
+                SET acked_id = GREATEST(acked_id, $3), updated_at = now()
+                WHERE client_id = $1 AND channel = $2
+            ";
+            let updated = diesel::sql_query(raw_query)
+                .bind::<sql_types::Text, _>(client)
+                .bind::<sql_types::Text, _>(chan)
+                .bind::<sql_types::BigInt, _>(event_id)
+                .execute(conn)?;
 
-		diesel::delete(watched)
-		    .filter(address.eq_any(addresses))
-			.execute(conn)
-	}
+            use crate::schema::pending_events::dsl::*;
+            let acked = pending_events
+                .filter(client_id.eq(client).and(channel.eq(chan)))
+                .filter(id.le(event_id));
+            diesel::delete(acked).execute(conn)?;
 
-    pub fn clear(conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::watched::dsl::*;
-        let utc_now = Utc::now();
+            Ok(updated)
+        })
+    }
+}
 
-        diesel::delete(watched)
-            .filter(watch_until.lt(utc_now))
+/// The secret a subscriber's notifications are signed with, stored like node
+/// passwords: encrypted when a credentials key is set, or as an `env:` or `file:`
+/// reference.
+#[derive(Clone, Debug, Queryable)]
+pub struct SubscriberSecret {
+    pub client_id: String,
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SubscriberSecret {
+    pub fn set(conn: &PgConnection, client: &str, sealed: &str) -> QueryResult<usize> {
+        use crate::schema::subscriber_secrets::dsl::*;
+
+        diesel::insert_into(subscriber_secrets)
+            .values((client_id.eq(client), secret.eq(sealed)))
+            .on_conflict(client_id)
+            .do_update()
+            .set((secret.eq(sealed), created_at.eq(Utc::now())))
             .execute(conn)
     }
 
-    pub fn fetch(conn: &PgConnection) -> QueryResult<Vec<Transaction>> {
-        use crate::schema::transaction::dsl as tdsl;
-        use crate::schema::transaction_addresses::dsl as tadsl;
-        use crate::schema::watched::dsl as wdsl;
-        use diesel::dsl::any;
+    pub fn remove(conn: &PgConnection, client: &str) -> QueryResult<usize> {
+        use crate::schema::subscriber_secrets::dsl::*;
 
-        let watched: Vec<_> = wdsl::watched.load(conn)?;
-        let watched: Vec<_> = watched.into_iter().map(|w: Watched| w.address).collect();
+        diesel::delete(subscriber_secrets)
+            .filter(client_id.eq(client))
+            .execute(conn)
+    }
 
-        let transactions: Vec<(String, String, String)> = tadsl::transaction_addresses
-            .filter(tadsl::address.eq(any(watched)))
-            .load(conn)?;
+    /// The stored secret of a subscriber, if it has one.
+    pub fn get(conn: &PgConnection, client: &str) -> QueryResult<Option<String>> {
+        use crate::schema::subscriber_secrets::dsl::*;
 
-        let mut block_ids = Vec::new();
-        let mut tx_ids = Vec::new();
+        subscriber_secrets
+            .filter(client_id.eq(client))
+            .select(secret)
+            .first(conn)
+            .optional()
+    }
+}
 
-        for (h, id, _) in transactions.into_iter() {
-            block_ids.push(h);
-            tx_ids.push(id);
-        }
+/// A consistency check of the database, `rows` is how many rows break it.
+#[derive(Clone, Debug, Serialize, JsonSchema, QueryableByName)]
+pub struct IntegrityCheck {
+    #[sql_type = "sql_types::Text"]
+    pub check: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub rows: i64,
+}
 
-        let transactions: Vec<_> = tdsl::transaction
-            .filter(
-                tdsl::block_id
-                    .eq_any(block_ids)
-                    .and(tdsl::txid.eq_any(tx_ids)),
+impl IntegrityCheck {
+    pub fn run(conn: &PgConnection) -> QueryResult<Vec<IntegrityCheck>> {
+        let raw_query = "
+            SELECT 'orphaned_templates' as check, count(*) as rows
+            FROM block_templates bt
+            WHERE NOT EXISTS (SELECT 1 FROM nodes n WHERE n.id = bt.node_id)
+            UNION ALL
+            SELECT 'orphaned_fee_rates', count(*)
+            FROM fee_rates fr
+            WHERE NOT EXISTS (
+                SELECT 1 FROM block_templates bt
+                WHERE bt.parent_block_hash = fr.parent_block_hash AND bt.node_id = fr.node_id
             )
-            .load(conn)?;
+            UNION ALL
+            SELECT 'fee_rates_not_in_template', count(*)
+            FROM fee_rates fr
+            JOIN block_templates bt
+            ON bt.parent_block_hash = fr.parent_block_hash AND bt.node_id = fr.node_id
+            WHERE cardinality(bt.tx_fee_rates) > 0 AND NOT fr.fee_rate = ANY(bt.tx_fee_rates)
+        ";
 
-        Ok(transactions)
+        diesel::sql_query(raw_query).load(conn)
     }
 }
 
-#[derive(Clone, Serialize, QueryableByName, Queryable, Insertable)]
-#[table_name = "lags"]
-pub struct Lags {
-    pub node_id: i64,
-    pub created_at: DateTime<Utc>,
-    pub deleted_at: Option<DateTime<Utc>>,
+/// How far behind a subscriber that acks is.
+#[derive(Clone, Debug, Serialize, JsonSchema, QueryableByName)]
+pub struct SubscriberLag {
+    #[sql_type = "sql_types::Text"]
+    pub client_id: String,
+    #[sql_type = "sql_types::Text"]
+    pub channel: String,
+    #[sql_type = "sql_types::BigInt"]
+    pub acked_id: i64,
+    /// The last event sent to the subscriber.
+    #[sql_type = "sql_types::BigInt"]
+    pub last_event_id: i64,
+    /// How many events it didn't ack.
+    #[sql_type = "sql_types::BigInt"]
+    pub unacked: i64,
+    #[sql_type = "sql_types::Nullable<sql_types::Timestamptz>"]
+    pub oldest_unacked_at: Option<DateTime<Utc>>,
+    /// When it last acked or subscribed.
+    #[sql_type = "sql_types::Timestamptz"]
     pub updated_at: DateTime<Utc>,
 }
 
-impl Lags {
-    pub fn purge(conn: &PgConnection) -> QueryResult<usize> {
-        use crate::schema::lags::dsl::*;
-        diesel::delete(lags).execute(conn)
-    }
-
-    pub fn insert(conn: &PgConnection, id: i64) -> QueryResult<usize> {
-        use crate::schema::lags::dsl::*;
-
-        let lag = Lags {
-            node_id: id,
-            created_at: Utc::now(),
-            deleted_at: None,
-            updated_at: Utc::now(),
-        };
-
-        diesel::insert_into(lags).values(lag).execute(conn)
-    }
+impl SubscriberLag {
+    pub fn list(conn: &PgConnection) -> QueryResult<Vec<SubscriberLag>> {
+        let raw_query = "
+            SELECT a.client_id, a.channel, a.acked_id,
+                COALESCE(max(e.id), a.acked_id) as last_event_id,
+                count(e.id) as unacked,
+                min(e.created_at) as oldest_unacked_at,
+                a.updated_at
+            FROM subscriber_acks a
+            LEFT JOIN pending_events e
+            ON e.client_id = a.client_id AND e.channel = a.channel AND e.id > a.acked_id
+            GROUP BY a.client_id, a.channel, a.acked_id, a.updated_at
+            ORDER BY a.client_id, a.channel
+        ";
 
-    pub fn list(conn: &PgConnection) -> QueryResult<Vec<Lags>> {
-        use crate::schema::lags::dsl::*;
-        lags.load(conn)
+        diesel::sql_query(raw_query).load(conn)
     }
 }
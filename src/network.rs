@@ -0,0 +1,271 @@
+//! Chain parameters of the network the nodes are on. The network is configured, or the
+//! scanner takes it from the `chain` the archive node reports in `getblockchaininfo`,
+//! until then subsidies and block times are worked out for mainnet.
+use crate::credentials::{resolve_password, CredentialError};
+use crate::rpc_client::{rpc_client, RpcTimeouts};
+use bitcoincore_rpc::{bitcoin::BlockHash, Auth, RpcApi};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use thiserror::Error;
+
+static NETWORK: AtomicU8 = AtomicU8::new(Network::Main as u8);
+static NETWORK_KNOWN: AtomicBool = AtomicBool::new(false);
+
+const SATOSHI_TO_BTC: i64 = 100_000_000;
+pub(crate) const INITIAL_SUBSIDY: i64 = 50 * SATOSHI_TO_BTC;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Network {
+    Main,
+    Test,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+/// The parameters that differ between networks.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, JsonSchema)]
+pub struct NetworkParams {
+    pub network: Network,
+    /// Blocks between subsidy halvings.
+    pub halving_interval: i64,
+    /// Seconds the difficulty adjustment aims for between blocks.
+    pub target_block_secs: i64,
+    pub genesis_hash: &'static str,
+}
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("Could not query the node, {0}")]
+    Rpc(#[from] bitcoincore_rpc::Error),
+    #[error("Node credentials error {0:?}")]
+    Credentials(#[from] CredentialError),
+    #[error("{0}")]
+    UnknownChain(String),
+    #[error("Node doesn't have the {0} genesis block")]
+    MissingGenesis(Network),
+    #[error("Node is on {node}, forkscanner monitors {expected}")]
+    Mismatch { node: Network, expected: Network },
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    /// Parse a chain name the way `getblockchaininfo` reports it.
+    fn from_str(s: &str) -> Result<Network, String> {
+        match s {
+            "main" => Ok(Network::Main),
+            "test" => Ok(Network::Test),
+            "testnet4" => Ok(Network::Testnet4),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            other => Err(format!("Unknown chain {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let chain = match self {
+            Network::Main => "main",
+            Network::Test => "test",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+        f.write_str(chain)
+    }
+}
+
+impl Network {
+    pub fn params(self) -> NetworkParams {
+        let (halving_interval, target_block_secs, genesis_hash) = match self {
+            Network::Main => (
+                210_000,
+                600,
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            ),
+            Network::Test => (
+                210_000,
+                600,
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            ),
+            Network::Testnet4 => (
+                210_000,
+                600,
+                "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+            ),
+            Network::Signet => (
+                210_000,
+                600,
+                "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            ),
+            Network::Regtest => (
+                150,
+                600,
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            ),
+        };
+
+        NetworkParams {
+            network: self,
+            halving_interval,
+            target_block_secs,
+            genesis_hash,
+        }
+    }
+
+    /// The subsidy of the block at `height`, in satoshis.
+    pub fn subsidy(self, height: i64) -> i64 {
+        let halvings = height / self.params().halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+        INITIAL_SUBSIDY >> halvings
+    }
+
+    /// The subsidy of every block up to and including `height`, in satoshis.
+    pub fn expected_supply(self, height: i64) -> i64 {
+        let interval = self.params().halving_interval;
+        let mut supply = 0;
+        let mut subsidy = INITIAL_SUBSIDY;
+        let mut remaining = height + 1;
+        while remaining > 0 && subsidy > 0 {
+            let blocks = remaining.min(interval);
+            supply += blocks * subsidy;
+            remaining -= blocks;
+            subsidy >>= 1;
+        }
+        supply
+    }
+
+    /// The height of the first halving after `height`.
+    pub fn next_halving(self, height: i64) -> i64 {
+        let interval = self.params().halving_interval;
+        (height / interval + 1) * interval
+    }
+
+    /// Whether `address` is an address on this network. The test networks share their
+    /// prefixes, and regtest only has a bech32 prefix of its own.
+    pub fn accepts_address(self, address: &str) -> bool {
+        use bitcoin::Network as AddressNetwork;
+
+        let network = match bitcoin::Address::from_str(address) {
+            Ok(address) => address.network,
+            Err(_) => return false,
+        };
+        match self {
+            Network::Main => network == AddressNetwork::Bitcoin,
+            Network::Test | Network::Testnet4 | Network::Signet => {
+                network == AddressNetwork::Testnet || network == AddressNetwork::Signet
+            }
+            Network::Regtest => {
+                network == AddressNetwork::Regtest || network == AddressNetwork::Testnet
+            }
+        }
+    }
+}
+
+/// Set the network subsidies and block times are worked out for.
+pub fn set_network(network: Network) {
+    NETWORK.store(network as u8, Ordering::SeqCst);
+    NETWORK_KNOWN.store(true, Ordering::SeqCst);
+}
+
+/// The network once it's configured or the scanner found it out.
+pub fn known_network() -> Option<Network> {
+    if NETWORK_KNOWN.load(Ordering::SeqCst) {
+        Some(network())
+    } else {
+        None
+    }
+}
+
+/// The network a node reports, checked against the genesis block it has.
+pub fn node_network(client: &impl RpcApi) -> Result<Network, NetworkError> {
+    let network = client
+        .get_blockchain_info()?
+        .chain
+        .parse::<Network>()
+        .map_err(NetworkError::UnknownChain)?;
+
+    let genesis = BlockHash::from_str(network.params().genesis_hash).expect("Bad genesis hash");
+    if client.get_block_header_info(&genesis).is_err() {
+        return Err(NetworkError::MissingGenesis(network));
+    }
+    Ok(network)
+}
+
+/// Check a node is on the network forkscanner monitors before adding it, so the blocks
+/// of another network don't end up in the tables. If no network is known yet the node
+/// sets it.
+pub fn check_node(
+    host: &str,
+    port: i32,
+    user: &str,
+    pass: &str,
+    timeouts: RpcTimeouts,
+) -> Result<Network, NetworkError> {
+    let auth = Auth::UserPass(user.to_string(), resolve_password(pass)?);
+    let client = rpc_client(&format!("http://{}:{}", host, port), auth, timeouts)?;
+    let node = node_network(&client)?;
+
+    match known_network() {
+        Some(expected) if expected != node => Err(NetworkError::Mismatch { node, expected }),
+        Some(_) => Ok(node),
+        None => {
+            set_network(node);
+            Ok(node)
+        }
+    }
+}
+
+/// The network the nodes are on, mainnet until the scanner found out.
+pub fn network() -> Network {
+    match NETWORK.load(Ordering::SeqCst) {
+        n if n == Network::Test as u8 => Network::Test,
+        n if n == Network::Testnet4 as u8 => Network::Testnet4,
+        n if n == Network::Signet as u8 => Network::Signet,
+        n if n == Network::Regtest as u8 => Network::Regtest,
+        _ => Network::Main,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_network_params() {
+        assert_eq!("signet".parse(), Ok(Network::Signet));
+        assert!("mainnet".parse::<Network>().is_err());
+        assert_eq!(Network::Testnet4.to_string().parse(), Ok(Network::Testnet4));
+        assert_eq!(Network::Main.params().halving_interval, 210_000);
+
+        let main = Network::Main;
+        assert_eq!(main.subsidy(0), INITIAL_SUBSIDY);
+        assert_eq!(main.subsidy(840_000), INITIAL_SUBSIDY / 16);
+        assert_eq!(main.next_halving(840_000), 1_050_000);
+
+        // regtest halves every 150 blocks, and the subsidy runs out
+        let regtest = Network::Regtest;
+        assert_eq!(regtest.subsidy(149), INITIAL_SUBSIDY);
+        assert_eq!(regtest.subsidy(150), INITIAL_SUBSIDY / 2);
+        assert_eq!(regtest.subsidy(150 * 64), 0);
+        assert_eq!(regtest.expected_supply(299), 150 * INITIAL_SUBSIDY * 3 / 2);
+        assert_eq!(regtest.next_halving(10), 150);
+
+        let main_address = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let test_address = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7";
+        assert!(main.accepts_address(main_address));
+        assert!(!main.accepts_address(test_address));
+        assert!(Network::Signet.accepts_address(test_address));
+        assert!(regtest.accepts_address(test_address));
+        assert!(!regtest.accepts_address(main_address));
+        assert!(!main.accepts_address("not an address"));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::scanner::PeerInfo;
+use bitcoincore_rpc::bitcoincore_rpc_json::GetPeerInfoResultConnectionType;
+use std::{cmp::Ordering, collections::HashSet};
+
+/// How likely a peer is to be able to serve a block body, based on the
+/// inventory hints reported by `getpeerinfo`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PeerHint {
+    /// Peer has told us nothing useful about this height.
+    Unknown,
+    /// Peer has announced a header chain that reaches this height.
+    HasHeader,
+    /// Peer has fully synced blocks up to this height.
+    HasBlock,
+    /// We are currently downloading this height from the peer.
+    InFlight,
+}
+
+/// Classify a peer for a block at the given height.
+pub fn peer_hint(peer: &PeerInfo, height: i64) -> PeerHint {
+    let in_flight = peer
+        .inflight
+        .as_ref()
+        .map(|heights| heights.iter().any(|h| *h as i64 == height))
+        .unwrap_or(false);
+
+    if in_flight {
+        PeerHint::InFlight
+    } else if peer.synced_blocks.map(|h| h >= height).unwrap_or(false) {
+        PeerHint::HasBlock
+    } else if peer.synced_headers.map(|h| h >= height).unwrap_or(false) {
+        PeerHint::HasHeader
+    } else {
+        PeerHint::Unknown
+    }
+}
+
+// Outbound full relay peers are the ones we picked ourselves, and they are
+// expected to serve blocks.
+fn is_outbound_full_relay(peer: &PeerInfo) -> bool {
+    match peer.connection_type {
+        Some(GetPeerInfoResultConnectionType::OutboundFullRelay) => true,
+        Some(_) => false,
+        None => !peer.inbound,
+    }
+}
+
+fn compare_peers(l: &PeerInfo, r: &PeerInfo, height: i64) -> Ordering {
+    let ping = |p: &PeerInfo| p.minping.or(p.pingtime).unwrap_or(f64::MAX);
+
+    peer_hint(r, height)
+        .cmp(&peer_hint(l, height))
+        .then_with(|| is_outbound_full_relay(r).cmp(&is_outbound_full_relay(l)))
+        .then_with(|| r.last_block.unwrap_or(0).cmp(&l.last_block.unwrap_or(0)))
+        .then_with(|| ping(l).partial_cmp(&ping(r)).unwrap_or(Ordering::Equal))
+        .then_with(|| l.id.cmp(&r.id))
+}
+
+/// Order peers by how likely they are to have the block at `height`, best first.
+pub fn rank_peers(mut peers: Vec<PeerInfo>, height: i64) -> Vec<PeerInfo> {
+    peers.sort_by(|l, r| compare_peers(l, r, height));
+    peers
+}
+
+/// The host of a peer address without its port, so a peer keeps its reputation
+/// when it reconnects from another port.
+pub fn peer_host(addr: &str) -> &str {
+    if let Some(v6) = addr.strip_prefix('[') {
+        return v6.split(']').next().unwrap_or(v6);
+    }
+
+    match addr.rsplit_once(':') {
+        // more than one colon is an IPv6 address without a port
+        Some((host, _)) if !host.contains(':') => host,
+        _ => addr,
+    }
+}
+
+/// Move peers whose host is in `flagged` behind all others, keeping the ranking
+/// within both groups.
+pub fn demote_flagged(mut peers: Vec<PeerInfo>, flagged: &HashSet<String>) -> Vec<PeerInfo> {
+    peers.sort_by_key(|p| flagged.contains(peer_host(&p.addr)));
+    peers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn peer(
+        id: u64,
+        synced_headers: i64,
+        synced_blocks: i64,
+        inflight: Vec<u64>,
+        inbound: bool,
+        minping: f64,
+    ) -> PeerInfo {
+        serde_json::from_value(json!({
+            "id": id,
+            "addr": format!("10.0.0.{}:8333", id),
+            "addrbind": "10.0.0.254:8333",
+            "services": "0000000000000409",
+            "relaytxes": true,
+            "lastsend": 0,
+            "lastrecv": 0,
+            "bytessent": 0,
+            "bytesrecv": 0,
+            "conntime": 0,
+            "timeoffset": 0,
+            "minping": minping,
+            "version": 70016,
+            "subver": "/Satoshi:23.0.0/",
+            "inbound": inbound,
+            "synced_headers": synced_headers,
+            "synced_blocks": synced_blocks,
+            "inflight": inflight,
+        }))
+        .expect("Bad peer JSON")
+    }
+
+    fn ids(peers: Vec<PeerInfo>) -> Vec<u64> {
+        peers.into_iter().map(|p| p.id).collect()
+    }
+
+    #[test]
+    fn test_peer_hint() {
+        assert_eq!(
+            peer_hint(&peer(0, 100, 90, vec![100], false, 0.1), 100),
+            PeerHint::InFlight
+        );
+        assert_eq!(
+            peer_hint(&peer(0, 100, 100, vec![], false, 0.1), 100),
+            PeerHint::HasBlock
+        );
+        assert_eq!(
+            peer_hint(&peer(0, 100, 99, vec![], false, 0.1), 100),
+            PeerHint::HasHeader
+        );
+        assert_eq!(
+            peer_hint(&peer(0, 99, 99, vec![], false, 0.1), 100),
+            PeerHint::Unknown
+        );
+        assert_eq!(
+            peer_hint(&peer(0, -1, -1, vec![], false, 0.1), 100),
+            PeerHint::Unknown
+        );
+    }
+
+    #[test]
+    fn test_rank_by_hint() {
+        let peers = vec![
+            peer(1, 50, 50, vec![], false, 0.1),
+            peer(2, 100, 99, vec![], false, 0.1),
+            peer(3, 100, 100, vec![], false, 0.1),
+            peer(4, 100, 99, vec![100], false, 0.1),
+        ];
+
+        assert_eq!(ids(rank_peers(peers, 100)), vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_rank_tie_breaks() {
+        let peers = vec![
+            peer(1, 100, 100, vec![], true, 0.01),
+            peer(2, 100, 100, vec![], false, 0.5),
+            peer(3, 100, 100, vec![], false, 0.05),
+        ];
+
+        // outbound first, then lowest ping
+        assert_eq!(ids(rank_peers(peers, 100)), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_rank_empty() {
+        assert!(rank_peers(vec![], 100).is_empty());
+    }
+
+    #[test]
+    fn test_peer_host() {
+        assert_eq!(peer_host("10.0.0.1:8333"), "10.0.0.1");
+        assert_eq!(peer_host("[2001:db8::1]:8333"), "2001:db8::1");
+        assert_eq!(peer_host("2001:db8::1"), "2001:db8::1");
+        assert_eq!(peer_host("abcd.onion:8333"), "abcd.onion");
+        assert_eq!(peer_host("10.0.0.1"), "10.0.0.1");
+    }
+
+    #[test]
+    fn test_demote_flagged() {
+        let peers = vec![
+            peer(1, 100, 100, vec![], false, 0.1),
+            peer(2, 100, 100, vec![], false, 0.1),
+            peer(3, 100, 100, vec![], false, 0.1),
+        ];
+        let flagged = vec!["10.0.0.1".to_string()].into_iter().collect();
+
+        assert_eq!(ids(demote_flagged(peers, &flagged)), vec![2, 3, 1]);
+    }
+}
@@ -0,0 +1,155 @@
+//! A read only REST API over the queries of the RPC server, for dashboards and curl
+//! scripts that would rather `GET` a URL than post JSON-RPC. It is served on its own
+//! port when `rest_port` is set, with the same api keys and rate limits as RPC.
+use crate::auth::{http_meta, Caller};
+use crate::limits::RateLimiter;
+use crate::service::{self, Conn};
+use crate::Node;
+use diesel::prelude::{PgConnection, QueryResult};
+use jsonrpc_core::{Error, ErrorCode, Params};
+use jsonrpc_http_server::{
+    hyper::{header::HeaderValue, Body, Method, Request, StatusCode},
+    RequestMiddlewareAction, Response,
+};
+use log::error;
+use r2d2_diesel::ConnectionManager;
+use serde_json::{json, Map, Value};
+
+type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+fn error_response(code: StatusCode, message: &str) -> Response {
+    Response {
+        code,
+        content_type: HeaderValue::from_static("application/json; charset=utf-8"),
+        content: json!({ "error": message }).to_string(),
+    }
+}
+
+// Query parameters as RPC params. Values that parse as JSON are taken as such, e.g.
+// `?active_only=true&limit=10`, anything else is a string.
+fn query_params(query: Option<&str>) -> Map<String, Value> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, "true"));
+            let value = serde_json::from_str(value).unwrap_or_else(|_| value.into());
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn node_list(conn: Conn) -> Result<Value, Error> {
+    let stats = Node::list(&conn).and_then(|nodes| {
+        nodes
+            .into_iter()
+            .map(|node| service::node_stats_json(&conn, node))
+            .collect::<QueryResult<Vec<_>>>()
+    });
+
+    match stats {
+        Ok(stats) => Ok(stats.into()),
+        Err(e) => {
+            error!("Could not fetch node stats {:?}", e);
+            Err(Error::internal_error())
+        }
+    }
+}
+
+// Route a path to the RPC query answering it, `None` if there is no such path.
+fn route(conn: Conn, path: &str, mut params: Map<String, Value>) -> Option<Result<Value, Error>> {
+    let parts: Vec<_> = path.trim_matches('/').split('/').collect();
+    let result = match parts.as_slice() {
+        ["blocks", hash] => {
+            let mut params = Map::new();
+            params.insert("hashes".into(), json!([hash]));
+            match service::get_blocks(conn, Params::Map(params)) {
+                Ok(Value::Array(blocks)) => {
+                    Ok(blocks.into_iter().next().filter(|b| !b.is_null())?)
+                }
+                other => other,
+            }
+        }
+        ["chaintips"] => {
+            params.entry("active_only").or_insert(false.into());
+            service::get_tips(Params::Map(params), conn)
+        }
+        ["nodes"] => node_list(conn),
+        ["nodes", id] => {
+            params.insert("id".into(), id.parse::<i64>().ok()?.into());
+            service::get_node_stats(conn, Params::Map(params))
+        }
+        ["stale_candidates"] => service::get_stale_candidates(conn, Params::Map(params)),
+        ["peers"] => service::get_peers(conn, Params::Map(params)),
+        ["peers", id] => {
+            params.insert("id".into(), id.parse::<i64>().ok()?.into());
+            service::get_peers(conn, Params::Map(params))
+        }
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+/// HTTP middleware answering every request of the REST server, the RPC handler
+/// behind it is never reached.
+pub fn rest_middleware(
+    pool: ManagedPool,
+    limiter: RateLimiter,
+) -> impl Fn(Request<Body>) -> RequestMiddlewareAction + Send + Sync + 'static {
+    let meta = http_meta(pool.clone());
+
+    move |request: Request<Body>| {
+        if request.method() != Method::GET {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported").into();
+        }
+
+        let meta = meta(&request);
+        if meta.role().is_none() {
+            return error_response(StatusCode::UNAUTHORIZED, "Unauthorized").into();
+        }
+        if !limiter.take(meta.key_name()) {
+            return error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests").into();
+        }
+
+        let conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Could not get a database connection {:?}", e);
+                return Response::internal_error("Database unavailable").into();
+            }
+        };
+
+        let params = query_params(request.uri().query());
+        match route(conn, request.uri().path(), params) {
+            Some(Ok(value)) => Response::ok(value.to_string()).into(),
+            Some(Err(e)) if e.code == ErrorCode::InvalidParams => {
+                error_response(StatusCode::BAD_REQUEST, &e.message).into()
+            }
+            Some(Err(e)) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.message).into(),
+            None => error_response(StatusCode::NOT_FOUND, "Not found").into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query_params() {
+        let params = query_params(Some(
+            "active_only=true&limit=10&source=bitcoin-data&with_branch",
+        ));
+        assert_eq!(params["active_only"], json!(true));
+        assert_eq!(params["limit"], json!(10));
+        assert_eq!(params["source"], json!("bitcoin-data"));
+        assert_eq!(params["with_branch"], json!(true));
+
+        // hashes with leading zeros aren't JSON numbers
+        let params = query_params(Some("hash=000000000019d6689c&"));
+        assert_eq!(params["hash"], json!("000000000019d6689c"));
+        assert!(query_params(None).is_empty());
+    }
+}
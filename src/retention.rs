@@ -0,0 +1,187 @@
+//! How long fetched transactions, raw blocks, block templates and fee rates, stale
+//! candidates, peer history, external observations, tip history and unacked subscriber
+//! events are kept. Blocks themselves are never pruned, branches and
+//! confirmations are worked out from them. A policy keeps `days` or `blocks`, whichever
+//! keeps more, counting a day as 144 blocks so one setting covers both the data kept by
+//! height and by time.
+use crate::{
+    ExternalObservation, PeerReputation, PendingEvent, StaleCandidate, TipHistory, WatchedRelay,
+};
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use diesel::sql_types;
+use serde::{Deserialize, Serialize};
+
+const BLOCKS_PER_DAY: i64 = 144;
+const DEFAULT_INTERVAL_MINUTES: i64 = 60;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Days of data to keep, `FORKSCANNER_RETENTION_DAYS`.
+    pub days: Option<i64>,
+    /// Blocks below the tip to keep data of, `FORKSCANNER_RETENTION_BLOCKS`.
+    pub blocks: Option<i64>,
+    /// Minutes between prunes.
+    pub interval_minutes: i64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> RetentionPolicy {
+        RetentionPolicy {
+            days: None,
+            blocks: None,
+            interval_minutes: DEFAULT_INTERVAL_MINUTES,
+        }
+    }
+}
+
+/// Rows deleted by a prune, per kind of data.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PruneReport {
+    pub transactions: usize,
+    pub transaction_inputs: usize,
+    pub transaction_addresses: usize,
+    pub block_transactions: usize,
+    pub raw_blocks: usize,
+    pub raw_block_attempts: usize,
+    pub block_templates: usize,
+    pub template_snapshots: usize,
+    pub template_txs: usize,
+    pub fee_rates: usize,
+    pub stale_candidates: usize,
+    pub peer_reputation: usize,
+    pub external_observations: usize,
+    pub tip_history: usize,
+    pub watched_relays: usize,
+    pub pending_events: usize,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.days.is_some() || self.blocks.is_some()
+    }
+
+    /// Blocks below the tip whose data is kept.
+    pub fn keep_blocks(&self) -> Option<i64> {
+        let days = self.days.map(|d| d * BLOCKS_PER_DAY);
+        days.max(self.blocks)
+    }
+
+    /// Data last updated before this is pruned.
+    pub fn keep_since(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let blocks = self
+            .blocks
+            .map(|b| (b + BLOCKS_PER_DAY - 1) / BLOCKS_PER_DAY);
+        let days = self.days.max(blocks)?;
+        Some(now - Duration::days(days))
+    }
+
+    /// Whether a prune is due, the last one was at `last`.
+    pub fn is_due(&self, last: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        match last {
+            Some(last) => now - last >= Duration::minutes(self.interval_minutes),
+            None => true,
+        }
+    }
+
+    /// Delete what falls outside the policy, with the highest block at `tip_height`.
+    /// Stale candidates imported from a dataset are kept, they are history on purpose.
+    pub fn prune(&self, conn: &PgConnection, tip_height: i64) -> QueryResult<PruneReport> {
+        let mut report = PruneReport::default();
+
+        if let Some(keep) = self.keep_blocks() {
+            let below = tip_height - keep;
+            report.transactions = prune_block_rows(conn, "transaction", "block_id", below)?;
+            report.transaction_inputs =
+                prune_block_rows(conn, "transaction_inputs", "block_id", below)?;
+            report.transaction_addresses =
+                prune_block_rows(conn, "transaction_addresses", "hash", below)?;
+            report.block_transactions =
+                prune_block_rows(conn, "block_transactions", "block_hash", below)?;
+            report.raw_blocks = prune_height_rows(conn, "raw_blocks", below)?;
+            report.raw_block_attempts =
+                prune_block_rows(conn, "raw_block_attempts", "block_hash", below)?;
+            report.block_templates = prune_height_rows(conn, "block_templates", below)?;
+            report.template_snapshots = prune_height_rows(conn, "template_snapshots", below)?;
+            report.template_txs = prune_height_rows(conn, "template_txs", below)?;
+            report.fee_rates = prune_block_rows(conn, "fee_rates", "parent_block_hash", below)?;
+            report.stale_candidates = StaleCandidate::prune_below(conn, below)?;
+        }
+        if let Some(since) = self.keep_since(Utc::now()) {
+            report.peer_reputation = PeerReputation::prune_before(conn, since)?;
+            report.external_observations = ExternalObservation::prune_before(conn, since)?;
+            report.tip_history = TipHistory::prune_before(conn, since)?;
+            report.watched_relays = WatchedRelay::prune_before(conn, since)?;
+            report.pending_events = PendingEvent::prune_before(conn, since)?;
+        }
+
+        Ok(report)
+    }
+}
+
+// Delete the rows of a transaction table whose block is below `height`.
+fn prune_block_rows(
+    conn: &PgConnection,
+    table: &str,
+    column: &str,
+    height: i64,
+) -> QueryResult<usize> {
+    let raw_query = format!(
+        "DELETE FROM {} t USING blocks b WHERE b.hash = t.{} AND b.height < $1",
+        table, column
+    );
+
+    diesel::sql_query(raw_query)
+        .bind::<sql_types::BigInt, _>(height)
+        .execute(conn)
+}
+
+// Delete the rows of a table with its own `height` below `height`.
+fn prune_height_rows(conn: &PgConnection, table: &str, height: i64) -> QueryResult<usize> {
+    let raw_query = format!("DELETE FROM {} WHERE height < $1", table);
+
+    diesel::sql_query(raw_query)
+        .bind::<sql_types::BigInt, _>(height)
+        .execute(conn)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retention_policy() {
+        let now = Utc::now();
+        let policy = |days, blocks| RetentionPolicy {
+            days,
+            blocks,
+            ..RetentionPolicy::default()
+        };
+
+        let off = policy(None, None);
+        assert!(!off.is_enabled());
+        assert_eq!(off.keep_blocks(), None);
+        assert_eq!(off.keep_since(now), None);
+        assert!(!off.is_due(None, now));
+
+        // whichever keeps more
+        let both = policy(Some(2), Some(1000));
+        assert_eq!(both.keep_blocks(), Some(1000));
+        assert_eq!(both.keep_since(now), Some(now - Duration::days(7)));
+
+        let days = policy(Some(30), None);
+        assert_eq!(days.keep_blocks(), Some(30 * 144));
+        assert_eq!(days.keep_since(now), Some(now - Duration::days(30)));
+
+        let blocks = policy(None, Some(100));
+        assert_eq!(blocks.keep_since(now), Some(now - Duration::days(1)));
+
+        assert!(blocks.is_due(None, now));
+        assert!(!blocks.is_due(Some(now - Duration::minutes(59)), now));
+        assert!(blocks.is_due(Some(now - Duration::minutes(60)), now));
+    }
+}
@@ -0,0 +1,399 @@
+//! RPC clients for the nodes, with the connect and read timeouts set per node. The
+//! jsonrpc crate's own transport has one timeout for both, so a node that is slow to
+//! answer can't be given longer without also waiting longer for one that is down.
+//!
+//! Read calls that fail on the way to or from the node, rather than with an RPC error,
+//! are retried a couple of times with a jittered backoff. A call like `submitblock` may
+//! have reached the node before failing, so it's never sent twice. The retries and
+//! failures of a [`node_rpc_client`] are counted per node.
+use crate::{Node, NodeTimeouts};
+use base64::Engine;
+use bitcoincore_rpc::{Auth, Client};
+use jsonrpc::{Request, Response, Transport};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io, thread};
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 15;
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 15;
+
+const MAX_RETRIES: u32 = 2;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
+
+// Calls that only read from the node, any other call isn't retried. Some `get` calls
+// change the node's state.
+const READ_PREFIXES: &[&str] = &["get", "list", "estimate", "decode", "testmempoolaccept"];
+const NOT_READ: &[&str] = &["getblockfrompeer", "getnewaddress", "getrawchangeaddress"];
+
+static ERROR_COUNTS: Mutex<BTreeMap<i64, RpcErrorCounts>> = Mutex::new(BTreeMap::new());
+
+/// Calls to a node that were retried, and that still failed after their retries.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RpcErrorCounts {
+    pub retries: i64,
+    pub failures: i64,
+}
+
+/// The error counts of the node since they were last taken.
+pub fn take_error_counts(node_id: i64) -> RpcErrorCounts {
+    ERROR_COUNTS
+        .lock()
+        .expect("Error counts poisoned")
+        .remove(&node_id)
+        .unwrap_or_default()
+}
+
+fn count_error(node_id: i64, retried: bool) {
+    let mut counts = ERROR_COUNTS.lock().expect("Error counts poisoned");
+    let counts = counts.entry(node_id).or_default();
+    if retried {
+        counts.retries += 1;
+    } else {
+        counts.failures += 1;
+    }
+}
+
+// Doubles with each attempt, and is cut by up to half so that the nodes' clients don't
+// all come back at once.
+fn backoff(attempt: u32) -> Duration {
+    let delay = RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(RETRY_MAX_DELAY);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+fn is_read(method: &str) -> bool {
+    READ_PREFIXES
+        .iter()
+        .any(|prefix| method.starts_with(prefix))
+        && !NOT_READ.contains(&method)
+}
+
+/// How long to wait for a node to accept a connection, and for each read of its
+/// answer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RpcTimeouts {
+    pub connect: Duration,
+    pub read: Duration,
+}
+
+impl Default for RpcTimeouts {
+    fn default() -> RpcTimeouts {
+        RpcTimeouts {
+            connect: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            read: Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl From<&NodeTimeouts> for RpcTimeouts {
+    fn from(timeouts: &NodeTimeouts) -> RpcTimeouts {
+        let secs =
+            |secs: Option<i32>, default| Duration::from_secs(secs.map_or(default, |s| s as u64));
+        RpcTimeouts {
+            connect: secs(
+                timeouts.rpc_connect_timeout_secs,
+                DEFAULT_CONNECT_TIMEOUT_SECS,
+            ),
+            read: secs(timeouts.rpc_read_timeout_secs, DEFAULT_READ_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl From<&Node> for RpcTimeouts {
+    fn from(node: &Node) -> RpcTimeouts {
+        RpcTimeouts::from(&NodeTimeouts {
+            rpc_connect_timeout_secs: node.rpc_connect_timeout_secs,
+            rpc_read_timeout_secs: node.rpc_read_timeout_secs,
+        })
+    }
+}
+
+struct HttpTransport {
+    agent: ureq::Agent,
+    url: String,
+    authorization: Option<String>,
+    // the node the errors are counted for
+    node_id: Option<i64>,
+    // no retry is started once a call has taken this long, so a node that hangs isn't
+    // waited on again
+    retry_budget: Duration,
+}
+
+impl HttpTransport {
+    fn request<T, R>(&self, body: &T, retry: bool) -> Result<R, jsonrpc::Error>
+    where
+        T: serde::Serialize,
+        R: serde::de::DeserializeOwned,
+    {
+        let body = serde_json::to_string(body)?;
+        let start = Instant::now();
+        let mut attempt = 0;
+        loop {
+            let e = match self.send(&body) {
+                Ok(response) => return Ok(serde_json::from_str(&response)?),
+                Err((e, false)) => return Err(e),
+                Err((e, true)) => e,
+            };
+            if !retry || attempt == MAX_RETRIES || start.elapsed() >= self.retry_budget {
+                self.count_error(false);
+                return Err(e);
+            }
+            self.count_error(true);
+            thread::sleep(backoff(attempt));
+            attempt += 1;
+        }
+    }
+
+    fn count_error(&self, retried: bool) {
+        if let Some(node_id) = self.node_id {
+            count_error(node_id, retried);
+        }
+    }
+
+    // The body of the node's answer, or the error and whether it is worth a retry.
+    fn send(&self, body: &str) -> Result<String, (jsonrpc::Error, bool)> {
+        let transport = |e: io::Error| jsonrpc::Error::Transport(Box::new(e));
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json");
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+
+        match request.send_string(body) {
+            Ok(response) => response.into_string().map_err(|e| (transport(e), true)),
+            // bitcoind answers RPC errors with a status code and the JSON-RPC error as the
+            // body, anything else, like a 503 when its work queue is full, is plain text
+            Err(ureq::Error::Status(status, response)) => {
+                let text = response.into_string().map_err(|e| (transport(e), true))?;
+                if serde_json::from_str::<serde_json::Value>(&text).is_ok() {
+                    return Ok(text);
+                }
+                let e = io::Error::other(format!("HTTP {}: {}", status, text.trim()));
+                Err((transport(e), status >= 500))
+            }
+            Err(ureq::Error::Transport(e)) => {
+                let transient = matches!(
+                    e.kind(),
+                    ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+                );
+                let e = ureq::Error::Transport(e);
+                Err((jsonrpc::Error::Transport(Box::new(e)), transient))
+            }
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+        self.request(&request, is_read(request.method))
+    }
+
+    fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+        let retry = requests.iter().all(|request| is_read(request.method));
+        self.request(&requests, retry)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.url)
+    }
+}
+
+/// A client for the node at `url`, e.g. `http://127.0.0.1:8332`.
+pub fn rpc_client(
+    url: &str,
+    auth: Auth,
+    timeouts: RpcTimeouts,
+) -> Result<Client, bitcoincore_rpc::Error> {
+    client(url, auth, timeouts, None)
+}
+
+/// A client for a monitored node, that counts its retried and failed calls under
+/// `node_id`, see [`take_error_counts`].
+pub fn node_rpc_client(
+    node_id: i64,
+    url: &str,
+    auth: Auth,
+    timeouts: RpcTimeouts,
+) -> Result<Client, bitcoincore_rpc::Error> {
+    client(url, auth, timeouts, Some(node_id))
+}
+
+fn client(
+    url: &str,
+    auth: Auth,
+    timeouts: RpcTimeouts,
+    node_id: Option<i64>,
+) -> Result<Client, bitcoincore_rpc::Error> {
+    let credentials = match auth {
+        Auth::None => None,
+        Auth::UserPass(user, pass) => Some(format!("{}:{}", user, pass)),
+        Auth::CookieFile(path) => Some(fs::read_to_string(path)?.trim().to_string()),
+    };
+    let authorization = credentials.map(|credentials| {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+        format!("Basic {}", encoded)
+    });
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(timeouts.connect)
+        .timeout_read(timeouts.read)
+        .timeout_write(timeouts.read)
+        .build();
+
+    let transport = HttpTransport {
+        agent,
+        url: url.to_string(),
+        authorization,
+        node_id,
+        retry_budget: timeouts.read,
+    };
+    Ok(Client::from_jsonrpc(jsonrpc::Client::with_transport(
+        transport,
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoincore_rpc::RpcApi;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn test_rpc_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Bind failed");
+        let url = format!("http://{}", listener.local_addr().expect("No address"));
+
+        let server = thread::spawn(move || {
+            // answers the first call, and never the second
+            let (mut stream, _) = listener.accept().expect("Accept failed");
+            // the headers and the JSON body may come in separate reads
+            let mut request = String::new();
+            let mut buf = [0; 4096];
+            while !request.ends_with('}') {
+                let read = stream.read(&mut buf).expect("Read failed");
+                if read == 0 {
+                    break;
+                }
+                request.push_str(&String::from_utf8_lossy(&buf[..read]));
+            }
+            let body = r#"{"result":800000,"error":null,"id":1}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).expect("Write failed");
+
+            let (hung, _) = listener.accept().expect("Accept failed");
+            thread::sleep(Duration::from_secs(2));
+            drop(hung);
+            request
+        });
+
+        let timeouts = RpcTimeouts {
+            connect: Duration::from_secs(1),
+            read: Duration::from_millis(200),
+        };
+        let auth = Auth::UserPass("user".into(), "pass".into());
+        let client = rpc_client(&url, auth, timeouts).expect("Client failed");
+        assert_eq!(client.get_block_count().expect("Call failed"), 800_000);
+
+        let start = Instant::now();
+        assert!(client.get_block_count().is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let request = server.join().expect("Server failed");
+        assert!(request.contains("Authorization: Basic dXNlcjpwYXNz"));
+        assert!(request.contains(r#""method":"getblockcount""#));
+    }
+
+    #[test]
+    fn test_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Bind failed");
+        let url = format!("http://{}", listener.local_addr().expect("No address"));
+
+        let server = thread::spawn(move || {
+            let answers = [
+                ("503 Service Unavailable", "Work queue depth exceeded"),
+                ("200 OK", r#"{"result":800000,"error":null,"id":1}"#),
+                (
+                    "500 Internal Server Error",
+                    r#"{"result":null,"error":{"code":-8,"message":"Bad height"},"id":2}"#,
+                ),
+            ];
+            for (status, body) in answers.iter() {
+                let (mut stream, _) = listener.accept().expect("Accept failed");
+                let mut request = String::new();
+                let mut buf = [0; 4096];
+                while !request.ends_with('}') {
+                    let read = stream.read(&mut buf).expect("Read failed");
+                    if read == 0 {
+                        break;
+                    }
+                    request.push_str(&String::from_utf8_lossy(&buf[..read]));
+                }
+                let response = format!(
+                    "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).expect("Write failed");
+            }
+        });
+
+        let client =
+            node_rpc_client(7, &url, Auth::None, RpcTimeouts::default()).expect("Client failed");
+        assert_eq!(client.get_block_count().expect("Call failed"), 800_000);
+        assert_eq!(
+            take_error_counts(7),
+            RpcErrorCounts {
+                retries: 1,
+                failures: 0
+            }
+        );
+
+        // an RPC error is the node's answer, and isn't tried again
+        assert!(client.get_block_hash(900_000).is_err());
+        assert_eq!(take_error_counts(7), RpcErrorCounts::default());
+        server.join().expect("Server failed");
+
+        // nothing listens on the port any more
+        assert!(client.get_block_count().is_err());
+        assert_eq!(
+            take_error_counts(7),
+            RpcErrorCounts {
+                retries: 2,
+                failures: 1
+            }
+        );
+
+        // a transaction may have reached the node before the call failed, it's sent once
+        assert!(client.send_raw_transaction("00").is_err());
+        assert_eq!(
+            take_error_counts(7),
+            RpcErrorCounts {
+                retries: 0,
+                failures: 1
+            }
+        );
+
+        assert!(is_read("getblockcount"));
+        assert!(!is_read("getblockfrompeer"));
+        assert!(!is_read("sendrawtransaction"));
+
+        for attempt in 0..10 {
+            let delay = backoff(attempt);
+            assert!(delay >= RETRY_BASE_DELAY / 2);
+            assert!(delay <= RETRY_MAX_DELAY);
+        }
+    }
+}
@@ -1,5 +1,38 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Int8,
+        name -> Varchar,
+        key_hash -> Varchar,
+        role -> Varchar,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    block_assists (block_hash, node_id) {
+        block_hash -> Varchar,
+        node_id -> Int8,
+        source_node -> Nullable<Int8>,
+        attempts -> Int4,
+        accepted -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    block_payouts (block_hash) {
+        block_hash -> Varchar,
+        address -> Varchar,
+        amount -> Int8,
+        tagged -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     block_templates (parent_block_hash, node_id) {
         parent_block_hash -> Varchar,
@@ -12,6 +45,16 @@ diesel::table! {
         n_transactions -> Int4,
         tx_ids -> Bytea,
         lowest_fee_rate -> Int4,
+        rules -> Array<Text>,
+        tx_fee_rates -> Array<Int4>,
+    }
+}
+
+diesel::table! {
+    block_transactions (block_hash, position) {
+        block_hash -> Varchar,
+        txid -> Varchar,
+        position -> Int4,
     }
 }
 
@@ -33,6 +76,44 @@ diesel::table! {
         lowest_template_fee_rate -> Nullable<Numeric>,
         total_fee -> Nullable<Numeric>,
         coinbase_message -> Nullable<Bytea>,
+        body_source -> Nullable<Varchar>,
+        body_source_node -> Nullable<Int8>,
+        template_match_node -> Nullable<Int8>,
+        template_similarity -> Nullable<Float8>,
+        template_matched_at -> Nullable<Timestamptz>,
+        first_seen_peer -> Nullable<Varchar>,
+        first_seen_peer_at -> Nullable<Timestamptz>,
+        template_latency -> Nullable<Float8>,
+        stale_template -> Nullable<Bool>,
+    }
+}
+
+diesel::table! {
+    bootstrap_progress (id) {
+        id -> Int8,
+        depth -> Int8,
+        tip_hash -> Varchar,
+        tip_height -> Int8,
+        next_hash -> Nullable<Varchar>,
+        next_height -> Int8,
+        started_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    censorship_candidates (txid, pool_name) {
+        txid -> Varchar,
+        pool_name -> Varchar,
+        fee_rate -> Int4,
+        omissions -> Int8,
+        first_height -> Int8,
+        last_height -> Int8,
+        last_block -> Varchar,
+        first_omitted_at -> Timestamptz,
+        last_omitted_at -> Timestamptz,
+        alerted_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -44,6 +125,18 @@ diesel::table! {
         block -> Varchar,
         height -> Int8,
         parent_chaintip -> Nullable<Int8>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    conflict_addresses (candidate_height, kind, address) {
+        candidate_height -> Int8,
+        kind -> Varchar,
+        address -> Varchar,
+        amount -> Float8,
+        txs -> Int4,
     }
 }
 
@@ -54,6 +147,24 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    expected_payouts (pool_name, address) {
+        pool_name -> Varchar,
+        address -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    external_observations (id) {
+        id -> Int8,
+        source -> Varchar,
+        block_hash -> Varchar,
+        height -> Int8,
+        observed_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     fee_rates (parent_block_hash, node_id, fee_rate) {
         parent_block_hash -> Varchar,
@@ -63,6 +174,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    imported_stale_blocks (hash) {
+        hash -> Varchar,
+        height -> Int8,
+        parent_hash -> Nullable<Varchar>,
+        source -> Varchar,
+        imported_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     inflated_blocks (block_hash) {
         block_hash -> Varchar,
@@ -80,7 +201,8 @@ diesel::table! {
     invalid_blocks (hash, node) {
         hash -> Varchar,
         node -> Int8,
-        created_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -90,6 +212,37 @@ diesel::table! {
         created_at -> Timestamptz,
         deleted_at -> Nullable<Timestamptz>,
         updated_at -> Timestamptz,
+        height_deficit -> Int8,
+        work_deficit_blocks -> Nullable<Float8>,
+    }
+}
+
+diesel::table! {
+    maintenance_windows (id) {
+        id -> Int8,
+        node_id -> Int8,
+        starts_at -> Timestamptz,
+        ends_at -> Timestamptz,
+        reason -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    node_status (node_id) {
+        node_id -> Int8,
+        version -> Nullable<Int8>,
+        subversion -> Nullable<Varchar>,
+        last_error -> Nullable<Varchar>,
+        last_error_at -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+        last_success_at -> Nullable<Timestamptz>,
+        best_block_hash -> Nullable<Varchar>,
+        best_height -> Nullable<Int8>,
+        header_height -> Nullable<Int8>,
+        initial_block_download -> Nullable<Bool>,
+        rpc_retries -> Int8,
+        rpc_failures -> Int8,
     }
 }
 
@@ -109,6 +262,35 @@ diesel::table! {
         mirror_last_polled -> Nullable<Timestamptz>,
         mirror_unreachable_since -> Nullable<Int8>,
         archive -> Bool,
+        zmq_endpoint -> Nullable<Varchar>,
+        fetch_templates -> Bool,
+        fetch_peers -> Bool,
+        participate_in_consensus -> Bool,
+        allow_mirror_ops -> Bool,
+        node_group -> Nullable<Varchar>,
+        rpc_connect_timeout_secs -> Nullable<Int4>,
+        rpc_read_timeout_secs -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    pending_events (id) {
+        id -> Int8,
+        client_id -> Varchar,
+        channel -> Varchar,
+        payload -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    peer_reputation (address) {
+        address -> Varchar,
+        failed_requests -> Int4,
+        ban_score -> Int4,
+        last_node_id -> Nullable<Int8>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -119,6 +301,20 @@ diesel::table! {
         peer_id -> Int8,
         address -> Varchar,
         version -> Int8,
+        last_block -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    persistent_forks (node_id, fork_point) {
+        node_id -> Int8,
+        fork_point -> Varchar,
+        tip -> Varchar,
+        branch_len -> Int8,
+        alerted_len -> Nullable<Int8>,
+        first_seen_at -> Timestamptz,
+        alerted_at -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -132,6 +328,35 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    pool_addresses (address) {
+        address -> Varchar,
+        name -> Varchar,
+        url -> Varchar,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    raw_block_attempts (block_hash) {
+        block_hash -> Varchar,
+        attempts -> Int4,
+        last_attempt_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    raw_blocks (block_hash) {
+        block_hash -> Varchar,
+        height -> Int8,
+        node_id -> Int8,
+        size -> Int4,
+        compressed -> Bytea,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     rbf_by (candidate_height, txid) {
         candidate_height -> Int8,
@@ -139,6 +364,46 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    reorgs (id) {
+        id -> Int8,
+        node_id -> Int8,
+        old_tip -> Varchar,
+        old_height -> Int8,
+        new_tip -> Varchar,
+        new_height -> Int8,
+        common_ancestor -> Varchar,
+        ancestor_height -> Int8,
+        depth -> Int8,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    rollback_checks (id) {
+        id -> Int8,
+        block_hash -> Varchar,
+        node_id -> Int8,
+        verdict -> Varchar,
+        detail -> Nullable<Varchar>,
+        started_at -> Timestamptz,
+        finished_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    rollback_schedule (block_hash, node_id) {
+        block_hash -> Varchar,
+        node_id -> Int8,
+        height -> Int8,
+        branch_len -> Int8,
+        priority -> Int4,
+        status -> Varchar,
+        last_checked_at -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     softforks (node_id, fork_type, name) {
         node_id -> Int8,
@@ -162,6 +427,7 @@ diesel::table! {
         rbf_total -> Float8,
         height_processed -> Nullable<Int8>,
         created_at -> Timestamptz,
+        source -> Varchar,
     }
 }
 
@@ -174,6 +440,65 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    subscriber_acks (client_id, channel) {
+        client_id -> Varchar,
+        channel -> Varchar,
+        acked_id -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    subscriber_secrets (client_id) {
+        client_id -> Varchar,
+        secret -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    template_snapshots (parent_block_hash, node_id) {
+        parent_block_hash -> Varchar,
+        node_id -> Int8,
+        height -> Int8,
+        tx_ids -> Bytea,
+        created_at -> Timestamptz,
+        rules -> Array<Text>,
+    }
+}
+
+diesel::table! {
+    template_txs (txid, parent_block_hash, node_id) {
+        txid -> Varchar,
+        parent_block_hash -> Varchar,
+        node_id -> Int8,
+        height -> Int8,
+        first_seen_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    tip_history (id) {
+        id -> Int8,
+        node_id -> Int8,
+        block_hash -> Varchar,
+        height -> Int8,
+        status -> Varchar,
+        observed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    tip_statuses (node_id, block_hash) {
+        node_id -> Int8,
+        block_hash -> Varchar,
+        height -> Int8,
+        status -> Varchar,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     transaction (block_id, txid) {
         block_id -> Varchar,
@@ -193,6 +518,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    transaction_inputs (block_id, txid, vin) {
+        block_id -> Varchar,
+        txid -> Varchar,
+        vin -> Int4,
+        prev_txid -> Varchar,
+        prev_vout -> Int8,
+    }
+}
+
 diesel::table! {
     tx_outsets (block_hash, node_id) {
         block_hash -> Varchar,
@@ -205,6 +540,30 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    unconfirmed_spends (node_id, txid, vin) {
+        node_id -> Int8,
+        txid -> Varchar,
+        vin -> Int4,
+        prev_txid -> Varchar,
+        prev_vout -> Int8,
+        seen_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    unknown_coinbase_tags (block_hash) {
+        block_hash -> Varchar,
+        height -> Int8,
+        coinbase_message -> Bytea,
+        tag -> Varchar,
+        payout_address -> Nullable<Varchar>,
+        pool_name -> Nullable<Varchar>,
+        labeled_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     valid_blocks (hash, node) {
         hash -> Varchar,
@@ -213,41 +572,144 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    wallet_conflicts (prev_txid, prev_vout) {
+        prev_txid -> Varchar,
+        prev_vout -> Int8,
+        address -> Varchar,
+        spending_txids -> Array<Text>,
+        first_seen_at -> Timestamptz,
+        alerted_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     watched (address) {
         address -> Varchar,
         created_at -> Timestamptz,
         watch_until -> Timestamptz,
+        descriptor -> Nullable<Varchar>,
+        derivation_index -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    watched_outpoints (txid, vout) {
+        txid -> Varchar,
+        vout -> Int8,
+        created_at -> Timestamptz,
+        watch_until -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    watched_descriptors (descriptor) {
+        descriptor -> Varchar,
+        gap_limit -> Int4,
+        derived -> Int4,
+        created_at -> Timestamptz,
+        watch_until -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    watched_relays (id) {
+        id -> Int8,
+        txid -> Varchar,
+        node_id -> Int8,
+        accepted -> Bool,
+        error -> Nullable<Varchar>,
+        relayed_at -> Timestamptz,
     }
 }
 
+diesel::joinable!(block_assists -> blocks (block_hash));
+diesel::joinable!(block_assists -> nodes (node_id));
+diesel::joinable!(block_payouts -> blocks (block_hash));
+diesel::joinable!(block_transactions -> blocks (block_hash));
+diesel::joinable!(conflict_addresses -> stale_candidate (candidate_height));
+diesel::joinable!(imported_stale_blocks -> stale_candidate (height));
 diesel::joinable!(inflated_blocks -> blocks (block_hash));
 diesel::joinable!(lags -> nodes (node_id));
+diesel::joinable!(maintenance_windows -> nodes (node_id));
+diesel::joinable!(node_status -> nodes (node_id));
+diesel::joinable!(peer_reputation -> nodes (last_node_id));
 diesel::joinable!(peers -> nodes (node_id));
+diesel::joinable!(persistent_forks -> nodes (node_id));
+diesel::joinable!(raw_block_attempts -> blocks (block_hash));
+diesel::joinable!(raw_blocks -> blocks (block_hash));
+diesel::joinable!(reorgs -> nodes (node_id));
+diesel::joinable!(rollback_checks -> blocks (block_hash));
+diesel::joinable!(rollback_checks -> nodes (node_id));
+diesel::joinable!(rollback_schedule -> blocks (block_hash));
+diesel::joinable!(rollback_schedule -> nodes (node_id));
 diesel::joinable!(softforks -> nodes (node_id));
 diesel::joinable!(stale_candidate_children -> stale_candidate (candidate_height));
+diesel::joinable!(template_snapshots -> nodes (node_id));
+diesel::joinable!(tip_history -> nodes (node_id));
+diesel::joinable!(tip_statuses -> nodes (node_id));
 diesel::joinable!(transaction -> blocks (block_id));
+diesel::joinable!(transaction_addresses -> blocks (hash));
+diesel::joinable!(transaction_inputs -> blocks (block_id));
 diesel::joinable!(tx_outsets -> blocks (block_hash));
+diesel::joinable!(unconfirmed_spends -> nodes (node_id));
+diesel::joinable!(unknown_coinbase_tags -> blocks (block_hash));
+diesel::joinable!(watched -> watched_descriptors (descriptor));
+diesel::joinable!(watched_relays -> nodes (node_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    block_assists,
+    block_payouts,
     block_templates,
+    block_transactions,
     blocks,
+    bootstrap_progress,
+    censorship_candidates,
     chaintips,
+    conflict_addresses,
     double_spent_by,
+    expected_payouts,
+    external_observations,
     fee_rates,
+    imported_stale_blocks,
     inflated_blocks,
     invalid_blocks,
     lags,
+    maintenance_windows,
+    node_status,
     nodes,
+    pending_events,
+    peer_reputation,
     peers,
+    persistent_forks,
     pool,
+    pool_addresses,
+    raw_block_attempts,
+    raw_blocks,
     rbf_by,
+    reorgs,
+    rollback_checks,
+    rollback_schedule,
     softforks,
     stale_candidate,
     stale_candidate_children,
+    subscriber_acks,
+    subscriber_secrets,
+    template_snapshots,
+    template_txs,
+    tip_history,
+    tip_statuses,
     transaction,
     transaction_addresses,
+    transaction_inputs,
     tx_outsets,
+    unconfirmed_spends,
+    unknown_coinbase_tags,
     valid_blocks,
+    wallet_conflicts,
     watched,
+    watched_descriptors,
+    watched_outpoints,
+    watched_relays,
 );
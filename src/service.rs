@@ -1,28 +1,63 @@
+#[cfg(feature = "graphql")]
+use crate::graphql;
 use crate::{
-    scanner::BtcClient, serde_bigdecimal, Block, Chaintip, ConflictingBlock, Lags, Node, Peer,
-    ScannerCommand, ScannerMessage, StaleCandidate, Transaction, Watched,
+    acks::{self, AckedSink},
+    alerts::Alerter,
+    attestation::{AttestationError, SupplyAttestation, SupplyStatement},
+    auth::{self, Caller, Role},
+    credentials::{is_reference, node_auth, seal_password, CredentialError},
+    describe::{array, object, one_of, ApiDoc},
+    descriptors::{self, Descriptor},
+    event_bus::EventBus,
+    feed,
+    json_numbers::{bigdecimal_schema, btc_json, decimal_json, serde_bigdecimal},
+    limits::{RateLimiter, RequestLimits},
+    network::{check_node, network, NetworkParams},
+    parse_stale_blocks, rest,
+    rpc_client::{node_rpc_client, RpcTimeouts},
+    scanner::BtcClient,
+    tls::{serve_tls, TlsConfig},
+    Anomaly, ApiKey, AssistStats, Block, BlockAssist, BlockFilter, BlockTemplate, BlockTransaction,
+    BootstrapProgress, CensorshipCandidate, Chaintip, ConflictAddress, ConflictingBlock,
+    CycleSummary, DeepReorg, DoubleSpends, ErrorCategory, ErrorEvent, ExpectedPayout,
+    ExternalDisagreement, ExternalObservation, FeeRate, ForkAnalysis, GroupConsensus, GroupSplit,
+    ImportedStaleBlock, InflatedBlock, IntegrityCheck, InvalidBlock, Lags, MaintenanceWindow,
+    MempoolDivergence, MempoolPayment, NewHeader, NewNode, Node, NodeFlags, NodeStatus,
+    NodeTimeouts, OutpointSpend, PayoutAnomaly, PayoutCluster, Peer, PeerChanges, PeerOverlap,
+    PeerReputation, RawBlock, Reorg, RollbackCheck, RollbackSchedule, ScannerCommand,
+    ScannerMessage, SharedPeer, StaleCandidate, StuckChain, SubscriberLag, SubscriberSecret,
+    TemplateLatency, TemplateMatchRate, TemplateMembership, TipBranch, TipHistory, TipTransition,
+    Transaction, TransactionAddress, TransactionInput, TxOutset, UnknownCoinbaseTag,
+    WalletConflict, WalletConflictAlert, Watched, WatchedDescriptor, WatchedOutpoint, WatchedRelay,
 };
+#[cfg(feature = "grpc")]
+use crate::{grpc::GrpcServer, tls::serve_tls_alpn};
 use bigdecimal::BigDecimal;
-use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::consensus::encode::{deserialize, serialize_hex};
+use bitcoin::Transaction as BitcoinTransaction;
 use bitcoincore_rpc::bitcoin::Block as BitcoinBlock;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::RpcApi;
 use chrono::prelude::*;
 use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
-use diesel::prelude::PgConnection;
+use diesel::prelude::{Connection, OptionalExtension, PgConnection, QueryResult};
+use diesel::result::DatabaseErrorKind;
 use hex::ToHex;
 use jsonrpc_core::types::error::Error as JsonRpcError;
 use jsonrpc_core::*;
 use jsonrpc_http_server as hts;
-use jsonrpc_pubsub::{PubSubHandler, Session, Sink, Subscriber, SubscriptionId};
+use jsonrpc_pubsub::{PubSubHandler, PubSubMetadata, Session, Sink, Subscriber, SubscriptionId};
 use jsonrpc_ws_server as wss;
 use log::{debug, error, info};
 use r2d2::PooledConnection;
 use r2d2_diesel::ConnectionManager;
 use rand::Rng;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{
-    collections::{HashMap, HashSet},
-    net::{IpAddr, SocketAddr},
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::atomic::{AtomicBool, Ordering},
     sync::{Arc, Mutex, RwLock},
     thread, time,
@@ -30,52 +65,288 @@ use std::{
 use thiserror::Error;
 
 const BLOCK_WINDOW: i64 = 10;
-
-type Conn = PooledConnection<ConnectionManager<diesel::PgConnection>>;
+const FLUSH_TIMEOUT_MS: u64 = 5000;
+// How often subscription threads check whether they were unsubscribed.
+const SUBSCRIPTION_POLL: time::Duration = time::Duration::from_millis(5000);
+const TEMPLATE_MATCH_DAYS: i64 = 30;
+const TEMPLATE_MATCH_THRESHOLD: f64 = 0.9;
+const STALE_TEMPLATE_MIN_BLOCKS: i64 = 3;
+const STALE_TEMPLATE_RATIO: f64 = 0.5;
+const STALE_CANDIDATE_WINDOW: i64 = 10;
+const LIST_BLOCKS_LIMIT: i64 = 100;
+const LIST_BLOCKS_MAX_LIMIT: i64 = 1000;
+const ADDRESS_TXS_LIMIT: i64 = 100;
+const ADDRESS_TXS_MAX_LIMIT: i64 = 1000;
+const EXTERNAL_OBSERVATIONS_LIMIT: i64 = 100;
+const EXTERNAL_OBSERVATIONS_MAX_LIMIT: i64 = 1000;
+const WATCHED_RELAYS_LIMIT: i64 = 100;
+const WATCHED_RELAYS_MAX_LIMIT: i64 = 1000;
+const NODE_STATS_BLOCKS: i64 = 10;
+const RECENT_ACTIVITY_LIMIT: i64 = 10;
+const RECENT_ACTIVITY_MAX_LIMIT: i64 = 100;
+const DESCRIPTOR_GAP_LIMIT: u32 = 20;
+const DESCRIPTOR_MAX_GAP_LIMIT: u32 = 1000;
+const BULK_MAX_ITEMS: usize = 1000;
+// subscribe method, notification and unsubscribe method
+const ACKED_SUBSCRIPTIONS: [(&str, &str, &str); 17] = [
+    (
+        "invalid_block_checks",
+        "invalid_block_checks",
+        "unsubscribe_invalid_block_checks",
+    ),
+    (
+        "subscribe_persistent_forks",
+        "persistent_forks",
+        "unsubscribe_persistent_forks",
+    ),
+    (
+        "subscribe_wallet_conflicts",
+        "wallet_conflicts",
+        "unsubscribe_wallet_conflicts",
+    ),
+    (
+        "subscribe_stuck_chain",
+        "stuck_chain",
+        "unsubscribe_stuck_chain",
+    ),
+    (
+        "subscribe_peer_partitions",
+        "peer_partitions",
+        "unsubscribe_peer_partitions",
+    ),
+    (
+        "subscribe_mempool_divergence",
+        "mempool_divergence",
+        "unsubscribe_mempool_divergence",
+    ),
+    (
+        "subscribe_payout_anomalies",
+        "payout_anomalies",
+        "unsubscribe_payout_anomalies",
+    ),
+    ("subscribe_anomalies", "anomalies", "unsubscribe_anomalies"),
+    (
+        "subscribe_censorship",
+        "censorship",
+        "unsubscribe_censorship",
+    ),
+    ("subscribe_reorgs", "reorgs", "unsubscribe_reorgs"),
+    (
+        "subscribe_deep_reorgs",
+        "deep_reorgs",
+        "unsubscribe_deep_reorgs",
+    ),
+    (
+        "subscribe_group_splits",
+        "group_splits",
+        "unsubscribe_group_splits",
+    ),
+    (
+        "subscribe_external_disagreements",
+        "external_disagreements",
+        "unsubscribe_external_disagreements",
+    ),
+    (
+        "subscribe_watched_address_mempool",
+        "watched_address_mempool",
+        "unsubscribe_watched_address_mempool",
+    ),
+    (
+        "subscribe_inflation",
+        "inflation_checks",
+        "unsubscribe_inflation",
+    ),
+    (
+        "subscribe_double_spends",
+        "double_spends",
+        "unsubscribe_double_spends",
+    ),
+    (
+        "subscribe_peer_changes",
+        "peer_changes",
+        "unsubscribe_peer_changes",
+    ),
+];
+
+pub(crate) type Conn = PooledConnection<ConnectionManager<diesel::PgConnection>>;
 type ManagedPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+type Subscriptions = Arc<Mutex<HashMap<&'static str, Vec<Sender<ScannerMessage>>>>>;
+
+// Error events the scanner sent since the server started, by category.
+#[derive(Debug, Serialize, JsonSchema)]
+struct ErrorCounts {
+    since: DateTime<Utc>,
+    counts: BTreeMap<ErrorCategory, u64>,
+}
+
+impl ErrorCounts {
+    fn new() -> ErrorCounts {
+        ErrorCounts {
+            since: Utc::now(),
+            counts: ErrorCategory::ALL.iter().map(|c| (*c, 0)).collect(),
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum WsError {
     #[error("Diesel query error {0:?}")]
-    DieselError(#[from] diesel::result::Error),
+    Diesel(#[from] diesel::result::Error),
     #[error("Connection pool error {0:?}")]
-    R2D2Error(#[from] r2d2::Error),
+    R2D2(#[from] r2d2::Error),
     #[error("Serde error {0:?}")]
-    SerdeError(#[from] serde_json::Error),
+    Serde(#[from] serde_json::Error),
     #[error("Sink error {0:?}")]
-    SinkError(#[from] futures::channel::mpsc::TrySendError<std::string::String>),
+    Sink(#[from] futures::channel::mpsc::TrySendError<std::string::String>),
+    #[error("Subscriber secret error {0:?}")]
+    Secret(#[from] CredentialError),
 }
 
 // https://docs.rs/bitcoin/0.27.1/bitcoin/blockdata/block/struct.Block.html
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct BlockUpload {
     node_id: i64,
+    #[schemars(with = "serde_json::Map<String, Value>")]
     block: BitcoinBlock,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct NodeId {
     id: i64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct PeerQuery {
+    id: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct CensorshipQuery {
+    pool_name: Option<String>,
+    min_omissions: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct UnknownCoinbaseQuery {
+    labeled: Option<bool>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct LabelCoinbaseTag {
+    tag: String,
+    name: String,
+    url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct PayoutClusterQuery {
+    pool_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ExpectedPayoutUpdate {
+    pool_name: String,
+    #[serde(default)]
+    add: Vec<String>,
+    #[serde(default)]
+    remove: Vec<String>,
+    #[serde(default)]
+    from_history: bool,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct PeerReputationQuery {
+    address: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct GetBlockFromPeer {
     node_id: i64,
     hash: String,
     peer_id: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct TipArgs {
     active_only: bool,
+    #[serde(default)]
+    with_branch: bool,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct NodeStatusQuery {
+    node_id: Option<i64>,
+}
+
+// How a node's mirror is doing, see `inflation_checks`.
+#[derive(Debug, Serialize, JsonSchema)]
+struct MirrorStatus {
+    host: String,
+    port: i32,
+    reachable: bool,
+    last_polled: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct NodeLag {
+    height_deficit: i64,
+    work_deficit_blocks: Option<f64>,
+    since: DateTime<Utc>,
+}
+
+// Whether the scanner is still getting through to a node, from the last scans.
+#[derive(Debug, Serialize, JsonSchema)]
+struct NodeConnectivity {
+    node_id: i64,
+    name: String,
+    last_success_at: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+    // the last scan failed, or none succeeded yet
+    failing: bool,
+    best_block_hash: Option<String>,
+    height: Option<i64>,
+    header_height: Option<i64>,
+    initial_block_download: Option<bool>,
+    // RPC calls that were retried, and that failed after their retries
+    rpc_retries: i64,
+    rpc_failures: i64,
+    mirror: Option<MirrorStatus>,
+    lag: Option<NodeLag>,
+}
+
+// The network parameters, with the next halving after the highest block.
+#[derive(Debug, Serialize, JsonSchema)]
+struct NetworkStatus {
+    #[serde(flatten)]
+    params: NetworkParams,
+    height: Option<i64>,
+    next_halving: Option<i64>,
+    blocks_to_halving: Option<i64>,
+}
+
+// A chaintip with where it forks off the consensus tip's chain.
+#[derive(Debug, Serialize, JsonSchema)]
+struct BranchTip {
+    #[serde(flatten)]
+    tip: Chaintip,
+    branch: Option<TipBranch>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 struct TxId {
     id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct TxIds {
+    ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct NodeArgs {
     name: String,
     rpc_host: String,
@@ -85,36 +356,424 @@ struct NodeArgs {
     pass: String,
     mirror_host: Option<String>,
     archive: bool,
+    zmq_endpoint: Option<String>,
+    group: Option<String>,
+    #[serde(flatten)]
+    flags: NodeFlags,
+    #[serde(flatten)]
+    timeouts: NodeTimeouts,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct NodeGroupArgs {
+    id: i64,
+    group: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct MaintenanceArgs {
+    node_id: i64,
+    starts_at: Option<DateTime<Utc>>,
+    ends_at: DateTime<Utc>,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct MaintenanceRemoveArgs {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ExternalTipArgs {
+    source: String,
+    hash: String,
+    height: i64,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ExternalObservationsQuery {
+    source: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct RecentActivityQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct TipsAsOfQuery {
+    timestamp: DateTime<Utc>,
+    node_id: Option<i64>,
+    #[serde(default)]
+    active_only: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct BlockStatusAsOfQuery {
+    hash: String,
+    timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct SubscriberSecretArgs {
+    client_id: String,
+    secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct SubscriberSecretRemoveArgs {
+    client_id: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ApiKeyArgs {
+    name: String,
+    role: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ApiKeyRevokeArgs {
+    name: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct PruneArgs {
+    days: Option<i64>,
+    blocks: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct NodeFlagArgs {
+    id: i64,
+    #[serde(flatten)]
+    flags: NodeFlags,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RollbackCheckQuery {
+    node_id: Option<i64>,
+    hash: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ReorgQuery {
+    node_id: Option<i64>,
+    min_depth: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct StaleCandidateQuery {
+    source: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ConflictAddressQuery {
+    address: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ListBlocksQuery {
+    min_height: Option<i64>,
+    max_height: Option<i64>,
+    pool_name: Option<String>,
+    headers_only: Option<bool>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct BlockTemplateQuery {
+    node_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct FeeRateQuery {
+    parent_block_hash: String,
+    node_id: i64,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct RawBlockQuery {
+    hash: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct AttestationQuery {
+    hash: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct TxOutsetQuery {
+    hash: String,
+    node_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct TemplateMatchQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    interval: Option<String>,
+    threshold: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct TemplateLatencyQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    min_blocks: Option<i64>,
+    stale_ratio: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct TemplateTxQuery {
+    txid: String,
+    node_id: Option<i64>,
+    height: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct WalletConflictQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct WatchedRelayQuery {
+    txid: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct ImportStaleBlocksArgs {
+    source: String,
+    csv: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+struct TipTransitionFilter {
+    node_id: Option<i64>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl TipTransitionFilter {
+    fn matches(&self, transition: &TipTransition) -> bool {
+        self.node_id
+            .map(|id| id == transition.node_id)
+            .unwrap_or(true)
+            && self
+                .from
+                .as_ref()
+                .map(|s| s == &transition.from)
+                .unwrap_or(true)
+            && self
+                .to
+                .as_ref()
+                .map(|s| s == &transition.to)
+                .unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct WatchedActivityQuery {
+    address: String,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct AddressTransactionsQuery {
+    address: String,
+    from_height: Option<i64>,
+    to_height: Option<i64>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct WatchlistEntry {
+    address: String,
+    watch_until: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct WatchlistImport {
+    addresses: Vec<WatchlistEntry>,
+    #[serde(default)]
+    descriptors: Vec<DescriptorWatch>,
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 struct WatchedAddressUpdate {
+    #[serde(default)]
     remove: Vec<String>,
-	add: Vec<(String, DateTime<Utc>)>,
+    #[serde(default)]
+    add: Vec<(String, DateTime<Utc>)>,
+    #[serde(default)]
+    remove_descriptors: Vec<String>,
+    #[serde(default)]
+    add_descriptors: Vec<DescriptorWatch>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct DescriptorWatch {
+    descriptor: String,
+    gap_limit: Option<u32>,
+    watch_until: DateTime<Utc>,
+}
+
+impl DescriptorWatch {
+    // the descriptor and the addresses of its first gap_limit indexes
+    fn derive(self) -> Result<(WatchedDescriptor, Vec<(u32, String)>)> {
+        let gap_limit = self.gap_limit.unwrap_or(DESCRIPTOR_GAP_LIMIT);
+        if gap_limit == 0 || gap_limit > DESCRIPTOR_MAX_GAP_LIMIT {
+            return Err(JsonRpcError::invalid_params(format!(
+                "Invalid parameters, gap_limit must be between 1 and {}",
+                DESCRIPTOR_MAX_GAP_LIMIT
+            )));
+        }
+
+        let addresses = self
+            .descriptor
+            .parse::<Descriptor>()
+            .and_then(|desc| desc.addresses(0..gap_limit))
+            .map_err(|e| JsonRpcError::invalid_params(format!("Invalid parameters, {}", e)))?;
+
+        let watched = WatchedDescriptor {
+            descriptor: descriptors::without_checksum(&self.descriptor).into(),
+            gap_limit: gap_limit as i32,
+            derived: gap_limit as i32,
+            created_at: Utc::now(),
+            watch_until: self.watch_until,
+        };
+        Ok((watched, addresses))
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 struct WatchAddress {
     watch: Vec<String>,
     watch_until: DateTime<Utc>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Outpoint {
+    txid: String,
+    vout: i64,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct OutpointWatch {
+    txid: String,
+    vout: i64,
+    watch_until: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct OutpointWatchUpdate {
+    #[serde(default)]
+    remove: Vec<Outpoint>,
+    #[serde(default)]
+    add: Vec<OutpointWatch>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct WatchOutpoints {
+    watch: Vec<Outpoint>,
+    watch_until: DateTime<Utc>,
+    // documented here, read and checked by `acks::client_id`
+    #[allow(dead_code)]
+    client_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct WatchTransaction {
+    txid: String,
+    #[serde(default = "default_target_confirmations")]
+    confirmations: i64,
+}
+
+fn default_target_confirmations() -> i64 {
+    6
+}
+
+// Where a watched transaction stands against the chain of the consensus tip.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum TxState {
+    Unconfirmed,
+    Confirmed,
+    Reorged,
+    DoubleSpent,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct TxConfirmation {
+    txid: String,
+    status: TxState,
+    confirmations: i64,
+    target: i64,
+    block_hash: Option<String>,
+    height: Option<i64>,
+    double_spent_by: Option<String>,
+}
+
+// A block with a transaction or a conflicting spend of it, and whether it is on the
+// consensus tip's chain.
+#[derive(Debug, Serialize, JsonSchema)]
+struct TxStatusBlock {
+    hash: String,
+    height: i64,
+    in_consensus_chain: bool,
+    invalid_on: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct TxConflict {
+    txid: String,
+    block: TxStatusBlock,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct TxStatus {
+    txid: String,
+    status: TxState,
+    confirmations: i64,
+    block_hash: Option<String>,
+    height: Option<i64>,
+    double_spent_by: Option<String>,
+    blocks: Vec<TxStatusBlock>,
+    conflicts: Vec<TxConflict>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 enum BlockQuery {
     Height(i64),
     Hash(String),
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+struct BlockHashes {
+    hashes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 struct SetTipQuery {
     node_id: i64,
     hash: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 struct ValidationCheck {
     tip: String,
@@ -143,7 +802,7 @@ impl ValidationCheck {
             block1 = block1.parent(conn).ok()?;
         }
 
-        let (stale_branch_len, stale_branch_root) = if &block1.hash == &candidate_hash {
+        let (stale_branch_len, stale_branch_root) = if block1.hash == candidate_hash {
             (0, candidate_hash.clone())
         } else {
             loop {
@@ -151,9 +810,9 @@ impl ValidationCheck {
 
                 let desc = block1.descendants(conn, None).ok()?;
 
-                let fork = desc.into_iter().find(|b| &b.hash == &candidate_hash);
+                let fork = desc.into_iter().find(|b| b.hash == candidate_hash);
 
-                if let Some(_) = fork {
+                if fork.is_some() {
                     break (candidate.height - block1.height, block1.hash.clone());
                 }
             }
@@ -171,12 +830,12 @@ impl ValidationCheck {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 struct BlockArg {
     max_height: i64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 struct BlockResult {
     pub hash: String,
     pub height: i64,
@@ -190,14 +849,26 @@ struct BlockResult {
     pub txids_omitted: Option<Vec<String>>,
     pub pool_name: Option<String>,
     #[serde(serialize_with = "serde_bigdecimal")]
+    #[schemars(schema_with = "bigdecimal_schema")]
     pub template_txs_fee_diff: Option<BigDecimal>,
     #[serde(serialize_with = "serde_bigdecimal")]
+    #[schemars(schema_with = "bigdecimal_schema")]
     pub tx_omitted_fee_rates: Option<BigDecimal>,
     #[serde(serialize_with = "serde_bigdecimal")]
+    #[schemars(schema_with = "bigdecimal_schema")]
     pub lowest_template_fee_rate: Option<BigDecimal>,
     #[serde(serialize_with = "serde_bigdecimal")]
+    #[schemars(schema_with = "bigdecimal_schema")]
     pub total_fee: Option<BigDecimal>,
     pub coinbase_message: Option<Vec<u8>>,
+    pub body_source: Option<String>,
+    pub body_source_node: Option<i64>,
+    pub template_match_node: Option<i64>,
+    pub template_similarity: Option<f64>,
+    pub first_seen_peer: Option<String>,
+    pub first_seen_peer_at: Option<DateTime<Utc>>,
+    pub template_latency: Option<f64>,
+    pub stale_template: Option<bool>,
 }
 
 fn txid_bytes_to_hex(txids: Option<Vec<u8>>) -> Option<Vec<String>> {
@@ -227,6 +898,14 @@ impl BlockResult {
             lowest_template_fee_rate: block.lowest_template_fee_rate,
             total_fee: block.total_fee,
             coinbase_message: block.coinbase_message,
+            body_source: block.body_source,
+            body_source_node: block.body_source_node,
+            template_match_node: block.template_match_node,
+            template_similarity: block.template_similarity,
+            first_seen_peer: block.first_seen_peer,
+            first_seen_peer_at: block.first_seen_peer_at,
+            template_latency: block.template_latency,
+            stale_template: block.stale_template,
         }
     }
 }
@@ -268,6 +947,154 @@ fn validation_checks(conn: Conn, window: i64) -> Result<Value> {
     }
 }
 
+// branch length, work deficit and miners of every valid-fork chaintip
+fn get_fork_analysis(conn: Conn) -> Result<Value> {
+    match ForkAnalysis::list(&conn) {
+        Ok(forks) => match serde_json::to_value(forks) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(_) => Err(JsonRpcError::internal_error()),
+    }
+}
+
+// per pool and period, how closely blocks matched the nodes' templates
+fn get_template_match_rates(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<TemplateMatchQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let interval = q.interval.unwrap_or_else(|| "day".into());
+    if !["hour", "day", "week"].contains(&interval.as_str()) {
+        let err = JsonRpcError::invalid_params(format!("Invalid interval {}", interval));
+        return Err(err);
+    }
+    let threshold = q.threshold.unwrap_or(TEMPLATE_MATCH_THRESHOLD);
+    let to = q.to.unwrap_or_else(Utc::now);
+    let from = q
+        .from
+        .unwrap_or_else(|| to - chrono::Duration::days(TEMPLATE_MATCH_DAYS));
+
+    match TemplateMatchRate::list(&conn, &interval, threshold, from, to) {
+        Ok(rates) => {
+            let rates: Vec<_> = rates
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "pool": r.pool_name,
+                        "period": r.period,
+                        "blocks": r.blocks,
+                        "matched": r.matched,
+                        "match_rate": r.matched as f64 / r.blocks as f64,
+                        "mean_similarity": r.mean_similarity,
+                    })
+                })
+                .collect();
+            Ok(rates.into())
+        }
+        Err(e) => {
+            error!("Could not fetch template match rates {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// per pool, how long blocks took to arrive after their templates, flagging the pools
+// that keep building on stale ones
+fn get_template_latency(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<TemplateLatencyQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let min_blocks = q.min_blocks.unwrap_or(STALE_TEMPLATE_MIN_BLOCKS);
+    let stale_ratio = q.stale_ratio.unwrap_or(STALE_TEMPLATE_RATIO);
+    let to = q.to.unwrap_or_else(Utc::now);
+    let from = q
+        .from
+        .unwrap_or_else(|| to - chrono::Duration::days(TEMPLATE_MATCH_DAYS));
+
+    match TemplateLatency::list(&conn, from, to) {
+        Ok(pools) => {
+            let pools: Vec<_> = pools
+                .into_iter()
+                .map(|p| {
+                    let ratio = p.stale_blocks as f64 / p.blocks as f64;
+                    json!({
+                        "pool": p.pool_name,
+                        "blocks": p.blocks,
+                        "mean_latency": p.mean_latency,
+                        "max_latency": p.max_latency,
+                        "mean_similarity": p.mean_similarity,
+                        "stale_blocks": p.stale_blocks,
+                        "stale_ratio": ratio,
+                        "flagged": p.blocks >= min_blocks && ratio >= stale_ratio,
+                    })
+                })
+                .collect();
+            Ok(pools.into())
+        }
+        Err(e) => {
+            error!("Could not fetch template latency {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// whether a transaction was in the nodes' templates at a height
+fn tx_in_templates(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<TemplateTxQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    // templates keep txids in internal byte order
+    let mut txid_bytes = match hex::decode(&q.txid) {
+        Ok(bytes) if bytes.len() == 32 => bytes,
+        _ => {
+            let err = JsonRpcError::invalid_params(format!("Invalid txid {}", q.txid));
+            return Err(err);
+        }
+    };
+    txid_bytes.reverse();
+
+    match TemplateMembership::list(&conn, &q.txid, q.node_id, q.height) {
+        Ok(templates) => {
+            let templates: Vec<_> = templates
+                .into_iter()
+                .map(|t| {
+                    let position = t.tx_ids.chunks(32).position(|txid| txid == txid_bytes);
+                    json!({
+                        "node_id": t.node_id,
+                        "height": t.height,
+                        "parent_block_hash": t.parent_block_hash,
+                        "included": t.first_seen_at.is_some(),
+                        "first_seen_at": t.first_seen_at,
+                        "in_latest": position.is_some(),
+                        "position": position,
+                        "template_at": t.template_at,
+                    })
+                })
+                .collect();
+            Ok(templates.into())
+        }
+        Err(e) => {
+            error!("Could not fetch template membership {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
 // check if tx is in active tip
 fn tx_is_active(conn: Conn, params: Params) -> Result<Value> {
     match params.parse::<TxId>() {
@@ -295,203 +1122,279 @@ fn tx_is_active(conn: Conn, params: Params) -> Result<Value> {
     }
 }
 
-// update watched addresses
-fn update_watched_addresses(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<WatchedAddressUpdate>() {
-	    Ok(updates) => {
-		    let WatchedAddressUpdate { remove, add } = updates;
-
-		    if let Err(_) = Watched::remove(&conn, remove) {
-                return Err(JsonRpcError::internal_error());
-			};
+// where a transaction stands against the consensus tip, with every block it and its
+// conflicting spends are in
+fn get_tx_status(conn: Conn, params: Params) -> Result<Value> {
+    let id = match params.parse::<TxId>() {
+        Ok(id) => id.id,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
 
-            if let Err(_) = Watched::insert(&conn, add) {
-                return Err(JsonRpcError::internal_error());
-			}
+    match tx_status(&conn, &id) {
+        Ok(status) => Ok(serde_json::to_value(status).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch transaction status {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
 
-		    Ok("OK".into())
-		}
+// The status of each transaction, in the order of the ids.
+fn get_tx_statuses(conn: Conn, params: Params) -> Result<Value> {
+    let ids = match params.parse::<TxIds>() {
+        Ok(q) if q.ids.len() <= BULK_MAX_ITEMS => q.ids,
+        Ok(q) => {
+            let err = JsonRpcError::invalid_params(format!(
+                "At most {} ids can be fetched at once, got {}",
+                BULK_MAX_ITEMS,
+                q.ids.len()
+            ));
+            return Err(err);
+        }
         Err(args) => {
             let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
-            Err(err)
+            return Err(err);
         }
-	}
+    };
+
+    match ids
+        .iter()
+        .map(|id| tx_status(&conn, id))
+        .collect::<QueryResult<Vec<_>>>()
+    {
+        Ok(statuses) => Ok(serde_json::to_value(statuses).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch transaction statuses {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
 }
 
-fn submit_block(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<BlockUpload>() {
-        Ok(upload) => match Node::get(&conn, upload.node_id) {
-            Ok(node) => {
-                let auth = Auth::UserPass(node.rpc_user.clone(), node.rpc_pass.clone());
+fn tx_status(conn: &PgConnection, txid: &str) -> QueryResult<TxStatus> {
+    let confirmation = tx_confirmation(conn, txid, 0)?;
+    let consensus = consensus_tip(conn)?;
+    let status_block = |block: Block| -> QueryResult<TxStatusBlock> {
+        let in_consensus_chain = match &consensus {
+            Some(tip) => block.is_ancestor_of(conn, tip)?,
+            None => false,
+        };
+        Ok(TxStatusBlock {
+            invalid_on: InvalidBlock::nodes_of(conn, &block.hash)?,
+            hash: block.hash,
+            height: block.height,
+            in_consensus_chain,
+        })
+    };
 
-                if let Ok(client) = Client::new(&node.rpc_host, auth) {
-                    let hash = upload.block.block_hash();
-                    let block_hex = serialize_hex(&upload.block);
+    let blocks = BlockTransaction::blocks_with(conn, txid)?
+        .into_iter()
+        .map(|(_, block)| status_block(block))
+        .collect::<QueryResult<_>>()?;
+    let conflicts = TransactionInput::conflicts_of(conn, txid)?
+        .into_iter()
+        .map(|(input, block)| {
+            Ok(TxConflict {
+                txid: input.txid,
+                block: status_block(block)?,
+            })
+        })
+        .collect::<QueryResult<_>>()?;
+
+    Ok(TxStatus {
+        txid: confirmation.txid,
+        status: confirmation.status,
+        confirmations: confirmation.confirmations,
+        block_hash: confirmation.block_hash,
+        height: confirmation.height,
+        double_spent_by: confirmation.double_spent_by,
+        blocks,
+        conflicts,
+    })
+}
 
-                    match client.submit_block(block_hex, &hash) {
-                        Ok(_) => Ok("OK".into()),
-                        Err(e) => {
-                            let errmsg = format!("Call to submit block failed. {:?}", e);
-                            return Err(JsonRpcError::invalid_params(errmsg));
-                        }
-                    }
-                } else {
-                    let err = JsonRpcError::invalid_params(format!(
-                        "Failed to establish a node connection."
-                    ));
-                    return Err(err);
-                }
-            }
-            Err(e) => {
-                let err = JsonRpcError::invalid_params(format!("Node not found, {:?}", e));
-                Err(err)
-            }
-        },
+// the blocks a transaction is in, on any branch
+fn get_tx_blocks(conn: Conn, params: Params) -> Result<Value> {
+    let id = match params.parse::<TxId>() {
+        Ok(id) => id.id,
         Err(args) => {
             let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
-            Err(err)
+            return Err(err);
         }
-    }
-}
+    };
 
-// get peer list for a node
-fn get_peers(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<NodeId>() {
-        Ok(id) => match Peer::list(&conn, id.id) {
-            Ok(peers) => match serde_json::to_value(peers) {
-                Ok(value) => Ok(value),
-                Err(_) => Err(JsonRpcError::internal_error()),
-            },
-            Err(_) => Err(JsonRpcError::internal_error()),
-        },
-        Err(args) => {
-            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
-            Err(err)
+    match BlockTransaction::blocks_with(&conn, &id) {
+        Ok(blocks) => {
+            let blocks: Vec<_> = blocks
+                .into_iter()
+                .map(|(tx, block)| {
+                    json!({
+                        "hash": block.hash,
+                        "height": block.height,
+                        "pool_name": block.pool_name,
+                        "position": tx.position,
+                    })
+                })
+                .collect();
+            Ok(blocks.into())
+        }
+        Err(e) => {
+            error!("Could not fetch blocks of transaction {:?}", e);
+            Err(JsonRpcError::internal_error())
         }
     }
 }
 
-// updated chaintip to the provided block
-fn set_tip(conn: Conn, cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
-    match params.parse::<SetTipQuery>() {
-        Ok(query) => {
-            match Node::list(&conn) {
-                Ok(nodes) => {
-                    let n = nodes.iter().find(|n| n.id == query.node_id);
-
-                    if n.is_none() {
-                        let err = JsonRpcError::invalid_params(format!(
-                            "Node not found: {:?}",
-                            query.node_id
-                        ));
-                        return Err(err);
-                    }
-                }
-                Err(e) => {
-                    let err =
-                        JsonRpcError::invalid_params(format!("Could not get node list, {:?}", e));
-                    return Err(err);
-                }
-            }
+fn block_json(block: &Block) -> Value {
+    json!({ "hash": block.hash, "height": block.height })
+}
 
-            let c = ScannerCommand::SetTip {
-                node_id: query.node_id,
-                hash: query.hash,
-            };
-            cmd.send(c).expect("Command channel broke");
-            Ok("success".into())
+// transactions a tx spends from and the ones spending it, on any branch
+fn get_tx_ancestry(conn: Conn, params: Params) -> Result<Value> {
+    let id = match params.parse::<TxId>() {
+        Ok(id) => id.id,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
         }
+    };
+
+    let copies = match Transaction::with_blocks(&conn, std::slice::from_ref(&id)) {
+        Ok(copies) => copies,
         Err(e) => {
-            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", e));
-            Err(err)
+            error!("Could not fetch transaction {:?}", e);
+            return Err(JsonRpcError::internal_error());
         }
-    }
-}
+    };
 
-// get a block from a connected peer
-fn get_block_from_peer(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<GetBlockFromPeer>() {
-        Ok(query) => match Node::list(&conn) {
-            Ok(nodes) => {
-                let n = nodes.iter().find(|n| n.id == query.node_id);
+    let tx = match copies.first() {
+        Some((tx, _)) => tx,
+        None => {
+            return Err(JsonRpcError::invalid_params(format!(
+                "Unknown transaction {}",
+                id
+            )))
+        }
+    };
 
-                if n.is_none() {
-                    let err = JsonRpcError::invalid_params(format!(
-                        "Node not found: {:?}",
-                        query.node_id
-                    ));
-                    return Err(err);
-                }
+    let tx: BitcoinTransaction = match hex::decode(&tx.hex).map(|raw| deserialize(&raw)) {
+        Ok(Ok(tx)) => tx,
+        e => {
+            error!("Could not decode transaction {} {:?}", id, e.err());
+            return Err(JsonRpcError::internal_error());
+        }
+    };
 
-                let node = n.unwrap();
+    let inputs: Vec<_> = if tx.is_coin_base() {
+        vec![]
+    } else {
+        tx.input.iter().map(|i| i.previous_output).collect()
+    };
+    let prev_txids: Vec<_> = inputs.iter().map(|o| o.txid.to_string()).collect();
+    let parents = match Transaction::with_blocks(&conn, &prev_txids) {
+        Ok(parents) => parents,
+        Err(e) => {
+            error!("Could not fetch parent transactions {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
 
-                let auth = Auth::UserPass(node.rpc_user.clone(), node.rpc_pass.clone());
-                if let Ok(client) = Client::new(&node.rpc_host, auth) {
-                    let peer_id =
-                        serde_json::Value::Number(serde_json::Number::from(query.peer_id));
-                    let result = RpcApi::call::<serde_json::Value>(
-                        &client,
-                        "getblockfrompeer",
-                        &[serde_json::Value::String(query.hash), peer_id],
-                    );
+    let parents: Vec<_> = inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(vin, outpoint)| {
+            let prev_txid = outpoint.txid.to_string();
+            let blocks: Vec<_> = parents
+                .iter()
+                .filter(|(t, _)| t.txid == prev_txid)
+                .map(|(_, b)| block_json(b))
+                .collect();
 
-                    match result {
-                        Ok(result) => Ok(result),
-                        Err(e) => {
-                            let errmsg = format!("Call to get blocks failed. {:?}", e);
-                            return Err(JsonRpcError::invalid_params(errmsg));
-                        }
-                    }
-                } else {
-                    let err = JsonRpcError::invalid_params(format!(
-                        "Failed to establish a node connection."
-                    ));
-                    return Err(err);
-                }
-            }
-            Err(e) => {
-                let err = JsonRpcError::invalid_params(format!("Could not get node list, {:?}", e));
-                return Err(err);
+            if blocks.is_empty() {
+                return None;
             }
-        },
+            Some(json!({
+                "txid": prev_txid,
+                "vout": outpoint.vout,
+                "vin": vin,
+                "blocks": blocks,
+            }))
+        })
+        .collect();
+
+    let spends = match TransactionInput::spends_of(&conn, &id) {
+        Ok(spends) => spends,
         Err(e) => {
-            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", e));
-            Err(err)
+            error!("Could not fetch transaction spends {:?}", e);
+            return Err(JsonRpcError::internal_error());
         }
-    }
+    };
+
+    // more than one transaction spending an output is a double spend across branches
+    let children: Vec<_> = spends
+        .iter()
+        .map(|(input, block)| {
+            let conflicting = spends
+                .iter()
+                .any(|(i, _)| i.prev_vout == input.prev_vout && i.txid != input.txid);
+
+            json!({
+                "txid": input.txid,
+                "vin": input.vin,
+                "vout": input.prev_vout,
+                "block": block_json(block),
+                "conflicting": conflicting,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "txid": id,
+        "blocks": copies.iter().map(|(_, b)| block_json(b)).collect::<Vec<_>>(),
+        "parents": parents,
+        "children": children,
+    }))
 }
 
-fn get_block(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<BlockQuery>() {
-        Ok(q) => match q {
-            BlockQuery::Height(h) => {
-                if let Ok(result) = Block::get_at_height(&conn, h) {
-                    let result: Vec<_> = result
-                        .into_iter()
-                        .map(|block| BlockResult::from_block(block))
-                        .collect();
+// update watched addresses
+fn update_watched_addresses(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<WatchedAddressUpdate>() {
+        Ok(updates) => {
+            let WatchedAddressUpdate {
+                remove,
+                add,
+                remove_descriptors,
+                add_descriptors,
+            } = updates;
+            let add_descriptors = add_descriptors
+                .into_iter()
+                .map(DescriptorWatch::derive)
+                .collect::<Result<Vec<_>>>()?;
 
-                    match serde_json::to_value(result) {
-                        Ok(s) => Ok(s),
-                        Err(_) => Err(JsonRpcError::internal_error()),
-                    }
-                } else {
-                    Err(JsonRpcError::internal_error())
-                }
+            if Watched::remove(&conn, remove).is_err() {
+                return Err(JsonRpcError::internal_error());
+            };
+
+            if Watched::insert(&conn, add).is_err() {
+                return Err(JsonRpcError::internal_error());
             }
-            BlockQuery::Hash(h) => {
-                if let Ok(result) = Block::get(&conn, &h) {
-                    let result = BlockResult::from_block(result);
 
-                    match serde_json::to_value(vec![result]) {
-                        Ok(s) => Ok(s),
-                        Err(_) => Err(JsonRpcError::internal_error()),
-                    }
-                } else {
-                    Err(JsonRpcError::internal_error())
+            if let Err(e) = WatchedDescriptor::remove(&conn, remove_descriptors) {
+                error!("Could not remove watched descriptors {:?}", e);
+                return Err(JsonRpcError::internal_error());
+            }
+
+            for (desc, addresses) in add_descriptors {
+                if let Err(e) = desc.watch(&conn, &addresses) {
+                    error!("Could not watch descriptor {}: {:?}", desc.descriptor, e);
+                    return Err(JsonRpcError::internal_error());
                 }
             }
-        },
+
+            Ok("OK".into())
+        }
         Err(args) => {
             let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
             Err(err)
@@ -499,437 +1402,4057 @@ fn get_block(conn: Conn, params: Params) -> Result<Value> {
     }
 }
 
-// add a new node to forkscanner
-fn add_node(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<NodeArgs>() {
-        Ok(args) => {
-            if let Ok(n) = Node::insert(
-                &conn,
-                args.name,
-                args.rpc_host,
-                args.rpc_port,
-                args.mirror_rpc_port,
-                args.user,
-                args.pass,
-                args.mirror_host,
-                args.archive,
-            ) {
-                Ok(n.id.into())
-            } else {
-                Err(JsonRpcError::internal_error())
-            }
+// A transaction is confirmed in a block on the consensus tip's chain, reorged if its blocks
+// all left that chain, and double spent if a conflicting transaction is on it.
+fn tx_confirmation(conn: &PgConnection, txid: &str, target: i64) -> QueryResult<TxConfirmation> {
+    let mut confirmation = TxConfirmation {
+        txid: txid.into(),
+        status: TxState::Unconfirmed,
+        confirmations: 0,
+        target,
+        block_hash: None,
+        height: None,
+        double_spent_by: None,
+    };
+    let consensus = match consensus_tip(conn)? {
+        Some(tip) => tip,
+        None => return Ok(confirmation),
+    };
+
+    let blocks = BlockTransaction::blocks_with(conn, txid)?;
+    for (_, block) in &blocks {
+        if block.is_ancestor_of(conn, &consensus)? {
+            confirmation.status = TxState::Confirmed;
+            confirmation.confirmations = consensus.height - block.height + 1;
+            confirmation.block_hash = Some(block.hash.clone());
+            confirmation.height = Some(block.height);
+            return Ok(confirmation);
         }
-        Err(args) => {
-            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
-            Err(err)
+    }
+
+    for (input, block) in TransactionInput::conflicts_of(conn, txid)? {
+        if block.is_ancestor_of(conn, &consensus)? {
+            confirmation.status = TxState::DoubleSpent;
+            confirmation.block_hash = Some(block.hash);
+            confirmation.height = Some(block.height);
+            confirmation.double_spent_by = Some(input.txid);
+            return Ok(confirmation);
         }
     }
+
+    if !blocks.is_empty() {
+        confirmation.status = TxState::Reorged;
+    }
+    Ok(confirmation)
 }
 
-// remove node from database
-fn remove_node(conn: Conn, params: Params) -> Result<Value> {
-    match params.parse::<NodeId>() {
-        Ok(id) => {
-            if let Ok(_) = Node::remove(&conn, id.id) {
-                Ok("OK".into())
-            } else {
-                Err(JsonRpcError::internal_error())
-            }
-        }
+// update watched outpoints
+fn watch_outpoints(conn: Conn, params: Params) -> Result<Value> {
+    let update = match params.parse::<OutpointWatchUpdate>() {
+        Ok(update) => update,
         Err(args) => {
             let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
-            Err(err)
+            return Err(err);
         }
+    };
+
+    let remove = update
+        .remove
+        .into_iter()
+        .map(|o| (o.txid, o.vout))
+        .collect();
+    if let Err(e) = WatchedOutpoint::remove(&conn, remove) {
+        error!("Could not remove watched outpoints {:?}", e);
+        return Err(JsonRpcError::internal_error());
     }
-}
 
-// fetch currently active chaintips
-fn get_tips(params: Params, conn: Conn) -> Result<Value> {
-    match params.parse::<TipArgs>() {
-        Ok(t) => {
-            let chaintips = if t.active_only {
-                Chaintip::list_active(&conn)
-            } else {
-                Chaintip::list(&conn)
-            };
+    let add = update
+        .add
+        .into_iter()
+        .map(|o| (o.txid, o.vout, o.watch_until))
+        .collect();
+    if let Err(e) = WatchedOutpoint::insert(&conn, add) {
+        error!("Could not watch outpoints {:?}", e);
+        return Err(JsonRpcError::internal_error());
+    }
 
-            if let Err(e) = chaintips {
-                let err =
-                    JsonRpcError::invalid_params(format!("Couldn't fetch chaintips, {:?}", e));
-                return Err(err);
-            }
+    Ok("OK".into())
+}
 
-            match serde_json::to_value(chaintips.unwrap()) {
-                Ok(t) => Ok(t),
-                Err(_) => Err(JsonRpcError::internal_error()),
-            }
-        }
+// The active tip most nodes agree on, leaving out nodes in maintenance.
+fn consensus_tip(conn: &PgConnection) -> QueryResult<Option<Block>> {
+    let maintenance = MaintenanceWindow::active_nodes(conn)?;
+    let tips: Vec<_> = Chaintip::list_active(conn)?
+        .into_iter()
+        .filter(|t| !maintenance.contains(&t.node))
+        .collect();
+    let counts = tips
+        .iter()
+        .map(|t| t.block.clone())
+        .collect::<counter::Counter<_>>();
+
+    let best = tips
+        .iter()
+        .max_by_key(|t| (counts[&t.block], t.height))
+        .map(|t| t.block.clone());
+
+    match best {
+        Some(hash) => Block::get(conn, &hash).optional(),
+        None => Ok(None),
+    }
+}
+
+// past transactions of an address, and whether they are still in the active chain
+fn get_watched_activity(conn: Conn, params: Params) -> Result<Value> {
+    let query = match params.parse::<WatchedActivityQuery>() {
+        Ok(query) => query,
         Err(args) => {
             let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
-            Err(err)
+            return Err(err);
         }
-    }
-}
+    };
 
-// validation endpoint subscription handler
-fn handle_validation_subscribe(
-    exit: Arc<AtomicBool>,
-    receiver: Receiver<ScannerMessage>,
-    pool: ManagedPool,
-    window: i64,
-    sink: Sink,
-) {
-    info!("New subscription");
-    let send_update = move |pool: &ManagedPool, sink: &Sink| -> std::result::Result<(), WsError> {
-        let conn = pool.get()?;
-        match validation_checks(conn, window) {
-            Ok(resp) => Ok(sink.notify(Params::Array(vec![resp]))?),
-            Err(_) => Ok(sink.notify(Params::Array(vec![
-                "Failed to update validation checks".into()
-            ]))?),
+    let activity = TransactionAddress::activity(&conn, &query.address, query.from, query.to);
+    let activity = match activity {
+        Ok(activity) => activity,
+        Err(e) => {
+            error!("Could not fetch address activity {:?}", e);
+            return Err(JsonRpcError::internal_error());
         }
     };
 
-    thread::spawn(move || {
-        if let Err(e) = send_update(&pool, &sink) {
-            error!(
-                "Error sending validation checks to initialize client {:?}",
-                e
-            );
+    let tip = match consensus_tip(&conn) {
+        Ok(tip) => tip,
+        Err(e) => {
+            error!("Could not fetch active tip {:?}", e);
+            return Err(JsonRpcError::internal_error());
         }
+    };
 
-        loop {
-            if exit.load(Ordering::SeqCst) {
-                break;
-            }
-
-            match receiver.recv_timeout(time::Duration::from_millis(5000)) {
-                Ok(ScannerMessage::StaleCandidateUpdate) => {
-                    if let Err(e) = send_update(&pool, &sink) {
-                        error!("Error sending chaintips to client {:?}", e);
-                    }
-                }
-                Ok(_) => {}
-                Err(RecvTimeoutError::Timeout) => {
-                    info!("No chaintip updates");
-                }
-                Err(e) => {
-                    error!("Error! {:?}", e);
-                }
+    let min_height = activity.iter().map(|(_, b)| b.height).min();
+    let active_chain: HashSet<_> = match (&tip, min_height) {
+        (Some(tip), Some(min_height)) => match tip.ancestors(&conn, min_height) {
+            Ok(blocks) => blocks.into_iter().map(|b| b.hash).collect(),
+            Err(e) => {
+                error!("Could not fetch active chain {:?}", e);
+                return Err(JsonRpcError::internal_error());
             }
+        },
+        _ => HashSet::new(),
+    };
+
+    // a transaction can be in several blocks if it was reorged
+    let mut txids: Vec<String> = vec![];
+    let mut blocks: HashMap<String, Vec<Block>> = HashMap::new();
+    for (tx, block) in activity {
+        if !blocks.contains_key(&tx.txid) {
+            txids.push(tx.txid.clone());
         }
-    });
+        blocks.entry(tx.txid).or_default().push(block);
+    }
+
+    let transactions: Vec<_> = txids
+        .into_iter()
+        .map(|txid| {
+            let blocks = blocks.remove(&txid).unwrap_or_default();
+            let confirmed = blocks.iter().find(|b| active_chain.contains(&b.hash));
+            let confirmations = match (confirmed, &tip) {
+                (Some(block), Some(tip)) => tip.height - block.height + 1,
+                _ => 0,
+            };
+
+            json!({
+                "txid": txid,
+                "status": if confirmed.is_some() { "confirmed" } else { "reorged" },
+                "confirmations": confirmations,
+                "blocks": blocks
+                    .iter()
+                    .map(|b| json!({
+                        "hash": b.hash,
+                        "height": b.height,
+                        "active": active_chain.contains(&b.hash),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "address": query.address,
+        "tip": tip.map(|t| json!({ "hash": t.hash, "height": t.height })),
+        "transactions": transactions,
+    }))
 }
 
-// Notify of watched address activity
-fn handle_watched_addresses(
-    exit: Arc<AtomicBool>,
-    receiver: Receiver<ScannerMessage>,
-    watch: Vec<String>,
-    watch_until: DateTime<Utc>,
-    pool: ManagedPool,
-    sink: Sink,
-) {
-    let conn = pool.get().expect("Connection pool failure");
+// a fetched transaction with its addresses and every block it is in
+fn get_transaction(conn: Conn, params: Params) -> Result<Value> {
+    let id = match params.parse::<TxId>() {
+        Ok(id) => id.id,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
 
-    let watches: Vec<_> = watch.into_iter().map(|w| (w, watch_until.clone())).collect();
-    Watched::insert(&conn, watches).expect("Could not insert watchlist!");
+    let fetched = Transaction::with_blocks(&conn, std::slice::from_ref(&id))
+        .and_then(|copies| Ok((copies, TransactionAddress::of_tx(&conn, &id)?)))
+        .and_then(|(copies, addresses)| Ok((copies, addresses, consensus_tip(&conn)?)));
+    let (copies, addresses, tip) = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            error!("Could not fetch transaction {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
 
-    info!("New address activity");
-    let send_update =
-        move |transactions: Vec<Transaction>, sink: &Sink| -> std::result::Result<(), WsError> {
-            let resp = transactions
-                .into_iter()
-                .map(|tx| serde_json::to_value(tx).expect("Could not serialize transaction"))
-                .collect();
-            Ok(sink.notify(Params::Array(resp))?)
+    let mut blocks = vec![];
+    for (_, block) in &copies {
+        let in_consensus_chain = match &tip {
+            Some(tip) => match block.is_ancestor_of(&conn, tip) {
+                Ok(ancestor) => ancestor,
+                Err(e) => {
+                    error!("Could not fetch active chain {:?}", e);
+                    return Err(JsonRpcError::internal_error());
+                }
+            },
+            None => false,
         };
+        blocks.push(json!({
+            "hash": block.hash,
+            "height": block.height,
+            "in_consensus_chain": in_consensus_chain,
+        }));
+    }
 
-    thread::spawn(move || loop {
-        if exit.load(Ordering::SeqCst) {
-            break;
+    match copies.into_iter().next() {
+        Some((tx, _)) => Ok(json!({
+            "txid": tx.txid,
+            "hex": tx.hex,
+            "is_coinbase": tx.is_coinbase,
+            "amount": btc_json(tx.amount),
+            "swept": tx.swept,
+            "addresses": addresses,
+            "blocks": blocks,
+        })),
+        None => {
+            let err = JsonRpcError::invalid_params(format!("No transaction {}", id));
+            Err(err)
         }
+    }
+}
 
-        match receiver.recv_timeout(time::Duration::from_millis(5000)) {
-            Ok(ScannerMessage::WatchedAddress(transactions)) => {
-                if let Err(e) = send_update(transactions, &sink) {
-                    error!("Error sending watched activity to client {:?}", e);
-                }
-            }
-            Ok(_) => {}
-            Err(RecvTimeoutError::Timeout) => {
-                info!("No lagging node updates");
-            }
-            Err(e) => {
-                error!("Error! {:?}", e);
+// Address history cursors are the height and hash of the block and the txid of the last
+// transaction returned.
+fn parse_tx_cursor(cursor: &str) -> Option<(i64, String, String)> {
+    let mut parts = cursor.splitn(3, ':');
+    let height = parts.next()?.parse().ok()?;
+    Some((height, parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+// page through the transactions of an address, highest first
+fn get_address_transactions(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<AddressTransactionsQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let after = match &q.cursor {
+        Some(cursor) => match parse_tx_cursor(cursor) {
+            Some(after) => Some(after),
+            None => {
+                let err = JsonRpcError::invalid_params(format!("Invalid cursor, {}", cursor));
+                return Err(err);
             }
+        },
+        None => None,
+    };
+    let limit = q
+        .limit
+        .unwrap_or(ADDRESS_TXS_LIMIT)
+        .clamp(1, ADDRESS_TXS_MAX_LIMIT);
+
+    let history =
+        TransactionAddress::history(&conn, &q.address, q.from_height, q.to_height, after, limit);
+    let history = match history {
+        Ok(history) => history,
+        Err(e) => {
+            error!("Could not fetch address history {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+
+    // a short page is the last one
+    let next_cursor = match history.last() {
+        Some((tx, block)) if history.len() as i64 == limit => {
+            Some(format!("{}:{}:{}", block.height, block.hash, tx.txid))
         }
+        _ => None,
+    };
+
+    let txids: Vec<_> = history.iter().map(|(tx, _)| tx.txid.clone()).collect();
+    let min_height = history.iter().map(|(_, b)| b.height).min();
+    let fetched = Transaction::with_blocks(&conn, &txids).and_then(|copies| {
+        let active_chain: HashSet<_> = match (consensus_tip(&conn)?, min_height) {
+            (Some(tip), Some(min_height)) => tip
+                .ancestors(&conn, min_height)?
+                .into_iter()
+                .map(|b| b.hash)
+                .collect(),
+            _ => HashSet::new(),
+        };
+        Ok((copies, active_chain))
     });
+    let (copies, active_chain) = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            error!("Could not fetch address transactions {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+
+    // transactions are only fetched for blocks the watcher or stale candidates needed
+    let copies: HashMap<_, _> = copies
+        .into_iter()
+        .map(|(tx, _)| ((tx.block_id.clone(), tx.txid.clone()), tx))
+        .collect();
+
+    let transactions: Vec<_> = history
+        .into_iter()
+        .map(|(tx, block)| {
+            let fetched = copies.get(&(block.hash.clone(), tx.txid.clone()));
+            json!({
+                "txid": tx.txid,
+                "block_hash": block.hash,
+                "height": block.height,
+                "in_consensus_chain": active_chain.contains(&block.hash),
+                "amount": fetched.map(|t| btc_json(t.amount)),
+                "is_coinbase": fetched.map(|t| t.is_coinbase),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "address": q.address,
+        "transactions": transactions,
+        "next_cursor": next_cursor,
+    }))
 }
 
-// Notify of lagging nodes
-fn handle_lagging_nodes_subscribe(
-    exit: Arc<AtomicBool>,
-    receiver: Receiver<ScannerMessage>,
-    sink: Sink,
-) {
-    info!("New subscription");
-    let send_update = move |lags: Vec<Lags>, sink: &Sink| -> std::result::Result<(), WsError> {
-        let resp = lags
-            .into_iter()
-            .map(|conf| serde_json::to_value(conf).expect("Could not serialize lagging node"))
-            .collect();
-        Ok(sink.notify(Params::Array(resp))?)
+// export the watchlist with expiries, in the format import_watchlist takes. Addresses
+// derived from a descriptor are exported as the descriptor.
+fn export_watchlist(conn: Conn) -> Result<Value> {
+    match (Watched::list(&conn), WatchedDescriptor::list(&conn)) {
+        (Ok(watched), Ok(descriptors)) => {
+            let addresses: Vec<_> = watched
+                .into_iter()
+                .filter(|w| w.descriptor.is_none())
+                .collect();
+            Ok(json!({ "addresses": addresses, "descriptors": descriptors }))
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Could not list watched addresses {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// import an exported watchlist, all or nothing
+fn import_watchlist(conn: Conn, params: Params) -> Result<Value> {
+    let import = match params.parse::<WatchlistImport>() {
+        Ok(import) => import,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
     };
 
-    thread::spawn(move || loop {
-        if exit.load(Ordering::SeqCst) {
-            break;
+    let network = network();
+    let invalid: Vec<_> = import
+        .addresses
+        .iter()
+        .filter(|w| !network.accepts_address(&w.address))
+        .map(|w| w.address.as_str())
+        .collect();
+    if !invalid.is_empty() {
+        return Err(JsonRpcError::invalid_params(format!(
+            "Invalid parameters, not {} addresses: {}",
+            network,
+            invalid.join(", ")
+        )));
+    }
+
+    let watches = import
+        .addresses
+        .into_iter()
+        .map(|w| (w.address, w.watch_until))
+        .collect();
+    let descriptors = import
+        .descriptors
+        .into_iter()
+        .map(DescriptorWatch::derive)
+        .collect::<Result<Vec<_>>>()?;
+
+    match Watched::import(&conn, watches, descriptors, import.replace) {
+        Ok(imported) => Ok(imported.into()),
+        Err(e) => {
+            error!("Watchlist import failed {:?}", e);
+            Err(JsonRpcError::internal_error())
         }
+    }
+}
 
-        match receiver.recv_timeout(time::Duration::from_millis(5000)) {
-            Ok(ScannerMessage::LaggingNodes(lags)) => {
-                if let Err(e) = send_update(lags, &sink) {
-                    error!("Error sending lagging nodes to client {:?}", e);
+fn submit_block(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<BlockUpload>() {
+        Ok(upload) => match Node::get(&conn, upload.node_id) {
+            Ok(node) => {
+                let auth = match node_auth(&node) {
+                    Ok(auth) => auth,
+                    Err(e) => {
+                        error!("No credentials for node {}: {:?}", node.id, e);
+                        return Err(JsonRpcError::internal_error());
+                    }
+                };
+
+                let host = format!("http://{}:{}", node.rpc_host, node.rpc_port);
+                let timeouts = RpcTimeouts::from(&node);
+                if let Ok(client) = node_rpc_client(node.id, &host, auth, timeouts) {
+                    let hash = upload.block.block_hash();
+                    let block_hex = serialize_hex(&upload.block);
+
+                    match client.submit_block(block_hex, &hash) {
+                        Ok(_) => Ok("OK".into()),
+                        Err(e) => {
+                            let errmsg = format!("Call to submit block failed. {:?}", e);
+                            Err(JsonRpcError::invalid_params(errmsg))
+                        }
+                    }
+                } else {
+                    let err =
+                        JsonRpcError::invalid_params("Failed to establish a node connection.");
+                    Err(err)
                 }
             }
-            Ok(_) => {}
-            Err(RecvTimeoutError::Timeout) => {
-                info!("No lagging node updates");
-            }
             Err(e) => {
-                error!("Error! {:?}", e);
+                let err = JsonRpcError::invalid_params(format!("Node not found, {:?}", e));
+                Err(err)
             }
+        },
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
         }
-    });
+    }
 }
 
-// invalid block endpoint subscription handler
-fn handle_invalid_block_subscribe(
-    exit: Arc<AtomicBool>,
-    receiver: Receiver<ScannerMessage>,
-    sink: Sink,
-) {
-    info!("New subscription");
-    let send_update = move |blocks: Vec<ConflictingBlock>,
-                            sink: &Sink|
-          -> std::result::Result<(), WsError> {
-        let resp = blocks
-            .into_iter()
-            .map(|conf| serde_json::to_value(conf).expect("Could not serialize conflicting block"))
-            .collect();
-        Ok(sink.notify(Params::Array(resp))?)
-    };
-
-    thread::spawn(move || loop {
-        if exit.load(Ordering::SeqCst) {
-            break;
+// get peer list for a node
+pub(crate) fn get_peers(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<Option<PeerQuery>>() {
+        Ok(Some(PeerQuery { id: Some(id) })) => match Peer::list(&conn, id) {
+            Ok(peers) => match serde_json::to_value(peers) {
+                Ok(value) => Ok(value),
+                Err(_) => Err(JsonRpcError::internal_error()),
+            },
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Ok(_) => get_all_peers(conn),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
         }
+    }
+}
 
-        match receiver.recv_timeout(time::Duration::from_millis(5000)) {
-            Ok(ScannerMessage::NewBlockConflicts(conflicts)) => {
-                if let Err(e) = send_update(conflicts, &sink) {
-                    error!("Error sending block conflicts to client {:?}", e);
-                }
-            }
-            Ok(_) => {}
-            Err(RecvTimeoutError::Timeout) => {
-                info!("No block conflict updates");
-            }
-            Err(e) => {
-                error!("Error! {:?}", e);
+// the peers of all nodes, with the nodes sharing each of them
+fn get_all_peers(conn: Conn) -> Result<Value> {
+    match SharedPeer::list(&conn) {
+        Ok(peers) => {
+            let mut per_node = HashMap::<i64, i64>::new();
+            for node in peers.iter().flat_map(|p| p.nodes.iter()) {
+                *per_node.entry(*node).or_default() += 1;
             }
+            let mut per_node: Vec<_> = per_node
+                .into_iter()
+                .map(|(node_id, peers)| json!({ "node_id": node_id, "peers": peers }))
+                .collect();
+            per_node.sort_by_key(|n| n["node_id"].as_i64());
+
+            Ok(json!({
+                "peers": peers,
+                "total": peers.len(),
+                "shared": peers.iter().filter(|p| p.nodes.len() > 1).count(),
+                "per_node": per_node,
+            }))
         }
-    });
+        Err(e) => {
+            error!("Could not fetch peers {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
 }
 
-fn handle_subscribe_forks(
-    exit: Arc<AtomicBool>,
-    pool: ManagedPool,
-    receiver: Receiver<ScannerMessage>,
-    _: Params,
-    sink: Sink,
-) {
-    info!("New subscription");
-    fn send_update(tips: Vec<Chaintip>, sink: &Sink) -> std::result::Result<(), WsError> {
-        let tips: Vec<_> = tips
-            .into_iter()
-            .map(|tip| serde_json::to_value(tip).expect("JSON serde failed"))
-            .collect();
+// block body sharing stats per node
+fn get_propagation_stats(conn: Conn) -> Result<Value> {
+    match BlockAssist::stats(&conn) {
+        Ok(stats) => match serde_json::to_value(stats) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(_) => Err(JsonRpcError::internal_error()),
+    }
+}
 
-        Ok(sink.notify(Params::Array(tips))?)
+// how many peers each pair of nodes shares
+fn get_peer_overlap(conn: Conn) -> Result<Value> {
+    match PeerOverlap::list(&conn) {
+        Ok(overlaps) => match serde_json::to_value(overlaps) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(_) => Err(JsonRpcError::internal_error()),
     }
+}
 
-    thread::spawn(move || {
-        let conn = pool.get().expect("Could not get pooled connection!");
-        match Chaintip::list_active(&conn) {
-            Ok(tips) => {
-                if let Err(e) = send_update(tips, &sink) {
-                    error!("Error sending chaintips to initialize client {:?}", e);
-                }
-            }
-            Err(e) => {
-                error!("Database error {:?}", e);
-            }
+// transactions that blocks of a pool kept leaving out, and how many per pool
+fn get_censorship_report(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<CensorshipQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
         }
+    };
 
-        loop {
-            if exit.load(Ordering::SeqCst) {
-                break;
+    let min_omissions = q.min_omissions.unwrap_or(2);
+    match CensorshipCandidate::list(&conn, q.pool_name, min_omissions, q.limit.unwrap_or(100)) {
+        Ok(candidates) => {
+            let mut pools = BTreeMap::<&str, i64>::new();
+            for candidate in &candidates {
+                *pools.entry(&candidate.pool_name).or_default() += 1;
             }
 
-            match receiver.recv_timeout(time::Duration::from_millis(5000)) {
-                Ok(ScannerMessage::NewChaintip) => {
-                    let conn = pool.get().expect("Could not get pooled connection!");
-                    let tips = match Chaintip::list_active(&conn) {
-                        Ok(tips) => tips,
-                        Err(e) => {
-                            error!("Database error {:?}", e);
-                            continue;
-                        }
-                    };
+            Ok(json!({
+                "pools": pools,
+                "candidates": candidates,
+            }))
+        }
+        Err(e) => {
+            error!("Could not fetch censorship candidates {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
 
-                    if let Err(e) = send_update(tips, &sink) {
-                        error!("Error sending chaintips to client {:?}", e);
-                    }
-                }
-                Ok(_) => {}
-                Err(RecvTimeoutError::Timeout) => {
-                    info!("No chaintip updates");
-                }
-                Err(e) => {
-                    error!("Error! {:?}", e);
-                }
-            }
+// blocks whose coinbase matched no pool, to label
+fn get_unknown_coinbase_tags(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<UnknownCoinbaseQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
         }
-    });
+    };
+
+    let labeled = q.labeled.unwrap_or(false);
+    match UnknownCoinbaseTag::list(&conn, labeled, q.limit.unwrap_or(100)) {
+        Ok(unknown) => match serde_json::to_value(unknown) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(e) => {
+            error!("Could not fetch unknown coinbase tags {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// add a coinbase tag to the pools, and attribute the blocks with it
+fn label_coinbase_tag(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<LabelCoinbaseTag>() {
+        Ok(args) if !args.tag.is_empty() && !args.name.is_empty() => args,
+        args => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let url = args.url.unwrap_or_default();
+    match UnknownCoinbaseTag::label(&conn, &args.tag, &args.name, &url) {
+        Ok(blocks) => Ok(json!({
+            "tag": args.tag,
+            "name": args.name,
+            "blocks": blocks,
+        })),
+        Err(e) => {
+            error!("Could not label coinbase tag {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
 }
 
-// handles subscriptions for chaintip updates
-fn handle_subscribe(
-    exit: Arc<AtomicBool>,
-    receiver: Receiver<ScannerMessage>,
-    tips: Arc<RwLock<Vec<Chaintip>>>,
-    _: Params,
-    sink: Sink,
-) {
-    info!("New subscription");
-    fn send_update(
-        tips: &Arc<RwLock<Vec<Chaintip>>>,
-        sink: &Sink,
-    ) -> std::result::Result<(), WsError> {
-        let values = tips.read().expect("Lock poisoned").clone();
-        let tips: Vec<_> = values
-            .into_iter()
-            .map(|tip| serde_json::to_value(tip).expect("JSON serde failed"))
-            .collect();
+// the payout addresses of each pool
+fn get_payout_clusters(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<PayoutClusterQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match PayoutCluster::list(&conn, q.pool_name.as_deref()) {
+        Ok(clusters) => match serde_json::to_value(clusters) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(e) => {
+            error!("Could not fetch payout clusters {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// add or remove addresses a pool's blocks are expected to pay to
+fn update_expected_payouts(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<ExpectedPayoutUpdate>() {
+        Ok(args) if !args.pool_name.is_empty() => args,
+        args => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match ExpectedPayout::update(
+        &conn,
+        &args.pool_name,
+        args.add,
+        args.remove,
+        args.from_history,
+    ) {
+        Ok(expected) => Ok(serde_json::to_value(expected).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not update expected payouts {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// the expected payout addresses of each pool
+fn get_expected_payouts(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<PayoutClusterQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match ExpectedPayout::list(&conn, q.pool_name.as_deref()) {
+        Ok(expected) => Ok(serde_json::to_value(expected).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch expected payouts {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// peers the nodes found misbehaving, the worst first
+fn get_peer_reputation(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<PeerReputationQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match PeerReputation::list(&conn, q.address, q.limit.unwrap_or(100)) {
+        Ok(peers) => match serde_json::to_value(peers) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(_) => Err(JsonRpcError::internal_error()),
+    }
+}
+
+// rollback check queue for the mirror nodes
+fn get_rollback_progress(conn: Conn) -> Result<Value> {
+    match RollbackSchedule::list(&conn) {
+        Ok(schedule) => match serde_json::to_value(schedule) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(JsonRpcError::internal_error()),
+        },
+        Err(_) => Err(JsonRpcError::internal_error()),
+    }
+}
+
+// rollback validation verdicts, newest first
+fn get_rollback_checks(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<RollbackCheckQuery>() {
+        Ok(q) => {
+            let limit = q.limit.unwrap_or(100);
+            match RollbackCheck::list(&conn, q.node_id, q.hash, limit) {
+                Ok(checks) => match serde_json::to_value(checks) {
+                    Ok(value) => Ok(value),
+                    Err(_) => Err(JsonRpcError::internal_error()),
+                },
+                Err(_) => Err(JsonRpcError::internal_error()),
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// reorgs of the nodes' active tips, most recent first
+fn get_reorgs(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<ReorgQuery>() {
+        Ok(q) => {
+            let limit = q.limit.unwrap_or(100);
+            match Reorg::list(&conn, q.node_id, q.min_depth, limit) {
+                Ok(reorgs) => match serde_json::to_value(reorgs) {
+                    Ok(value) => Ok(value),
+                    Err(_) => Err(JsonRpcError::internal_error()),
+                },
+                Err(e) => {
+                    error!("Could not fetch reorgs {:?}", e);
+                    Err(JsonRpcError::internal_error())
+                }
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// outputs of watched addresses spent more than once, most recently alerted first
+fn get_wallet_conflicts(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<WalletConflictQuery>() {
+        Ok(q) => {
+            let limit = q.limit.unwrap_or(100);
+            match WalletConflict::list(&conn, limit) {
+                Ok(conflicts) => match serde_json::to_value(conflicts) {
+                    Ok(value) => Ok(value),
+                    Err(_) => Err(JsonRpcError::internal_error()),
+                },
+                Err(e) => {
+                    error!("Could not fetch wallet conflicts {:?}", e);
+                    Err(JsonRpcError::internal_error())
+                }
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// watched transactions relayed to nodes missing them, newest first
+fn get_watched_relays(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<WatchedRelayQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let limit = q
+        .limit
+        .unwrap_or(WATCHED_RELAYS_LIMIT)
+        .clamp(1, WATCHED_RELAYS_MAX_LIMIT);
+    match WatchedRelay::list(&conn, q.txid, limit) {
+        Ok(relays) => Ok(serde_json::to_value(relays).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch watched relays {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// stale candidates, newest first, with their branches or imported blocks
+pub(crate) fn get_stale_candidates(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<StaleCandidateQuery>() {
+        Ok(q) => {
+            let limit = q.limit.unwrap_or(100);
+            let candidates = match StaleCandidate::list(&conn, q.source, limit) {
+                Ok(c) => c,
+                Err(_) => return Err(JsonRpcError::internal_error()),
+            };
+
+            let mut result = Vec::new();
+            for candidate in candidates {
+                match stale_candidate_json(&conn, candidate) {
+                    Ok(c) => result.push(c),
+                    Err(_) => return Err(JsonRpcError::internal_error()),
+                }
+            }
+
+            Ok(result.into())
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// A stale candidate with its branches, imported blocks and double spent txids.
+fn stale_candidate_json(
+    conn: &PgConnection,
+    candidate: StaleCandidate,
+) -> diesel::QueryResult<Value> {
+    let children: Vec<_> = candidate
+        .children(conn)?
+        .into_iter()
+        .map(|c| json!({ "root": c.root_id, "tip": c.tip_id, "len": c.len }))
+        .collect();
+    let imported = candidate.imported(conn)?;
+    let double_spent = candidate.double_spent_txids(conn)?;
+    let addresses = candidate.conflict_addresses(conn)?;
+
+    Ok(json!({
+        "height": candidate.height,
+        "n_children": candidate.n_children,
+        "source": candidate.source,
+        "confirmed_in_one_branch_total": btc_json(candidate.confirmed_in_one_branch_total),
+        "double_spent_in_one_branch_total": btc_json(candidate.double_spent_in_one_branch_total),
+        "rbf_total": btc_json(candidate.rbf_total),
+        "created_at": candidate.created_at,
+        "children": children,
+        "imported": imported,
+        "double_spent_txids": double_spent,
+        "conflict_addresses": addresses,
+    }))
+}
+
+// double spends and replacements per destination address, newest candidates first
+fn get_conflict_addresses(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<ConflictAddressQuery>() {
+        Ok(q) => match ConflictAddress::list(&conn, q.address, q.limit.unwrap_or(100)) {
+            Ok(addresses) => Ok(serde_json::to_value(addresses).unwrap()),
+            Err(e) => {
+                error!("Could not fetch conflict addresses {:?}", e);
+                Err(JsonRpcError::internal_error())
+            }
+        },
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// record a tip a trusted external watcher saw, it is checked against our nodes on the
+// next scanner run
+fn submit_external_tip(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<ExternalTipArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let source = args.source.trim();
+    if source.is_empty() {
+        return Err(JsonRpcError::invalid_params("source can't be empty"));
+    }
+    if args.hash.len() != 64 || hex::decode(&args.hash).is_err() {
+        let err = JsonRpcError::invalid_params(format!("Invalid block hash {}", args.hash));
+        return Err(err);
+    }
+    if args.height < 0 {
+        return Err(JsonRpcError::invalid_params("height can't be negative"));
+    }
+
+    let hash = args.hash.to_lowercase();
+    match ExternalObservation::insert(&conn, source, &hash, args.height) {
+        Ok(observation) => Ok(serde_json::to_value(observation).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not record external observation {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// The latest events of each kind, newest first, for dashboards to show on load.
+fn get_recent_activity(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<RecentActivityQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+    let limit = q
+        .limit
+        .unwrap_or(RECENT_ACTIVITY_LIMIT)
+        .clamp(1, RECENT_ACTIVITY_MAX_LIMIT);
+
+    let activity = || -> QueryResult<Value> {
+        let reorgs = Reorg::list(&conn, None, None, limit)?;
+        let conflicts = InvalidBlock::conflicts(&conn, limit)?;
+        let mut lags = Lags::list(&conn)?;
+        lags.sort_by_key(|lag| Reverse(lag.created_at));
+        lags.truncate(limit as usize);
+        let inflation: Vec<_> = InflatedBlock::list(&conn, limit)?
+            .iter()
+            .map(inflated_block_json)
+            .collect();
+
+        Ok(json!({
+            "reorgs": reorgs,
+            "conflicts": conflicts,
+            "lags": lags,
+            "inflation": inflation,
+        }))
+    };
+
+    match activity() {
+        Ok(activity) => Ok(activity),
+        Err(e) => {
+            error!("Could not fetch recent activity {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// The tips the nodes reported at a past moment, from the tip history.
+fn get_tips_as_of(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<TipsAsOfQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match TipHistory::as_of(&conn, q.timestamp, q.node_id) {
+        Ok(tips) => {
+            let tips: Vec<_> = tips
+                .into_iter()
+                .filter(|tip| !q.active_only || tip.status == "active")
+                .collect();
+            Ok(serde_json::to_value(tips).expect("JSON serde failed"))
+        }
+        Err(e) => {
+            error!("Could not fetch tip history {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// What was known of a block at a past moment: which nodes had found it valid or invalid,
+// the tip statuses they reported for it and which nodes had it in their active chain.
+fn get_block_status_as_of(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<BlockStatusAsOfQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let block = match Block::get(&conn, &q.hash).optional() {
+        Ok(Some(block)) => block,
+        Ok(None) => {
+            let err = JsonRpcError::invalid_params(format!("Unknown block {}", q.hash));
+            return Err(err);
+        }
+        Err(e) => {
+            error!("Could not fetch block {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+
+    let status = || -> QueryResult<Value> {
+        let (valid_by, invalid_by) = Block::validity_as_of(&conn, &block.hash, q.timestamp)?;
+        let tip_statuses: Vec<_> = TipHistory::of_block_as_of(&conn, &block.hash, q.timestamp)?
+            .into_iter()
+            .map(|tip| {
+                json!({
+                    "node_id": tip.node_id,
+                    "status": tip.status,
+                    "observed_at": tip.observed_at,
+                })
+            })
+            .collect();
+        let active_chain = block.active_nodes_as_of(&conn, q.timestamp)?;
+
+        Ok(json!({
+            "hash": block.hash,
+            "height": block.height,
+            "timestamp": q.timestamp,
+            "valid_by": valid_by,
+            "invalid_by": invalid_by,
+            "tip_statuses": tip_statuses,
+            "active_chain": active_chain,
+        }))
+    };
+
+    match status() {
+        Ok(status) => Ok(status),
+        Err(e) => {
+            error!("Could not fetch block status history {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// external observations, newest first
+fn get_external_observations(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<ExternalObservationsQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let limit = q
+        .limit
+        .unwrap_or(EXTERNAL_OBSERVATIONS_LIMIT)
+        .clamp(1, EXTERNAL_OBSERVATIONS_MAX_LIMIT);
+    match ExternalObservation::list(&conn, q.source, limit) {
+        Ok(observations) => Ok(serde_json::to_value(observations).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch external observations {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// add stale blocks from an external dataset
+fn import_stale_blocks(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<ImportStaleBlocksArgs>() {
+        Ok(args) => {
+            let blocks = match parse_stale_blocks(&args.source, &args.csv) {
+                Ok(blocks) => blocks,
+                Err(e) => return Err(JsonRpcError::invalid_params(e.to_string())),
+            };
+
+            match ImportedStaleBlock::import(&conn, blocks) {
+                Ok(imported) => Ok(json!({ "imported": imported })),
+                Err(_) => Err(JsonRpcError::internal_error()),
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// updated chaintip to the provided block
+fn set_tip(conn: Conn, cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
+    match params.parse::<SetTipQuery>() {
+        Ok(query) => {
+            match Node::list(&conn) {
+                Ok(nodes) => {
+                    let n = nodes.iter().find(|n| n.id == query.node_id);
+
+                    if n.is_none() {
+                        let err = JsonRpcError::invalid_params(format!(
+                            "Node not found: {:?}",
+                            query.node_id
+                        ));
+                        return Err(err);
+                    }
+                }
+                Err(e) => {
+                    let err =
+                        JsonRpcError::invalid_params(format!("Could not get node list, {:?}", e));
+                    return Err(err);
+                }
+            }
+
+            let c = ScannerCommand::SetTip {
+                node_id: query.node_id,
+                hash: query.hash,
+            };
+            cmd.send(c).expect("Command channel broke");
+            Ok("success".into())
+        }
+        Err(e) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", e));
+            Err(err)
+        }
+    }
+}
+
+// get a block from a connected peer
+fn get_block_from_peer(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<GetBlockFromPeer>() {
+        Ok(query) => match Node::list(&conn) {
+            Ok(nodes) => {
+                let n = nodes.iter().find(|n| n.id == query.node_id);
+
+                if n.is_none() {
+                    let err = JsonRpcError::invalid_params(format!(
+                        "Node not found: {:?}",
+                        query.node_id
+                    ));
+                    return Err(err);
+                }
+
+                let node = n.unwrap();
+
+                let auth = match node_auth(node) {
+                    Ok(auth) => auth,
+                    Err(e) => {
+                        error!("No credentials for node {}: {:?}", node.id, e);
+                        return Err(JsonRpcError::internal_error());
+                    }
+                };
+                let host = format!("http://{}:{}", node.rpc_host, node.rpc_port);
+                let timeouts = RpcTimeouts::from(node);
+                if let Ok(client) = node_rpc_client(node.id, &host, auth, timeouts) {
+                    let peer_id =
+                        serde_json::Value::Number(serde_json::Number::from(query.peer_id));
+                    let result = RpcApi::call::<serde_json::Value>(
+                        &client,
+                        "getblockfrompeer",
+                        &[serde_json::Value::String(query.hash), peer_id],
+                    );
+
+                    match result {
+                        Ok(result) => Ok(result),
+                        Err(e) => {
+                            let errmsg = format!("Call to get blocks failed. {:?}", e);
+                            Err(JsonRpcError::invalid_params(errmsg))
+                        }
+                    }
+                } else {
+                    let err =
+                        JsonRpcError::invalid_params("Failed to establish a node connection.");
+                    Err(err)
+                }
+            }
+            Err(e) => {
+                let err = JsonRpcError::invalid_params(format!("Could not get node list, {:?}", e));
+                Err(err)
+            }
+        },
+        Err(e) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", e));
+            Err(err)
+        }
+    }
+}
+
+fn get_block(conn: Conn, params: Params) -> Result<Value> {
+    match params.parse::<BlockQuery>() {
+        Ok(q) => match q {
+            BlockQuery::Height(h) => {
+                if let Ok(result) = Block::get_at_height(&conn, h) {
+                    let result: Vec<_> = result.into_iter().map(BlockResult::from_block).collect();
+
+                    match serde_json::to_value(result) {
+                        Ok(s) => Ok(s),
+                        Err(_) => Err(JsonRpcError::internal_error()),
+                    }
+                } else {
+                    Err(JsonRpcError::internal_error())
+                }
+            }
+            BlockQuery::Hash(h) => {
+                if let Ok(result) = Block::get(&conn, &h) {
+                    let result = BlockResult::from_block(result);
+
+                    match serde_json::to_value(vec![result]) {
+                        Ok(s) => Ok(s),
+                        Err(_) => Err(JsonRpcError::internal_error()),
+                    }
+                } else {
+                    Err(JsonRpcError::internal_error())
+                }
+            }
+        },
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// The blocks of the hashes in the order asked for, null for the ones that aren't stored.
+pub(crate) fn get_blocks(conn: Conn, params: Params) -> Result<Value> {
+    let hashes = match params.parse::<BlockHashes>() {
+        Ok(q) if q.hashes.len() <= BULK_MAX_ITEMS => q.hashes,
+        Ok(q) => {
+            let err = JsonRpcError::invalid_params(format!(
+                "At most {} blocks can be fetched at once, got {}",
+                BULK_MAX_ITEMS,
+                q.hashes.len()
+            ));
+            return Err(err);
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let found: HashMap<_, _> = match Block::get_many(&conn, &hashes) {
+        Ok(blocks) => blocks.into_iter().map(|b| (b.hash.clone(), b)).collect(),
+        Err(e) => {
+            error!("Could not fetch blocks {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+    let result: Vec<_> = hashes
+        .iter()
+        .map(|hash| found.get(hash).cloned().map(BlockResult::from_block))
+        .collect();
+
+    Ok(serde_json::to_value(result).expect("JSON serde failed"))
+}
+
+// Page cursors are the height and hash of the last block returned.
+fn block_cursor(block: &Block) -> String {
+    format!("{}:{}", block.height, block.hash)
+}
+
+fn parse_block_cursor(cursor: &str) -> Option<(i64, String)> {
+    let (height, hash) = cursor.split_once(':')?;
+    Some((height.parse().ok()?, hash.to_string()))
+}
+
+// page through blocks, highest first
+fn list_blocks(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<ListBlocksQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let after = match &q.cursor {
+        Some(cursor) => match parse_block_cursor(cursor) {
+            Some(after) => Some(after),
+            None => {
+                let err = JsonRpcError::invalid_params(format!("Invalid cursor, {}", cursor));
+                return Err(err);
+            }
+        },
+        None => None,
+    };
+    let limit = q
+        .limit
+        .unwrap_or(LIST_BLOCKS_LIMIT)
+        .clamp(1, LIST_BLOCKS_MAX_LIMIT);
+    let filter = BlockFilter {
+        min_height: q.min_height,
+        max_height: q.max_height,
+        pool_name: q.pool_name,
+        headers_only: q.headers_only,
+    };
+
+    match Block::list(&conn, &filter, after, limit) {
+        Ok(blocks) => {
+            // a short page is the last one
+            let next_cursor = match blocks.last() {
+                Some(last) if blocks.len() as i64 == limit => Some(block_cursor(last)),
+                _ => None,
+            };
+            let blocks: Vec<_> = blocks.into_iter().map(BlockResult::from_block).collect();
+
+            Ok(json!({
+                "blocks": blocks,
+                "next_cursor": next_cursor,
+            }))
+        }
+        Err(e) => {
+            error!("Could not list blocks {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// recent block templates, newest first
+fn get_block_templates(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<BlockTemplateQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match BlockTemplate::list(&conn, q.node_id, q.limit.unwrap_or(100)) {
+        Ok(templates) => {
+            let templates: Vec<_> = templates
+                .into_iter()
+                .map(|t| {
+                    json!({
+                        "node_id": t.node_id,
+                        "parent_block_hash": t.parent_block_hash,
+                        "height": t.height,
+                        "fee_total": decimal_json(&t.fee_total),
+                        "n_transactions": t.n_transactions,
+                        "lowest_fee_rate": t.lowest_fee_rate,
+                        "rules": t.rules,
+                        "ts": t.ts,
+                    })
+                })
+                .collect();
+            Ok(templates.into())
+        }
+        Err(e) => {
+            error!("Could not fetch block templates {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// fee rates of a node's template, and which of them the mined block left out
+fn get_fee_rates(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<FeeRateQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match FeeRate::list_by(&conn, q.parent_block_hash, q.node_id) {
+        Ok(mut rates) => {
+            rates.sort_by_key(|r| Reverse(r.fee_rate));
+            let rates: Vec<_> = rates
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "fee_rate": r.fee_rate,
+                        "omitted": r.omitted,
+                    })
+                })
+                .collect();
+            Ok(rates.into())
+        }
+        Err(e) => {
+            error!("Could not fetch fee rates {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// utxo set totals at a block, per mirror node that took them
+fn get_tx_outset(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<TxOutsetQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match TxOutset::list(&conn, &q.hash, q.node_id) {
+        Ok(outsets) => {
+            let outsets: Vec<_> = outsets
+                .into_iter()
+                .map(|o| {
+                    json!({
+                        "block_hash": o.block_hash,
+                        "node_id": o.node_id,
+                        "txouts": o.txouts,
+                        "total_amount": decimal_json(&o.total_amount),
+                        "inflated": o.inflated,
+                        "created_at": o.created_at,
+                        "updated_at": o.updated_at,
+                    })
+                })
+                .collect();
+            Ok(outsets.into())
+        }
+        Err(e) => {
+            error!("Could not fetch tx outsets {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// a signed statement of the utxo set totals at a block against the expected supply
+fn get_supply_attestation(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<AttestationQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match SupplyStatement::new(&conn, &q.hash).and_then(SupplyStatement::sign) {
+        Ok(attestation) => Ok(serde_json::to_value(attestation).expect("JSON serde failed")),
+        Err(e @ AttestationError::UnknownBlock(_)) | Err(e @ AttestationError::NoTotals(_)) => {
+            Err(JsonRpcError::invalid_params(e.to_string()))
+        }
+        Err(AttestationError::Database(e)) => {
+            error!("Could not fetch tx outsets {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+        Err(e) => {
+            error!("Could not sign supply attestation {}", e);
+            Err(JsonRpcError {
+                code: ErrorCode::InternalError,
+                message: e.to_string(),
+                data: None,
+            })
+        }
+    }
+}
+
+// the archived body of a block, see --archive-raw-blocks
+fn get_raw_block(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<RawBlockQuery>() {
+        Ok(q) => q,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let raw = match RawBlock::get(&conn, &q.hash) {
+        Ok(Some(raw)) => raw,
+        Ok(None) => {
+            let err = JsonRpcError::invalid_params(format!("Block {} is not archived", q.hash));
+            return Err(err);
+        }
+        Err(e) => {
+            error!("Could not fetch raw block {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+
+    match raw.block_hex() {
+        Ok(block_hex) => Ok(json!({
+            "block_hash": raw.block_hash,
+            "height": raw.height,
+            "node_id": raw.node_id,
+            "size": raw.size,
+            "compressed_size": raw.compressed.len(),
+            "hex": block_hex,
+            "created_at": raw.created_at,
+        })),
+        Err(e) => {
+            error!("Could not decompress block {} {:?}", raw.block_hash, e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// add a new node to forkscanner
+fn add_node(conn: Conn, cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
+    match params.parse::<NodeArgs>() {
+        Ok(args) => {
+            if !args.timeouts.is_valid() {
+                let err = JsonRpcError::invalid_params("Timeouts must be at least a second");
+                return Err(err);
+            }
+            // env: and file: references are only trusted from the config file, over
+            // RPC they would send a secret of this host to any rpc_host.
+            if is_reference(&args.pass) {
+                let err = JsonRpcError::invalid_params(
+                    "Secret references are only accepted for nodes in the config file",
+                );
+                return Err(err);
+            }
+            let timeouts = (&args.timeouts).into();
+            let checked = check_node(
+                &args.rpc_host,
+                args.rpc_port,
+                &args.user,
+                &args.pass,
+                timeouts,
+            );
+            if let Err(e) = checked {
+                let err = JsonRpcError::invalid_params(format!("Node can't be added, {}", e));
+                return Err(err);
+            }
+
+            let pass = match seal_password(&args.pass) {
+                Ok(pass) => pass,
+                Err(e) => {
+                    error!("Could not encrypt node password {:?}", e);
+                    return Err(JsonRpcError::internal_error());
+                }
+            };
+
+            let new_node = NewNode {
+                node: args.name,
+                rpc_host: args.rpc_host,
+                rpc_port: args.rpc_port,
+                mirror_rpc_port: args.mirror_rpc_port,
+                rpc_user: args.user,
+                rpc_pass: pass,
+                mirror_host: args.mirror_host,
+                archive: args.archive,
+                zmq_endpoint: args.zmq_endpoint,
+                flags: args.flags,
+            };
+            // the node is only added with its group and timeouts
+            let (group, node_timeouts) = (args.group, &args.timeouts);
+            let inserted = conn.transaction::<_, diesel::result::Error, _>(|| {
+                let n = Node::insert(&conn, new_node)?;
+                if group.is_some() {
+                    Node::set_group(&conn, n.id, group)?;
+                }
+                if !node_timeouts.is_empty() {
+                    Node::set_timeouts(&conn, n.id, node_timeouts)?;
+                }
+                Ok(n)
+            });
+            match inserted {
+                Ok(n) => {
+                    cmd.send(ScannerCommand::ReloadNodes)
+                        .expect("Command channel broke");
+                    Ok(n.id.into())
+                }
+                Err(e) => {
+                    error!("Could not add node {:?}", e);
+                    Err(JsonRpcError::internal_error())
+                }
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// remove node from database
+fn remove_node(conn: Conn, cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
+    match params.parse::<NodeId>() {
+        Ok(id) => {
+            if Node::remove(&conn, id.id).is_ok() {
+                cmd.send(ScannerCommand::ReloadNodes)
+                    .expect("Command channel broke");
+                Ok("OK".into())
+            } else {
+                Err(JsonRpcError::internal_error())
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// change which scanner work a node opts out of
+fn set_node_flags(conn: Conn, cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
+    let args = match params.parse::<NodeFlagArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let flags = &args.flags;
+    if flags.fetch_templates.is_none()
+        && flags.fetch_peers.is_none()
+        && flags.participate_in_consensus.is_none()
+        && flags.allow_mirror_ops.is_none()
+    {
+        let err = JsonRpcError::invalid_params("No flags given");
+        return Err(err);
+    }
+
+    match Node::set_flags(&conn, args.id, flags) {
+        Ok(_) => {
+            cmd.send(ScannerCommand::ReloadNodes)
+                .expect("Command channel broke");
+            Ok("OK".into())
+        }
+        Err(diesel::result::Error::NotFound) => {
+            Err(JsonRpcError::invalid_params(format!("No node {}", args.id)))
+        }
+        Err(e) => {
+            error!("Updating node flags failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn set_node_group(conn: Conn, cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
+    let args = match params.parse::<NodeGroupArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match Node::set_group(&conn, args.id, args.group) {
+        Ok(_) => {
+            cmd.send(ScannerCommand::ReloadNodes)
+                .expect("Command channel broke");
+            Ok("OK".into())
+        }
+        Err(diesel::result::Error::NotFound) => {
+            Err(JsonRpcError::invalid_params(format!("No node {}", args.id)))
+        }
+        Err(e) => {
+            error!("Updating node group failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// schedule a node's maintenance, starting now unless `starts_at` is given
+fn add_maintenance_window(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<MaintenanceArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    let starts_at = args.starts_at.unwrap_or_else(Utc::now);
+    if args.ends_at <= starts_at || args.ends_at <= Utc::now() {
+        let err = JsonRpcError::invalid_params("ends_at has to be after starts_at and now");
+        return Err(err);
+    }
+
+    let node_id = args.node_id;
+    let window = Node::get(&conn, node_id).and_then(|_| {
+        MaintenanceWindow::insert(&conn, node_id, starts_at, args.ends_at, args.reason)
+    });
+    match window {
+        Ok(window) => Ok(serde_json::to_value(window).expect("JSON serde failed")),
+        Err(diesel::result::Error::NotFound) => {
+            Err(JsonRpcError::invalid_params(format!("No node {}", node_id)))
+        }
+        Err(e) => {
+            error!("Adding maintenance window failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn remove_maintenance_window(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<MaintenanceRemoveArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match MaintenanceWindow::remove(&conn, args.id) {
+        Ok(0) => Err(JsonRpcError::invalid_params(format!(
+            "No maintenance window {}",
+            args.id
+        ))),
+        Ok(_) => Ok("OK".into()),
+        Err(e) => {
+            error!("Removing maintenance window failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// set the secret a subscriber's notifications are signed with, a random one unless
+// given. The secret is only shown here.
+fn set_subscriber_secret(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<SubscriberSecretArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    if args.secret.as_deref().is_some_and(is_reference) {
+        let err = JsonRpcError::invalid_params("Secret references can't be set over RPC");
+        return Err(err);
+    }
+
+    let secret = args
+        .secret
+        .unwrap_or_else(|| rand::rngs::OsRng.gen::<[u8; 32]>().encode_hex());
+    let sealed = match seal_password(&secret) {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            error!("Could not seal subscriber secret {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+
+    match SubscriberSecret::set(&conn, &args.client_id, &sealed) {
+        Ok(_) => Ok(json!({ "client_id": args.client_id, "secret": secret })),
+        Err(e) => {
+            error!("Setting subscriber secret failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn remove_subscriber_secret(conn: Conn, params: Params) -> Result<Value> {
+    let client_id = match params.parse::<SubscriberSecretRemoveArgs>() {
+        Ok(args) => args.client_id,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match SubscriberSecret::remove(&conn, &client_id) {
+        Ok(0) => {
+            let err = JsonRpcError::invalid_params(format!("No secret for {}", client_id));
+            Err(err)
+        }
+        Ok(_) => Ok("OK".into()),
+        Err(e) => {
+            error!("Removing subscriber secret failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// the key is only returned here, only its hash is stored
+fn add_api_key(conn: Conn, params: Params) -> Result<Value> {
+    let args = match params.parse::<ApiKeyArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    if args.name.is_empty() {
+        let err = JsonRpcError::invalid_params("name can't be empty");
+        return Err(err);
+    }
+
+    let role = match args.role.parse::<Role>() {
+        Ok(role) => role,
+        Err(e) => return Err(JsonRpcError::invalid_params(e)),
+    };
+
+    match auth::create_api_key(&conn, &args.name, role) {
+        Ok(key) => Ok(json!({ "name": args.name, "role": role.to_string(), "key": key })),
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            let err = JsonRpcError::invalid_params(format!("Key {} exists", args.name));
+            Err(err)
+        }
+        Err(e) => {
+            error!("Creating api key failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn revoke_api_key(conn: Conn, params: Params) -> Result<Value> {
+    let name = match params.parse::<ApiKeyRevokeArgs>() {
+        Ok(args) => args.name,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match ApiKey::revoke(&conn, &name) {
+        Ok(0) => {
+            let err = JsonRpcError::invalid_params(format!("No active key {}", name));
+            Err(err)
+        }
+        Ok(_) => Ok("OK".into()),
+        Err(e) => {
+            error!("Revoking api key failed {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn get_api_keys(conn: Conn) -> Result<Value> {
+    match ApiKey::list(&conn) {
+        Ok(keys) => Ok(serde_json::to_value(keys).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch api keys {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+// prune on the next scanner run, with the configured retention or the given one
+fn prune_now(cmd: Sender<ScannerCommand>, params: Params) -> Result<Value> {
+    let args = match params.parse::<PruneArgs>() {
+        Ok(args) => args,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    if args.days.unwrap_or(0) < 0 || args.blocks.unwrap_or(0) < 0 {
+        let err = JsonRpcError::invalid_params("days and blocks can't be negative");
+        return Err(err);
+    }
+
+    cmd.send(ScannerCommand::Prune {
+        days: args.days,
+        blocks: args.blocks,
+    })
+    .expect("Command channel broke");
+    Ok("OK".into())
+}
+
+fn get_maintenance_windows(conn: Conn) -> Result<Value> {
+    match MaintenanceWindow::list(&conn) {
+        Ok(windows) => Ok(serde_json::to_value(windows).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch maintenance windows {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn get_group_consensus(conn: Conn) -> Result<Value> {
+    match GroupConsensus::list(&conn) {
+        Ok(groups) => Ok(serde_json::to_value(groups).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch group consensus {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn get_subscriber_lag(conn: Conn) -> Result<Value> {
+    match SubscriberLag::list(&conn) {
+        Ok(lags) => Ok(serde_json::to_value(lags).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch subscriber lag {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn check_integrity(conn: Conn) -> Result<Value> {
+    match IntegrityCheck::run(&conn) {
+        Ok(checks) => Ok(serde_json::to_value(checks).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not check integrity {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn get_bootstrap_progress(conn: Conn) -> Result<Value> {
+    match BootstrapProgress::get(&conn) {
+        Ok(progress) => Ok(serde_json::to_value(progress).expect("JSON serde failed")),
+        Err(e) => {
+            error!("Could not fetch bootstrap progress {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn get_network(conn: Conn) -> Result<Value> {
+    let height = match Block::max_height(&conn) {
+        Ok(height) => height,
+        Err(e) => {
+            error!("Could not fetch the highest block {:?}", e);
+            return Err(JsonRpcError::internal_error());
+        }
+    };
+
+    let next_halving = height.map(|h| network().next_halving(h));
+    let status = NetworkStatus {
+        params: network().params(),
+        height,
+        next_halving,
+        blocks_to_halving: height.zip(next_halving).map(|(h, n)| n - h),
+    };
+    Ok(serde_json::to_value(status).expect("JSON serde failed"))
+}
+
+fn get_error_counts(counts: &Mutex<ErrorCounts>) -> Result<Value> {
+    let counts = counts.lock().expect("Lock poisoned");
+    serde_json::to_value(&*counts).map_err(|_| JsonRpcError::internal_error())
+}
+
+// everything a dashboard shows about one node
+pub(crate) fn get_node_stats(conn: Conn, params: Params) -> Result<Value> {
+    let id = match params.parse::<NodeId>() {
+        Ok(NodeId { id }) => id,
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match Node::get(&conn, id).and_then(|node| node_stats_json(&conn, node)) {
+        Ok(stats) => Ok(stats),
+        Err(diesel::result::Error::NotFound) => {
+            Err(JsonRpcError::invalid_params(format!("No node {}", id)))
+        }
+        Err(e) => {
+            error!("Could not fetch node stats {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+fn node_connectivity(
+    conn: &PgConnection,
+    node_id: Option<i64>,
+) -> diesel::QueryResult<Vec<NodeConnectivity>> {
+    let nodes = match node_id {
+        Some(id) => vec![Node::get(conn, id)?],
+        None => Node::list(conn)?,
+    };
+    let statuses = NodeStatus::list(conn)?;
+    let lags = Lags::list(conn)?;
+
+    let connectivity = nodes.into_iter().map(|node| {
+        let status = statuses.iter().find(|s| s.node_id == node.id);
+        let last_success_at = status.and_then(|s| s.last_success_at);
+        let last_error_at = status.and_then(|s| s.last_error_at);
+
+        let mirror = node.mirror_rpc_port.map(|port| MirrorStatus {
+            host: node
+                .mirror_host
+                .clone()
+                .unwrap_or_else(|| node.rpc_host.clone()),
+            port,
+            reachable: node.mirror_unreachable_since.is_none(),
+            last_polled: node.mirror_last_polled,
+        });
+        let lag = lags.iter().find(|l| l.node_id == node.id).map(|l| NodeLag {
+            height_deficit: l.height_deficit,
+            work_deficit_blocks: l.work_deficit_blocks,
+            since: l.created_at,
+        });
+
+        NodeConnectivity {
+            node_id: node.id,
+            name: node.node,
+            last_success_at,
+            last_error: status.and_then(|s| s.last_error.clone()),
+            last_error_at,
+            failing: last_success_at.is_none() || last_error_at > last_success_at,
+            best_block_hash: status.and_then(|s| s.best_block_hash.clone()),
+            height: status.and_then(|s| s.best_height),
+            header_height: status.and_then(|s| s.header_height),
+            initial_block_download: status.and_then(|s| s.initial_block_download),
+            rpc_retries: status.map_or(0, |s| s.rpc_retries),
+            rpc_failures: status.map_or(0, |s| s.rpc_failures),
+            mirror,
+            lag,
+        }
+    });
+    Ok(connectivity.collect())
+}
+
+// what the scanner last heard from each node, for finding one that stopped updating
+fn get_node_status(conn: Conn, params: Params) -> Result<Value> {
+    let q = match params.parse::<Option<NodeStatusQuery>>() {
+        Ok(q) => q.unwrap_or_default(),
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            return Err(err);
+        }
+    };
+
+    match node_connectivity(&conn, q.node_id) {
+        Ok(nodes) => Ok(serde_json::to_value(nodes).expect("JSON serde failed")),
+        Err(diesel::result::Error::NotFound) => Err(JsonRpcError::invalid_params(format!(
+            "No node {}",
+            q.node_id.unwrap_or_default()
+        ))),
+        Err(e) => {
+            error!("Could not fetch node status {:?}", e);
+            Err(JsonRpcError::internal_error())
+        }
+    }
+}
+
+pub(crate) fn node_stats_json(conn: &PgConnection, node: Node) -> diesel::QueryResult<Value> {
+    let tip = Chaintip::get_active(conn, node.id).optional()?.map(|tip| {
+        json!({
+            "hash": tip.block,
+            "height": tip.height,
+            "updated_at": tip.updated_at,
+        })
+    });
+
+    let lag = Lags::list(conn)?
+        .into_iter()
+        .find(|lag| lag.node_id == node.id)
+        .map(|lag| {
+            json!({
+                "height_deficit": lag.height_deficit,
+                "work_deficit_blocks": lag.work_deficit_blocks,
+                "since": lag.created_at,
+            })
+        });
+
+    let status = NodeStatus::get(conn, node.id)?;
+    let peers = Peer::list(conn, node.id)?.len();
+
+    let recent_blocks: Vec<_> = Block::first_seen(conn, node.id, NODE_STATS_BLOCKS)?
+        .into_iter()
+        .map(|b| {
+            json!({
+                "hash": b.hash,
+                "height": b.height,
+                "pool_name": b.pool_name,
+                "headers_only": b.headers_only,
+            })
+        })
+        .collect();
+
+    let template = BlockTemplate::list(conn, Some(node.id), 1)?
+        .into_iter()
+        .next()
+        .map(|t| {
+            json!({
+                "parent_block_hash": t.parent_block_hash,
+                "height": t.height,
+                "ts": t.ts,
+                "age_secs": Utc::now().signed_duration_since(t.ts).num_seconds(),
+            })
+        });
+
+    let mirror = node.mirror_rpc_port.map(|port| {
+        json!({
+            "host": node.mirror_host.as_ref().unwrap_or(&node.rpc_host),
+            "port": port,
+            "reachable": node.mirror_unreachable_since.is_none(),
+            "last_polled": node.mirror_last_polled,
+        })
+    });
+
+    Ok(json!({
+        "node_id": node.id,
+        "name": node.node,
+        "archive": node.archive,
+        "reachable": node.unreachable_since.is_none(),
+        "unreachable_since": node.unreachable_since,
+        "last_polled": node.last_polled,
+        "initial_block_download": node.initial_block_download,
+        "version": status.as_ref().and_then(|s| s.version),
+        "subversion": status.as_ref().and_then(|s| s.subversion.clone()),
+        "last_error": status.as_ref().and_then(|s| s.last_error.clone()),
+        "last_error_at": status.as_ref().and_then(|s| s.last_error_at),
+        "tip": tip,
+        "lag": lag,
+        "peers": peers,
+        "recent_blocks": recent_blocks,
+        "template": template,
+        "mirror": mirror,
+    }))
+}
+
+// fetch currently active chaintips
+pub(crate) fn get_tips(params: Params, conn: Conn) -> Result<Value> {
+    match params.parse::<TipArgs>() {
+        Ok(t) => {
+            let chaintips = if t.active_only {
+                Chaintip::list_active(&conn)
+            } else {
+                Chaintip::list(&conn)
+            };
+
+            if let Err(e) = chaintips {
+                let err =
+                    JsonRpcError::invalid_params(format!("Couldn't fetch chaintips, {:?}", e));
+                return Err(err);
+            }
+
+            let chaintips = chaintips.unwrap();
+            let tips = if t.with_branch {
+                branch_tips(&conn, chaintips).map(serde_json::to_value)
+            } else {
+                Ok(serde_json::to_value(chaintips))
+            };
+
+            match tips {
+                Ok(Ok(t)) => Ok(t),
+                Ok(Err(_)) => Err(JsonRpcError::internal_error()),
+                Err(e) => {
+                    error!("Could not find tip branches {:?}", e);
+                    Err(JsonRpcError::internal_error())
+                }
+            }
+        }
+        Err(args) => {
+            let err = JsonRpcError::invalid_params(format!("Invalid parameters, {:?}", args));
+            Err(err)
+        }
+    }
+}
+
+// the branch of each tip against the consensus tip, null for all without one
+fn branch_tips(conn: &PgConnection, chaintips: Vec<Chaintip>) -> QueryResult<Vec<BranchTip>> {
+    let consensus = consensus_tip(conn)?;
+
+    chaintips
+        .into_iter()
+        .map(|tip| {
+            let branch = match &consensus {
+                Some(consensus) => TipBranch::new(conn, &tip, consensus)?,
+                None => None,
+            };
+            Ok(BranchTip { tip, branch })
+        })
+        .collect()
+}
+
+// Hand a subscription every scanner message until it is unsubscribed or the scanner
+// stops, `send` ignores the messages it doesn't notify of.
+fn subscription_loop<F>(
+    name: &str,
+    exit: &AtomicBool,
+    receiver: &Receiver<ScannerMessage>,
+    mut send: F,
+) where
+    F: FnMut(ScannerMessage) -> std::result::Result<(), WsError>,
+{
+    while !exit.load(Ordering::SeqCst) {
+        match receiver.recv_timeout(SUBSCRIPTION_POLL) {
+            Ok(message) => {
+                if let Err(e) = send(message) {
+                    error!("Error sending {} to client {:?}", name, e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                debug!("Scanner stopped, ending {} subscription", name);
+                break;
+            }
+        }
+    }
+}
+
+// Run `subscription_loop` on a thread of its own.
+fn spawn_subscription<F>(
+    name: &'static str,
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    send: F,
+) where
+    F: FnMut(ScannerMessage) -> std::result::Result<(), WsError> + Send + 'static,
+{
+    thread::spawn(move || subscription_loop(name, &exit, &receiver, send));
+}
+
+// validation endpoint subscription handler
+fn handle_validation_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    pool: ManagedPool,
+    window: i64,
+    sink: Sink,
+) {
+    info!("New subscription");
+    let send_update = move |pool: &ManagedPool, sink: &Sink| -> std::result::Result<(), WsError> {
+        let conn = pool.get()?;
+        match validation_checks(conn, window) {
+            Ok(resp) => Ok(sink.notify(Params::Array(vec![resp]))?),
+            Err(_) => Ok(sink.notify(Params::Array(vec![
+                "Failed to update validation checks".into()
+            ]))?),
+        }
+    };
+
+    thread::spawn(move || {
+        if let Err(e) = send_update(&pool, &sink) {
+            error!(
+                "Error sending validation checks to initialize client {:?}",
+                e
+            );
+        }
+
+        subscription_loop(
+            "validation checks",
+            &exit,
+            &receiver,
+            |message| match message {
+                ScannerMessage::StaleCandidateUpdate => send_update(&pool, &sink),
+                _ => Ok(()),
+            },
+        );
+    });
+}
+
+// stale candidate subscription handler, sends the recent candidates and then the
+// ones that changed after each scan
+fn handle_stale_candidates_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    pool: ManagedPool,
+    sink: Sink,
+) {
+    info!("New subscription");
+    let mut sent = HashMap::new();
+    let mut send_update =
+        move |pool: &ManagedPool, sink: &Sink| -> std::result::Result<(), WsError> {
+            let conn = pool.get()?;
+            let candidates = match StaleCandidate::list(&conn, None, STALE_CANDIDATE_WINDOW) {
+                Ok(candidates) => candidates,
+                Err(e) => {
+                    error!("Could not fetch stale candidates {:?}", e);
+                    return Ok(());
+                }
+            };
+
+            let mut changed = Vec::new();
+            for candidate in candidates {
+                let height = candidate.height;
+                match stale_candidate_json(&conn, candidate) {
+                    Ok(value) => {
+                        if sent.get(&height) != Some(&value) {
+                            sent.insert(height, value.clone());
+                            changed.push(value);
+                        }
+                    }
+                    Err(e) => error!("Could not fetch stale candidate {} {:?}", height, e),
+                }
+            }
+
+            if changed.is_empty() {
+                return Ok(());
+            }
+            Ok(sink.notify(Params::Array(changed))?)
+        };
+
+    thread::spawn(move || {
+        if let Err(e) = send_update(&pool, &sink) {
+            error!(
+                "Error sending stale candidates to initialize client {:?}",
+                e
+            );
+        }
+
+        subscription_loop(
+            "stale candidates",
+            &exit,
+            &receiver,
+            |message| match message {
+                ScannerMessage::StaleCandidateUpdate => send_update(&pool, &sink),
+                _ => Ok(()),
+            },
+        );
+    });
+}
+
+// Notify of watched address activity
+fn handle_watched_addresses(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    watch: Vec<String>,
+    watch_until: DateTime<Utc>,
+    pool: ManagedPool,
+    sink: Sink,
+) {
+    let conn = pool.get().expect("Connection pool failure");
+
+    let watches: Vec<_> = watch.into_iter().map(|w| (w, watch_until)).collect();
+    Watched::insert(&conn, watches).expect("Could not insert watchlist!");
+
+    info!("New address activity");
+    let send_update =
+        move |transactions: Vec<Transaction>, sink: &Sink| -> std::result::Result<(), WsError> {
+            let resp = transactions
+                .into_iter()
+                .map(|tx| serde_json::to_value(tx).expect("Could not serialize transaction"))
+                .collect();
+            Ok(sink.notify(Params::Array(resp))?)
+        };
+
+    spawn_subscription(
+        "watched activity",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::WatchedAddress(transactions) => send_update(transactions, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+fn handle_watched_address_mempool_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |payments: Vec<MempoolPayment>, sink: &AckedSink| {
+        let resp = payments
+            .into_iter()
+            .map(|payment| serde_json::to_value(payment).expect("Could not serialize payment"))
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "mempool payments",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::WatchedAddressMempool(payments) => send_update(payments, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// spends of the outpoints a subscription watches
+fn handle_outpoint_spends_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    watch: Vec<Outpoint>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |spends: Vec<OutpointSpend>, sink: &AckedSink| {
+        let resp: Vec<_> = spends
+            .into_iter()
+            .filter(|s| watch.iter().any(|o| o.txid == s.txid && o.vout == s.vout))
+            .map(|spend| serde_json::to_value(spend).expect("Could not serialize outpoint spend"))
+            .collect();
+        if resp.is_empty() {
+            return Ok(());
+        }
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "outpoint spends",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::OutpointSpends(spends) => send_update(spends, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// confirmations of a watched transaction, sent when it is first seen, when its status or
+// block changes and when it reaches the target
+fn handle_watch_transaction_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    pool: ManagedPool,
+    watch: WatchTransaction,
+    sink: Sink,
+) {
+    info!("New subscription");
+    let mut last: Option<TxConfirmation> = None;
+    let mut send_update =
+        move |pool: &ManagedPool, sink: &Sink| -> std::result::Result<(), WsError> {
+            let conn = pool.get()?;
+            let current = tx_confirmation(&conn, &watch.txid, watch.confirmations)?;
+
+            let changed = match &last {
+                Some(last) => {
+                    last.status != current.status
+                        || last.block_hash != current.block_hash
+                        || (last.confirmations < current.target
+                            && current.confirmations >= current.target)
+                }
+                None => true,
+            };
+            if changed {
+                sink.notify(Params::Array(vec![serde_json::to_value(&current)?]))?;
+            }
+            last = Some(current);
+            Ok(())
+        };
+
+    thread::spawn(move || {
+        if let Err(e) = send_update(&pool, &sink) {
+            error!("Error sending transaction confirmations to client {:?}", e);
+        }
+
+        let name = "transaction confirmations";
+        subscription_loop(name, &exit, &receiver, |message| match message {
+            ScannerMessage::CycleSummary(_) => send_update(&pool, &sink),
+            _ => Ok(()),
+        });
+    });
+}
+
+// node group split subscription handler
+fn handle_group_splits_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |splits: Vec<GroupSplit>, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let resp = splits
+                .into_iter()
+                .map(|split| {
+                    let mut value =
+                        serde_json::to_value(split).expect("Could not serialize group split");
+                    value["alert"] = "group_split".into();
+                    value
+                })
+                .collect();
+            sink.notify(Params::Array(resp))
+        };
+
+    spawn_subscription(
+        "group splits",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::GroupSplits(splits) => send_update(splits, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// external observation disagreement subscription handler
+fn handle_external_disagreements_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |disagreements: Vec<ExternalDisagreement>, sink: &AckedSink| {
+        let resp = disagreements
+            .into_iter()
+            .map(|disagreement| {
+                let mut value = serde_json::to_value(disagreement)
+                    .expect("Could not serialize external disagreement");
+                value["alert"] = "external_disagreement".into();
+                value
+            })
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "external disagreements",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::ExternalDisagreements(disagreements) => {
+                send_update(disagreements, &sink)
+            }
+            _ => Ok(()),
+        },
+    );
+}
+
+// Notify of lagging nodes
+fn handle_lagging_nodes_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: Sink,
+) {
+    info!("New subscription");
+    let send_update = move |lags: Vec<Lags>, sink: &Sink| -> std::result::Result<(), WsError> {
+        let resp = lags
+            .into_iter()
+            .map(|conf| serde_json::to_value(conf).expect("Could not serialize lagging node"))
+            .collect();
+        Ok(sink.notify(Params::Array(resp))?)
+    };
+
+    spawn_subscription(
+        "lagging nodes",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::LaggingNodes(lags) => send_update(lags, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// persistent fork subscription handler
+fn handle_persistent_forks_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |forks: Vec<ForkAnalysis>, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let resp = forks
+                .into_iter()
+                .map(|fork| {
+                    let mut value =
+                        serde_json::to_value(fork).expect("Could not serialize persistent fork");
+                    value["alert"] = "persistent_fork".into();
+                    value
+                })
+                .collect();
+            sink.notify(Params::Array(resp))
+        };
+
+    spawn_subscription(
+        "persistent forks",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::PersistentForks(forks) => send_update(forks, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// wallet conflict subscription handler
+fn handle_wallet_conflicts_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |conflicts: Vec<WalletConflictAlert>,
+                            sink: &AckedSink|
+          -> std::result::Result<(), WsError> {
+        let resp = conflicts
+            .into_iter()
+            .map(|conflict| {
+                let mut value =
+                    serde_json::to_value(conflict).expect("Could not serialize wallet conflict");
+                value["alert"] = "wallet_conflict".into();
+                value
+            })
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "wallet conflicts",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::WalletConflicts(conflicts) => send_update(conflicts, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// stuck chain subscription handler
+fn handle_stuck_chain_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |stuck: StuckChain, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let mut value = serde_json::to_value(stuck).expect("Could not serialize stuck chain");
+            value["alert"] = "stuck_chain".into();
+            sink.notify(Params::Array(vec![value]))
+        };
+
+    spawn_subscription(
+        "stuck chain",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::StuckChain(stuck) => send_update(stuck, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+fn inflated_block_json(ib: &InflatedBlock) -> Value {
+    let extra = &ib.actual_inflation - &ib.max_inflation;
+    json!({
+        "block_hash": ib.block_hash,
+        "node_id": ib.node_id,
+        "max_inflation": decimal_json(&ib.max_inflation),
+        "actual_inflation": decimal_json(&ib.actual_inflation),
+        "extra_inflation": decimal_json(&extra),
+        "created_at": ib.created_at,
+    })
+}
+
+// inflated block subscription handler
+fn handle_inflation_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |ib: InflatedBlock, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let mut value = inflated_block_json(&ib);
+            value["alert"] = "inflation".into();
+            sink.notify(Params::Array(vec![value]))
+        };
+
+    spawn_subscription(
+        "inflated block",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::InflatedBlock(ib) => send_update(ib, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// double spend subscription handler
+fn handle_double_spends_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |found: DoubleSpends, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let mut value = serde_json::to_value(found).expect("Could not serialize double spends");
+            value["alert"] = "double_spend".into();
+            sink.notify(Params::Array(vec![value]))
+        };
+
+    spawn_subscription(
+        "double spends",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::DoubleSpends(found) => send_update(found, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// peer change subscription handler
+fn handle_peer_changes_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |changes: PeerChanges,
+                            sink: &AckedSink|
+          -> std::result::Result<(), WsError> {
+        let mut value = serde_json::to_value(changes).expect("Could not serialize peer changes");
+        value["alert"] = "peer_change".into();
+        sink.notify(Params::Array(vec![value]))
+    };
+
+    spawn_subscription(
+        "peer changes",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::PeerChanges(changes) => send_update(changes, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// cycle summary subscription handler
+fn handle_cycle_summary_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: Sink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |summary: CycleSummary, sink: &Sink| -> std::result::Result<(), WsError> {
+            let value = serde_json::to_value(summary).expect("Could not serialize cycle summary");
+            Ok(sink.notify(Params::Array(vec![value]))?)
+        };
+
+    spawn_subscription(
+        "cycle summary",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::CycleSummary(summary) => send_update(summary, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// new block header subscription handler
+fn handle_headers_subscribe(exit: Arc<AtomicBool>, receiver: Receiver<ScannerMessage>, sink: Sink) {
+    info!("New subscription");
+    let send_update = move |header: NewHeader, sink: &Sink| -> std::result::Result<(), WsError> {
+        let value = serde_json::to_value(header).expect("Could not serialize header");
+        Ok(sink.notify(Params::Array(vec![value]))?)
+    };
+
+    spawn_subscription("header", exit, receiver, move |message| match message {
+        ScannerMessage::NewHeader(header) => send_update(header, &sink),
+        _ => Ok(()),
+    });
+}
+
+// scanner error subscription handler
+fn handle_errors_subscribe(exit: Arc<AtomicBool>, receiver: Receiver<ScannerMessage>, sink: Sink) {
+    info!("New subscription");
+    let send_update = move |event: ErrorEvent, sink: &Sink| -> std::result::Result<(), WsError> {
+        let value = serde_json::to_value(event).expect("Could not serialize error event");
+        Ok(sink.notify(Params::Array(vec![value]))?)
+    };
+
+    spawn_subscription(
+        "scanner error",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::Error(event) => send_update(event, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// peer partition subscription handler
+fn handle_peer_partitions_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |pairs: Vec<PeerOverlap>, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let resp = pairs
+                .into_iter()
+                .map(|pair| {
+                    let mut value =
+                        serde_json::to_value(pair).expect("Could not serialize peer overlap");
+                    value["alert"] = "peer_partition".into();
+                    value
+                })
+                .collect();
+            sink.notify(Params::Array(resp))
+        };
+
+    spawn_subscription(
+        "peer partitions",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::PeerPartitions(pairs) => send_update(pairs, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// mempool divergence subscription handler
+fn handle_mempool_divergence_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |found: Vec<MempoolDivergence>, sink: &AckedSink| -> std::result::Result<(), _> {
+            let resp = found
+                .into_iter()
+                .map(|node| {
+                    let mut value =
+                        serde_json::to_value(node).expect("Could not serialize mempool divergence");
+                    value["alert"] = "mempool_divergence".into();
+                    value
+                })
+                .collect();
+            sink.notify(Params::Array(resp))
+        };
+
+    spawn_subscription(
+        "mempool divergence",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::MempoolDivergence(nodes) => send_update(nodes, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// censorship candidate subscription handler
+fn handle_censorship_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |found: Vec<CensorshipCandidate>, sink: &AckedSink| {
+        let resp = found
+            .into_iter()
+            .map(|candidate| {
+                let mut value = serde_json::to_value(candidate)
+                    .expect("Could not serialize censorship candidate");
+                value["alert"] = "censorship_candidate".into();
+                value
+            })
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "censorship candidates",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::Censorship(candidates) => send_update(candidates, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// reorg subscription handler
+fn handle_reorgs_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |reorg: Reorg, sink: &AckedSink| -> std::result::Result<(), WsError> {
+        let mut value = serde_json::to_value(reorg).expect("Could not serialize reorg");
+        value["alert"] = "reorg".into();
+        sink.notify(Params::Array(vec![value]))
+    };
+
+    spawn_subscription("reorg", exit, receiver, move |message| match message {
+        ScannerMessage::Reorg(reorg) => send_update(reorg, &sink),
+        _ => Ok(()),
+    });
+}
+
+// deep reorg subscription handler
+fn handle_deep_reorgs_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |deep: DeepReorg, sink: &AckedSink| -> std::result::Result<(), WsError> {
+            let mut value = serde_json::to_value(deep).expect("Could not serialize deep reorg");
+            value["alert"] = "deep_reorg".into();
+            sink.notify(Params::Array(vec![value]))
+        };
+
+    spawn_subscription("deep reorg", exit, receiver, move |message| match message {
+        ScannerMessage::DeepReorg(deep) => send_update(deep, &sink),
+        _ => Ok(()),
+    });
+}
+
+// pool payout anomaly subscription handler
+fn handle_payout_anomalies_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |found: Vec<PayoutAnomaly>, sink: &AckedSink| {
+        let resp = found
+            .into_iter()
+            .map(|anomaly| {
+                let mut value =
+                    serde_json::to_value(anomaly).expect("Could not serialize payout anomaly");
+                value["alert"] = "payout_anomaly".into();
+                value
+            })
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "payout anomalies",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::PayoutAnomalies(anomalies) => send_update(anomalies, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+// anomaly detector subscription handler
+fn handle_anomalies_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |found: Vec<Anomaly>, sink: &AckedSink| {
+        let resp = found
+            .into_iter()
+            .map(|anomaly| {
+                let mut value = serde_json::to_value(anomaly).expect("Could not serialize anomaly");
+                value["alert"] = "anomaly".into();
+                value
+            })
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription("anomalies", exit, receiver, move |message| match message {
+        ScannerMessage::Anomalies(anomalies) => send_update(anomalies, &sink),
+        _ => Ok(()),
+    });
+}
+
+// chaintip status transition subscription handler
+fn handle_tip_transitions_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    filter: TipTransitionFilter,
+    sink: Sink,
+) {
+    info!("New subscription");
+    let send_update =
+        move |transitions: Vec<TipTransition>, sink: &Sink| -> std::result::Result<(), WsError> {
+            let resp = transitions
+                .into_iter()
+                .map(|t| serde_json::to_value(t).expect("Could not serialize tip transition"))
+                .collect();
+            Ok(sink.notify(Params::Array(resp))?)
+        };
+
+    spawn_subscription(
+        "tip transitions",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::TipStatusChanged(transitions) => {
+                let transitions: Vec<_> = transitions
+                    .into_iter()
+                    .filter(|t| filter.matches(t))
+                    .collect();
+
+                if transitions.is_empty() {
+                    return Ok(());
+                }
+                send_update(transitions, &sink)
+            }
+            _ => Ok(()),
+        },
+    );
+}
+
+// invalid block endpoint subscription handler
+fn handle_invalid_block_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    sink: AckedSink,
+) {
+    info!("New subscription");
+    let send_update = move |blocks: Vec<ConflictingBlock>,
+                            sink: &AckedSink|
+          -> std::result::Result<(), WsError> {
+        let resp = blocks
+            .into_iter()
+            .map(|conf| serde_json::to_value(conf).expect("Could not serialize conflicting block"))
+            .collect();
+        sink.notify(Params::Array(resp))
+    };
+
+    spawn_subscription(
+        "block conflicts",
+        exit,
+        receiver,
+        move |message| match message {
+            ScannerMessage::NewBlockConflicts(conflicts) => send_update(conflicts, &sink),
+            _ => Ok(()),
+        },
+    );
+}
+
+fn handle_subscribe_forks(
+    exit: Arc<AtomicBool>,
+    pool: ManagedPool,
+    receiver: Receiver<ScannerMessage>,
+    _: Params,
+    sink: Sink,
+) {
+    info!("New subscription");
+    fn send_update(tips: Vec<Chaintip>, sink: &Sink) -> std::result::Result<(), WsError> {
+        let tips: Vec<_> = tips
+            .into_iter()
+            .map(|tip| serde_json::to_value(tip).expect("JSON serde failed"))
+            .collect();
+
+        Ok(sink.notify(Params::Array(tips))?)
+    }
+
+    thread::spawn(move || {
+        let conn = pool.get().expect("Could not get pooled connection!");
+        match Chaintip::list_active(&conn) {
+            Ok(tips) => {
+                if let Err(e) = send_update(tips, &sink) {
+                    error!("Error sending chaintips to initialize client {:?}", e);
+                }
+            }
+            Err(e) => {
+                error!("Database error {:?}", e);
+            }
+        }
+
+        subscription_loop("chaintips", &exit, &receiver, |message| match message {
+            ScannerMessage::NewChaintip => {
+                let conn = pool.get()?;
+                let tips = match Chaintip::list_active(&conn) {
+                    Ok(tips) => tips,
+                    Err(e) => {
+                        error!("Database error {:?}", e);
+                        return Ok(());
+                    }
+                };
+                send_update(tips, &sink)
+            }
+            _ => Ok(()),
+        });
+    });
+}
+
+// handles subscriptions for chaintip updates
+fn handle_subscribe(
+    exit: Arc<AtomicBool>,
+    receiver: Receiver<ScannerMessage>,
+    tips: Arc<RwLock<Vec<Chaintip>>>,
+    _: Params,
+    sink: Sink,
+) {
+    info!("New subscription");
+    fn send_update(
+        tips: &Arc<RwLock<Vec<Chaintip>>>,
+        sink: &Sink,
+    ) -> std::result::Result<(), WsError> {
+        let values = tips.read().expect("Lock poisoned").clone();
+        let tips: Vec<_> = values
+            .into_iter()
+            .map(|tip| serde_json::to_value(tip).expect("JSON serde failed"))
+            .collect();
+
+        Ok(sink.notify(Params::Array(tips))?)
+    }
+
+    thread::spawn(move || {
+        if let Err(e) = send_update(&tips, &sink) {
+            error!("Error sending chaintips to initialize client {:?}", e);
+        }
+
+        subscription_loop("chaintips", &exit, &receiver, |message| match message {
+            ScannerMessage::NewChaintip => send_update(&tips, &sink),
+            _ => Ok(()),
+        });
+    });
+}
+
+/// A websocket connection, with the role and name of the api key it was opened with.
+#[derive(Clone)]
+pub struct WsMeta {
+    session: Option<Arc<Session>>,
+    role: Option<Role>,
+    key_name: Option<String>,
+}
+
+impl Metadata for WsMeta {}
+
+impl Caller for WsMeta {
+    fn role(&self) -> Option<Role> {
+        self.role
+    }
+
+    fn key_name(&self) -> Option<&str> {
+        self.key_name.as_deref()
+    }
+}
+
+impl PubSubMetadata for WsMeta {
+    fn session(&self) -> Option<Arc<Session>> {
+        self.session.clone()
+    }
+}
+
+fn session_meta(context: &wss::RequestContext) -> WsMeta {
+    debug!("Request context {:#?}", context);
+    let (role, key_name) = match auth::take_ws_caller() {
+        Some((role, key_name)) => (Some(role), key_name),
+        None => (None, None),
+    };
+    WsMeta {
+        session: Some(Arc::new(Session::new(context.sender()))),
+        role,
+        key_name,
+    }
+}
+
+type WsIo = PubSubHandler<WsMeta, (auth::AuthMiddleware, RateLimiter)>;
+
+// Registers websocket subscriptions. Each gets a kill switch that its unsubscribe
+// method sets, and a channel in `channels` the notification thread sends on.
+struct WsSubscriptions {
+    killers: Arc<Mutex<HashMap<SubscriptionId, Arc<AtomicBool>>>>,
+    channels: Subscriptions,
+    pool: ManagedPool,
+}
+
+impl WsSubscriptions {
+    // Add `subscribe` and its unsubscribe method. `parse` turns the parameters into
+    // what `handle` needs and rejects the subscription on an error.
+    fn add<A, P, H>(
+        &self,
+        io: &mut WsIo,
+        notification: &'static str,
+        subscribe: &str,
+        channel: &'static str,
+        parse: P,
+        handle: H,
+    ) where
+        P: Fn(Params, &WsMeta) -> Result<A> + Send + Sync + 'static,
+        H: Fn(Arc<AtomicBool>, Receiver<ScannerMessage>, A, Sink) + Send + Sync + 'static,
+    {
+        let (killers, channels) = (self.killers.clone(), self.channels.clone());
+        let subscribe_method = move |params: Params, meta: WsMeta, subscriber: Subscriber| {
+            info!("Subscribe to {}", notification);
+            let args = match parse(params, &meta) {
+                Ok(args) => args,
+                Err(e) => {
+                    subscriber.reject(e).unwrap();
+                    return;
+                }
+            };
+
+            let kill_switch = Arc::new(AtomicBool::new(false));
+            let sub_id = SubscriptionId::Number(rand::rngs::OsRng.gen());
+            let sink = subscriber.assign_id(sub_id.clone()).unwrap();
+            killers
+                .lock()
+                .expect("Lock poisoned")
+                .insert(sub_id, kill_switch.clone());
+            let (notify_tx, notify_rx) = unbounded();
+            channels
+                .lock()
+                .expect("Lock poisoned")
+                .entry(channel)
+                .or_insert(vec![])
+                .push(notify_tx);
+
+            handle(kill_switch, notify_rx, args, sink)
+        };
+
+        let killers = self.killers.clone();
+        let unsubscribe_method = move |id: SubscriptionId, _| {
+            if let Some(arc) = killers.lock().expect("Lock poisoned").remove(&id) {
+                arc.store(true, Ordering::SeqCst);
+            }
+            Box::pin(futures::future::ok(Value::Bool(true)))
+        };
+
+        let unsubscribe = format!("unsubscribe_{}", subscribe.trim_start_matches("subscribe_"));
+        io.add_subscription(
+            notification,
+            (subscribe, subscribe_method),
+            (&unsubscribe, unsubscribe_method),
+        );
+    }
+
+    // Add a subscription whose events are kept for subscribers that ack, see `acks`.
+    // The events are kept under the name of the notification.
+    fn add_acked<A, P, H>(
+        &self,
+        io: &mut WsIo,
+        notification: &'static str,
+        subscribe: &str,
+        parse: P,
+        handle: H,
+    ) where
+        P: Fn(Params, &WsMeta) -> Result<A> + Send + Sync + 'static,
+        H: Fn(Arc<AtomicBool>, Receiver<ScannerMessage>, A, AckedSink) + Send + Sync + 'static,
+    {
+        let pool = self.pool.clone();
+        self.add(
+            io,
+            notification,
+            subscribe,
+            notification,
+            move |params: Params, meta: &WsMeta| {
+                let client_id = acks::client_id(params.clone(), meta.key_name.as_deref())
+                    .map_err(|_| rejected("Invalid parameters. Subscription rejected.".into()))?;
+                Ok((parse(params, meta)?, client_id))
+            },
+            move |exit, receiver, (args, client_id), sink| {
+                let sink = AckedSink::new(sink, pool.clone(), notification, client_id);
+                sink.redeliver();
+                handle(exit, receiver, args, sink)
+            },
+        );
+    }
+}
+
+fn rejected(message: String) -> Error {
+    Error {
+        code: ErrorCode::ParseError,
+        message,
+        data: None,
+    }
+}
+
+// The parameters of subscriptions that take none.
+fn no_params(params: Params, _: &WsMeta) -> Result<()> {
+    match params {
+        Params::None => Ok(()),
+        _ => Err(rejected(
+            "Invalid parameters. Subscription rejected.".into(),
+        )),
+    }
+}
+
+// Where a server listens, with TLS it only takes connections forwarded from the TLS
+// listener on the public port.
+fn bind_addr(listen: &str, port: u16, tls: bool) -> SocketAddr {
+    if tls {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0))
+    } else {
+        SocketAddr::from((listen.parse::<IpAddr>().unwrap(), port))
+    }
+}
+
+// Start an HTTP server whose requests are all answered by `middleware`, the handler
+// behind it has no methods.
+fn start_middleware_server<M: hts::RequestMiddleware>(
+    name: &str,
+    listen: &str,
+    port: u16,
+    tls: Option<&TlsConfig>,
+    max_body_bytes: usize,
+    middleware: M,
+) -> hts::Server {
+    let server = hts::ServerBuilder::new(IoHandler::default())
+        .request_middleware(middleware)
+        .max_request_body_size(max_body_bytes)
+        .start_http(&bind_addr(listen, port, tls.is_some()))
+        .unwrap_or_else(|e| panic!("Failed to start {} server {:?}", name, e));
+
+    if let Some(tls) = tls {
+        let public = SocketAddr::from((listen.parse::<IpAddr>().unwrap(), port));
+        serve_tls(tls, public, *server.address())
+            .unwrap_or_else(|e| panic!("Failed to start {} TLS {:?}", name, e));
+    }
+    info!("{} service on {} {}", name, listen, port);
+    server
+}
+
+// Wait for subscription threads to take their queued notifications, or give up after
+// a timeout.
+fn flush_subscriptions(subscriptions: &Subscriptions) {
+    let deadline = time::Instant::now() + time::Duration::from_millis(FLUSH_TIMEOUT_MS);
+    loop {
+        let pending = subscriptions
+            .lock()
+            .expect("Lock poisoned")
+            .values()
+            .flatten()
+            .any(|sub| !sub.is_empty());
+
+        if !pending {
+            break;
+        }
+        if time::Instant::now() > deadline {
+            error!("Timed out delivering notifications");
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(50));
+    }
+}
+
+// The OpenRPC document of every method and subscription, returned by `describe`.
+// Results built with `json!` are described by their fields.
+pub(crate) fn api_doc() -> Value {
+    let mut doc = ApiDoc::default();
+
+    doc.method::<TipArgs, Vec<BranchTip>>("get_tips");
+    doc.method::<NodeArgs, i64>("add_node");
+    doc.method::<NodeId, String>("remove_node");
+    doc.method::<NodeFlagArgs, String>("set_node_flags");
+    doc.method::<NodeGroupArgs, String>("set_node_group");
+    doc.method::<BlockQuery, Vec<BlockResult>>("get_block");
+    doc.method::<BlockHashes, Vec<Option<BlockResult>>>("get_blocks");
+    doc.method::<GetBlockFromPeer, Value>("get_block_from_peer");
+    doc.method::<SetTipQuery, String>("set_tip");
+    doc.method::<TxId, bool>("tx_is_active");
+    doc.method::<TxId, TxStatus>("get_tx_status");
+    doc.method::<TxIds, Vec<TxStatus>>("get_tx_statuses");
+    doc.method_returning::<TxId>(
+        "get_tx_blocks",
+        array(object(&["hash", "height", "pool_name", "position"])),
+    );
+    doc.method_returning::<TxId>(
+        "get_tx_ancestry",
+        object(&["txid", "blocks", "parents", "children"]),
+    );
+    let peers = doc.schema::<Vec<Peer>>();
+    doc.method_returning::<PeerQuery>(
+        "get_peers",
+        one_of(vec![
+            peers,
+            object(&["peers", "total", "shared", "per_node"]),
+        ]),
+    );
+    doc.method::<(), Vec<PeerOverlap>>("get_peer_overlap");
+    doc.method_returning::<CensorshipQuery>(
+        "get_censorship_report",
+        object(&["pools", "candidates"]),
+    );
+    doc.method::<UnknownCoinbaseQuery, Vec<UnknownCoinbaseTag>>("get_unknown_coinbase_tags");
+    doc.method_returning::<LabelCoinbaseTag>(
+        "label_coinbase_tag",
+        object(&["tag", "name", "blocks"]),
+    );
+    doc.method::<PayoutClusterQuery, Vec<PayoutCluster>>("get_payout_clusters");
+    doc.method::<ExpectedPayoutUpdate, Vec<ExpectedPayout>>("update_expected_payouts");
+    doc.method::<PayoutClusterQuery, Vec<ExpectedPayout>>("get_expected_payouts");
+    doc.method::<PeerReputationQuery, Vec<PeerReputation>>("get_peer_reputation");
+    doc.method_returning::<NodeId>(
+        "get_node_stats",
+        object(&[
+            "node_id",
+            "name",
+            "archive",
+            "reachable",
+            "unreachable_since",
+            "last_polled",
+            "initial_block_download",
+            "version",
+            "subversion",
+            "last_error",
+            "last_error_at",
+            "tip",
+            "lag",
+            "peers",
+            "recent_blocks",
+            "template",
+            "mirror",
+        ]),
+    );
+    doc.method::<NodeStatusQuery, Vec<NodeConnectivity>>("get_node_status");
+    doc.method::<(), Vec<SubscriberLag>>("get_subscriber_lag");
+    doc.method::<(), Vec<IntegrityCheck>>("check_integrity");
+    doc.method::<(), NetworkStatus>("get_network");
+    doc.method::<(), Option<BootstrapProgress>>("get_bootstrap_progress");
+    doc.method::<(), Vec<GroupConsensus>>("get_group_consensus");
+    doc.method::<MaintenanceArgs, MaintenanceWindow>("add_maintenance_window");
+    doc.method::<MaintenanceRemoveArgs, String>("remove_maintenance_window");
+    doc.method_returning::<SubscriberSecretArgs>(
+        "set_subscriber_secret",
+        object(&["client_id", "secret"]),
+    );
+    doc.method::<SubscriberSecretRemoveArgs, String>("remove_subscriber_secret");
+    doc.method_returning::<ApiKeyArgs>("add_api_key", object(&["name", "role", "key"]));
+    doc.method::<ApiKeyRevokeArgs, String>("revoke_api_key");
+    doc.method::<(), Vec<ApiKey>>("get_api_keys");
+    doc.method::<PruneArgs, String>("prune_now");
+    doc.method::<(), Vec<MaintenanceWindow>>("get_maintenance_windows");
+    doc.method::<(), ErrorCounts>("get_error_counts");
+    doc.method::<(), Vec<AssistStats>>("get_propagation_stats");
+    doc.method::<(), Vec<RollbackSchedule>>("get_rollback_progress");
+    doc.method::<RollbackCheckQuery, Vec<RollbackCheck>>("get_rollback_checks");
+    doc.method::<ReorgQuery, Vec<Reorg>>("get_reorgs");
+    doc.method_returning::<RecentActivityQuery>(
+        "get_recent_activity",
+        object(&["reorgs", "conflicts", "lags", "inflation"]),
+    );
+    doc.method::<TipsAsOfQuery, Vec<TipHistory>>("get_tips_as_of");
+    doc.method_returning::<BlockStatusAsOfQuery>(
+        "get_block_status_as_of",
+        object(&[
+            "hash",
+            "height",
+            "timestamp",
+            "valid_by",
+            "invalid_by",
+            "tip_statuses",
+            "active_chain",
+        ]),
+    );
+    doc.method::<(), Vec<ForkAnalysis>>("get_fork_analysis");
+    doc.method_returning::<ListBlocksQuery>("list_blocks", object(&["blocks", "next_cursor"]));
+    doc.method_returning::<BlockTemplateQuery>(
+        "get_block_templates",
+        array(object(&[
+            "node_id",
+            "parent_block_hash",
+            "height",
+            "fee_total",
+            "n_transactions",
+            "lowest_fee_rate",
+            "rules",
+            "ts",
+        ])),
+    );
+    doc.method_returning::<FeeRateQuery>("get_fee_rates", array(object(&["fee_rate", "omitted"])));
+    doc.method_returning::<TxOutsetQuery>(
+        "get_tx_outset",
+        array(object(&[
+            "block_hash",
+            "node_id",
+            "txouts",
+            "total_amount",
+            "inflated",
+            "created_at",
+            "updated_at",
+        ])),
+    );
+    doc.method::<AttestationQuery, SupplyAttestation>("get_supply_attestation");
+    doc.method_returning::<RawBlockQuery>(
+        "get_raw_block",
+        object(&[
+            "block_hash",
+            "height",
+            "node_id",
+            "size",
+            "compressed_size",
+            "hex",
+            "created_at",
+        ]),
+    );
+    doc.method_returning::<TemplateMatchQuery>(
+        "get_template_match_rates",
+        array(object(&[
+            "pool",
+            "period",
+            "blocks",
+            "matched",
+            "match_rate",
+            "mean_similarity",
+        ])),
+    );
+    doc.method_returning::<TemplateLatencyQuery>(
+        "get_template_latency",
+        array(object(&[
+            "pool",
+            "blocks",
+            "mean_latency",
+            "max_latency",
+            "mean_similarity",
+            "stale_blocks",
+            "stale_ratio",
+            "flagged",
+        ])),
+    );
+    doc.method_returning::<TemplateTxQuery>(
+        "tx_in_templates",
+        array(object(&[
+            "node_id",
+            "height",
+            "parent_block_hash",
+            "included",
+            "first_seen_at",
+            "in_latest",
+            "position",
+            "template_at",
+        ])),
+    );
+    doc.method::<WalletConflictQuery, Vec<WalletConflict>>("get_wallet_conflicts");
+    doc.method::<WatchedRelayQuery, Vec<WatchedRelay>>("get_watched_relays");
+    doc.method_returning::<StaleCandidateQuery>(
+        "get_stale_candidates",
+        array(object(&[
+            "height",
+            "n_children",
+            "source",
+            "confirmed_in_one_branch_total",
+            "double_spent_in_one_branch_total",
+            "rbf_total",
+            "created_at",
+            "children",
+            "imported",
+            "double_spent_txids",
+            "conflict_addresses",
+        ])),
+    );
+    doc.method::<ConflictAddressQuery, Vec<ConflictAddress>>("get_conflict_addresses");
+    doc.method::<ExternalTipArgs, ExternalObservation>("submit_external_tip");
+    doc.method::<ExternalObservationsQuery, Vec<ExternalObservation>>("get_external_observations");
+    doc.method_returning::<ImportStaleBlocksArgs>("import_stale_blocks", object(&["imported"]));
+    doc.method::<BlockUpload, String>("submit_block");
+    doc.method::<WatchedAddressUpdate, String>("update_watched_addresses");
+    doc.method::<OutpointWatchUpdate, String>("watch_outpoints");
+    doc.method_returning::<WatchedActivityQuery>(
+        "get_watched_activity",
+        object(&["address", "tip", "transactions"]),
+    );
+    doc.method_returning::<TxId>(
+        "get_transaction",
+        object(&[
+            "txid",
+            "hex",
+            "is_coinbase",
+            "amount",
+            "swept",
+            "addresses",
+            "blocks",
+        ]),
+    );
+    doc.method_returning::<AddressTransactionsQuery>(
+        "get_address_transactions",
+        object(&["address", "transactions", "next_cursor"]),
+    );
+    doc.method_returning::<()>("export_watchlist", object(&["addresses", "descriptors"]));
+    doc.method::<WatchlistImport, usize>("import_watchlist");
+    doc.method::<(), Value>("describe");
+
+    doc.websocket_method::<(), String>("ping");
+    doc.websocket_method::<acks::AckArgs, bool>("ack_events");
+    doc.subscription::<()>(
+        "subscribe_active_fork",
+        "active_fork",
+        "unsubscribe_active_fork",
+    );
+    doc.subscription::<()>("subscribe_forks", "forks", "unsubscribe_forks");
+    doc.subscription::<BlockArg>(
+        "validation_checks",
+        "validation_checks",
+        "unsubscribe_validation_checks",
+    );
+    doc.subscription::<()>(
+        "lagging_nodes_checks",
+        "lagging_nodes_checks",
+        "unsubscribe_lagging_nodes_checks",
+    );
+    doc.subscription::<WatchAddress>(
+        "watched_address_checks",
+        "watched_address_checks",
+        "unsubscribe_watched_address_checks",
+    );
+    doc.subscription::<TipTransitionFilter>(
+        "subscribe_tip_transitions",
+        "tip_transitions",
+        "unsubscribe_tip_transitions",
+    );
+    doc.subscription::<WatchOutpoints>(
+        "watch_outpoints",
+        "outpoint_spends",
+        "unsubscribe_watch_outpoints",
+    );
+    doc.subscription::<WatchTransaction>(
+        "watch_transaction",
+        "watched_transaction",
+        "unsubscribe_watch_transaction",
+    );
+    doc.subscription::<()>(
+        "subscribe_stale_candidates",
+        "stale_candidates",
+        "unsubscribe_stale_candidates",
+    );
+    doc.subscription::<()>(
+        "subscribe_cycle_summary",
+        "cycle_summary",
+        "unsubscribe_cycle_summary",
+    );
+    doc.subscription::<()>("subscribe_headers", "headers", "unsubscribe_headers");
+    doc.subscription::<()>("subscribe_errors", "errors", "unsubscribe_errors");
+    // subscriptions that take a client_id to ack their events with
+    for (subscribe, notification, unsubscribe) in ACKED_SUBSCRIPTIONS {
+        doc.subscription::<acks::SubscribeArgs>(subscribe, notification, unsubscribe);
+    }
+
+    doc.document()
+}
+
+/// The address and ports the servers listen on, the REST, GraphQL and gRPC servers
+/// only run when given a port.
+pub struct Listeners {
+    pub listen: String,
+    pub rpc_port: u16,
+    pub ws_port: u16,
+    pub rest_port: Option<u16>,
+    pub graphql_port: Option<u16>,
+    pub grpc_port: Option<u16>,
+    pub tls: Option<TlsConfig>,
+}
+
+/// RPC service endpoints for users of forkscanner. Returns once the scanner has
+/// stopped and dropped its notification channel, after pending notifications are
+/// delivered and the servers closed.
+pub fn run_server(
+    listeners: Listeners,
+    db_url: String,
+    receiver: Receiver<ScannerMessage>,
+    command: Sender<ScannerCommand>,
+    limits: RequestLimits,
+    alerter: Alerter,
+    event_bus: EventBus,
+) {
+    let Listeners {
+        listen,
+        rpc_port: rpc,
+        ws_port: subs,
+        rest_port,
+        graphql_port,
+        grpc_port,
+        tls,
+    } = listeners;
+    let manager = ConnectionManager::<PgConnection>::new(db_url);
+    let tips = Arc::new(RwLock::new(vec![]));
+    let pool = r2d2::Pool::builder()
+        .build(manager)
+        .expect("Connection pool");
+    let pool2 = pool.clone();
+
+    let tips1 = tips.clone();
+    let error_counts = Arc::new(Mutex::new(ErrorCounts::new()));
+    let error_counts1 = error_counts.clone();
+    let l1 = listen.clone();
+    let tls1 = tls.clone();
+    let (close_tx, close_rx) = unbounded::<Box<dyn FnOnce() + Send>>();
+    let close_tx1 = close_tx.clone();
+    let limiter = RateLimiter::new(limits);
+    let limiter1 = limiter.clone();
+    let limiter2 = limiter.clone();
+
+    let rest_server = rest_port.map(|port| {
+        let middleware = rest::rest_middleware(pool.clone(), limiter.clone());
+        start_middleware_server(
+            "REST",
+            &listen,
+            port,
+            tls.as_ref(),
+            limits.max_body_bytes,
+            middleware,
+        )
+    });
+    #[cfg(feature = "graphql")]
+    let graphql_server = graphql_port.map(|port| {
+        let middleware =
+            graphql::graphql_middleware(pool.clone(), limiter.clone(), limits.max_body_bytes);
+        start_middleware_server(
+            "GraphQL",
+            &listen,
+            port,
+            tls.as_ref(),
+            limits.max_body_bytes,
+            middleware,
+        )
+    });
+    #[cfg(not(feature = "graphql"))]
+    if graphql_port.is_some() {
+        panic!("graphql_port is set, but forkscanner was built without the graphql feature");
+    }
+    #[cfg(feature = "grpc")]
+    let grpc_server = grpc_port.map(|port| {
+        let server = GrpcServer::start(
+            pool.clone(),
+            limiter.clone(),
+            bind_addr(&listen, port, tls.is_some()),
+        );
+        if let Some(tls) = &tls {
+            let public = SocketAddr::from((listen.parse::<IpAddr>().unwrap(), port));
+            serve_tls_alpn(tls, public, server.address(), &[b"h2"])
+                .expect("Failed to start gRPC TLS");
+        }
+        info!("gRPC service on {} {}", listen, port);
+        server
+    });
+    #[cfg(feature = "grpc")]
+    let grpc_events = grpc_server.as_ref().map(GrpcServer::events);
+    #[cfg(not(feature = "grpc"))]
+    if grpc_port.is_some() {
+        panic!("grpc_port is set, but forkscanner was built without the grpc feature");
+    }
+
+    // set up some rpc endpoints
+    let t1 = thread::spawn(move || {
+        let mut io = MetaIoHandler::with_middleware((auth::AuthMiddleware, limiter1));
+        let p = pool.clone();
+        io.add_sync_method("get_tips", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tips(params, conn)
+        });
+
+        let p = pool.clone();
+        let cmd = command.clone();
+        io.add_sync_method("add_node", move |params: Params| {
+            let conn = p.get().unwrap();
+            let c = cmd.clone();
+            add_node(conn, c, params)
+        });
+
+        let p = pool.clone();
+        let cmd = command.clone();
+        io.add_sync_method("remove_node", move |params: Params| {
+            let conn = p.get().unwrap();
+            let c = cmd.clone();
+            remove_node(conn, c, params)
+        });
+
+        let p = pool.clone();
+        let cmd = command.clone();
+        io.add_sync_method("set_node_flags", move |params: Params| {
+            let conn = p.get().unwrap();
+            let c = cmd.clone();
+            set_node_flags(conn, c, params)
+        });
+
+        let p = pool.clone();
+        let cmd = command.clone();
+        io.add_sync_method("set_node_group", move |params: Params| {
+            let conn = p.get().unwrap();
+            let c = cmd.clone();
+            set_node_group(conn, c, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_block", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_block(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_blocks", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_blocks(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_block_from_peer", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_block_from_peer(conn, params)
+        });
+
+        let p = pool.clone();
+        let cmd = command.clone();
+        io.add_sync_method("set_tip", move |params: Params| {
+            let conn = p.get().unwrap();
+            let c = cmd.clone();
+            set_tip(conn, c, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("tx_is_active", move |params: Params| {
+            let conn = p.get().unwrap();
+            tx_is_active(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_tx_status", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tx_status(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_tx_statuses", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tx_statuses(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_tx_blocks", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tx_blocks(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_tx_ancestry", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tx_ancestry(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_peers", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_peers(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_peer_overlap", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_peer_overlap(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_censorship_report", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_censorship_report(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_unknown_coinbase_tags", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_unknown_coinbase_tags(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("label_coinbase_tag", move |params: Params| {
+            let conn = p.get().unwrap();
+            label_coinbase_tag(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_payout_clusters", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_payout_clusters(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("update_expected_payouts", move |params: Params| {
+            let conn = p.get().unwrap();
+            update_expected_payouts(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_expected_payouts", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_expected_payouts(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_peer_reputation", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_peer_reputation(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_node_stats", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_node_stats(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_node_status", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_node_status(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_subscriber_lag", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_subscriber_lag(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("check_integrity", move |_: Params| {
+            let conn = p.get().unwrap();
+            check_integrity(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_network", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_network(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_bootstrap_progress", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_bootstrap_progress(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_group_consensus", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_group_consensus(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("add_maintenance_window", move |params: Params| {
+            let conn = p.get().unwrap();
+            add_maintenance_window(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("remove_maintenance_window", move |params: Params| {
+            let conn = p.get().unwrap();
+            remove_maintenance_window(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("set_subscriber_secret", move |params: Params| {
+            let conn = p.get().unwrap();
+            set_subscriber_secret(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("remove_subscriber_secret", move |params: Params| {
+            let conn = p.get().unwrap();
+            remove_subscriber_secret(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("add_api_key", move |params: Params| {
+            let conn = p.get().unwrap();
+            add_api_key(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("revoke_api_key", move |params: Params| {
+            let conn = p.get().unwrap();
+            revoke_api_key(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_api_keys", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_api_keys(conn)
+        });
+
+        let cmd = command.clone();
+        io.add_sync_method("prune_now", move |params: Params| {
+            prune_now(cmd.clone(), params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_maintenance_windows", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_maintenance_windows(conn)
+        });
+
+        io.add_sync_method("get_error_counts", move |_: Params| {
+            get_error_counts(&error_counts1)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_propagation_stats", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_propagation_stats(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_rollback_progress", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_rollback_progress(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_rollback_checks", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_rollback_checks(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_reorgs", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_reorgs(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_recent_activity", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_recent_activity(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_tips_as_of", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tips_as_of(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_block_status_as_of", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_block_status_as_of(conn, params)
+        });
 
-        Ok(sink.notify(Params::Array(tips))?)
-    }
+        let p = pool.clone();
+        io.add_sync_method("get_fork_analysis", move |_: Params| {
+            let conn = p.get().unwrap();
+            get_fork_analysis(conn)
+        });
 
-    thread::spawn(move || {
-        if let Err(e) = send_update(&tips, &sink) {
-            error!("Error sending chaintips to initialize client {:?}", e);
-        }
+        let p = pool.clone();
+        io.add_sync_method("list_blocks", move |params: Params| {
+            let conn = p.get().unwrap();
+            list_blocks(conn, params)
+        });
 
-        loop {
-            if exit.load(Ordering::SeqCst) {
-                break;
-            }
+        let p = pool.clone();
+        io.add_sync_method("get_block_templates", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_block_templates(conn, params)
+        });
 
-            match receiver.recv_timeout(time::Duration::from_millis(5000)) {
-                Ok(ScannerMessage::NewChaintip) => {
-                    if let Err(e) = send_update(&tips, &sink) {
-                        error!("Error sending chaintips to client {:?}", e);
-                    }
-                }
-                Ok(_) => {}
-                Err(RecvTimeoutError::Timeout) => {
-                    info!("No chaintip updates");
-                }
-                Err(e) => {
-                    error!("Error! {:?}", e);
-                }
-            }
-        }
-    });
-}
+        let p = pool.clone();
+        io.add_sync_method("get_fee_rates", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_fee_rates(conn, params)
+        });
 
-fn session_meta(context: &wss::RequestContext) -> Option<Arc<Session>> {
-    debug!("Request context {:#?}", context);
-    Some(Arc::new(Session::new(context.sender())))
-}
+        let p = pool.clone();
+        io.add_sync_method("get_tx_outset", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_tx_outset(conn, params)
+        });
 
-/// RPC service endpoints for users of forkscanner.
-pub fn run_server(
-    listen: String,
-    rpc: u16,
-    subs: u16,
-    db_url: String,
-    receiver: Receiver<ScannerMessage>,
-    command: Sender<ScannerCommand>,
-) {
-    let manager = ConnectionManager::<PgConnection>::new(db_url);
-    let tips = Arc::new(RwLock::new(vec![]));
-    let pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Connection pool");
-    let pool2 = pool.clone();
+        let p = pool.clone();
+        io.add_sync_method("get_supply_attestation", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_supply_attestation(conn, params)
+        });
 
-    let tips1 = tips.clone();
-    let l1 = listen.clone();
+        let p = pool.clone();
+        io.add_sync_method("get_raw_block", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_raw_block(conn, params)
+        });
 
-    // set up some rpc endpoints
-    let t1 = thread::spawn(move || {
-        let mut io = IoHandler::new();
         let p = pool.clone();
-        io.add_sync_method("get_tips", move |params: Params| {
+        io.add_sync_method("get_template_match_rates", move |params: Params| {
             let conn = p.get().unwrap();
-            get_tips(params, conn)
+            get_template_match_rates(conn, params)
         });
 
         let p = pool.clone();
-        io.add_sync_method("add_node", move |params: Params| {
+        io.add_sync_method("get_template_latency", move |params: Params| {
             let conn = p.get().unwrap();
-            add_node(conn, params)
+            get_template_latency(conn, params)
         });
 
         let p = pool.clone();
-        io.add_sync_method("remove_node", move |params: Params| {
+        io.add_sync_method("tx_in_templates", move |params: Params| {
             let conn = p.get().unwrap();
-            remove_node(conn, params)
+            tx_in_templates(conn, params)
         });
 
         let p = pool.clone();
-        io.add_sync_method("get_block", move |params: Params| {
+        io.add_sync_method("get_wallet_conflicts", move |params: Params| {
             let conn = p.get().unwrap();
-            get_block(conn, params)
+            get_wallet_conflicts(conn, params)
         });
 
         let p = pool.clone();
-        io.add_sync_method("get_block_from_peer", move |params: Params| {
+        io.add_sync_method("get_watched_relays", move |params: Params| {
             let conn = p.get().unwrap();
-            get_block_from_peer(conn, params)
+            get_watched_relays(conn, params)
         });
 
         let p = pool.clone();
-        let cmd = command.clone();
-        io.add_sync_method("set_tip", move |params: Params| {
+        io.add_sync_method("get_stale_candidates", move |params: Params| {
             let conn = p.get().unwrap();
-            let c = cmd.clone();
-            set_tip(conn, c, params)
+            get_stale_candidates(conn, params)
         });
 
         let p = pool.clone();
-        io.add_sync_method("tx_is_active", move |params: Params| {
+        io.add_sync_method("get_conflict_addresses", move |params: Params| {
             let conn = p.get().unwrap();
-            tx_is_active(conn, params)
+            get_conflict_addresses(conn, params)
         });
 
         let p = pool.clone();
-        io.add_sync_method("get_peers", move |params: Params| {
+        io.add_sync_method("submit_external_tip", move |params: Params| {
             let conn = p.get().unwrap();
-            get_peers(conn, params)
+            submit_external_tip(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_external_observations", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_external_observations(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("import_stale_blocks", move |params: Params| {
+            let conn = p.get().unwrap();
+            import_stale_blocks(conn, params)
         });
 
         let p = pool.clone();
@@ -944,102 +5467,432 @@ pub fn run_server(
             update_watched_addresses(conn, params)
         });
 
-        let server = hts::ServerBuilder::new(io)
-            .start_http(&SocketAddr::from((l1.parse::<IpAddr>().unwrap(), rpc)))
+        let p = pool.clone();
+        io.add_sync_method("watch_outpoints", move |params: Params| {
+            let conn = p.get().unwrap();
+            watch_outpoints(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_watched_activity", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_watched_activity(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_transaction", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_transaction(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("get_address_transactions", move |params: Params| {
+            let conn = p.get().unwrap();
+            get_address_transactions(conn, params)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("export_watchlist", move |_params: Params| {
+            let conn = p.get().unwrap();
+            export_watchlist(conn)
+        });
+
+        let p = pool.clone();
+        io.add_sync_method("import_watchlist", move |params: Params| {
+            let conn = p.get().unwrap();
+            import_watchlist(conn, params)
+        });
+
+        let api = api_doc();
+        io.add_sync_method("describe", move |_: Params| Ok(api.clone()));
+
+        let server = hts::ServerBuilder::with_meta_extractor(io, auth::http_meta(pool.clone()))
+            .request_middleware(feed::feed_middleware(pool.clone(), limiter2))
+            .max_request_body_size(limits.max_body_bytes)
+            .start_http(&bind_addr(&l1, rpc, tls1.is_some()))
             .expect("Failed to start RPC server");
 
+        let _tls = tls1.map(|tls| {
+            let public = SocketAddr::from((l1.parse::<IpAddr>().unwrap(), rpc));
+            serve_tls(&tls, public, *server.address()).expect("Failed to start RPC TLS")
+        });
+
+        let handle = server.close_handle();
+        let _ = close_tx1.send(Box::new(move || handle.close()));
         server.wait();
+        info!("RPC service is exiting");
     });
 
-    let subscriptions = Arc::new(Mutex::new(
-        HashMap::<&str, Vec<Sender<ScannerMessage>>>::default(),
-    ));
+    let subscriptions: Subscriptions = Arc::new(Mutex::new(HashMap::default()));
     let subscriptions2 = subscriptions.clone();
-    let subscriptions3 = subscriptions.clone();
-    let subscriptions4 = subscriptions.clone();
-    let subscriptions5 = subscriptions.clone();
+    let subscriptions9 = subscriptions.clone();
     // listener thread for notifications from forkscanner
     let t2 = thread::spawn(move || loop {
-        match receiver.recv() {
+        let message = receiver.recv();
+        if let Ok(message) = &message {
+            alerter.handle(message);
+            event_bus.handle(message);
+            #[cfg(feature = "grpc")]
+            if let Some(events) = &grpc_events {
+                events.handle(message);
+            }
+        }
+
+        match message {
             Ok(ScannerMessage::NewChaintip) => {
                 debug!("New chaintip updates");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("active_fork")
+                    .get_mut("active_fork")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::NewChaintip).is_ok());
+                }
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("forks")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::NewChaintip).is_ok());
+                }
+            }
+            Ok(ScannerMessage::LaggingNodes(lags)) => {
+                debug!("New lagging nodes updates");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("lagging_nodes")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::LaggingNodes(lags.clone())).is_ok());
+                }
+            }
+            Ok(ScannerMessage::NewBlockConflicts(conflicts)) => {
+                debug!("New block conflict updates");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("invalid_block_checks")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::NewBlockConflicts(conflicts.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::TipUpdated(invalidated_hashes)) => {
+                debug!("New chaintip updates");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("active_fork")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::TipUpdated(invalidated_hashes.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::TipUpdateFailed(err)) => {
+                debug!("New chaintip updates");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("active_fork")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::TipUpdateFailed(err.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::WatchedAddress(txs)) => {
+                debug!("New watched address activity");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("watched_addresses")
+                {
+                    debug!(
+                        "New watched address activity: updating {} subscriptions",
+                        subs.len()
+                    );
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::WatchedAddress(txs.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::WatchedAddressMempool(payments)) => {
+                debug!("New watched address payments in mempools");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("watched_address_mempool")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::WatchedAddressMempool(payments.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::PersistentForks(forks)) => {
+                debug!("New persistent forks");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("persistent_forks")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::PersistentForks(forks.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::WalletConflicts(conflicts)) => {
+                debug!("New wallet conflicts");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("wallet_conflicts")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::WalletConflicts(conflicts.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::StuckChain(stuck)) => {
+                debug!("Stuck chain");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("stuck_chain")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::StuckChain(stuck.clone())).is_ok());
+                }
+            }
+            Ok(ScannerMessage::PeerPartitions(pairs)) => {
+                debug!("New peer partitions");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("peer_partitions")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::PeerPartitions(pairs.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::GroupSplits(splits)) => {
+                debug!("New group splits");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("group_splits")
                 {
-                    subs.retain(|sub| sub.send(ScannerMessage::NewChaintip).is_ok());
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::GroupSplits(splits.clone()))
+                            .is_ok()
+                    });
                 }
+            }
+            Ok(ScannerMessage::ExternalDisagreements(disagreements)) => {
+                debug!("New external disagreements");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("forks")
+                    .get_mut("external_disagreements")
                 {
-                    subs.retain(|sub| sub.send(ScannerMessage::NewChaintip).is_ok());
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::ExternalDisagreements(disagreements.clone()))
+                            .is_ok()
+                    });
                 }
             }
-            Ok(ScannerMessage::LaggingNodes(lags)) => {
-                debug!("New lagging nodes updates");
+            Ok(ScannerMessage::MempoolDivergence(nodes)) => {
+                debug!("New mempool divergence");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("lagging_nodes")
+                    .get_mut("mempool_divergence")
                 {
-                    subs.retain(|sub| sub.send(ScannerMessage::LaggingNodes(lags.clone())).is_ok());
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::MempoolDivergence(nodes.clone()))
+                            .is_ok()
+                    });
                 }
             }
-            Ok(ScannerMessage::NewBlockConflicts(conflicts)) => {
-                debug!("New block conflict updates");
+            Ok(ScannerMessage::Censorship(candidates)) => {
+                debug!("New censorship candidates");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("invalid_block_checks")
+                    .get_mut("censorship")
                 {
                     subs.retain(|sub| {
-                        sub.send(ScannerMessage::NewBlockConflicts(conflicts.clone()))
+                        sub.send(ScannerMessage::Censorship(candidates.clone()))
                             .is_ok()
                     });
                 }
             }
-            Ok(ScannerMessage::TipUpdated(invalidated_hashes)) => {
-                debug!("New chaintip updates");
+            Ok(ScannerMessage::Reorg(reorg)) => {
+                debug!("New reorg");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("active_fork")
+                    .get_mut("reorgs")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::Reorg(reorg.clone())).is_ok());
+                }
+            }
+            Ok(ScannerMessage::DeepReorg(deep)) => {
+                debug!("New deep reorg");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("deep_reorgs")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::DeepReorg(deep.clone())).is_ok());
+                }
+            }
+            Ok(ScannerMessage::OutpointSpends(spends)) => {
+                debug!("New outpoint spends");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("outpoint_spends")
                 {
                     subs.retain(|sub| {
-                        sub.send(ScannerMessage::TipUpdated(invalidated_hashes.clone()))
+                        sub.send(ScannerMessage::OutpointSpends(spends.clone()))
                             .is_ok()
                     });
                 }
             }
-            Ok(ScannerMessage::TipUpdateFailed(err)) => {
-                debug!("New chaintip updates");
+            Ok(ScannerMessage::PayoutAnomalies(anomalies)) => {
+                debug!("New payout anomalies");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("active_fork")
+                    .get_mut("payout_anomalies")
                 {
                     subs.retain(|sub| {
-                        sub.send(ScannerMessage::TipUpdateFailed(err.clone()))
+                        sub.send(ScannerMessage::PayoutAnomalies(anomalies.clone()))
                             .is_ok()
                     });
                 }
             }
-            Ok(ScannerMessage::WatchedAddress(txs)) => {
-                debug!("New watched address activity");
+            Ok(ScannerMessage::Anomalies(anomalies)) => {
+                debug!("New detector anomalies");
                 if let Some(subs) = subscriptions2
                     .lock()
                     .expect("Lock poisoned")
-                    .get_mut("watched_addresses")
+                    .get_mut("anomalies")
                 {
-                    debug!(
-                        "New watched address activity: updating {} subscriptions",
-                        subs.len()
-                    );
                     subs.retain(|sub| {
-                        sub.send(ScannerMessage::WatchedAddress(txs.clone()))
+                        sub.send(ScannerMessage::Anomalies(anomalies.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::InflatedBlock(inflated)) => {
+                debug!("New inflated block");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("inflation_checks")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::InflatedBlock(inflated.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::DoubleSpends(found)) => {
+                debug!("New double spends");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("double_spends")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::DoubleSpends(found.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::PeerChanges(changes)) => {
+                debug!("New peer changes");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("peer_changes")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::PeerChanges(changes.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::CycleSummary(summary)) => {
+                debug!("Scan cycle finished");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("cycle_summary")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::CycleSummary(summary.clone()))
+                            .is_ok()
+                    });
+                }
+                // watched transactions are checked again after every cycle
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("watched_transactions")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::CycleSummary(summary.clone()))
+                            .is_ok()
+                    });
+                }
+            }
+            Ok(ScannerMessage::NewHeader(header)) => {
+                debug!("New header {}", header.hash);
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("headers")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::NewHeader(header.clone())).is_ok());
+                }
+            }
+            Ok(ScannerMessage::Error(event)) => {
+                *error_counts
+                    .lock()
+                    .expect("Lock poisoned")
+                    .counts
+                    .entry(event.category)
+                    .or_insert(0) += 1;
+
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("errors")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::Error(event.clone())).is_ok());
+                }
+            }
+            Ok(ScannerMessage::TipStatusChanged(transitions)) => {
+                debug!("New tip status transitions");
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("tip_transitions")
+                {
+                    subs.retain(|sub| {
+                        sub.send(ScannerMessage::TipStatusChanged(transitions.clone()))
                             .is_ok()
                     });
                 }
@@ -1055,7 +5908,14 @@ pub fn run_server(
                         "New stale candidates: updating {} subscriptions",
                         subs.len()
                     );
-					subs.retain(|sub| sub.send(ScannerMessage::StaleCandidateUpdate).is_ok());
+                    subs.retain(|sub| sub.send(ScannerMessage::StaleCandidateUpdate).is_ok());
+                }
+                if let Some(subs) = subscriptions2
+                    .lock()
+                    .expect("Lock poisoned")
+                    .get_mut("stale_candidates")
+                {
+                    subs.retain(|sub| sub.send(ScannerMessage::StaleCandidateUpdate).is_ok());
                 }
             }
             Ok(ScannerMessage::AllChaintips(mut t)) => {
@@ -1069,358 +5929,438 @@ pub fn run_server(
                     subs.retain(|sub| sub.send(ScannerMessage::NewChaintip).is_ok());
                 }
             }
-            Err(e) => {
-                error!("Channel broke {:?}", e);
+            Err(_) => {
+                info!("Scanner notification channel closed");
                 break;
             }
         }
     });
 
     let t3 = thread::spawn(move || {
-        let killers = Arc::new(Mutex::new(
-            HashMap::<SubscriptionId, Arc<AtomicBool>>::default(),
-        ));
-
-        let mut io = PubSubHandler::new(MetaIoHandler::default());
+        let mut io = PubSubHandler::new(MetaIoHandler::with_middleware((
+            auth::AuthMiddleware,
+            limiter.clone(),
+        )));
         io.add_sync_method("ping", |_: Params| Ok(Value::String("pong".into())));
 
-        let killer_clone1 = killers.clone();
-        let killer_clone2 = killers.clone();
-        let killer_clone3 = killers.clone();
-        let killer_clone4 = killers.clone();
-        let killer_clone5 = killers.clone();
-        let killer_clone6 = killers.clone();
-        let killer_clone7 = killers.clone();
-        let killer_clone8 = killers.clone();
-        let killer_clone9 = killers.clone();
-        let killer_clone10 = killers.clone();
-        let killer_clone11 = killers.clone();
-        let pool3 = pool2.clone();
-        let pool4 = pool2.clone();
-        let pool5 = pool2.clone();
-        let subscriptions2 = subscriptions.clone();
-        let subscriptions6 = subscriptions.clone();
-        // ws subscription endpoint for fork notifications
-        io.add_subscription(
-            "active_fork",
-            (
-                "subscribe_active_fork",
-                move |params: Params, _, subscriber: Subscriber| {
-                    info!("Subscribe to active fork");
-                    let mut rng = rand::rngs::OsRng::default();
-                    if params != Params::None {
-                        subscriber
-                            .reject(Error {
-                                code: ErrorCode::ParseError,
-                                message: "Invalid parameters. Subscription rejected.".into(),
-                                data: None,
-                            })
-                            .unwrap();
-                        return;
-                    }
+        let pool = pool2.clone();
+        io.add_method_with_meta("ack_events", move |params: Params, meta: WsMeta| {
+            let conn = pool.get().unwrap();
+            let result = acks::ack_events(&conn, params, meta.key_name.as_deref());
+            futures::future::ready(result)
+        });
 
-                    let kill_switch = Arc::new(AtomicBool::new(false));
-                    let sub_id = SubscriptionId::Number(rng.gen());
-                    let sink = subscriber.assign_id(sub_id.clone()).unwrap();
-                    killer_clone1
-                        .lock()
-                        .expect("Lock poisoned")
-                        .insert(sub_id, kill_switch.clone());
-                    let (notify_tx, notify_rx) = unbounded();
-                    {
-                        let mut sub_lock = subscriptions.lock().expect("Lock poisoned");
-                        sub_lock
-                            .entry("active_fork")
-                            .or_insert(vec![])
-                            .push(notify_tx);
-                    }
+        let ws = WsSubscriptions {
+            killers: Arc::new(Mutex::new(HashMap::default())),
+            channels: subscriptions,
+            pool: pool2.clone(),
+        };
 
-                    handle_subscribe(kill_switch, notify_rx, tips1.clone(), params, sink)
-                },
-            ),
-            ("unsubscribe_active_fork", move |id: SubscriptionId, _| {
-                if let Some(arc) = killer_clone2.lock().expect("Lock poisoned").remove(&id) {
-                    arc.store(true, Ordering::SeqCst);
-                }
-                Box::pin(futures::future::ok(Value::Bool(true)))
-            }),
+        // ws subscription endpoint for fork notifications
+        ws.add(
+            &mut io,
+            "active_fork",
+            "subscribe_active_fork",
+            "active_fork",
+            no_params,
+            move |exit, receiver, _, sink| {
+                handle_subscribe(exit, receiver, tips1.clone(), Params::None, sink)
+            },
         );
 
         // ws subscription endpoint for fork notifications
-        io.add_subscription(
+        let pool = pool2.clone();
+        ws.add(
+            &mut io,
             "forks",
-            (
-                "subscribe_forks",
-                move |params: Params, _, subscriber: Subscriber| {
-                    info!("Subscribe to forks");
-                    let mut rng = rand::rngs::OsRng::default();
-                    if params != Params::None {
-                        subscriber
-                            .reject(Error {
-                                code: ErrorCode::ParseError,
-                                message: "Invalid parameters. Subscription rejected.".into(),
-                                data: None,
-                            })
-                            .unwrap();
-                        return;
-                    }
+            "subscribe_forks",
+            "forks",
+            no_params,
+            move |exit, receiver, _, sink| {
+                handle_subscribe_forks(exit, pool.clone(), receiver, Params::None, sink)
+            },
+        );
 
-                    let kill_switch = Arc::new(AtomicBool::new(false));
-                    let sub_id = SubscriptionId::Number(rng.gen());
-                    let sink = subscriber.assign_id(sub_id.clone()).unwrap();
-                    killer_clone10
-                        .lock()
-                        .expect("Lock poisoned")
-                        .insert(sub_id, kill_switch.clone());
-                    let (notify_tx, notify_rx) = unbounded();
-                    {
-                        let mut sub_lock = subscriptions6.lock().expect("Lock poisoned");
-                        sub_lock.entry("forks").or_insert(vec![]).push(notify_tx);
-                    }
+        // subscription endpoint for giving diff between tip height and stale block heights
+        let pool = pool2.clone();
+        ws.add(
+            &mut io,
+            "validation_checks",
+            "validation_checks",
+            "validation_checks",
+            |params: Params, _: &WsMeta| match params {
+                Params::None => Ok(BLOCK_WINDOW),
+                params => params
+                    .parse()
+                    .map(|BlockArg { max_height }| max_height)
+                    .map_err(|_| {
+                        rejected("Invalid parameters. Expected None, or max_height: i64".into())
+                    }),
+            },
+            move |exit, receiver, block_window, sink| {
+                handle_validation_subscribe(exit, receiver, pool.clone(), block_window, sink)
+            },
+        );
 
-                    handle_subscribe_forks(kill_switch, pool5.clone(), notify_rx, params, sink)
-                },
-            ),
-            ("unsubscribe_forks", move |id: SubscriptionId, _| {
-                if let Some(arc) = killer_clone11.lock().expect("Lock poisoned").remove(&id) {
-                    arc.store(true, Ordering::SeqCst);
+        ws.add_acked(
+            &mut io,
+            "invalid_block_checks",
+            "invalid_block_checks",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_invalid_block_subscribe(exit, receiver, sink),
+        );
+
+        ws.add(
+            &mut io,
+            "lagging_nodes_checks",
+            "lagging_nodes_checks",
+            "lagging_nodes",
+            no_params,
+            |exit, receiver, _, sink| handle_lagging_nodes_subscribe(exit, receiver, sink),
+        );
+
+        let pool = pool2.clone();
+        ws.add(
+            &mut io,
+            "watched_address_checks",
+            "watched_address_checks",
+            "watched_addresses",
+            |params: Params, _: &WsMeta| {
+                params.parse::<WatchAddress>().map_err(|e| {
+                    rejected(format!(
+                        "Invalid parameters. Expected list of addresses to watch. {:?}",
+                        e
+                    ))
+                })
+            },
+            move |exit, receiver, WatchAddress { watch, watch_until }, sink| {
+                handle_watched_addresses(exit, receiver, watch, watch_until, pool.clone(), sink)
+            },
+        );
+
+        ws.add(
+            &mut io,
+            "tip_transitions",
+            "subscribe_tip_transitions",
+            "tip_transitions",
+            |params: Params, _: &WsMeta| match params {
+                Params::None => Ok(TipTransitionFilter::default()),
+                params => params.parse().map_err(|e| {
+                    rejected(format!(
+                        "Invalid parameters. Expected None, or node_id, from, to. {:?}",
+                        e
+                    ))
+                }),
+            },
+            |exit, receiver, filter, sink| {
+                handle_tip_transitions_subscribe(exit, receiver, filter, sink)
+            },
+        );
+
+        ws.add_acked(
+            &mut io,
+            "persistent_forks",
+            "subscribe_persistent_forks",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_persistent_forks_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "wallet_conflicts",
+            "subscribe_wallet_conflicts",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_wallet_conflicts_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "stuck_chain",
+            "subscribe_stuck_chain",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_stuck_chain_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "peer_partitions",
+            "subscribe_peer_partitions",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_peer_partitions_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "mempool_divergence",
+            "subscribe_mempool_divergence",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_mempool_divergence_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "payout_anomalies",
+            "subscribe_payout_anomalies",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_payout_anomalies_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "anomalies",
+            "subscribe_anomalies",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_anomalies_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "censorship",
+            "subscribe_censorship",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_censorship_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "reorgs",
+            "subscribe_reorgs",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_reorgs_subscribe(exit, receiver, sink),
+        );
+
+        ws.add_acked(
+            &mut io,
+            "deep_reorgs",
+            "subscribe_deep_reorgs",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_deep_reorgs_subscribe(exit, receiver, sink),
+        );
+
+        // the watchlist is only saved once the subscription is accepted
+        let pool = pool2.clone();
+        ws.add_acked(
+            &mut io,
+            "outpoint_spends",
+            "watch_outpoints",
+            move |params: Params, meta: &WsMeta| {
+                if meta.role < Some(Role::Operator) {
+                    return Err(Error {
+                        code: ErrorCode::ServerError(auth::UNAUTHORIZED),
+                        message: "Unauthorized, watch_outpoints needs the operator role".into(),
+                        data: None,
+                    });
                 }
-                Box::pin(futures::future::ok(Value::Bool(true)))
-            }),
+
+                let WatchOutpoints {
+                    watch, watch_until, ..
+                } = params.parse().map_err(|e| {
+                    rejected(format!(
+                        "Invalid parameters. Expected list of outpoints to watch. {:?}",
+                        e
+                    ))
+                })?;
+
+                let watches = watch
+                    .iter()
+                    .map(|o| (o.txid.clone(), o.vout, watch_until))
+                    .collect();
+                let inserted = pool.get().map_err(|e| e.to_string()).and_then(|conn| {
+                    WatchedOutpoint::insert(&conn, watches).map_err(|e| e.to_string())
+                });
+                if let Err(e) = inserted {
+                    error!("Could not insert watchlist {}", e);
+                    return Err(Error {
+                        code: ErrorCode::InternalError,
+                        message: "Could not save the watchlist.".into(),
+                        data: None,
+                    });
+                }
+                Ok(watch)
+            },
+            |exit, receiver, watch, sink| {
+                handle_outpoint_spends_subscribe(exit, receiver, watch, sink)
+            },
         );
-        // subscription endpoint for giving diff between tip height and stale block heights
-        io.add_subscription(
-            "validation_checks",
-            (
-                "validation_checks",
-                move |params: Params, _, subscriber: Subscriber| {
-                    info!("Subscribe to validation checks");
-                    let mut rng = rand::rngs::OsRng::default();
-
-                    let block_window = if let Params::None = params {
-                        BLOCK_WINDOW
-                    } else {
-                        let BlockArg { max_height } = if let Ok(parm) = params.parse() {
-                            parm
-                        } else {
-                            subscriber
-                                .reject(Error {
-                                    code: ErrorCode::ParseError,
-                                    message:
-                                        "Invalid parameters. Expected None, or max_height: i64"
-                                            .into(),
-                                    data: None,
-                                })
-                                .unwrap();
-                            return;
-                        };
-                        max_height
-                    };
-
-                    let kill_switch = Arc::new(AtomicBool::new(false));
-                    let sub_id = SubscriptionId::Number(rng.gen());
-                    let sink = subscriber.assign_id(sub_id.clone()).unwrap();
-                    killer_clone3
-                        .lock()
-                        .expect("Lock poisoned")
-                        .insert(sub_id, kill_switch.clone());
-                    let (notify_tx, notify_rx) = unbounded();
-                    {
-                        let mut sub_lock = subscriptions2.lock().expect("Lock poisoned");
-                        sub_lock
-                            .entry("validation_checks")
-                            .or_insert(vec![])
-                            .push(notify_tx);
-                    }
 
-                    handle_validation_subscribe(
-                        kill_switch,
-                        notify_rx,
-                        pool3.clone(),
-                        block_window,
-                        sink,
-                    )
-                },
-            ),
-            (
-                "unsubscribe_validation_checks",
-                move |id: SubscriptionId, _| {
-                    if let Some(arc) = killer_clone4.lock().expect("Lock poisoned").remove(&id) {
-                        arc.store(true, Ordering::SeqCst);
-                    }
-                    Box::pin(futures::future::ok(Value::Bool(true)))
-                },
-            ),
+        let pool = pool2.clone();
+        ws.add(
+            &mut io,
+            "watched_transaction",
+            "watch_transaction",
+            "watched_transactions",
+            |params: Params, _: &WsMeta| {
+                params.parse::<WatchTransaction>().map_err(|e| {
+                    rejected(format!(
+                        "Invalid parameters. Expected txid to watch. {:?}",
+                        e
+                    ))
+                })
+            },
+            move |exit, receiver, watch, sink| {
+                handle_watch_transaction_subscribe(exit, receiver, pool.clone(), watch, sink)
+            },
         );
 
-        io.add_subscription(
-            "invalid_block_checks",
-            (
-                "invalid_block_checks",
-                move |params: Params, _, subscriber: Subscriber| {
-                    info!("Subscribe to invalid block checks");
-                    let mut rng = rand::rngs::OsRng::default();
-
-                    if params != Params::None {
-                        subscriber
-                            .reject(Error {
-                                code: ErrorCode::ParseError,
-                                message: "Invalid parameters. Subscription rejected.".into(),
-                                data: None,
-                            })
-                            .unwrap();
-                        return;
-                    }
+        ws.add_acked(
+            &mut io,
+            "group_splits",
+            "subscribe_group_splits",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_group_splits_subscribe(exit, receiver, sink),
+        );
 
-                    let kill_switch = Arc::new(AtomicBool::new(false));
-                    let sub_id = SubscriptionId::Number(rng.gen());
-                    let sink = subscriber.assign_id(sub_id.clone()).unwrap();
-                    killers
-                        .lock()
-                        .expect("Lock poisoned")
-                        .insert(sub_id, kill_switch.clone());
-                    let (notify_tx, notify_rx) = unbounded();
-                    {
-                        let mut sub_lock = subscriptions3.lock().expect("Lock poisoned");
-                        sub_lock
-                            .entry("invalid_block_checks")
-                            .or_insert(vec![])
-                            .push(notify_tx);
-                    }
+        ws.add_acked(
+            &mut io,
+            "external_disagreements",
+            "subscribe_external_disagreements",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_external_disagreements_subscribe(exit, receiver, sink),
+        );
 
-                    handle_invalid_block_subscribe(kill_switch, notify_rx, sink)
-                },
-            ),
-            (
-                "unsubscribe_invalid_block_checks",
-                move |id: SubscriptionId, _| {
-                    if let Some(arc) = killer_clone5.lock().expect("Lock poisoned").remove(&id) {
-                        arc.store(true, Ordering::SeqCst);
-                    }
-                    Box::pin(futures::future::ok(Value::Bool(true)))
-                },
-            ),
+        ws.add_acked(
+            &mut io,
+            "watched_address_mempool",
+            "subscribe_watched_address_mempool",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| {
+                handle_watched_address_mempool_subscribe(exit, receiver, sink)
+            },
         );
 
-        io.add_subscription(
-            "lagging_nodes_checks",
-            (
-                "lagging_nodes_checks",
-                move |params: Params, _, subscriber: Subscriber| {
-                    info!("Subscribe to lagging nodes checks");
-                    let mut rng = rand::rngs::OsRng::default();
-
-                    if params != Params::None {
-                        subscriber
-                            .reject(Error {
-                                code: ErrorCode::ParseError,
-                                message: "Invalid parameters. Subscription rejected.".into(),
-                                data: None,
-                            })
-                            .unwrap();
-                        return;
-                    }
+        let pool = pool2.clone();
+        ws.add(
+            &mut io,
+            "stale_candidates",
+            "subscribe_stale_candidates",
+            "stale_candidates",
+            no_params,
+            move |exit, receiver, _, sink| {
+                handle_stale_candidates_subscribe(exit, receiver, pool.clone(), sink)
+            },
+        );
 
-                    let kill_switch = Arc::new(AtomicBool::new(false));
-                    let sub_id = SubscriptionId::Number(rng.gen());
-                    let sink = subscriber.assign_id(sub_id.clone()).unwrap();
-                    killer_clone6
-                        .lock()
-                        .expect("Lock poisoned")
-                        .insert(sub_id, kill_switch.clone());
-                    let (notify_tx, notify_rx) = unbounded();
-                    {
-                        let mut sub_lock = subscriptions4.lock().expect("Lock poisoned");
-                        sub_lock
-                            .entry("lagging_nodes")
-                            .or_insert(vec![])
-                            .push(notify_tx);
-                    }
+        ws.add_acked(
+            &mut io,
+            "inflation_checks",
+            "subscribe_inflation",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_inflation_subscribe(exit, receiver, sink),
+        );
 
-                    handle_lagging_nodes_subscribe(kill_switch, notify_rx, sink)
-                },
-            ),
-            (
-                "unsubscribe_lagging_nodes_checks",
-                move |id: SubscriptionId, _| {
-                    if let Some(arc) = killer_clone7.lock().expect("Lock poisoned").remove(&id) {
-                        arc.store(true, Ordering::SeqCst);
-                    }
-                    Box::pin(futures::future::ok(Value::Bool(true)))
-                },
-            ),
+        ws.add_acked(
+            &mut io,
+            "double_spends",
+            "subscribe_double_spends",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_double_spends_subscribe(exit, receiver, sink),
         );
 
-        io.add_subscription(
-            "watched_address_checks",
-            (
-                "watched_address_checks",
-                move |params: Params, _, subscriber: Subscriber| {
-                    info!("Subscribe to watched address checks");
-                    let mut rng = rand::rngs::OsRng::default();
-
-                    let WatchAddress { watch, watch_until } = match params.parse() {
-					    Ok(parm) => parm,
-						Err(e) => {
-							subscriber
-								.reject(Error {
-									code: ErrorCode::ParseError,
-									message: format!("Invalid parameters. Expected list of addresses to watch. {:?}", e)
-										.into(),
-									data: None,
-								})
-								.unwrap();
-							return;
-						}
-                    };
-
-                    let kill_switch = Arc::new(AtomicBool::new(false));
-                    let sub_id = SubscriptionId::Number(rng.gen());
-                    let sink = subscriber.assign_id(sub_id.clone()).unwrap();
-                    killer_clone8
-                        .lock()
-                        .expect("Lock poisoned")
-                        .insert(sub_id, kill_switch.clone());
-                    let (notify_tx, notify_rx) = unbounded();
-                    {
-                        let mut sub_lock = subscriptions5.lock().expect("Lock poisoned");
-                        sub_lock
-                            .entry("watched_addresses")
-                            .or_insert(vec![])
-                            .push(notify_tx);
-                    }
+        ws.add_acked(
+            &mut io,
+            "peer_changes",
+            "subscribe_peer_changes",
+            |_, _: &WsMeta| Ok(()),
+            |exit, receiver, _, sink| handle_peer_changes_subscribe(exit, receiver, sink),
+        );
 
-                    handle_watched_addresses(
-                        kill_switch,
-                        notify_rx,
-                        watch,
-                        watch_until,
-                        pool4.clone(),
-                        sink,
-                    )
-                },
-            ),
-            (
-                "unsubscribe_watched_address_checks",
-                move |id: SubscriptionId, _| {
-                    if let Some(arc) = killer_clone9.lock().expect("Lock poisoned").remove(&id) {
-                        arc.store(true, Ordering::SeqCst);
-                    }
-                    Box::pin(futures::future::ok(Value::Bool(true)))
-                },
-            ),
+        ws.add(
+            &mut io,
+            "cycle_summary",
+            "subscribe_cycle_summary",
+            "cycle_summary",
+            no_params,
+            |exit, receiver, _, sink| handle_cycle_summary_subscribe(exit, receiver, sink),
+        );
+
+        ws.add(
+            &mut io,
+            "headers",
+            "subscribe_headers",
+            "headers",
+            no_params,
+            |exit, receiver, _, sink| handle_headers_subscribe(exit, receiver, sink),
+        );
+
+        ws.add(
+            &mut io,
+            "errors",
+            "subscribe_errors",
+            "errors",
+            no_params,
+            |exit, receiver, _, sink| handle_errors_subscribe(exit, receiver, sink),
         );
 
         info!("Coming up on {} {}", listen, subs);
         let server = wss::ServerBuilder::with_meta_extractor(io, session_meta)
-            .start(&SocketAddr::from((listen.parse::<IpAddr>().unwrap(), subs)))
+            .request_middleware(auth::ws_middleware(pool2.clone(), limiter))
+            .max_payload(limits.max_body_bytes)
+            .max_connections(limits.max_ws_connections)
+            .start(&bind_addr(&listen, subs, tls.is_some()))
             .expect("Failed to start sub server");
 
+        let _tls = tls.map(|tls| {
+            let public = SocketAddr::from((listen.parse::<IpAddr>().unwrap(), subs));
+            serve_tls(&tls, public, *server.addr()).expect("Failed to start WS TLS")
+        });
+
+        let handle = server.close_handle();
+        let _ = close_tx.send(Box::new(move || handle.close()));
         server.wait().expect("WS server crashed");
         info!("WS service is exiting");
     });
 
-    t1.join().expect("Thread join");
+    // the notification thread stops once the scanner is gone
     t2.join().expect("Thread join");
+    info!("Scanner stopped, delivering pending notifications");
+    flush_subscriptions(&subscriptions9);
+
+    for close in close_rx.iter().take(2) {
+        close();
+    }
+    if let Some(server) = rest_server {
+        server.close();
+    }
+    #[cfg(feature = "graphql")]
+    if let Some(server) = graphql_server {
+        server.close();
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(server) = grpc_server {
+        server.close();
+    }
+    t1.join().expect("Thread join");
     t3.join().expect("Thread join");
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ws_roles() {
+        let mut io = MetaIoHandler::with_middleware(auth::AuthMiddleware);
+        io.add_method_with_meta("watch_outpoints", |_: Params, _: WsMeta| {
+            futures::future::ok(Value::Bool(true))
+        });
+        io.add_method_with_meta("subscribe_reorgs", |_: Params, _: WsMeta| {
+            futures::future::ok(Value::Bool(true))
+        });
+        let meta = |role| WsMeta {
+            session: None,
+            role,
+            key_name: Some("dashboard".into()),
+        };
+        let call = |method: &str, role| {
+            let request = format!(r#"{{"jsonrpc":"2.0","method":"{}","id":1}}"#, method);
+            io.handle_request_sync(&request, meta(role))
+                .expect("No response")
+        };
+
+        let refused = call("watch_outpoints", Some(Role::Observer));
+        assert!(refused.contains("Unauthorized, watch_outpoints needs the operator role"));
+        assert!(call("watch_outpoints", None).contains("Unauthorized"));
+        assert!(call("watch_outpoints", Some(Role::Operator)).contains(r#""result":true"#));
+        assert!(call("subscribe_reorgs", Some(Role::Observer)).contains(r#""result":true"#));
+    }
+}
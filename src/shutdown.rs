@@ -0,0 +1,63 @@
+//! Graceful shutdown on SIGINT and SIGTERM. The scanner stops at the next point where
+//! no mirror is rolled back and restores its mirrors, then the servers deliver pending
+//! notifications and close. A second signal exits immediately.
+use log::{error, warn};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Set once a shutdown was requested, shared by the scanner and the servers.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Trigger `shutdown` on SIGINT or SIGTERM, and call `on_signal` to wake up whatever
+/// has to notice it.
+pub fn handle_signals(
+    shutdown: Shutdown,
+    on_signal: impl Fn() + Send + 'static,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .expect("Starting signal runtime failed");
+
+        runtime.block_on(async move {
+            let mut interrupt = signal(SignalKind::interrupt()).expect("SIGINT handler failed");
+            let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler failed");
+
+            loop {
+                tokio::select! {
+                    _ = interrupt.recv() => {},
+                    _ = terminate.recv() => {},
+                }
+
+                if shutdown.is_triggered() {
+                    error!("Second signal, exiting without cleaning up");
+                    std::process::exit(1);
+                }
+
+                warn!("Shutting down after the current scan, signal again to exit now");
+                shutdown.trigger();
+                on_signal();
+            }
+        });
+    })
+}
@@ -0,0 +1,68 @@
+//! HMAC-SHA256 signatures of outgoing payloads, so receivers can check an alert came
+//! from this instance. Webhook sinks with a `secret` sign `<timestamp>.<body>` and send
+//! the signature and timestamp as headers. Websocket subscribers with a `client_id` and
+//! a secret set with `set_subscriber_secret` get a `signature` in every notification
+//! object, over the object without it as compact JSON with sorted keys.
+use bitcoin_hashes::{hex::ToHex, sha256, Hash, HashEngine, Hmac, HmacEngine};
+use serde_json::Value;
+
+pub const SIGNATURE_HEADER: &str = "X-Forkscanner-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Forkscanner-Timestamp";
+
+/// The hex HMAC-SHA256 of `message`.
+pub fn sign(secret: &str, message: &str) -> String {
+    let mut engine = HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+    engine.input(message.as_bytes());
+    Hmac::<sha256::Hash>::from_engine(engine).to_hex()
+}
+
+/// The `X-Forkscanner-Signature` of a webhook body sent at `timestamp`.
+pub fn sign_webhook(secret: &str, timestamp: u64, body: &str) -> String {
+    format!(
+        "sha256={}",
+        sign(secret, &format!("{}.{}", timestamp, body))
+    )
+}
+
+/// Add a `signature` to a notification object.
+pub fn sign_event(secret: &str, value: &mut Value) {
+    if let Value::Object(map) = value {
+        map.remove("signature");
+        let signature = sign(secret, &Value::Object(map.clone()).to_string());
+        map.insert("signature".into(), signature.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            sign_webhook("Jefe", 1700000000, "{}"),
+            format!("sha256={}", sign("Jefe", "1700000000.{}"))
+        );
+
+        let mut event = json!({ "height": 10, "block_hash": "00aa", "event_id": 3 });
+        sign_event("secret", &mut event);
+        let signature = event["signature"].as_str().unwrap().to_string();
+        assert_eq!(
+            signature,
+            sign(
+                "secret",
+                r#"{"block_hash":"00aa","event_id":3,"height":10}"#
+            )
+        );
+
+        // signing again replaces the signature
+        sign_event("secret", &mut event);
+        assert_eq!(event["signature"], json!(signature));
+    }
+}
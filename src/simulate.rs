@@ -0,0 +1,394 @@
+//! Forks on demand, for rehearsing reorg handling in staging. Two connected regtest
+//! nodes are cut off from each other, each mines its own branch with one side of some
+//! double spends, and the longer branch is then handed to the other node, which
+//! reorgs to it. A scanner watching the nodes sends the same notifications as for a
+//! fork on mainnet: the stale branch, the reorg and the double spent transactions.
+use crate::credentials::{node_auth, CredentialError};
+use crate::rpc_client::node_rpc_client;
+use crate::Node;
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+// wallet the double spent coins are mined to, on the node mining the stale branch
+const WALLET_NAME: &str = "forkscanner-simulation";
+const COINBASE_MATURITY: usize = 100;
+const FEE_BTC: f64 = 0.0001;
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Error)]
+pub enum SimulateError {
+    #[error("Could not query the node, {0}")]
+    Rpc(#[from] bitcoincore_rpc::Error),
+    #[error("Node credentials error {0:?}")]
+    Credentials(#[from] CredentialError),
+    #[error("Node is on {0}, forks are only simulated on regtest")]
+    NotRegtest(String),
+    #[error("The nodes didn't reach the same tip, are they connected?")]
+    NotInSync,
+    #[error("Node still has peers with its network disabled")]
+    StillConnected,
+    #[error("The wallet could not sign a double spend of {0}")]
+    Unsigned(String),
+}
+
+/// An output spent by a different transaction on each branch.
+#[derive(Debug, Serialize)]
+pub struct DoubleSpend {
+    pub outpoint: String,
+    pub stale_txid: String,
+    pub winning_txid: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulatedFork {
+    /// Height of the last block both branches share.
+    pub fork_height: u64,
+    pub stale_blocks: Vec<String>,
+    pub winning_blocks: Vec<String>,
+    pub double_spends: Vec<DoubleSpend>,
+}
+
+#[derive(Deserialize)]
+struct Unspent {
+    txid: String,
+    vout: u32,
+    amount: f64,
+    spendable: bool,
+}
+
+#[derive(Deserialize)]
+struct Signed {
+    hex: String,
+    complete: bool,
+}
+
+/// A client for a node's RPC port.
+pub fn node_client(node: &Node) -> Result<Client, SimulateError> {
+    let host = format!("http://{}:{}", node.rpc_host, node.rpc_port);
+    Ok(node_rpc_client(
+        node.id,
+        &host,
+        node_auth(node)?,
+        node.into(),
+    )?)
+}
+
+// Poll `done` until it holds, or give up with `err`.
+fn wait_for<F>(mut done: F, err: SimulateError) -> Result<(), SimulateError>
+where
+    F: FnMut() -> Result<bool, SimulateError>,
+{
+    let start = Instant::now();
+    while !done()? {
+        if start.elapsed() > WAIT_TIMEOUT {
+            return Err(err);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn check_regtest(client: &impl RpcApi) -> Result<(), SimulateError> {
+    let info: Value = client.call("getblockchaininfo", &[])?;
+    match info["chain"].as_str() {
+        Some("regtest") => Ok(()),
+        chain => Err(SimulateError::NotRegtest(chain.unwrap_or("unknown").into())),
+    }
+}
+
+// Load the simulation wallet, creating it the first time, unless the node has one.
+fn load_wallet(client: &impl RpcApi) -> Result<(), SimulateError> {
+    let wallets: Vec<String> = client.call("listwallets", &[])?;
+    if wallets.is_empty()
+        && client
+            .call::<Value>("loadwallet", &[WALLET_NAME.into()])
+            .is_err()
+    {
+        client.call::<Value>("createwallet", &[WALLET_NAME.into()])?;
+    }
+    Ok(())
+}
+
+fn new_address(client: &impl RpcApi) -> Result<String, SimulateError> {
+    Ok(client.call("getnewaddress", &[])?)
+}
+
+fn mine(client: &impl RpcApi, blocks: usize, address: &str) -> Result<Vec<String>, SimulateError> {
+    Ok(client.call("generatetoaddress", &[blocks.into(), address.into()])?)
+}
+
+// Confirmed outputs of the wallet worth spending, mining some first if there are
+// fewer than `count`.
+fn spendable(
+    client: &impl RpcApi,
+    count: usize,
+    address: &str,
+) -> Result<Vec<Unspent>, SimulateError> {
+    let list = || -> Result<Vec<Unspent>, SimulateError> {
+        let unspent: Vec<Unspent> = client.call("listunspent", &[1.into()])?;
+        Ok(unspent
+            .into_iter()
+            .filter(|u| u.spendable && u.amount > 2.0 * FEE_BTC)
+            .collect())
+    };
+
+    let mut unspent = list()?;
+    if unspent.len() < count {
+        mine(client, count - unspent.len() + COINBASE_MATURITY, address)?;
+        unspent = list()?;
+    }
+    unspent.truncate(count);
+    Ok(unspent)
+}
+
+// A signed transaction sending `coin` to `address`, less the fee.
+fn spend(client: &impl RpcApi, coin: &Unspent, address: &str) -> Result<String, SimulateError> {
+    let inputs = json!([{ "txid": coin.txid, "vout": coin.vout }]);
+    let amount = ((coin.amount - FEE_BTC) * 1e8).round() / 1e8;
+    let mut outputs = serde_json::Map::new();
+    outputs.insert(address.into(), amount.into());
+    let raw: String = client.call("createrawtransaction", &[inputs, outputs.into()])?;
+
+    let signed: Signed = client.call("signrawtransactionwithwallet", &[raw.into()])?;
+    if !signed.complete {
+        return Err(SimulateError::Unsigned(format!(
+            "{}:{}",
+            coin.txid, coin.vout
+        )));
+    }
+    Ok(signed.hex)
+}
+
+fn set_network_active(client: &impl RpcApi, active: bool) -> Result<(), SimulateError> {
+    client.call::<Value>("setnetworkactive", &[active.into()])?;
+    Ok(())
+}
+
+// Hand `blocks` from one node to the other.
+fn copy_blocks(
+    from: &impl RpcApi,
+    to: &impl RpcApi,
+    blocks: &[String],
+) -> Result<(), SimulateError> {
+    for hash in blocks {
+        let hex: String = from.call("getblock", &[hash.as_str().into(), 0.into()])?;
+        to.call::<Value>("submitblock", &[hex.into()])?;
+    }
+    Ok(())
+}
+
+// A transaction for each side of a double spend of `outpoint`.
+struct Conflict {
+    outpoint: String,
+    stale_tx: String,
+    winning_tx: String,
+}
+
+// The fork itself, with the nodes cut off from each other.
+fn mine_branches<C: RpcApi>(
+    stale: &C,
+    winner: &C,
+    depth: usize,
+    address: &str,
+    conflicts: &[Conflict],
+    fork: &mut SimulatedFork,
+) -> Result<(), SimulateError> {
+    for client in [stale, winner].iter() {
+        set_network_active(*client, false)?;
+        wait_for(
+            || Ok(client.call::<u64>("getconnectioncount", &[])? == 0),
+            SimulateError::StillConnected,
+        )?;
+    }
+
+    for conflict in conflicts {
+        let stale_tx = conflict.stale_tx.as_str();
+        let winning_tx = conflict.winning_tx.as_str();
+        fork.double_spends.push(DoubleSpend {
+            outpoint: conflict.outpoint.clone(),
+            stale_txid: stale.call("sendrawtransaction", &[stale_tx.into()])?,
+            winning_txid: winner.call("sendrawtransaction", &[winning_tx.into()])?,
+        });
+    }
+
+    fork.stale_blocks = mine(stale, depth, address)?;
+    fork.winning_blocks = mine(winner, depth + 1, address)?;
+
+    // both nodes learn both branches, so the stale one is a valid-fork tip everywhere
+    copy_blocks(winner, stale, &fork.winning_blocks)?;
+    copy_blocks(stale, winner, &fork.stale_blocks)?;
+    Ok(())
+}
+
+/// Make two connected regtest nodes fork: `stale` mines `depth` blocks and `winner`
+/// one more on top of the current tip, each with their side of `double_spends` double
+/// spends, after which `stale` reorgs to the winning branch. The coins are mined to a
+/// wallet on `stale` first if it doesn't have enough.
+pub fn simulate_fork<C: RpcApi>(
+    stale: &C,
+    winner: &C,
+    depth: usize,
+    double_spends: usize,
+) -> Result<SimulatedFork, SimulateError> {
+    check_regtest(stale)?;
+    check_regtest(winner)?;
+    load_wallet(stale)?;
+
+    let address = new_address(stale)?;
+    let coins = spendable(stale, double_spends, &address)?;
+    wait_for(
+        || {
+            let stale_tip: String = stale.call("getbestblockhash", &[])?;
+            let winning_tip: String = winner.call("getbestblockhash", &[])?;
+            Ok(stale_tip == winning_tip)
+        },
+        SimulateError::NotInSync,
+    )?;
+
+    let mut conflicts = vec![];
+    for coin in &coins {
+        conflicts.push(Conflict {
+            outpoint: format!("{}:{}", coin.txid, coin.vout),
+            stale_tx: spend(stale, coin, &new_address(stale)?)?,
+            winning_tx: spend(stale, coin, &new_address(stale)?)?,
+        });
+    }
+
+    let mut fork = SimulatedFork {
+        fork_height: stale.call("getblockcount", &[])?,
+        stale_blocks: vec![],
+        winning_blocks: vec![],
+        double_spends: vec![],
+    };
+    // the nodes are reconnected even if the fork fails half way
+    let mined = mine_branches(stale, winner, depth, &address, &conflicts, &mut fork);
+    set_network_active(stale, true)?;
+    set_network_active(winner, true)?;
+    mined?;
+    Ok(fork)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Just enough of a regtest node: blocks are named after the node that mined them,
+    // transactions after the address they pay.
+    #[derive(Default)]
+    struct MockNode {
+        name: &'static str,
+        chain: RefCell<Vec<String>>,
+        known: RefCell<Vec<String>>,
+        network: RefCell<Vec<bool>>,
+        wallets: RefCell<Vec<String>>,
+        addresses: RefCell<usize>,
+    }
+
+    impl MockNode {
+        fn new(name: &'static str, height: usize) -> MockNode {
+            MockNode {
+                name,
+                chain: RefCell::new((0..=height).map(|h| format!("shared-{}", h)).collect()),
+                ..Default::default()
+            }
+        }
+
+        fn respond(&self, cmd: &str, args: &[Value]) -> Value {
+            match cmd {
+                "getblockchaininfo" => json!({ "chain": "regtest" }),
+                "listwallets" => json!(*self.wallets.borrow()),
+                "loadwallet" | "createwallet" => {
+                    self.wallets.borrow_mut().push(args[0].to_string());
+                    json!({ "name": args[0] })
+                }
+                "getnewaddress" => {
+                    *self.addresses.borrow_mut() += 1;
+                    json!(format!("bcrt1{}{}", self.name, self.addresses.borrow()))
+                }
+                "generatetoaddress" => {
+                    let mut chain = self.chain.borrow_mut();
+                    let mut mined = vec![];
+                    for _ in 0..args[0].as_u64().unwrap() {
+                        let hash = format!("{}-{}", self.name, chain.len());
+                        chain.push(hash.clone());
+                        mined.push(hash);
+                    }
+                    json!(mined)
+                }
+                // the coinbases of mature blocks
+                "listunspent" => {
+                    let chain = self.chain.borrow();
+                    let mature = chain.len().saturating_sub(COINBASE_MATURITY);
+                    json!(chain[..mature]
+                        .iter()
+                        .map(|c| json!({ "txid": c, "vout": 0, "amount": 50.0, "spendable": true }))
+                        .collect::<Vec<_>>())
+                }
+                "createrawtransaction" => {
+                    let inputs = &args[0][0];
+                    let (address, amount) = args[1].as_object().unwrap().iter().next().unwrap();
+                    json!(format!(
+                        "{}:{}>{}:{}",
+                        inputs["txid"], inputs["vout"], address, amount
+                    ))
+                }
+                "signrawtransactionwithwallet" => json!({ "hex": args[0], "complete": true }),
+                "getbestblockhash" => json!(self.chain.borrow().last()),
+                "getblockcount" => json!(self.chain.borrow().len() - 1),
+                "setnetworkactive" => {
+                    self.network.borrow_mut().push(args[0].as_bool().unwrap());
+                    json!(args[0])
+                }
+                "getconnectioncount" => json!(0),
+                "sendrawtransaction" => json!(args[0].as_str().unwrap().split('>').nth(1)),
+                "getblock" => args[0].clone(),
+                "submitblock" => {
+                    self.known
+                        .borrow_mut()
+                        .push(args[0].as_str().unwrap().into());
+                    json!(null)
+                }
+                _ => panic!("Unexpected call {}", cmd),
+            }
+        }
+    }
+
+    impl RpcApi for MockNode {
+        fn call<T: for<'a> serde::de::Deserialize<'a>>(
+            &self,
+            cmd: &str,
+            args: &[Value],
+        ) -> bitcoincore_rpc::Result<T> {
+            Ok(serde_json::from_value(self.respond(cmd, args))?)
+        }
+    }
+
+    #[test]
+    fn test_simulate_fork() {
+        let stale = MockNode::new("a", 101);
+        let winner = MockNode::new("b", 101);
+        let fork = simulate_fork(&stale, &winner, 2, 2).expect("Simulation failed");
+
+        assert_eq!(fork.fork_height, 101);
+        assert_eq!(fork.stale_blocks, vec!["a-102", "a-103"]);
+        assert_eq!(fork.winning_blocks, vec!["b-102", "b-103", "b-104"]);
+        assert_eq!(*stale.known.borrow(), fork.winning_blocks);
+        assert_eq!(*winner.known.borrow(), fork.stale_blocks);
+        assert_eq!(*stale.network.borrow(), vec![false, true]);
+        assert_eq!(*winner.network.borrow(), vec![false, true]);
+
+        let outpoints: Vec<_> = fork.double_spends.iter().map(|d| &d.outpoint).collect();
+        assert_eq!(outpoints, vec!["shared-0:0", "shared-1:0"]);
+        for spend in &fork.double_spends {
+            assert_ne!(spend.stale_txid, spend.winning_txid);
+        }
+        assert_eq!(stale.wallets.borrow().len(), 1);
+        assert!(winner.wallets.borrow().is_empty());
+    }
+}
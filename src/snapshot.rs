@@ -0,0 +1,335 @@
+//! Snapshots of the scanner's fork history: the nodes, the blocks and which nodes found
+//! them valid or invalid, chaintips, stale candidates and the watchlists. A snapshot is
+//! read in one transaction, so it is consistent while the scanner runs, and restored
+//! into an empty database, to move an instance or to seed a staging one with
+//! production forks. The file is a line naming each table followed by a JSON line per
+//! row, gzipped when its name ends in `.gz`.
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::sql_types;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+const SNAPSHOT_VERSION: i64 = 1;
+// rows read from the cursor, and inserted, at a time
+const BATCH_ROWS: usize = 1000;
+
+/// The tables in a snapshot, the ones other tables refer to first.
+const SNAPSHOT_TABLES: [&str; 14] = [
+    "nodes",
+    "blocks",
+    "valid_blocks",
+    "invalid_blocks",
+    "chaintips",
+    "stale_candidate",
+    "stale_candidate_children",
+    "double_spent_by",
+    "rbf_by",
+    "conflict_addresses",
+    "imported_stale_blocks",
+    "watched_descriptors",
+    "watched",
+    "watched_outpoints",
+];
+
+// tables with an identity column, which continues after the restored ids
+const IDENTITY_TABLES: [&str; 2] = ["nodes", "chaintips"];
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Snapshot file error {0}")]
+    Io(#[from] io::Error),
+    #[error("Database error {0}")]
+    Database(#[from] diesel::result::Error),
+    #[error("Line {0}: {1}")]
+    BadLine(usize, String),
+    #[error("Table {0} is not empty, snapshots are restored into an empty database")]
+    NotEmpty(&'static str),
+}
+
+#[derive(QueryableByName)]
+struct JsonRow {
+    #[sql_type = "sql_types::Text"]
+    row: String,
+}
+
+#[derive(QueryableByName)]
+struct RowCount {
+    #[sql_type = "sql_types::BigInt"]
+    rows: i64,
+}
+
+/// Rows written or restored, per table.
+pub type TableRows = Vec<(&'static str, usize)>;
+
+fn is_gzip(path: &Path) -> bool {
+    path.extension().map(|ext| ext == "gz").unwrap_or(false)
+}
+
+// Copy the rows of `table` to `out`, through a cursor so they aren't all in memory.
+fn write_table<W: Write>(
+    conn: &PgConnection,
+    out: &mut W,
+    table: &str,
+    credentials: bool,
+) -> Result<usize, SnapshotError> {
+    let row = if table == "nodes" && !credentials {
+        "(to_jsonb(t) || jsonb_build_object('rpc_user', '', 'rpc_pass', ''))::text"
+    } else {
+        "row_to_json(t)::text"
+    };
+    // tips point at the active tip of their node, which has no parent, so those come
+    // first and are inserted before the tips pointing at them
+    let order = match table {
+        "chaintips" => "ORDER BY parent_chaintip IS NOT NULL, id",
+        _ => "",
+    };
+    diesel::sql_query(format!(
+        "DECLARE snapshot_rows NO SCROLL CURSOR FOR SELECT {} AS row FROM {} t {}",
+        row, table, order
+    ))
+    .execute(conn)?;
+
+    writeln!(out, "{}", json!({ "table": table }))?;
+    let mut rows = 0;
+    loop {
+        let batch: Vec<JsonRow> =
+            diesel::sql_query(format!("FETCH {} FROM snapshot_rows", BATCH_ROWS)).load(conn)?;
+        for JsonRow { row } in &batch {
+            writeln!(out, "{}", row)?;
+        }
+        rows += batch.len();
+        if batch.len() < BATCH_ROWS {
+            break;
+        }
+    }
+
+    diesel::sql_query("CLOSE snapshot_rows").execute(conn)?;
+    Ok(rows)
+}
+
+/// Write a snapshot to `out`. Without `credentials` the nodes' RPC user and password
+/// are left empty, e.g. for a snapshot handed to a staging environment.
+pub fn snapshot<W: Write>(
+    conn: &PgConnection,
+    out: &mut W,
+    credentials: bool,
+) -> Result<TableRows, SnapshotError> {
+    let header = json!({ "forkscanner_snapshot": SNAPSHOT_VERSION, "created_at": Utc::now() });
+    writeln!(out, "{}", header)?;
+
+    conn.build_transaction()
+        .serializable()
+        .read_only()
+        .deferrable()
+        .run(|| {
+            SNAPSHOT_TABLES
+                .iter()
+                .map(|table| Ok((*table, write_table(conn, out, table, credentials)?)))
+                .collect()
+        })
+}
+
+// The table a snapshot is being restored into, with the rows not inserted yet.
+struct Section {
+    table: &'static str,
+    columns: Vec<String>,
+    rows: Vec<Value>,
+    restored: usize,
+}
+
+impl Section {
+    fn new(table: &'static str) -> Section {
+        Section {
+            table,
+            columns: vec![],
+            rows: vec![],
+            restored: 0,
+        }
+    }
+
+    // Add a row, the columns must be plain names as they go into the insert, and the
+    // same for every row of the table.
+    fn add(&mut self, row: Map<String, Value>) -> Result<(), String> {
+        let valid = |name: &String| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        };
+        if let Some(name) = row.keys().find(|name| !valid(name)) {
+            return Err(format!("bad column name {}", name));
+        }
+        if self.columns.is_empty() {
+            self.columns = row.keys().cloned().collect();
+        } else if !row.keys().eq(self.columns.iter()) {
+            return Err(format!(
+                "columns differ from the first row of {}",
+                self.table
+            ));
+        }
+        self.rows.push(Value::Object(row));
+        Ok(())
+    }
+
+    fn insert(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = Value::Array(self.rows.split_off(0));
+        self.restored += diesel::sql_query(format!(
+            "INSERT INTO {table} ({columns}) OVERRIDING SYSTEM VALUE
+             SELECT {columns} FROM json_populate_recordset(null::{table}, $1::json)",
+            table = self.table,
+            columns = self.columns.join(", ")
+        ))
+        .bind::<sql_types::Text, _>(rows.to_string())
+        .execute(conn)?;
+        Ok(())
+    }
+}
+
+/// Restore a snapshot into a database whose snapshot tables are all empty, in one
+/// transaction. Columns added since the snapshot was taken get their defaults.
+pub fn restore<R: BufRead>(conn: &PgConnection, input: R) -> Result<TableRows, SnapshotError> {
+    conn.transaction(|| {
+        for table in SNAPSHOT_TABLES.iter() {
+            let count: RowCount = diesel::sql_query(format!(
+                "SELECT count(*) AS rows FROM (SELECT 1 FROM {} LIMIT 1) t",
+                table
+            ))
+            .get_result(conn)?;
+            if count.rows > 0 {
+                return Err(SnapshotError::NotEmpty(table));
+            }
+        }
+
+        let mut lines = input.lines().enumerate().map(|(idx, line)| (idx + 1, line));
+        let header = match lines.next() {
+            Some((_, line)) => serde_json::from_str::<Value>(&line?).ok(),
+            None => None,
+        };
+        if header.map(|h| h["forkscanner_snapshot"].clone()) != Some(json!(SNAPSHOT_VERSION)) {
+            return Err(SnapshotError::BadLine(
+                1,
+                "not a forkscanner snapshot".into(),
+            ));
+        }
+
+        let mut restored: TableRows = vec![];
+        let mut section: Option<Section> = None;
+        for (line_no, line) in lines {
+            let bad_line = |msg: String| SnapshotError::BadLine(line_no, msg);
+            let row = match serde_json::from_str(&line?) {
+                Ok(Value::Object(row)) => row,
+                Ok(_) => return Err(bad_line("expected an object".into())),
+                Err(e) => return Err(bad_line(format!("bad JSON {}", e))),
+            };
+
+            if let Some(name) = row.get("table").filter(|_| row.len() == 1) {
+                let table = SNAPSHOT_TABLES
+                    .iter()
+                    .find(|table| name == *table)
+                    .ok_or_else(|| bad_line(format!("unknown table {}", name)))?;
+                if let Some(mut done) = section.replace(Section::new(table)) {
+                    done.insert(conn)?;
+                    restored.push((done.table, done.restored));
+                }
+                continue;
+            }
+
+            let current = section
+                .as_mut()
+                .ok_or_else(|| bad_line("row before a table".into()))?;
+            current.add(row).map_err(bad_line)?;
+            if current.rows.len() == BATCH_ROWS {
+                current.insert(conn)?;
+            }
+        }
+        if let Some(mut done) = section {
+            done.insert(conn)?;
+            restored.push((done.table, done.restored));
+        }
+
+        for table in IDENTITY_TABLES.iter() {
+            diesel::sql_query(format!(
+                "SELECT setval(pg_get_serial_sequence('{table}', 'id'), max(id)) FROM {table}
+                 HAVING max(id) IS NOT NULL",
+                table = table
+            ))
+            .execute(conn)?;
+        }
+        Ok(restored)
+    })
+}
+
+/// Write a snapshot to a file, see `snapshot`.
+pub fn snapshot_file(
+    conn: &PgConnection,
+    path: &Path,
+    credentials: bool,
+) -> Result<TableRows, SnapshotError> {
+    let file = BufWriter::new(File::create(path)?);
+    if is_gzip(path) {
+        let mut out = GzEncoder::new(file, Compression::default());
+        let written = snapshot(conn, &mut out, credentials)?;
+        out.finish()?.flush()?;
+        Ok(written)
+    } else {
+        let mut out = file;
+        let written = snapshot(conn, &mut out, credentials)?;
+        out.flush()?;
+        Ok(written)
+    }
+}
+
+/// Restore a snapshot file, see `restore`.
+pub fn restore_file(conn: &PgConnection, path: &Path) -> Result<TableRows, SnapshotError> {
+    let file = File::open(path)?;
+    let input: Box<dyn Read> = if is_gzip(path) {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    restore(conn, BufReader::new(input))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn row(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(row) => row,
+            _ => panic!("Not an object"),
+        }
+    }
+
+    #[test]
+    fn test_section() {
+        let mut section = Section::new("chaintips");
+        section
+            .add(row(json!({ "id": 1, "node": 2, "parent_chaintip": null })))
+            .expect("Row rejected");
+        section
+            .add(row(json!({ "parent_chaintip": 1, "node": 2, "id": 2 })))
+            .expect("Row rejected");
+        assert_eq!(section.columns, vec!["id", "node", "parent_chaintip"]);
+        assert_eq!(section.rows.len(), 2);
+
+        assert!(section.add(row(json!({ "id": 3, "node": 2 }))).is_err());
+        assert!(section
+            .add(row(json!({ "id": 3, "node": 2, "parent_chaintip) --": 1 })))
+            .is_err());
+        assert!(Section::new("nodes").add(row(json!({ "Id": 1 }))).is_err());
+        assert_eq!(section.rows.len(), 2);
+
+        assert!(is_gzip(Path::new("/tmp/forks.jsonl.gz")));
+        assert!(!is_gzip(Path::new("/tmp/forks.jsonl")));
+    }
+}
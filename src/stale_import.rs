@@ -0,0 +1,146 @@
+//! Parsing of external stale block datasets, so forks that happened before this
+//! deployment show up next to the ones the scanner saw itself.
+use crate::ImportedStaleBlock;
+use bitcoin::{consensus::deserialize, BlockHash, BlockHeader};
+use chrono::prelude::*;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("Dataset has no header row")]
+    Empty,
+    #[error("Dataset has no {0} column")]
+    MissingColumn(&'static str),
+    #[error("Line {0}: {1}")]
+    BadLine(usize, String),
+}
+
+fn column(names: &[&str], wanted: &[&'static str]) -> Option<usize> {
+    names.iter().position(|n| wanted.contains(n))
+}
+
+// Parent hash of a hex encoded 80 byte header, checking it hashes to `hash`.
+fn header_parent(header: &str, hash: &BlockHash) -> Result<String, String> {
+    let bytes = hex::decode(header).map_err(|e| format!("bad header hex {}", e))?;
+    let header: BlockHeader = deserialize(&bytes).map_err(|e| format!("bad header {}", e))?;
+
+    if &header.block_hash() != hash {
+        return Err(format!("header does not match hash {}", hash));
+    }
+
+    Ok(header.prev_blockhash.to_string())
+}
+
+/// Parse a CSV dataset of stale blocks. The first row names the columns, `height` and
+/// `hash` are required. The parent hash is read from a `parent_hash` or `prev_hash`
+/// column, or from a hex encoded `header` column, other columns are ignored.
+pub fn parse_stale_blocks(
+    source: &str,
+    data: &str,
+) -> Result<Vec<ImportedStaleBlock>, ImportError> {
+    let mut lines = data
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let (_, header) = lines.next().ok_or(ImportError::Empty)?;
+    let names: Vec<_> = header.split(',').map(|n| n.trim()).collect();
+
+    let height_col = column(&names, &["height"]).ok_or(ImportError::MissingColumn("height"))?;
+    let hash_col = column(&names, &["hash"]).ok_or(ImportError::MissingColumn("hash"))?;
+    let parent_col = column(&names, &["parent_hash", "prev_hash"]);
+    let header_col = column(&names, &["header"]);
+
+    let imported_at = Utc::now();
+    lines
+        .map(|(line_no, line)| {
+            let fields: Vec<_> = line.split(',').map(|f| f.trim()).collect();
+            let field = |col: usize| fields.get(col).cloned().filter(|f| !f.is_empty());
+            let bad_line = |msg: String| ImportError::BadLine(line_no, msg);
+
+            let height = field(height_col)
+                .ok_or_else(|| bad_line("missing height".into()))?
+                .parse::<i64>()
+                .map_err(|e| bad_line(format!("bad height {}", e)))?;
+            let hash = field(hash_col).ok_or_else(|| bad_line("missing hash".into()))?;
+            let hash =
+                BlockHash::from_str(hash).map_err(|e| bad_line(format!("bad hash {}", e)))?;
+
+            let parent_hash = match (parent_col.and_then(field), header_col.and_then(field)) {
+                (Some(parent), _) => Some(
+                    BlockHash::from_str(parent)
+                        .map_err(|e| bad_line(format!("bad parent hash {}", e)))?
+                        .to_string(),
+                ),
+                (None, Some(header)) => Some(header_parent(header, &hash).map_err(bad_line)?),
+                (None, None) => None,
+            };
+
+            Ok(ImportedStaleBlock {
+                hash: hash.to_string(),
+                height,
+                parent_hash,
+                source: source.to_string(),
+                imported_at,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Header of block 1.
+    const HEADER: &str = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+    const HASH: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+    const PARENT: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+    #[test]
+    fn test_parse_stale_blocks() {
+        let data = format!(
+            "height,hash,parent_hash\n\n# comment\n1,{},{}\n2,{},\n",
+            HASH, PARENT, PARENT
+        );
+        let blocks = parse_stale_blocks("test", &data).expect("Parse failed");
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].height, 1);
+        assert_eq!(blocks[0].hash, HASH);
+        assert_eq!(blocks[0].parent_hash.as_deref(), Some(PARENT));
+        assert_eq!(blocks[0].source, "test");
+        assert_eq!(blocks[1].parent_hash, None);
+    }
+
+    #[test]
+    fn test_parse_stale_block_header() {
+        let data = format!("hash,height,header\n{},1,{}\n", HASH, HEADER);
+        let blocks = parse_stale_blocks("test", &data).expect("Parse failed");
+        assert_eq!(blocks[0].parent_hash.as_deref(), Some(PARENT));
+
+        // header of a different block
+        let data = format!("hash,height,header\n{},1,{}\n", PARENT, HEADER);
+        assert!(matches!(
+            parse_stale_blocks("test", &data),
+            Err(ImportError::BadLine(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_parse_stale_blocks_errors() {
+        assert!(matches!(
+            parse_stale_blocks("test", ""),
+            Err(ImportError::Empty)
+        ));
+        assert!(matches!(
+            parse_stale_blocks("test", "height,parent_hash\n"),
+            Err(ImportError::MissingColumn("hash"))
+        ));
+        assert!(matches!(
+            parse_stale_blocks("test", "height,hash\nabc,def\n"),
+            Err(ImportError::BadLine(2, _))
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! TLS for the RPC and websocket listeners. Neither jsonrpc server supports TLS, so
+//! when a certificate is configured they listen on loopback and connections to the
+//! public port are decrypted here and forwarded to them.
+use log::{debug, error, info};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, thread};
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Could not read certificate {0}")]
+    Certificate(String),
+    #[error("Could not read private key {0}")]
+    PrivateKey(String),
+    #[error("Invalid certificate or key {0}")]
+    Config(#[from] rustls::Error),
+    #[error("Could not listen on {0}: {1}")]
+    Listen(SocketAddr, #[source] std::io::Error),
+}
+
+/// PEM encoded certificate chain and private key to serve.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsConfig {
+    fn acceptor(&self, alpn: &[&[u8]]) -> Result<TlsAcceptor, TlsError> {
+        let cert_err = |e| TlsError::Certificate(format!("{}: {:?}", self.cert.display(), e));
+        let certs = CertificateDer::pem_file_iter(&self.cert)
+            .map_err(cert_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(cert_err)?;
+        let key = PrivateKeyDer::from_pem_file(&self.key)
+            .map_err(|e| TlsError::PrivateKey(format!("{}: {:?}", self.key.display(), e)))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+async fn forward(acceptor: TlsAcceptor, stream: TcpStream, backend: SocketAddr) {
+    let mut stream = match acceptor.accept(stream).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            debug!("TLS handshake failed {:?}", e);
+            return;
+        }
+    };
+
+    match TcpStream::connect(backend).await {
+        Ok(mut server) => {
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut server).await;
+        }
+        Err(e) => error!("Could not connect to {} {:?}", backend, e),
+    }
+}
+
+/// Serve TLS on `listen`, forwarding decrypted connections to `backend`.
+pub fn serve_tls(
+    config: &TlsConfig,
+    listen: SocketAddr,
+    backend: SocketAddr,
+) -> Result<thread::JoinHandle<()>, TlsError> {
+    serve_tls_alpn(config, listen, backend, &[])
+}
+
+/// Serve TLS like `serve_tls`, offering the `alpn` protocols, e.g. `h2` for gRPC, whose
+/// clients won't connect without it.
+pub fn serve_tls_alpn(
+    config: &TlsConfig,
+    listen: SocketAddr,
+    backend: SocketAddr,
+    alpn: &[&[u8]],
+) -> Result<thread::JoinHandle<()>, TlsError> {
+    let acceptor = config.acceptor(alpn)?;
+    // bind here, so a port in use is an error for the caller and not a dead thread
+    let listener = std::net::TcpListener::bind(listen)
+        .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+        .map_err(|e| TlsError::Listen(listen, e))?;
+    let runtime = Runtime::new().map_err(|e| TlsError::Listen(listen, e))?;
+
+    let handle = thread::spawn(move || {
+        runtime.block_on(async move {
+            let listener = match TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Could not start TLS listener on {} {:?}", listen, e);
+                    return;
+                }
+            };
+            info!("TLS on {} for {}", listen, backend);
+
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(forward(acceptor.clone(), stream, backend));
+                    }
+                    Err(e) => error!("TLS accept failed {:?}", e),
+                }
+            }
+        });
+    });
+
+    Ok(handle)
+}
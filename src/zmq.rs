@@ -0,0 +1,165 @@
+//! Listens for `hashblock` notifications from nodes that have a ZMQ endpoint, built
+//! with the `zmq` feature, so the scanner runs as soon as a node sees a new block
+//! instead of at the next poll. Only `hashblock` is subscribed to, transactions are
+//! still fetched by the scanner, so nodes don't need `zmqpubrawtx`.
+use crate::{Node, ScannerCommand};
+use crossbeam::channel::Sender;
+use log::{info, warn};
+use std::collections::{hash_map::Entry, HashMap};
+use std::{thread, time::Duration};
+use tokio::{sync::mpsc, task::JoinHandle};
+use zeromq::{Socket, SocketRecv, SubSocket, ZmqMessage};
+
+const HASHBLOCK_TOPIC: &str = "hashblock";
+const RECONNECT_SECS: u64 = 10;
+
+// Block hash of a `hashblock` message, in the byte order RPC calls use.
+fn block_hash(message: &ZmqMessage) -> Option<String> {
+    let topic = message.get(0)?;
+    let body = message.get(1)?;
+
+    if topic.as_ref() != HASHBLOCK_TOPIC.as_bytes() || body.len() != 32 {
+        return None;
+    }
+
+    // bitcoind already sends the hash reversed.
+    Some(hex::encode(body))
+}
+
+async fn listen(node_id: i64, endpoint: String, command: Sender<ScannerCommand>) {
+    loop {
+        let mut socket = SubSocket::new();
+
+        let connected = match socket.connect(&endpoint).await {
+            Ok(_) => socket.subscribe(HASHBLOCK_TOPIC).await,
+            Err(e) => Err(e),
+        };
+
+        match connected {
+            Ok(_) => {
+                info!("Listening for blocks from node {} on {}", node_id, endpoint);
+                loop {
+                    match socket.recv().await {
+                        Ok(message) => {
+                            if let Some(hash) = block_hash(&message) {
+                                let cmd = ScannerCommand::NewBlockHash { node_id, hash };
+                                if command.send(cmd).is_err() {
+                                    // the scanner has stopped
+                                    return;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            warn!("ZMQ connection to node {} failed {:?}", node_id, e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => warn!("Could not connect to ZMQ on node {} {:?}", node_id, e),
+        }
+
+        tokio::time::sleep(Duration::from_secs(RECONNECT_SECS)).await;
+    }
+}
+
+// The ZMQ endpoint of every node that has one.
+fn endpoints(nodes: Vec<Node>) -> HashMap<i64, String> {
+    nodes
+        .into_iter()
+        .filter_map(|n| {
+            let node_id = n.id;
+            n.zmq_endpoint.map(|e| (node_id, e))
+        })
+        .collect()
+}
+
+// Keep one listener per endpoint, restarting the ones whose node changed or is gone
+// every time a new node list comes in.
+async fn supervise(
+    mut nodes_rx: mpsc::UnboundedReceiver<Vec<Node>>,
+    command: Sender<ScannerCommand>,
+) {
+    let mut listeners: HashMap<i64, (String, JoinHandle<()>)> = HashMap::new();
+
+    while let Some(nodes) = nodes_rx.recv().await {
+        let endpoints = endpoints(nodes);
+
+        listeners.retain(|node_id, (endpoint, listener)| {
+            let keep = endpoints.get(node_id) == Some(endpoint);
+            if !keep {
+                info!(
+                    "Stopped listening for blocks from node {} on {}",
+                    node_id, endpoint
+                );
+                listener.abort();
+            }
+            keep
+        });
+
+        for (node_id, endpoint) in endpoints {
+            if let Entry::Vacant(entry) = listeners.entry(node_id) {
+                let listener = tokio::spawn(listen(node_id, endpoint.clone(), command.clone()));
+                entry.insert((endpoint, listener));
+            }
+        }
+    }
+}
+
+/// The ZMQ listeners of the nodes, see [`listen_zmq`].
+pub struct ZmqListeners {
+    nodes_tx: mpsc::UnboundedSender<Vec<Node>>,
+}
+
+impl ZmqListeners {
+    /// Listen to the endpoints of a new node list, e.g. after the scanner reloaded
+    /// nodes. Listeners of nodes that didn't change keep running.
+    pub fn reload(&self, nodes: Vec<Node>) {
+        if self.nodes_tx.send(nodes).is_err() {
+            warn!("ZMQ listeners have stopped, not reloading them");
+        }
+    }
+}
+
+/// Start a thread listening to every node that has a ZMQ endpoint, announcing new
+/// blocks to the scanner with [`ScannerCommand::NewBlockHash`].
+pub fn listen_zmq(nodes: Vec<Node>, command: Sender<ScannerCommand>) -> ZmqListeners {
+    let (nodes_tx, nodes_rx) = mpsc::unbounded_channel();
+    let listeners = ZmqListeners { nodes_tx };
+    listeners.reload(nodes);
+
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Starting tokio runtime failed");
+
+        runtime.block_on(supervise(nodes_rx, command));
+    });
+
+    listeners
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(topic: &str, body: Vec<u8>) -> ZmqMessage {
+        let mut message = ZmqMessage::from(body);
+        message.prepend(&ZmqMessage::from(topic));
+        message
+    }
+
+    #[test]
+    fn test_block_hash() {
+        let mut body = vec![0; 32];
+        body[31] = 0xab;
+
+        assert_eq!(
+            block_hash(&message("hashblock", body.clone())),
+            Some(format!("{}ab", "0".repeat(62)))
+        );
+        assert_eq!(block_hash(&message("rawtx", body)), None);
+        assert_eq!(block_hash(&message("hashblock", vec![0; 4])), None);
+    }
+}